@@ -0,0 +1,140 @@
+use ergo_lib::ergotree_ir::chain::token::Token;
+use itertools::Itertools;
+
+use crate::boxes::liquidity_box::LiquidityProvider;
+
+/// A profitable round trip through two liquidity providers trading the same pair.
+/// `input` of asset x is swapped for `intermediate` of asset y in `buy_from`, which is
+/// then swapped back to `output` of asset x in `sell_to`.
+pub struct ArbitrageOpportunity<'a, T> {
+    pub buy_from: &'a T,
+    pub sell_to: &'a T,
+    pub input: Token,
+    pub intermediate: Token,
+    pub output: Token,
+}
+
+impl<T> ArbitrageOpportunity<'_, T> {
+    pub fn profit(&self) -> u64 {
+        self.output.amount.as_u64() - self.input.amount.as_u64()
+    }
+}
+
+/// Swap `input_amount` of asset x through `buy_from` and back through `sell_to`.
+fn round_trip<T>(buy_from: &T, sell_to: &T, input_amount: u64) -> Option<(Token, Token, Token)>
+where
+    T: LiquidityProvider,
+{
+    let input: Token = (buy_from.asset_x().token_id, input_amount.try_into().ok()?).into();
+    let intermediate = buy_from.output_amount(&input).ok()?;
+    let output = sell_to.output_amount(&intermediate).ok()?;
+
+    Some((input, intermediate, output))
+}
+
+fn round_trip_profit<T>(buy_from: &T, sell_to: &T, input_amount: u64) -> i128
+where
+    T: LiquidityProvider,
+{
+    round_trip(buy_from, sell_to, input_amount)
+        .map(|(input, _, output)| *output.amount.as_u64() as i128 - *input.amount.as_u64() as i128)
+        .unwrap_or(i128::MIN)
+}
+
+/// Find the most profitable input size for buying asset y in `buy_from` and selling it in
+/// `sell_to`. The round trip profit is concave in the input amount, so a ternary search
+/// over the asset x reserves of `buy_from` is used.
+fn best_round_trip<'a, T>(buy_from: &'a T, sell_to: &'a T) -> Option<ArbitrageOpportunity<'a, T>>
+where
+    T: LiquidityProvider,
+{
+    let mut lo = 1u64;
+    let mut hi = *buy_from.asset_x().amount.as_u64();
+
+    while hi - lo > 2 {
+        let m1 = lo + (hi - lo) / 3;
+        let m2 = hi - (hi - lo) / 3;
+
+        if round_trip_profit(buy_from, sell_to, m1) < round_trip_profit(buy_from, sell_to, m2) {
+            lo = m1;
+        } else {
+            hi = m2;
+        }
+    }
+
+    let best_input =
+        (lo..=hi).max_by_key(|amount| round_trip_profit(buy_from, sell_to, *amount))?;
+
+    let (input, intermediate, output) = round_trip(buy_from, sell_to, best_input)?;
+
+    if output.amount.as_u64() > input.amount.as_u64() {
+        Some(ArbitrageOpportunity {
+            buy_from,
+            sell_to,
+            input,
+            intermediate,
+            output,
+        })
+    } else {
+        None
+    }
+}
+
+/// Check both directions between two liquidity providers for the same pair and return the
+/// profitable one, if any.
+pub fn find_opportunity<'a, T>(a: &'a T, b: &'a T) -> Option<ArbitrageOpportunity<'a, T>>
+where
+    T: LiquidityProvider,
+{
+    if a.asset_x().token_id != b.asset_x().token_id || a.asset_y().token_id != b.asset_y().token_id
+    {
+        return None;
+    }
+
+    best_round_trip(a, b).or_else(|| best_round_trip(b, a))
+}
+
+/// Find arbitrage opportunities between every pair of liquidity providers trading the
+/// same assets.
+pub fn find_opportunities<T>(providers: &[T]) -> Vec<ArbitrageOpportunity<'_, T>>
+where
+    T: LiquidityProvider,
+{
+    providers
+        .iter()
+        .tuple_combinations()
+        .filter_map(|(a, b)| find_opportunity(a, b))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::spectrum::pool::arbitrary::test_pool;
+
+    use super::{find_opportunities, find_opportunity};
+
+    #[test]
+    fn mispriced_pools() {
+        let cheap = test_pool(1_000_000_000, 1_000_000, 997);
+        let expensive = test_pool(2_000_000_000, 1_000_000, 997);
+
+        for (a, b) in [(&cheap, &expensive), (&expensive, &cheap)] {
+            let opportunity = find_opportunity(a, b).expect("Opportunity expected");
+
+            assert!(std::ptr::eq(opportunity.buy_from, &cheap));
+            assert!(std::ptr::eq(opportunity.sell_to, &expensive));
+            assert_eq!(opportunity.input.token_id, cheap.asset_x.token_id);
+            assert_eq!(opportunity.intermediate.token_id, cheap.asset_y.token_id);
+            assert!(opportunity.profit() > 0);
+        }
+    }
+
+    #[test]
+    fn fees_exceed_discrepancy() {
+        let pool_a = test_pool(1_000_000_000, 1_000_000, 997);
+        let pool_b = test_pool(1_004_000_000, 1_000_000, 997);
+
+        assert!(find_opportunities(&[pool_a.clone(), pool_a.clone()]).is_empty());
+        assert!(find_opportunities(&[pool_a, pool_b]).is_empty());
+    }
+}
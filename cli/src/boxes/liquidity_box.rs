@@ -1,13 +1,23 @@
-use ergo_lib::ergotree_ir::chain::{
-    ergo_box::ErgoBoxCandidate,
-    token::{Token, TokenId},
-};
+use ergo_lib::ergotree_ir::chain::token::{Token, TokenId};
 use std::cmp::Ordering;
-
-use crate::grid::multigrid_order::{
-    FillMultiGridOrders, GridOrderEntries, GridOrderEntry, MultiGridOrder, MultiGridRef, OrderState,
+use thiserror::Error;
+
+use crate::{
+    grid::multigrid_order::{
+        FillMultiGridOrders, GridOrderEntries, GridOrderEntry, MultiGridOrder, MultiGridRef,
+        OrderState,
+    },
+    units::Fraction,
 };
 
+#[derive(Error, Debug)]
+pub enum LiquidityProviderError<E> {
+    #[error("Liquidity provider has no liquidity")]
+    NoLiquidity,
+    #[error(transparent)]
+    Swap(E),
+}
+
 /// Trait for boxes that can be used to swap tokens
 pub trait LiquidityProvider: Sized + Clone {
     type Error: std::error::Error;
@@ -16,8 +26,6 @@ pub trait LiquidityProvider: Sized + Clone {
 
     fn with_swap(self, input: &Token) -> Result<Self, Self::Error>;
 
-    fn into_box_candidate(self, creation_height: u32) -> Result<ErgoBoxCandidate, Self::Error>;
-
     fn output_amount(&self, input: &Token) -> Result<Token, Self::Error>;
 
     fn input_amount(&self, output: &Token) -> Result<Token, Self::Error>;
@@ -25,6 +33,41 @@ pub trait LiquidityProvider: Sized + Clone {
     fn asset_x(&self) -> &Token;
 
     fn asset_y(&self) -> &Token;
+
+    /// Relative difference between the spot price of asset y in asset x and the effective
+    /// price of swapping `input`. Fails instead of reporting a full loss if the provider has
+    /// no liquidity or the output of the swap is zero.
+    fn price_impact(&self, input: &Token) -> Result<Fraction, LiquidityProviderError<Self::Error>> {
+        let x_amount = *self.asset_x().amount.as_u64() as u128;
+        let y_amount = *self.asset_y().amount.as_u64() as u128;
+
+        if x_amount == 0 || y_amount == 0 {
+            return Err(LiquidityProviderError::NoLiquidity);
+        }
+
+        let output = self
+            .output_amount(input)
+            .map_err(LiquidityProviderError::Swap)?;
+
+        let input_amount = *input.amount.as_u64() as u128;
+        let output_amount = *output.amount.as_u64() as u128;
+
+        // Both prices in asset x per asset y
+        let spot = Fraction::new(x_amount, y_amount);
+        let effective = if input.token_id == self.asset_x().token_id {
+            Fraction::new(input_amount, output_amount)
+        } else {
+            Fraction::new(output_amount, input_amount)
+        };
+
+        let difference = if effective > spot {
+            effective - spot
+        } else {
+            spot - effective
+        };
+
+        Ok(difference / spot)
+    }
 }
 
 /// The state of the order matching process for a specific multi-grid order
@@ -43,11 +86,11 @@ impl OrderMatchingState<'_> {
         match self {
             OrderMatchingState::NotMatched(entries) => {
                 let mut entries = entries.clone();
-                entries.bid_entry_mut().unwrap().state = OrderState::Sell;
+                entries.fill_bid().unwrap();
                 *self = OrderMatchingState::MatchedBid(entries);
             }
             OrderMatchingState::MatchedBid(entries) => {
-                entries.bid_entry_mut().unwrap().state = OrderState::Sell;
+                entries.fill_bid().unwrap();
             }
             OrderMatchingState::MatchedAsk(_) => {
                 panic!("Cannot fill bid when ask is already filled");
@@ -59,14 +102,14 @@ impl OrderMatchingState<'_> {
         match self {
             OrderMatchingState::NotMatched(entries) => {
                 let mut entries = entries.clone();
-                entries.ask_entry_mut().unwrap().state = OrderState::Buy;
+                entries.fill_ask().unwrap();
                 *self = OrderMatchingState::MatchedAsk(entries);
             }
             OrderMatchingState::MatchedBid(_) => {
                 panic!("Cannot fill ask when bid is already filled");
             }
             OrderMatchingState::MatchedAsk(entries) => {
-                entries.ask_entry_mut().unwrap().state = OrderState::Buy;
+                entries.fill_ask().unwrap();
             }
         }
     }
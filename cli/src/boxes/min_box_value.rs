@@ -0,0 +1,111 @@
+use ergo_lib::{
+    chain::transaction::TxId,
+    ergotree_ir::{
+        chain::ergo_box::{box_value::BoxValue, ErgoBox, ErgoBoxCandidate},
+        serialization::{SigmaSerializable, SigmaSerializationError},
+    },
+};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum MinBoxValueError {
+    #[error("Box value {0} is below the minimum value {1} required for its size")]
+    BelowMinimum(u64, u64),
+
+    #[error(transparent)]
+    Serialization(#[from] SigmaSerializationError),
+}
+
+/// Minimum value the node accepts for a box created from the candidate.
+/// The protocol requires a fixed amount of nanoERGs per serialized byte, so every token
+/// and register added to the box raises its minimum value.
+pub fn min_box_value(candidate: &ErgoBoxCandidate) -> Result<u64, SigmaSerializationError> {
+    min_box_value_for(candidate, BoxValue::MIN_VALUE_PER_BOX_BYTE as u64)
+}
+
+/// Minimum value of a box created from the candidate for the given `min_value_per_byte`
+/// protocol parameter.
+pub fn min_box_value_for(
+    candidate: &ErgoBoxCandidate,
+    min_value_per_byte: u64,
+) -> Result<u64, SigmaSerializationError> {
+    // The transaction id and output index are part of the serialized box but do not
+    // depend on the box content, so placeholders are used.
+    let ergo_box = ErgoBox::from_box_candidate(candidate, TxId::zero(), 0)?;
+    let box_size = ergo_box.sigma_serialize_bytes()?.len() as u64;
+
+    Ok(box_size * min_value_per_byte)
+}
+
+/// Check that the candidate holds enough value to be accepted by the node.
+pub fn check_min_box_value(candidate: &ErgoBoxCandidate) -> Result<(), MinBoxValueError> {
+    check_min_box_value_for(candidate, BoxValue::MIN_VALUE_PER_BOX_BYTE as u64)
+}
+
+/// Check that the candidate holds enough value for the given `min_value_per_byte`
+/// protocol parameter.
+pub fn check_min_box_value_for(
+    candidate: &ErgoBoxCandidate,
+    min_value_per_byte: u64,
+) -> Result<(), MinBoxValueError> {
+    let min_value = min_box_value_for(candidate, min_value_per_byte)?;
+    let value = *candidate.value.as_u64();
+
+    if value < min_value {
+        Err(MinBoxValueError::BelowMinimum(value, min_value))
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ergo_lib::{
+        ergo_chain_types::{ec_point::generator, Digest32},
+        ergotree_ir::{
+            chain::{
+                address::Address,
+                ergo_box::{ErgoBoxCandidate, NonMandatoryRegisters},
+                token::Token,
+            },
+            sigma_protocol::sigma_boolean::ProveDlog,
+        },
+    };
+
+    use super::{check_min_box_value, min_box_value, MinBoxValueError};
+
+    fn change_candidate(value: u64, with_token: bool) -> ErgoBoxCandidate {
+        let tokens = with_token.then(|| {
+            let token: Token = (Digest32::zero().into(), 1.try_into().unwrap()).into();
+            vec![token].try_into().unwrap()
+        });
+
+        ErgoBoxCandidate {
+            value: value.try_into().unwrap(),
+            ergo_tree: Address::P2Pk(ProveDlog::new(generator())).script().unwrap(),
+            tokens,
+            additional_registers: NonMandatoryRegisters::empty(),
+            creation_height: 0,
+        }
+    }
+
+    #[test]
+    fn small_change_output() {
+        let candidate = change_candidate(20000, false);
+
+        assert!(matches!(
+            check_min_box_value(&candidate),
+            Err(MinBoxValueError::BelowMinimum(20000, _))
+        ));
+
+        check_min_box_value(&change_candidate(1000000, false)).expect("Value is sufficient");
+    }
+
+    #[test]
+    fn tokens_raise_minimum() {
+        let without_token = min_box_value(&change_candidate(1000000, false)).unwrap();
+        let with_token = min_box_value(&change_candidate(1000000, true)).unwrap();
+
+        assert!(with_token > without_token);
+    }
+}
@@ -1,4 +1,5 @@
 pub mod describe_box;
 pub mod liquidity_box;
+pub mod min_box_value;
 pub mod tracked_box;
 pub mod wallet_box;
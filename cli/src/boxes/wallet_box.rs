@@ -50,14 +50,12 @@ where
         let amount = UnitAmount::new(*ERG_UNIT, *self.value().as_u64());
 
         match self.tokens().as_ref().map(|tokens| tokens.as_slice()) {
-            None => {
-                return BoxAssetDisplay::Single(amount);
-            }
+            None => BoxAssetDisplay::Single(amount),
             Some([token]) => {
                 let unit = token_store.get_unit(&token.token_id);
 
                 let token_amount = UnitAmount::new(unit, *token.amount.as_u64());
-                return BoxAssetDisplay::Double(amount, token_amount);
+                BoxAssetDisplay::Double(amount, token_amount)
             }
             Some(tokens) => BoxAssetDisplay::Many(amount, tokens.len()),
         }
@@ -0,0 +1,99 @@
+use clap::{Args, Subcommand};
+use itertools::Itertools;
+use off_the_grid::{
+    arbitrage::find_opportunities,
+    node::client::NodeClient,
+    spectrum::pool::SpectrumPool,
+    units::{AmountParser, TokenStore, UnitAmount, ERG_UNIT},
+};
+
+use crate::{commands::unique_pools, profile::Profile, scan_config::ScanConfig};
+
+#[derive(Subcommand)]
+pub enum Commands {
+    /// Scan liquidity pools for price discrepancies between pools of the same token
+    Scan {
+        #[clap(long, help = "Scan configuration file path [default: scan_config]")]
+        scan_config: Option<String>,
+        #[clap(
+            short,
+            long,
+            help = "Only report opportunities with a profit above this transaction fee value",
+            default_value = "0.001"
+        )]
+        fee: String,
+    },
+}
+
+#[derive(Args)]
+pub struct ArbCommand {
+    #[command(subcommand)]
+    pub command: Commands,
+}
+
+pub async fn handle_arb_command(
+    node_client: NodeClient,
+    profile: &Profile,
+    arb_command: ArbCommand,
+) -> anyhow::Result<()> {
+    match arb_command.command {
+        Commands::Scan { scan_config, fee } => {
+            let scan_config = ScanConfig::try_create(profile, scan_config, None)?;
+            let tokens = TokenStore::load(Some(profile.token_store_path())).unwrap_or_default();
+
+            let fee_amount = AmountParser::new(*ERG_UNIT, "fee").parse(&fee)?;
+
+            let n2t_pools: Vec<SpectrumPool> = unique_pools(
+                node_client
+                    .get_scan_unspent(scan_config.n2t_scan_id)
+                    .await?
+                    .into_iter()
+                    .filter_map(|b| b.try_into().ok())
+                    .collect(),
+            )
+            .into_iter()
+            .map(|b| b.value)
+            .collect();
+
+            let grouped_pools = n2t_pools
+                .into_iter()
+                .into_group_map_by(|p| p.asset_y.token_id);
+
+            let mut found = false;
+
+            for pools in grouped_pools.values() {
+                for opportunity in find_opportunities(pools) {
+                    if opportunity.profit() <= fee_amount.amount() {
+                        continue;
+                    }
+
+                    found = true;
+
+                    let token_unit = tokens.get_unit(&opportunity.intermediate.token_id);
+
+                    let input = UnitAmount::new(*ERG_UNIT, *opportunity.input.amount.as_u64());
+                    let intermediate =
+                        UnitAmount::new(token_unit, *opportunity.intermediate.amount.as_u64());
+                    let output = UnitAmount::new(*ERG_UNIT, *opportunity.output.amount.as_u64());
+                    let profit = UnitAmount::new(*ERG_UNIT, opportunity.profit());
+
+                    println!(
+                        "Buy {} for {} in pool {}, sell for {} in pool {}, profit {}",
+                        intermediate,
+                        input,
+                        String::from(opportunity.buy_from.pool_nft.token_id),
+                        output,
+                        String::from(opportunity.sell_to.pool_nft.token_id),
+                        profit,
+                    );
+                }
+            }
+
+            if !found {
+                println!("No arbitrage opportunities found");
+            }
+        }
+    }
+
+    Ok(())
+}
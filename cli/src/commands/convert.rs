@@ -0,0 +1,121 @@
+use anyhow::anyhow;
+use clap::Args;
+use off_the_grid::{
+    boxes::tracked_box::TrackedBox,
+    node::client::NodeClient,
+    spectrum::pool::{erg_price, SpectrumPool},
+    units::{AmountParser, TokenStore, Unit, UnitAmount},
+};
+
+use crate::{commands::unique_pools, profile::Profile, scan_config::ScanConfig};
+
+#[derive(Args)]
+pub struct ConvertCommand {
+    #[clap(help = "Amount to convert")]
+    amount: String,
+    #[clap(help = "Unit of the amount, by name or token ID")]
+    unit: String,
+    #[clap(
+        long,
+        help = "Unit to convert to, by name or token ID",
+        default_value = "ERG"
+    )]
+    to: String,
+    #[clap(long, help = "Scan configuration file path [default: scan_config]")]
+    scan_config: Option<String>,
+}
+
+pub async fn handle_convert_command(
+    node_client: NodeClient,
+    profile: &Profile,
+    convert_command: ConvertCommand,
+) -> anyhow::Result<()> {
+    let ConvertCommand {
+        amount,
+        unit,
+        to,
+        scan_config,
+    } = convert_command;
+
+    let scan_config = ScanConfig::try_create(profile, scan_config, None)?;
+    let tokens = TokenStore::load(Some(profile.token_store_path())).unwrap_or_default();
+
+    let from = tokens.get_unit_by_id(&unit)?;
+    let to = tokens.get_unit_by_id(&to)?;
+
+    let amount = AmountParser::new(from, "amount").parse(&amount)?;
+
+    let n2t_pools = unique_pools(
+        node_client
+            .get_scan_unspent(scan_config.n2t_scan_id)
+            .await?
+            .into_iter()
+            .filter_map(|b| b.try_into().ok())
+            .collect(),
+    );
+
+    let converted = convert_amount(&amount, to, &n2t_pools, &tokens)?;
+
+    println!("{} = {}", amount, converted);
+
+    Ok(())
+}
+
+/// Convert `amount` into `to` through the ERG prices of the deepest pools of both units
+fn convert_amount<'a>(
+    amount: &UnitAmount<'a>,
+    to: Unit<'a>,
+    pools: &[TrackedBox<SpectrumPool>],
+    tokens: &'a TokenStore,
+) -> anyhow::Result<UnitAmount<'a>> {
+    let from_price = erg_price(*amount.unit(), pools, tokens)?;
+    let to_price = erg_price(to, pools, tokens)?;
+
+    let ergs_per_to = to_price.indirect();
+
+    let converted = from_price
+        .chain(&ergs_per_to)
+        .and_then(|price| price.convert_price(amount).map(|a| a.amount()))
+        .ok_or_else(|| anyhow!("Failed to convert {} to {}", amount, to.name()))?;
+
+    Ok(UnitAmount::new(to, converted))
+}
+
+#[cfg(test)]
+mod tests {
+    use ergo_lib::{ergo_chain_types::Digest32, ergotree_ir::chain::token::TokenId};
+    use off_the_grid::units::{TokenInfo, TokenStore, ERG_UNIT};
+
+    use crate::commands::test_util::tracked_pool;
+
+    use super::convert_amount;
+
+    #[test]
+    fn convert_token_to_erg() {
+        let token_id: TokenId = Digest32::from([1u8; 32]).into();
+        let tokens = TokenStore::with_tokens(vec![TokenInfo {
+            token_id,
+            name: "TKN".to_string(),
+            decimals: 3,
+        }]);
+
+        // 2000 ERG against 1000 TKN
+        let pools = vec![tracked_pool(10, token_id, 2_000_000_000_000, 1_000_000)];
+
+        let token = tokens.get_unit_by_id("TKN").unwrap();
+        let erg = tokens.get_unit_by_id("ERG").unwrap();
+
+        let amount = token.str_amount("1.5").unwrap();
+        let converted = convert_amount(&amount, erg, &pools, &tokens).unwrap();
+
+        assert_eq!(converted.unit(), &*ERG_UNIT);
+        assert_eq!(converted.amount(), 3_000_000_000);
+        assert_eq!(converted.to_string(), "3 ERG");
+
+        let amount = erg.str_amount("3").unwrap();
+        let converted = convert_amount(&amount, token, &pools, &tokens).unwrap();
+
+        assert_eq!(converted.unit(), &token);
+        assert_eq!(converted.amount(), 1_500);
+    }
+}
@@ -0,0 +1,122 @@
+use std::{future::Future, iter::once};
+
+use anyhow::anyhow;
+use colored::Colorize;
+
+/// Outcome of every item of a batch, such as the transactions of a redeem split into
+/// several transactions
+pub(super) struct BatchReport<T> {
+    pub succeeded: Vec<(String, T)>,
+    pub failed: Vec<(String, anyhow::Error)>,
+}
+
+impl<T> BatchReport<T> {
+    fn len(&self) -> usize {
+        self.succeeded.len() + self.failed.len()
+    }
+
+    /// Number of items that succeeded, followed by one line per failed item and its reason
+    pub fn summary_lines(&self) -> Vec<String> {
+        once(format!(
+            "{} of {} succeeded",
+            self.succeeded.len(),
+            self.len()
+        ))
+        .chain(
+            self.failed
+                .iter()
+                .map(|(label, error)| format!("  {} failed: {:#}", label, error)),
+        )
+        .collect()
+    }
+
+    /// Fail if any item failed. The error of a batch of one item is returned as is.
+    pub fn into_result(mut self, items: &str) -> anyhow::Result<Vec<(String, T)>> {
+        let total = self.len();
+
+        match self.failed.len() {
+            0 => Ok(self.succeeded),
+            1 if total == 1 => Err(self.failed.remove(0).1),
+            num_failed => Err(anyhow!("{} of {} {} failed", num_failed, total, items)),
+        }
+    }
+}
+
+/// Run every labelled item in turn. Items must not depend on each other: a failed item is
+/// reported and the batch continues with the next one. The failure of a lone item is left
+/// to the caller to report.
+pub(super) async fn run_batch<I, T, F, Fut>(items: Vec<(String, I)>, mut run: F) -> BatchReport<T>
+where
+    F: FnMut(&str, I) -> Fut,
+    Fut: Future<Output = anyhow::Result<T>>,
+{
+    let mut report = BatchReport {
+        succeeded: vec![],
+        failed: vec![],
+    };
+
+    let num_items = items.len();
+
+    for (label, item) in items {
+        match run(&label, item).await {
+            Ok(value) => report.succeeded.push((label, value)),
+            Err(error) => {
+                if num_items > 1 {
+                    eprintln!("{}", format!("{} failed: {:#}", label, error).red());
+                }
+                report.failed.push((label, error));
+            }
+        }
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::anyhow;
+
+    use super::run_batch;
+
+    #[tokio::test]
+    async fn batch_continues_past_failed_item() {
+        let items = (1..=3)
+            .map(|i| (format!("Transaction {} of 3", i), i))
+            .collect();
+
+        let mut attempted = vec![];
+        let report = run_batch(items, |_, i| {
+            attempted.push(i);
+            async move {
+                match i {
+                    2 => Err(anyhow!("Double spending attempt")),
+                    i => Ok(i * 10),
+                }
+            }
+        })
+        .await;
+
+        assert_eq!(attempted, vec![1, 2, 3]);
+        assert_eq!(
+            report.summary_lines(),
+            vec![
+                "2 of 3 succeeded",
+                "  Transaction 2 of 3 failed: Double spending attempt"
+            ]
+        );
+        assert_eq!(
+            report.into_result("transactions").unwrap_err().to_string(),
+            "1 of 3 transactions failed"
+        );
+
+        let report = run_batch(vec![("Transaction".to_string(), ())], |_, _| async {
+            Err::<(), _>(anyhow!("Node unavailable"))
+        })
+        .await;
+
+        assert_eq!(
+            report.into_result("transactions").unwrap_err().to_string(),
+            "Node unavailable"
+        );
+    }
+}
@@ -0,0 +1,207 @@
+use anyhow::{anyhow, Context};
+use clap::Parser;
+use ergo_lib::ergo_chain_types::EcPoint;
+use off_the_grid::{
+    boxes::tracked_box::TrackedBox,
+    grid::multigrid_order::{
+        GridMetadata, GridOrderEntry, MultiGridOrder, MultiGridOrderError, OrderState,
+    },
+    node::client::NodeClient,
+    spectrum::pool::SpectrumPool,
+};
+use tokio::try_join;
+
+use crate::{
+    commands::{
+        error::{CommandResult, Hint},
+        warn_wallet_error,
+    },
+    scan_config::ScanConfig,
+};
+
+use super::{
+    create::{
+        build_grid_order_data, find_liquidity_boxes, grid_min_box_value, owner_ec_point,
+        ChangeOptions, NewGridTxData,
+    },
+    resolve_fee,
+};
+
+#[derive(Parser)]
+pub struct CloneOptions {
+    #[clap(
+        short = 'i',
+        long,
+        required = true,
+        help = "Grid group identity of the grid to clone, followed by the identity of the new grid"
+    )]
+    grid_identity: Vec<String>,
+    #[clap(
+        short,
+        long,
+        help = "transaction fee value [default: default_fee of the node config]"
+    )]
+    fee: Option<String>,
+    #[clap(long, help = "Disable auto filling the grid orders")]
+    no_auto_fill: bool,
+    #[clap(
+        long,
+        help = "Base value of the grid order box [default: derived from the node's protocol parameters]"
+    )]
+    min_box_value: Option<String>,
+}
+
+/// Create a new grid order with the same entries as `source`. Every entry is reset to a
+/// buy order, the same state a newly created grid starts in. The strategy tag of `source`
+/// is kept.
+fn clone_order(
+    source: &MultiGridOrder,
+    owner_ec_point: EcPoint,
+    grid_identity: String,
+    min_box_value: u64,
+) -> Result<MultiGridOrder, MultiGridOrderError> {
+    let entries = source
+        .entries
+        .iter()
+        .map(|e| GridOrderEntry::new(OrderState::Buy, e.token_amount, e.bid_value, e.ask_value))
+        .collect();
+
+    MultiGridOrder::new(
+        owner_ec_point,
+        source.token_id,
+        entries,
+        Some(GridMetadata::new(grid_identity, source.strategy()).to_bytes()),
+        min_box_value,
+    )
+}
+
+pub async fn handle_grid_clone(
+    node_client: &NodeClient,
+    scan_config: ScanConfig,
+    default_fee: &str,
+    options: CloneOptions,
+) -> CommandResult<NewGridTxData<SpectrumPool>> {
+    let CloneOptions {
+        grid_identity,
+        fee,
+        no_auto_fill,
+        min_box_value,
+    } = options;
+
+    let (source_identity, new_identity) = match grid_identity.as_slice() {
+        [source, new] => (source.clone(), new.clone()),
+        _ => {
+            return Err(anyhow!(
+                "expected exactly two grid identities, got {}",
+                grid_identity.len()
+            ))
+            .hint("Usage: `off-the-grid grid clone -i <source> -i <new>`")
+        }
+    };
+
+    let fee_value = resolve_fee(fee.as_deref(), default_fee)?;
+
+    let grid_orders: Vec<TrackedBox<MultiGridOrder>> = node_client
+        .get_scan_unspent(scan_config.wallet_multigrid_scan_id)
+        .await?
+        .into_iter()
+        .filter_map(|b| b.try_into().ok())
+        .collect();
+
+    let has_identity = |order: &TrackedBox<MultiGridOrder>, identity: &str| {
+        order.value.identity() == Some(identity.as_bytes())
+    };
+
+    if grid_orders.iter().any(|o| has_identity(o, &new_identity)) {
+        return Err(anyhow!(
+            "grid identity `{}` is already in use",
+            new_identity
+        ))
+        .hint("Choose a different identity for the new grid");
+    }
+
+    let source = grid_orders
+        .into_iter()
+        .find(|o| has_identity(o, &source_identity))
+        .ok_or_else(|| anyhow!("no grid order found with identity `{}`", source_identity))?;
+
+    let (wallet_boxes, wallet_status) = try_join!(
+        node_client.wallet_boxes_unspent(),
+        node_client.wallet_status()
+    )?;
+
+    let min_box_value = grid_min_box_value(node_client, min_box_value).await?;
+
+    let liquidity_boxes = if !no_auto_fill {
+        find_liquidity_boxes(node_client, &scan_config, source.value.token_id, None).await?
+    } else {
+        vec![]
+    };
+
+    wallet_status.error_if_locked()?;
+
+    warn_wallet_error(&wallet_status);
+
+    let owner_address = wallet_status.change_address()?;
+
+    let new_order = clone_order(
+        &source.value,
+        owner_ec_point(&owner_address)?,
+        new_identity,
+        min_box_value,
+    )?;
+
+    let grid_tx_data = build_grid_order_data(
+        liquidity_boxes,
+        vec![new_order],
+        owner_address,
+        fee_value,
+        wallet_boxes,
+        None,
+        ChangeOptions::default(),
+    )
+    .context("Building grid transaction")?;
+
+    Ok(grid_tx_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use ergo_lib::ergo_chain_types::{ec_point::generator, Digest32};
+    use off_the_grid::grid::multigrid_order::{
+        GridOrderEntries, GridOrderEntry, OrderState, DEFAULT_MIN_BOX_VALUE,
+    };
+
+    use crate::commands::test_util::test_order;
+
+    use super::clone_order;
+
+    #[test]
+    fn clone_matches_source() {
+        let entries = GridOrderEntries::new(vec![
+            GridOrderEntry::new(OrderState::Sell, 10.try_into().unwrap(), 100, 110),
+            GridOrderEntry::new(OrderState::Buy, 12.try_into().unwrap(), 130, 140),
+        ]);
+
+        let source = test_order(Digest32::zero().into(), entries, Some(b"source".to_vec()));
+
+        let cloned = clone_order(
+            &source,
+            generator(),
+            "clone".to_string(),
+            DEFAULT_MIN_BOX_VALUE,
+        )
+        .unwrap();
+
+        assert_eq!(cloned.metadata, Some(b"clone".to_vec()));
+        assert_ne!(cloned.metadata, source.metadata);
+        assert_eq!(cloned.token_id, source.token_id);
+
+        for (cloned, source) in cloned.entries.iter().zip(source.entries.iter()) {
+            assert_eq!(cloned.bid(), source.bid());
+            assert_eq!(cloned.ask(), source.ask());
+            assert_eq!(cloned.token_amount, source.token_amount);
+            assert_eq!(cloned.state, OrderState::Buy);
+        }
+    }
+}
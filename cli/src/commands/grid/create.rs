@@ -1,38 +1,49 @@
 use std::iter::once;
 
 use anyhow::{anyhow, Context};
-use clap::{ArgGroup, Parser};
+use clap::{ArgGroup, Parser, ValueEnum};
+use colored::Colorize;
+use config::{Config, Environment};
 use ergo_lib::{
     chain::transaction::TransactionError,
-    ergo_chain_types::EcPoint,
-    ergotree_ir::{
-        chain::{
-            address::Address,
-            ergo_box::{
-                box_value::{BoxValue, BoxValueError},
-                ErgoBox,
-            },
-            token::{TokenAmount, TokenAmountError, TokenId},
+    ergo_chain_types::{Digest32, EcPoint},
+    ergotree_ir::chain::{
+        address::Address,
+        ergo_box::{
+            box_value::{BoxValue, BoxValueError},
+            BoxId, ErgoBox,
         },
-        serialization::SigmaParsingError,
+        token::{Token, TokenAmount, TokenAmountError, TokenId},
+    },
+    wallet::box_selector::{
+        sum_tokens_from_boxes, sum_value, BoxSelector, BoxSelectorError, ErgoBoxAssetsData,
+        SimpleBoxSelector,
     },
-    wallet::box_selector::{BoxSelector, BoxSelectorError, ErgoBoxAssetsData, SimpleBoxSelector},
 };
 use num_traits::ToPrimitive;
 use off_the_grid::{
     boxes::{
-        describe_box::ErgoBoxDescriptors, liquidity_box::LiquidityProvider,
-        tracked_box::TrackedBox, wallet_box::WalletBox,
+        describe_box::ErgoBoxDescriptors,
+        liquidity_box::{LiquidityProvider, LiquidityProviderError},
+        tracked_box::TrackedBox,
+        wallet_box::WalletBox,
     },
     grid::multigrid_order::{
-        FillMultiGridOrders, GridOrderEntries, GridOrderEntry, MultiGridOrder, MultiGridOrderError,
-        OrderState,
+        min_box_value_for_parameters, FillMultiGridOrders, GridMetadata, GridOrderEntries,
+        GridOrderEntry, MultiGridOrder, MultiGridOrderError, OrderState, DEFAULT_MIN_BOX_VALUE,
+        MAX_FEE,
     },
     node::client::NodeClient,
-    spectrum::pool::{SpectrumPool, SpectrumSwapError},
-    units::{Fraction, Price, TokenStore, ERG_UNIT},
+    spectrum::{
+        pool::{select_pool, PoolSelectionError, SpectrumPool, SpectrumSwapError, ERG_TOKEN_ID},
+        router::{Router, RouterError},
+    },
+    units::{
+        token_id_hex, AmountParser, Fraction, Price, TokenLookupError, TokenStore, Unit,
+        UnitAmount, ERG_UNIT,
+    },
 };
-use tabled::Tabled;
+use serde::Deserialize;
 use thiserror::Error;
 use tokio::try_join;
 
@@ -40,24 +51,75 @@ use crate::{
     commands::{
         error::{CommandResult, Hint},
         grid::SummarizedOutput,
+        unique_pools, warn_wallet_error, AddressScriptError,
     },
     scan_config::ScanConfig,
 };
 
 use super::{
+    export::GridDefinition,
+    resolve_fee, sorted_tokens,
+    subcommands::{break_even_text, grid_owner_fees},
     IntoSummarizedTransaction, MinerFeeValue, SummarizedInput, SummarizedTransaction,
     TryIntoErgoBoxCandidate,
 };
 
+/// Prefix of the environment variables read by `grid create`, e.g. `OTG_GRID_NUM_ORDERS`
+pub(super) const CREATE_ENV_PREFIX: &str = "OTG_GRID";
+
+/// Options of `grid create` given as environment variables. Options given as flags take
+/// precedence. `EXCLUDE_BOXES` is a comma separated list of box IDs.
+#[derive(Debug, Default, Deserialize)]
+struct CreateEnvOptions {
+    token_id: Option<String>,
+    token_amount: Option<String>,
+    total_value: Option<String>,
+    range: Option<String>,
+    num_orders: Option<String>,
+    spacing: Option<GridSpacing>,
+    fee: Option<String>,
+    no_auto_fill: Option<bool>,
+    grid_identity: Option<String>,
+    strategy: Option<String>,
+    exclude_boxes: Option<String>,
+    sponsor_box: Option<String>,
+    single_change: Option<bool>,
+    fold_change_below: Option<String>,
+    pool_nft: Option<String>,
+    min_box_value: Option<String>,
+    from_qr: Option<String>,
+    sell_only: Option<bool>,
+    split: Option<bool>,
+}
+
+/// Options of [`CreateOptions`] that cannot be used together, as declared to clap. Clap
+/// only checks flags, so the options are checked again once the environment is applied.
+const CONFLICTING_OPTIONS: [(&str, &str); 10] = [
+    ("token_amount", "total_value"),
+    ("token_amount", "from_qr"),
+    ("total_value", "from_qr"),
+    ("token_id", "from_qr"),
+    ("range", "from_qr"),
+    ("num_orders", "from_qr"),
+    ("spacing", "from_qr"),
+    ("pool_nft", "no_auto_fill"),
+    ("sell_only", "pool_nft"),
+    ("sell_only", "from_qr"),
+];
+
 #[derive(Parser)]
 #[command(group(
     ArgGroup::new("amount")
-        .required(true)
-        .args(&["token_amount", "total_value"])
+        .args(&["token_amount", "total_value", "from_qr"])
 ))]
 pub struct CreateOptions {
-    #[clap(short = 't', long, help = "TokenID of the token to be traded")]
-    token_id: String,
+    #[clap(
+        short = 't',
+        long,
+        conflicts_with = "from_qr",
+        help = "TokenID of the token to be traded"
+    )]
+    token_id: Option<String>,
     /// Total amount of tokens in the grid.
     /// If specified, the number of tokens traded in each order will be calculated as
     /// token_amount / num_orders
@@ -71,40 +133,302 @@ pub struct CreateOptions {
     #[clap(
         short = 'r',
         long,
+        conflicts_with = "from_qr",
         help = "Range of the grid, in the form start-stop",
         value_parser = grid_order_range_from_str
     )]
-    range: (String, String),
-    #[clap(short = 'o', long, help = "Number of orders in the grid")]
-    num_orders: u64,
-    #[clap(short, long, help = "transaction fee value", default_value = "0.001")]
-    fee: String,
+    range: Option<(Fraction, Fraction)>,
+    #[clap(
+        short = 'o',
+        long,
+        conflicts_with = "from_qr",
+        help = "Number of orders in the grid",
+        value_parser = num_orders_from_str
+    )]
+    num_orders: Option<u64>,
+    #[clap(
+        long,
+        value_enum,
+        conflicts_with = "from_qr",
+        help = "Spacing of the order prices across the range [default: linear]"
+    )]
+    spacing: Option<GridSpacing>,
+    #[clap(
+        short,
+        long,
+        help = "Fee of the transaction creating the grid [default: default_fee of the node config]. Fills are paid by the matcher instead"
+    )]
+    fee: Option<String>,
     #[clap(long, help = "Disable auto filling the grid orders")]
     no_auto_fill: bool,
     #[clap(short = 'i', long, help = "Grid group identity")]
-    grid_identity: String,
+    grid_identity: Option<String>,
+    #[clap(
+        long,
+        help = "Strategy tag stored with the grid, to filter by in `grid list`"
+    )]
+    strategy: Option<String>,
+    #[clap(
+        long = "exclude-box",
+        help = "BoxID of a wallet box that must not be used to fund the grid. Can be repeated"
+    )]
+    exclude_boxes: Vec<String>,
+    /// BoxID of a box provided by a third party that pays the transaction fee.
+    /// The wallet only funds the grid, the rest of the sponsor box is returned to its
+    /// address. The node must be able to sign for the sponsor box.
+    #[clap(long)]
+    sponsor_box: Option<String>,
+    #[clap(long, help = "Consolidate all change into a single box")]
+    single_change: bool,
+    #[clap(
+        long,
+        help = "Add change below this value to the transaction fee instead of creating a change box"
+    )]
+    fold_change_below: Option<String>,
+    #[clap(
+        long,
+        conflicts_with = "no_auto_fill",
+        help = "NFT ID of the liquidity pool used to auto fill the grid, instead of the deepest pool"
+    )]
+    pool_nft: Option<String>,
+    #[clap(
+        long,
+        help = "Base value of the grid order box [default: derived from the node's protocol parameters]"
+    )]
+    min_box_value: Option<String>,
+    /// Grid definition printed by `grid export`, or scanned from its QR code.
+    /// The orders of the definition are used instead of a range.
+    #[clap(long, group = "amount")]
+    from_qr: Option<String>,
+    /// Fund the grid with tokens only, creating every order as a sell order.
+    /// The grid is not auto filled.
+    #[clap(long, conflicts_with_all = ["pool_nft", "from_qr"])]
+    sell_only: bool,
+    /// Split the orders across as many grid boxes as needed, with at most 50 orders in
+    /// each. All boxes share the grid identity and are listed as a single grid.
+    #[clap(long)]
+    split: bool,
 }
 
-fn grid_order_range_from_str(s: &str) -> Result<(String, String), String> {
-    let parts: Vec<&str> = s.split('-').collect();
-    if let [start, stop] = parts.as_slice() {
-        Ok((start.to_string(), stop.to_string()))
-    } else {
-        Err(format!("Invalid range: {}", s))
+impl CreateOptions {
+    pub(super) fn token_id(&self) -> Option<&str> {
+        self.token_id.as_deref()
+    }
+
+    /// Fill the options that were not given as flags from the variables of `environment`.
+    /// The amount options are only taken from the environment if none of them is a flag,
+    /// and variables conflicting with a flag are ignored. Conflicts between the variables
+    /// themselves are an error.
+    pub(super) fn apply_env(&mut self, environment: Environment) -> anyhow::Result<()> {
+        let mut env: CreateEnvOptions = Config::builder()
+            .add_source(environment)
+            .build()?
+            .try_deserialize()?;
+
+        let flags = self.set_options();
+        let conflicts_with_flag = |name: &str| {
+            CONFLICTING_OPTIONS.iter().any(|(a, b)| {
+                (*a == name && flags.contains(b)) || (*b == name && flags.contains(a))
+            })
+        };
+
+        if conflicts_with_flag("token_id") {
+            env.token_id = None;
+        }
+        if conflicts_with_flag("range") {
+            env.range = None;
+        }
+        if conflicts_with_flag("num_orders") {
+            env.num_orders = None;
+        }
+        if conflicts_with_flag("spacing") {
+            env.spacing = None;
+        }
+        if conflicts_with_flag("from_qr") {
+            env.from_qr = None;
+        }
+        if conflicts_with_flag("pool_nft") {
+            env.pool_nft = None;
+        }
+        if conflicts_with_flag("no_auto_fill") {
+            env.no_auto_fill = None;
+        }
+        if conflicts_with_flag("sell_only") {
+            env.sell_only = None;
+        }
+
+        if self.token_amount.is_none() && self.total_value.is_none() && self.from_qr.is_none() {
+            self.token_amount = env.token_amount;
+            self.total_value = env.total_value;
+            self.from_qr = env.from_qr;
+        }
+
+        if self.range.is_none() {
+            self.range = env
+                .range
+                .map(|range| grid_order_range_from_str(&range))
+                .transpose()
+                .map_err(|e| anyhow!("{} in {}_RANGE", e, CREATE_ENV_PREFIX))?;
+        }
+
+        if self.exclude_boxes.is_empty() {
+            self.exclude_boxes = env
+                .exclude_boxes
+                .iter()
+                .flat_map(|boxes| boxes.split(','))
+                .map(|box_id| box_id.trim().to_string())
+                .filter(|box_id| !box_id.is_empty())
+                .collect();
+        }
+
+        self.token_id = self.token_id.take().or(env.token_id);
+        if self.num_orders.is_none() {
+            self.num_orders = env
+                .num_orders
+                .map(|num_orders| num_orders_from_str(&num_orders))
+                .transpose()
+                .map_err(|e| anyhow!("{} in {}_NUM_ORDERS", e, CREATE_ENV_PREFIX))?;
+        }
+        self.spacing = self.spacing.or(env.spacing);
+        self.fee = self.fee.take().or(env.fee);
+        self.grid_identity = self.grid_identity.take().or(env.grid_identity);
+        self.strategy = self.strategy.take().or(env.strategy);
+        self.sponsor_box = self.sponsor_box.take().or(env.sponsor_box);
+        self.fold_change_below = self.fold_change_below.take().or(env.fold_change_below);
+        self.pool_nft = self.pool_nft.take().or(env.pool_nft);
+        self.min_box_value = self.min_box_value.take().or(env.min_box_value);
+
+        self.no_auto_fill |= env.no_auto_fill.unwrap_or(false);
+        self.single_change |= env.single_change.unwrap_or(false);
+        self.sell_only |= env.sell_only.unwrap_or(false);
+        self.split |= env.split.unwrap_or(false);
+
+        self.check_conflicts()
+    }
+
+    /// Names of the options in [`CONFLICTING_OPTIONS`] that are set
+    fn set_options(&self) -> Vec<&'static str> {
+        [
+            ("token_id", self.token_id.is_some()),
+            ("token_amount", self.token_amount.is_some()),
+            ("total_value", self.total_value.is_some()),
+            ("range", self.range.is_some()),
+            ("num_orders", self.num_orders.is_some()),
+            ("spacing", self.spacing.is_some()),
+            ("from_qr", self.from_qr.is_some()),
+            ("pool_nft", self.pool_nft.is_some()),
+            ("no_auto_fill", self.no_auto_fill),
+            ("sell_only", self.sell_only),
+        ]
+        .into_iter()
+        .filter(|(_, set)| *set)
+        .map(|(name, _)| name)
+        .collect()
+    }
+
+    fn check_conflicts(&self) -> anyhow::Result<()> {
+        let set = self.set_options();
+        let flag = |name: &str| format!("--{}", name.replace('_', "-"));
+
+        match CONFLICTING_OPTIONS
+            .iter()
+            .find(|(a, b)| set.contains(a) && set.contains(b))
+        {
+            Some((a, b)) => Err(anyhow!(
+                "{} cannot be used with {}, given as flags or {}_ variables",
+                flag(a),
+                flag(b),
+                CREATE_ENV_PREFIX
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Parse a grid range of the form start-stop. Both bounds must be positive, finite prices
+/// with start below stop.
+pub(super) fn grid_order_range_from_str(s: &str) -> Result<(Fraction, Fraction), String> {
+    if s.trim_start().starts_with('-') {
+        return Err(format!("Invalid range {}: prices must be positive", s));
+    }
+
+    let (start, stop) = s
+        .split_once('-')
+        .ok_or_else(|| format!("Invalid range {}: expected start-stop", s))?;
+
+    let parse_bound = |bound: &str| {
+        let price: Fraction = bound
+            .trim()
+            .parse()
+            .map_err(|_| format!("Invalid price {} in range {}", bound, s))?;
+
+        if price.is_nan() || price.is_infinite() {
+            Err(format!("Invalid range {}: prices must be finite", s))
+        } else if price.is_sign_negative() || price == Fraction::from(0u64) {
+            Err(format!("Invalid range {}: prices must be positive", s))
+        } else {
+            Ok(price)
+        }
+    };
+
+    let start = parse_bound(start)?;
+    let stop = parse_bound(stop)?;
+
+    if start >= stop {
+        return Err(format!("Invalid range {}: start must be below stop", s));
+    }
+
+    Ok((start, stop))
+}
+
+/// Parse the number of orders of a grid. Amounts and the price range are divided by it, so
+/// it must be at least one.
+fn num_orders_from_str(s: &str) -> Result<u64, String> {
+    let num_orders: u64 = s
+        .trim()
+        .parse()
+        .map_err(|_| format!("Invalid number of orders {}", s))?;
+
+    if num_orders == 0 {
+        return Err(format!(
+            "Invalid number of orders {}: a grid needs at least one order",
+            s
+        ));
     }
+
+    Ok(num_orders)
+}
+
+/// How the price levels of a grid are spread across its range
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GridSpacing {
+    /// Every level is the same price step above the previous one
+    #[default]
+    Linear,
+    /// Every level is the same ratio above the previous one, which keeps the relative
+    /// margin of the orders equal across wide ranges
+    Geometric,
 }
 
+/// Most entries a single grid order box holds when a grid is split across boxes. Spending a
+/// box with many more entries gets expensive for the matcher.
+const MAX_ENTRIES_PER_BOX: usize = 50;
+
 #[derive(Clone, Debug)]
-struct GridPriceRange<'a> {
+pub(super) struct GridPriceRange<'a> {
     start: Price<'a>,
     stop: Price<'a>,
     num_orders: u64,
+    spacing: GridSpacing,
 }
 
 #[derive(Error, Debug)]
-enum GridOrderRangeError {
+pub(super) enum GridOrderRangeError {
     #[error("Invalid range: start must be below stop")]
     InvalidRange,
+    #[error("Invalid range: a grid needs at least one order")]
+    NoOrders,
 }
 
 impl<'a> GridPriceRange<'a> {
@@ -117,32 +441,68 @@ impl<'a> GridPriceRange<'a> {
             return Err(GridOrderRangeError::InvalidRange);
         }
 
+        if num_orders == 0 {
+            return Err(GridOrderRangeError::NoOrders);
+        }
+
         Ok(GridPriceRange {
             start,
             stop,
             num_orders,
+            spacing: GridSpacing::default(),
         })
     }
+
+    pub fn with_spacing(self, spacing: GridSpacing) -> Self {
+        Self { spacing, ..self }
+    }
 }
 
 impl IntoIterator for GridPriceRange<'_> {
     type Item = (Fraction, Fraction);
-    type IntoIter = GridPriceIterator;
+    type IntoIter = GridPriceLevels;
 
     fn into_iter(self) -> Self::IntoIter {
         let start = self.start.price();
         let stop = self.stop.price();
-        let step = (stop - start) / self.num_orders;
-        GridPriceIterator {
-            base: start,
-            current: 0,
-            num_orders: self.num_orders,
-            step,
+
+        match self.spacing {
+            GridSpacing::Linear => {
+                let step = (stop - start) / self.num_orders;
+                GridPriceLevels::Linear(GridPriceIterator {
+                    base: start,
+                    current: 0,
+                    num_orders: self.num_orders,
+                    step,
+                })
+            }
+            GridSpacing::Geometric => GridPriceLevels::Geometric(GeometricPriceIterator {
+                start,
+                stop,
+                current: 0,
+                num_orders: self.num_orders,
+            }),
         }
     }
 }
 
-struct GridPriceIterator {
+pub(super) enum GridPriceLevels {
+    Linear(GridPriceIterator),
+    Geometric(GeometricPriceIterator),
+}
+
+impl Iterator for GridPriceLevels {
+    type Item = (Fraction, Fraction);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            GridPriceLevels::Linear(iter) => iter.next(),
+            GridPriceLevels::Geometric(iter) => iter.next(),
+        }
+    }
+}
+
+pub(super) struct GridPriceIterator {
     base: Fraction,
     current: u64,
     num_orders: u64,
@@ -167,6 +527,53 @@ impl Iterator for GridPriceIterator {
     }
 }
 
+/// Denominator of the powers of the geometric ratio, which are rounded to 12 decimals
+const GEOMETRIC_RATIO_PRECISION: u128 = 1_000_000_000_000;
+
+pub(super) struct GeometricPriceIterator {
+    start: Fraction,
+    stop: Fraction,
+    current: u64,
+    num_orders: u64,
+}
+
+impl GeometricPriceIterator {
+    /// Level `index` of the range, start * (stop / start)^(index / num_orders). The n-th
+    /// root of a fraction is rarely a fraction itself, so the power of the ratio is
+    /// computed in floating point and rounded. The bounds of the range are kept exact.
+    fn level(&self, index: u64) -> Fraction {
+        if index == 0 {
+            return self.start;
+        }
+
+        if index >= self.num_orders {
+            return self.stop;
+        }
+
+        let ratio = (self.stop / self.start).to_f64().unwrap_or(1.0);
+        let power = ratio.powf(index as f64 / self.num_orders as f64);
+        let scaled = (power * GEOMETRIC_RATIO_PRECISION as f64).round() as u128;
+
+        self.start * Fraction::new(scaled, GEOMETRIC_RATIO_PRECISION)
+    }
+}
+
+impl Iterator for GeometricPriceIterator {
+    type Item = (Fraction, Fraction);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.current >= self.num_orders {
+            return None;
+        }
+
+        let lo = self.level(self.current);
+        let hi = self.level(self.current + 1);
+
+        self.current += 1;
+        Some((hi.recip(), lo.recip()))
+    }
+}
+
 #[derive(Error, Debug)]
 pub enum BuildNewGridTxError<T>
 where
@@ -187,7 +594,31 @@ where
     #[error("Invalid fraction: {0}")]
     InvalidFraction(Fraction),
     #[error(transparent)]
-    SigmaParsing(#[from] SigmaParsingError),
+    AddressScript(#[from] AddressScriptError),
+    #[error("Change holds {0} distinct tokens, too many for a single box")]
+    TooManyChangeTokens(usize),
+    #[error("Sponsor box value {0} does not cover the fee and change, needs {1}")]
+    InsufficientSponsor(u64, u64),
+    #[error("Order {level} of {amount} tokens has an ask value of {ask_value} nanoERG, not above its bid value of {bid_value} nanoERG. Use a wider range, fewer orders or more tokens per order")]
+    NoSpread {
+        level: usize,
+        amount: u64,
+        bid_value: u64,
+        ask_value: u64,
+    },
+    #[error(
+        "Liquidity pool trades {} against {}, expected ERG against the grid token {}",
+        token_id_hex(.asset_x),
+        token_id_hex(.asset_y),
+        token_id_hex(.token_id)
+    )]
+    PoolAssetMismatch {
+        asset_x: TokenId,
+        asset_y: TokenId,
+        token_id: TokenId,
+    },
+    #[error("Sell orders need {required} tokens but the wallet holds {available}, {} short", required - available)]
+    InsufficientTokens { required: u64, available: u64 },
 }
 
 impl From<SpectrumSwapError> for BuildNewGridTxError<SpectrumSwapError> {
@@ -196,10 +627,30 @@ impl From<SpectrumSwapError> for BuildNewGridTxError<SpectrumSwapError> {
     }
 }
 
+impl<E> From<RouterError<E>> for BuildNewGridTxError<RouterError<E>>
+where
+    E: std::error::Error,
+{
+    fn from(value: RouterError<E>) -> Self {
+        Self::Liquidity(value)
+    }
+}
+
+/// Source of the orders of a new grid
+#[allow(clippy::large_enum_variant)]
+enum GridPlan<'a> {
+    Range {
+        range: GridPriceRange<'a>,
+        order_value_target: OrderValueTarget,
+    },
+    Definition(GridDefinition),
+}
+
 pub async fn handle_grid_create(
     node_client: &NodeClient,
     scan_config: ScanConfig,
     token_store: &TokenStore,
+    default_fee: &str,
     options: CreateOptions,
 ) -> CommandResult<NewGridTxData<SpectrumPool>> {
     let CreateOptions {
@@ -208,116 +659,522 @@ pub async fn handle_grid_create(
         total_value,
         range,
         num_orders,
+        spacing,
         fee,
         no_auto_fill,
         grid_identity,
+        strategy,
+        exclude_boxes,
+        sponsor_box,
+        single_change,
+        fold_change_below,
+        pool_nft,
+        min_box_value,
+        from_qr,
+        sell_only,
+        split,
     } = options;
 
     let erg_unit = *ERG_UNIT;
 
-    let unit = token_store
-        .get_unit_by_id(&token_id)
-        .ok_or_else(|| anyhow!("`{}` is not a known token or a valid token ID", token_id))
-        .hint("Token names are case-sensitive, i.e. `sigusd` is not the same as `SigUSD`")
-        .hint("To ensure the token store is up to date run `off-the-grid tokens update`")?;
+    let grid_identity = grid_identity
+        .ok_or_else(|| anyhow!("grid_identity must be specified"))
+        .hint(format!(
+            "Pass `--grid-identity` or set {}_GRID_IDENTITY",
+            CREATE_ENV_PREFIX
+        ))?;
 
-    if unit == erg_unit {
-        return Err(anyhow!("cannot create a grid for ERG/ERG pair"))
-            .hint("Specify the token name or ID of the token that will be traded against ERG instead, e.g. `SigUSD`");
-    }
+    let mut exclude_boxes = exclude_boxes
+        .into_iter()
+        .map(BoxId::try_from)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let sponsor_box = match sponsor_box {
+        Some(box_id) => {
+            let box_id = BoxId::try_from(box_id)?;
+            // The sponsor box must not also be selected to fund the grid
+            exclude_boxes.push(box_id);
+            Some(find_sponsor_box(node_client, &box_id).await?)
+        }
+        None => None,
+    };
 
-    let token_id = unit.token_id();
+    let pool_nft: Option<TokenId> = pool_nft
+        .map(|i| Digest32::try_from(i).map(|i| i.into()))
+        .transpose()?;
 
-    let fee_amount = erg_unit
-        .str_amount(&fee)
-        .ok_or_else(|| anyhow!("Invalid fee value"))?;
+    let fee_value = resolve_fee(fee.as_deref(), default_fee)?;
 
-    let fee_value: BoxValue = fee_amount.amount().try_into()?;
+    let fold_below = fold_change_below
+        .map(|value| AmountParser::new(erg_unit, "change threshold").parse(&value))
+        .transpose()?
+        .map(|amount| amount.amount().try_into())
+        .transpose()?;
 
-    let token_per_grid = match (token_amount, total_value) {
-        (Some(token_amount), None) => {
-            let token_amount = unit
-                .str_amount(&token_amount)
-                .ok_or_else(|| anyhow!("Invalid token amount {}", token_amount))?;
+    let change_options = ChangeOptions {
+        single_change,
+        fold_below,
+    };
 
-            let tokens_per_grid = token_amount.amount() / num_orders;
-            Ok(OrderValueTarget::Token(tokens_per_grid.try_into()?))
+    let (token_id, plan) = match from_qr {
+        Some(payload) => {
+            let definition = GridDefinition::decode(&payload)
+                .hint("Use the output of `off-the-grid grid export` as the definition")?;
+            (definition.token_id, GridPlan::Definition(definition))
         }
-        (None, Some(total_value)) => {
-            let total_value = erg_unit
-                .str_amount(&total_value)
-                .ok_or_else(|| anyhow!("Invalid total value {}", total_value))?;
+        None => {
+            let (token_id, range, num_orders) = match (token_id, range, num_orders) {
+                (Some(token_id), Some(range), Some(num_orders)) => (token_id, range, num_orders),
+                _ => {
+                    return Err(anyhow!("token_id, range and num_orders must be specified")).hint(
+                        "Use `--from-qr` to create a grid from an exported definition instead",
+                    )
+                }
+            };
+
+            let unit = match token_store.get_unit_by_id(&token_id) {
+                Ok(unit) => unit,
+                Err(TokenLookupError::Ambiguous(name, token_ids)) => {
+                    let hint = format!(
+                        "Tokens named `{}`: {}",
+                        name,
+                        token_ids
+                            .iter()
+                            .map(token_id_hex)
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    );
+
+                    return Err(anyhow!(TokenLookupError::Ambiguous(name, token_ids))).hint(hint);
+                }
+                Err(e) => return Err(anyhow!(e))
+                    .hint(
+                        "Token names are case-sensitive, i.e. `sigusd` is not the same as `SigUSD`",
+                    )
+                    .hint(
+                        "To ensure the token store is up to date run `off-the-grid tokens update`",
+                    ),
+            };
+
+            if unit == erg_unit {
+                return Err(anyhow!("cannot create a grid for ERG/ERG pair"))
+                    .hint("Specify the token name or ID of the token that will be traded against ERG instead, e.g. `SigUSD`");
+            }
 
-            let value_per_grid = total_value.amount() / num_orders;
-            Ok(OrderValueTarget::Value(value_per_grid.try_into()?))
+            let order_value_target = match (token_amount, total_value) {
+                (Some(token_amount), None) => {
+                    let token_amount =
+                        AmountParser::new(unit, "token amount").parse(&token_amount)?;
+
+                    let tokens_per_grid = token_amount.amount() / num_orders;
+                    Ok(OrderValueTarget::Token(tokens_per_grid.try_into()?))
+                }
+                (None, Some(total_value)) => {
+                    let total_value =
+                        AmountParser::new(erg_unit, "total value").parse(&total_value)?;
+
+                    let value_per_grid = total_value.amount() / num_orders;
+                    Ok(OrderValueTarget::Value(value_per_grid.try_into()?))
+                }
+                _ => Err(anyhow!(
+                    "Either token_amount or total_value must be specified"
+                )),
+            }?;
+
+            let (start, end) = range;
+
+            let start_price = Price::new(unit, erg_unit, start);
+            let end_price = Price::new(unit, erg_unit, end);
+
+            let range = GridPriceRange::new(start_price, end_price, num_orders)?
+                .with_spacing(spacing.unwrap_or_default());
+
+            (
+                unit.token_id(),
+                GridPlan::Range {
+                    range,
+                    order_value_target,
+                },
+            )
         }
-        _ => Err(anyhow!(
-            "Either token_amount or total_value must be specified"
-        )),
-    }?;
+    };
 
     let (wallet_boxes, wallet_status) = try_join!(
         node_client.wallet_boxes_unspent(),
         node_client.wallet_status()
     )?;
 
-    let liquidity_box = if !no_auto_fill {
-        let n2t_pool_boxes = node_client
-            .get_scan_unspent(scan_config.n2t_scan_id)
-            .await?;
+    let min_box_value = grid_min_box_value(node_client, min_box_value).await?;
 
-        if n2t_pool_boxes.is_empty() {
-            Err(anyhow!("no liquidity boxes found"))
-        } else {
-            n2t_pool_boxes
-                .into_iter()
-                .filter_map(|b| {
-                    b.try_into()
-                        .ok()
-                        .filter(|b: &TrackedBox<SpectrumPool>| b.value.asset_y.token_id == token_id)
-                })
-                .max_by_key(|lb| lb.value.amm_factor())
-                .ok_or_else(|| anyhow!("no liquidity box for {:?}", token_id))
-        }
-        .map(Some)
-        .hint("If a scan config was recently created it might be required to trigger a rescan")
-        .hint("Use `off-the-grid scans create-config --help` for more information")?
+    let liquidity_boxes = if !no_auto_fill && !sell_only {
+        find_liquidity_boxes(node_client, &scan_config, token_id, pool_nft).await?
     } else {
-        None
+        vec![]
     };
 
     wallet_status.error_if_locked()?;
 
-    let start: Fraction = range
-        .0
-        .parse()
-        .map_err(|_| anyhow!("Failed to parse start price {}", range.0))?;
+    warn_wallet_error(&wallet_status);
 
-    let end: Fraction = range
-        .1
-        .parse()
-        .map_err(|_| anyhow!("Failed to parse end price {}", range.1))?;
+    let initial_state = if sell_only {
+        OrderState::Sell
+    } else {
+        OrderState::Buy
+    };
+
+    // Boxes holding other tokens are only needed when funding the grid with tokens
+    let allowed_token = match &plan {
+        GridPlan::Range {
+            order_value_target: OrderValueTarget::Token(_),
+            ..
+        } => None,
+        _ => Some(token_id),
+    };
 
-    let start_price = Price::new(unit, erg_unit, start);
-    let end_price = Price::new(unit, erg_unit, end);
+    let wallet_boxes = exclude_wallet_boxes(wallet_boxes, &exclude_boxes, allowed_token);
 
-    let range = GridPriceRange::new(start_price, end_price, num_orders)?;
+    let owner_address = wallet_status.change_address()?;
 
-    let grid_tx_data = build_new_grid_data(
-        liquidity_box,
-        range,
+    let pool_state = Router::new(
+        liquidity_boxes.iter().map(|b| b.value.clone()).collect(),
+        *ERG_TOKEN_ID,
         token_id,
-        token_per_grid,
-        wallet_status.change_address()?,
-        fee_value,
-        wallet_boxes,
-        grid_identity,
     )
+    .ok();
+
+    let max_entries = split.then_some(MAX_ENTRIES_PER_BOX);
+
+    let grid_tx_data = match plan {
+        GridPlan::Range {
+            range,
+            order_value_target,
+        } => build_new_grid_data(
+            liquidity_boxes,
+            range,
+            token_id,
+            order_value_target,
+            initial_state,
+            owner_address,
+            fee_value,
+            wallet_boxes,
+            sponsor_box,
+            GridMetadata::new(grid_identity, strategy),
+            min_box_value,
+            max_entries,
+            change_options,
+        ),
+        GridPlan::Definition(definition) => {
+            let initial_order = definition.into_order(
+                owner_ec_point(&owner_address)?,
+                GridMetadata::new(grid_identity, strategy),
+                min_box_value,
+            )?;
+
+            let initial_orders = match max_entries {
+                Some(max_entries) => initial_order.split(max_entries, min_box_value)?,
+                None => vec![initial_order],
+            };
+
+            build_grid_order_data(
+                liquidity_boxes,
+                initial_orders,
+                owner_address,
+                fee_value,
+                wallet_boxes,
+                sponsor_box,
+                change_options,
+            )
+        }
+    }
     .context("Building grid transaction")?;
 
+    if let (Some(pool), LiquidityData::WithoutLiquidity) =
+        (&pool_state, &grid_tx_data.liquidity_data)
+    {
+        let explanation = unfilled_explanation(
+            pool,
+            &grid_tx_data.grid_outputs,
+            token_store.get_unit(&token_id),
+        );
+
+        eprintln!(
+            "{}",
+            format!(
+                "Warning: No orders were filled by the pool, {}",
+                explanation
+            )
+            .yellow()
+        );
+    }
+
+    if let LiquidityData::WithLiquidity { inputs, outputs } = &grid_tx_data.liquidity_data {
+        // The price impact of the whole route, from the pools it passed through
+        let before = Router::new(
+            inputs.iter().map(|b| b.value.clone()).collect(),
+            *ERG_TOKEN_ID,
+            token_id,
+        );
+        let after = Router::new(outputs.clone(), *ERG_TOKEN_ID, token_id);
+
+        let impact = before
+            .and_then(|before| Ok((before, after?)))
+            .map_err(LiquidityProviderError::Swap)
+            .and_then(|(before, after)| pool_price_impact(&before, &after));
+
+        match impact {
+            Ok(Some(impact)) => println!(
+                "Price impact: {:.2}% from filling orders with the pool",
+                impact * Fraction::from(100u64)
+            ),
+            Ok(None) => {}
+            Err(e) => eprintln!(
+                "{}",
+                format!("Warning: Could not determine the price impact: {}", e).yellow()
+            ),
+        }
+    }
+
+    let entries = GridOrderEntries::new(
+        grid_tx_data
+            .grid_outputs
+            .iter()
+            .flat_map(|o| o.entries.iter().copied())
+            .collect(),
+    );
+
+    for line in fee_lines(*grid_tx_data.fee_value.0.as_u64()) {
+        println!("{}", line);
+    }
+
+    let owner_fees = grid_owner_fees(*grid_tx_data.fee_value.0.as_u64());
+    match entries.break_even_orders(owner_fees) {
+        Some(_) => println!(
+            "Break-even: {} must complete a buy-sell cycle to cover the fees of creating and redeeming the grid",
+            break_even_text(&entries, owner_fees)
+        ),
+        None => eprintln!(
+            "{}",
+            "Warning: The margins of all orders together do not cover the fees of creating and redeeming the grid"
+                .yellow()
+        ),
+    }
+
     Ok(grid_tx_data)
 }
 
+/// Price impact of the swap that moved the pool from `before` to `after`, `None` if the
+/// reserves did not change
+fn pool_price_impact<T: LiquidityProvider>(
+    before: &T,
+    after: &T,
+) -> Result<Option<Fraction>, LiquidityProviderError<T::Error>> {
+    let x_before = *before.asset_x().amount.as_u64();
+    let x_after = *after.asset_x().amount.as_u64();
+    let y_before = *before.asset_y().amount.as_u64();
+    let y_after = *after.asset_y().amount.as_u64();
+
+    let input = if x_after > x_before {
+        (before.asset_x().token_id, x_after - x_before)
+    } else if y_after > y_before {
+        (before.asset_y().token_id, y_after - y_before)
+    } else {
+        return Ok(None);
+    };
+
+    let input = (
+        input.0,
+        input
+            .1
+            .try_into()
+            .expect("Difference of increasing amounts is non-zero"),
+    )
+        .into();
+
+    before.price_impact(&input).map(Some)
+}
+
+/// The fee paid once by the creation transaction, and the fee of each later fill which the
+/// matcher pays from the surplus of the fill
+fn fee_lines(creation_fee: u64) -> Vec<String> {
+    vec![
+        format!(
+            "Creation fee: {} paid once by this transaction",
+            UnitAmount::new(*ERG_UNIT, creation_fee)
+        ),
+        format!(
+            "Fill fee: up to {} per fill, paid by the matcher from the surplus of the fill, not by the grid",
+            UnitAmount::new(*ERG_UNIT, MAX_FEE)
+        ),
+    ]
+}
+
+/// Fetch the unspent box paying the fee on behalf of the wallet
+async fn find_sponsor_box(
+    node_client: &NodeClient,
+    box_id: &BoxId,
+) -> CommandResult<WalletBox<ErgoBox>> {
+    let sponsor_box = node_client.box_by_id(box_id).await?.ok_or_else(|| {
+        anyhow!(
+            "Sponsor box {} not found or already spent",
+            String::from(*box_id)
+        )
+    })?;
+
+    let sponsor_address = Address::recreate_from_ergo_tree(&sponsor_box.ergo_tree)
+        .map_err(|e| anyhow!("Invalid sponsor box script: {}", e))?;
+
+    Ok(WalletBox::new(sponsor_box, sponsor_address))
+}
+
+/// Remove wallet boxes that must not be used to fund a grid. If `allowed_token` is set,
+/// boxes holding any other token are removed as well.
+fn exclude_wallet_boxes(
+    wallet_boxes: Vec<WalletBox<ErgoBox>>,
+    exclude_boxes: &[BoxId],
+    allowed_token: Option<TokenId>,
+) -> Vec<WalletBox<ErgoBox>> {
+    wallet_boxes
+        .into_iter()
+        .filter(|b| !exclude_boxes.contains(&b.assets.box_id()))
+        .filter(|b| {
+            allowed_token
+                .map(|allowed| {
+                    b.assets
+                        .tokens
+                        .as_ref()
+                        .map(|tokens| tokens.iter().all(|t| t.token_id == allowed))
+                        .unwrap_or(true)
+                })
+                .unwrap_or(true)
+        })
+        .collect()
+}
+
+/// Base value of new grid order boxes. Uses `min_box_value` if given, otherwise the value
+/// is derived from the node's current protocol parameters.
+pub(super) async fn grid_min_box_value(
+    node_client: &NodeClient,
+    min_box_value: Option<String>,
+) -> anyhow::Result<u64> {
+    match min_box_value {
+        Some(value) => {
+            let amount = AmountParser::new(*ERG_UNIT, "minimum box value").parse(&value)?;
+
+            if amount.amount() < DEFAULT_MIN_BOX_VALUE {
+                Err(anyhow!(
+                    "Minimum box value must be at least {}",
+                    UnitAmount::new(*ERG_UNIT, DEFAULT_MIN_BOX_VALUE)
+                ))
+            } else {
+                Ok(amount.amount())
+            }
+        }
+        None => {
+            let parameters = node_client.blockchain_parameters().await?;
+            Ok(min_box_value_for_parameters(parameters.min_value_per_byte))
+        }
+    }
+}
+
+/// Find the N2T pool with the most liquidity for the given token, or the pool holding
+/// `pool_nft` if it is set. Without an N2T pool for the token, the pools of every two hop
+/// route through a T2T pool are returned instead.
+pub(super) async fn find_liquidity_boxes(
+    node_client: &NodeClient,
+    scan_config: &ScanConfig,
+    token_id: TokenId,
+    pool_nft: Option<TokenId>,
+) -> CommandResult<Vec<TrackedBox<SpectrumPool>>> {
+    let n2t_pool_boxes = node_client
+        .get_scan_unspent(scan_config.n2t_scan_id)
+        .await?;
+
+    if n2t_pool_boxes.is_empty() {
+        Err(anyhow!("no liquidity boxes found"))
+    } else {
+        let pools = unique_pools(
+            n2t_pool_boxes
+                .into_iter()
+                .filter_map(|b| b.try_into().ok())
+                .collect(),
+        );
+
+        match (
+            select_pool(&pools, token_id, pool_nft),
+            scan_config.t2t_scan_id,
+        ) {
+            (Ok(pool), _) => Ok(vec![pool.clone()]),
+            (Err(PoolSelectionError::NoPool(_)), Some(t2t_scan_id)) => {
+                let t2t_pools = unique_pools(
+                    node_client
+                        .get_scan_unspent(t2t_scan_id)
+                        .await?
+                        .into_iter()
+                        .filter_map(|b| b.try_into().ok())
+                        .collect(),
+                );
+
+                let route_pools = two_hop_pools(&pools, &t2t_pools, token_id);
+
+                if route_pools.is_empty() {
+                    Err(PoolSelectionError::NoPool(token_id).into())
+                } else {
+                    Ok(route_pools)
+                }
+            }
+            (Err(e), _) => Err(e.into()),
+        }
+    }
+    .hint("If a scan config was recently created it might be required to trigger a rescan")
+    .hint("Use `off-the-grid scans create-config --help` for more information")
+}
+
+/// Pools routing ERG to `token_id` through a T2T pool trading the token against a token
+/// with an N2T pool. Each T2T pool is paired with the N2T pool of the other token with the
+/// most liquidity.
+fn two_hop_pools(
+    n2t_pools: &[TrackedBox<SpectrumPool>],
+    t2t_pools: &[TrackedBox<SpectrumPool>],
+    token_id: TokenId,
+) -> Vec<TrackedBox<SpectrumPool>> {
+    let mut route_pools: Vec<TrackedBox<SpectrumPool>> = Vec::new();
+
+    for t2t_pool in t2t_pools {
+        let pool = &t2t_pool.value;
+
+        let middle = if pool.asset_x.token_id == token_id {
+            pool.asset_y.token_id
+        } else if pool.asset_y.token_id == token_id {
+            pool.asset_x.token_id
+        } else {
+            continue;
+        };
+
+        let Ok(n2t_pool) = select_pool(n2t_pools, middle, None) else {
+            continue;
+        };
+
+        for route_pool in [n2t_pool, t2t_pool] {
+            if !route_pools
+                .iter()
+                .any(|p| p.ergo_box.box_id() == route_pool.ergo_box.box_id())
+            {
+                route_pools.push(route_pool.clone());
+            }
+        }
+    }
+
+    route_pools
+}
+
+pub(super) fn owner_ec_point(owner_address: &Address) -> anyhow::Result<EcPoint> {
+    if let Address::P2Pk(owner_dlog) = owner_address {
+        Ok(*owner_dlog.h.clone())
+    } else {
+        Err(anyhow!("change address is not P2PK"))
+    }
+}
+
 fn fraction_to_u64<E>(fraction: Fraction) -> Result<u64, BuildNewGridTxError<E>>
 where
     E: std::error::Error,
@@ -330,26 +1187,40 @@ where
 fn new_multi_order<F, E>(
     range: GridPriceRange,
     token_id: TokenId,
-    grid_identity: String,
+    initial_state: OrderState,
+    metadata: GridMetadata,
     owner_ec_point: EcPoint,
     grid_value_fn: F,
+    min_box_value: u64,
 ) -> Result<MultiGridOrder, BuildNewGridTxError<E>>
 where
     F: Fn(Fraction) -> Result<u64, BuildNewGridTxError<E>>,
     E: std::error::Error,
 {
-    let grid_identity = grid_identity.into_bytes();
-
     let initial_orders: GridOrderEntries = range
         .into_iter()
-        .map(|(bid, ask)| {
+        .enumerate()
+        .map(|(level, (bid, ask))| {
             let amount = grid_value_fn(bid)?;
+            let bid_value = fraction_to_u64((bid * amount).floor())?;
+            let ask_value = fraction_to_u64((ask * amount).floor())?;
+
+            // Flooring can leave no spread between the values of a finely spaced order,
+            // which could never be filled at a profit
+            if ask_value <= bid_value {
+                return Err(BuildNewGridTxError::NoSpread {
+                    level: level + 1,
+                    amount,
+                    bid_value,
+                    ask_value,
+                });
+            }
 
-            Result::<_, BuildNewGridTxError<E>>::Ok(GridOrderEntry::new(
-                OrderState::Buy,
+            Ok(GridOrderEntry::new(
+                initial_state,
                 amount.try_into()?,
-                fraction_to_u64((bid * amount).floor())?,
-                fraction_to_u64((ask * amount).floor())?,
+                bid_value,
+                ask_value,
             ))
         })
         .collect::<Result<_, _>>()?;
@@ -358,17 +1229,31 @@ where
         owner_ec_point,
         token_id,
         initial_orders,
-        Some(grid_identity),
+        Some(metadata.to_bytes()),
+        min_box_value,
     )?)
 }
 
+/// How the change of the box selection is returned to the wallet
+#[derive(Clone, Copy, Debug, Default)]
+pub(super) struct ChangeOptions {
+    /// Merge all change into a single box
+    pub single_change: bool,
+    /// Change without tokens below this value is added to the miner fee
+    pub fold_below: Option<BoxValue>,
+}
+
 enum OrderValueTarget {
     Value(BoxValue),
     Token(TokenAmount),
 }
 
+/// Pools spent to fill orders, the pools of the route the swap went through
 enum LiquidityData<T: LiquidityProvider> {
-    WithLiquidity { input: TrackedBox<T>, output: T },
+    WithLiquidity {
+        inputs: Vec<TrackedBox<T>>,
+        outputs: Vec<T>,
+    },
     WithoutLiquidity,
 }
 
@@ -378,25 +1263,81 @@ where
 {
     pub fn creation_height(&self) -> u32 {
         match self {
-            LiquidityData::WithLiquidity { input, output: _ } => input.ergo_box.creation_height,
+            LiquidityData::WithLiquidity { inputs, outputs: _ } => inputs
+                .iter()
+                .map(|input| input.ergo_box.creation_height)
+                .max()
+                .unwrap_or(0),
             LiquidityData::WithoutLiquidity => 0,
         }
     }
 }
 
-pub struct NewGridTxData<T: LiquidityProvider> {
-    liquidity_data: LiquidityData<T>,
-    selected_boxes: Vec<WalletBox<ErgoBox>>,
-    change_boxes: Vec<WalletBox<ErgoBoxAssetsData>>,
-    grid_output: MultiGridOrder,
-    fee_value: MinerFeeValue,
+/// Box of a third party paying the miner fee, and the change returned to it
+struct SponsorData {
+    input: WalletBox<ErgoBox>,
+    change: Option<WalletBox<ErgoBoxAssetsData>>,
 }
 
-impl<T> IntoSummarizedTransaction for NewGridTxData<T>
-where
-    T: LiquidityProvider + ErgoBoxDescriptors + TryIntoErgoBoxCandidate,
-    <T as TryIntoErgoBoxCandidate>::Error:
-        Into<BuildNewGridTxError<<T as LiquidityProvider>::Error>>,
+impl SponsorData {
+    /// Spend `sponsor_box` for the fee, returning its remaining value and tokens to the
+    /// sponsor's address. Any change must be worth at least [`DEFAULT_MIN_BOX_VALUE`].
+    fn new<E>(
+        sponsor_box: WalletBox<ErgoBox>,
+        fee_value: BoxValue,
+    ) -> Result<Self, BuildNewGridTxError<E>>
+    where
+        E: std::error::Error,
+    {
+        let value = *sponsor_box.assets.value.as_u64();
+        let fee_value = *fee_value.as_u64();
+        let insufficient =
+            || BuildNewGridTxError::InsufficientSponsor(value, fee_value + DEFAULT_MIN_BOX_VALUE);
+
+        let tokens = sponsor_box.assets.tokens.clone();
+
+        let change_value = value.checked_sub(fee_value).ok_or_else(|| match tokens {
+            Some(_) => insufficient(),
+            None => BuildNewGridTxError::InsufficientSponsor(value, fee_value),
+        })?;
+
+        let change = if change_value > 0 || tokens.is_some() {
+            if change_value < DEFAULT_MIN_BOX_VALUE {
+                return Err(insufficient());
+            }
+
+            Some(WalletBox::new(
+                ErgoBoxAssetsData {
+                    value: change_value.try_into()?,
+                    tokens,
+                },
+                sponsor_box.address.clone(),
+            ))
+        } else {
+            None
+        };
+
+        Ok(Self {
+            input: sponsor_box,
+            change,
+        })
+    }
+}
+
+pub struct NewGridTxData<T: LiquidityProvider> {
+    liquidity_data: LiquidityData<T>,
+    selected_boxes: Vec<WalletBox<ErgoBox>>,
+    change_boxes: Vec<WalletBox<ErgoBoxAssetsData>>,
+    sponsor: Option<SponsorData>,
+    grid_outputs: Vec<MultiGridOrder>,
+    fee_value: MinerFeeValue,
+}
+
+impl<T> IntoSummarizedTransaction for NewGridTxData<T>
+where
+    T: LiquidityProvider + ErgoBoxDescriptors + TryIntoErgoBoxCandidate,
+    <T as TryIntoErgoBoxCandidate>::Error:
+        Into<BuildNewGridTxError<<T as LiquidityProvider>::Error>>,
 {
     type Error = BuildNewGridTxError<<T as LiquidityProvider>::Error>;
 
@@ -407,50 +1348,70 @@ where
         let creation_height = self
             .selected_boxes
             .iter()
+            .chain(self.sponsor.iter().map(|sponsor| &sponsor.input))
             .map(|input| input.assets.creation_height)
             .chain(once(self.liquidity_data.creation_height()))
             .max()
             .unwrap_or(0);
 
-        let (liquidity_input, liquidity_output) = match self.liquidity_data {
-            LiquidityData::WithLiquidity {
-                input: liquidity_box_input,
-                output,
-            } => {
-                let input = SummarizedInput::new(liquidity_box_input, token_store);
-
-                let output_candidate = SummarizedOutput::new(output, token_store, creation_height)
-                    .map_err(|e| e.into())?;
-
-                (Some(input), Some(output_candidate))
+        let (liquidity_inputs, liquidity_outputs) = match self.liquidity_data {
+            LiquidityData::WithLiquidity { inputs, outputs } => {
+                let inputs = inputs
+                    .into_iter()
+                    .map(|input| SummarizedInput::new(input, token_store))
+                    .collect();
+
+                let outputs = outputs
+                    .into_iter()
+                    .map(|output| {
+                        SummarizedOutput::new(output, token_store, creation_height)
+                            .map_err(|e| e.into())
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                (inputs, outputs)
             }
-            LiquidityData::WithoutLiquidity => (None, None),
+            LiquidityData::WithoutLiquidity => (vec![], vec![]),
+        };
+
+        let (sponsor_input, sponsor_change) = match self.sponsor {
+            Some(SponsorData { input, change }) => (Some(input), change),
+            None => (None, None),
         };
 
         let selected_as_inputs = self
             .selected_boxes
             .into_iter()
+            .chain(sponsor_input)
             .map(|b| SummarizedInput::new(b, token_store));
 
-        let inputs: Vec<_> = liquidity_input
+        let inputs: Vec<_> = liquidity_inputs
             .into_iter()
             .chain(selected_as_inputs)
             .collect();
 
-        let change_outputs = self.change_boxes.into_iter().map(|b| {
-            SummarizedOutput::new(b, token_store, creation_height)
-                .map_err(BuildNewGridTxError::from)
-        });
+        let change_outputs = self
+            .change_boxes
+            .into_iter()
+            .chain(sponsor_change)
+            .map(|b| {
+                SummarizedOutput::new(b, token_store, creation_height)
+                    .map_err(BuildNewGridTxError::from)
+            });
 
         let fee_output = SummarizedOutput::new(self.fee_value, token_store, creation_height)
             .expect("Fee conversion cannot fail");
 
-        let grid_output = SummarizedOutput::new(self.grid_output, token_store, creation_height)?;
+        let grid_outputs = self
+            .grid_outputs
+            .into_iter()
+            .map(|o| SummarizedOutput::new(o, token_store, creation_height))
+            .collect::<Result<Vec<_>, _>>()?;
 
-        let outputs: Result<Vec<_>, _> = liquidity_output
+        let outputs: Result<Vec<_>, _> = liquidity_outputs
             .into_iter()
             .map(Ok)
-            .chain(once(Ok(grid_output)))
+            .chain(grid_outputs.into_iter().map(Ok))
             .chain(change_outputs)
             .chain(once(Ok(fee_output)))
             .collect();
@@ -462,21 +1423,24 @@ where
     }
 }
 
-/// Build a transaction that creates a new grid of orders
+/// Build a transaction that creates a new grid of orders. With `max_entries` the grid is
+/// split across as many grid order boxes as needed.
 #[allow(clippy::too_many_arguments)]
 fn build_new_grid_data<T: LiquidityProvider>(
-    liquidity_box: Option<TrackedBox<T>>,
+    liquidity_boxes: Vec<TrackedBox<T>>,
     grid_range: GridPriceRange,
     token_id: TokenId,
     order_value_target: OrderValueTarget,
+    initial_state: OrderState,
     owner_address: Address,
     fee_value: BoxValue,
     wallet_boxes: Vec<WalletBox<ErgoBox>>,
-    grid_identity: String,
-) -> Result<NewGridTxData<T>, BuildNewGridTxError<T::Error>>
-where
-    BuildNewGridTxError<T::Error>: From<T::Error>,
-{
+    sponsor_box: Option<WalletBox<ErgoBox>>,
+    metadata: GridMetadata,
+    min_box_value: u64,
+    max_entries: Option<usize>,
+    change_options: ChangeOptions,
+) -> Result<NewGridTxData<T>, BuildNewGridTxError<RouterError<T::Error>>> {
     let grid_value_fn: Box<dyn Fn(Fraction) -> Result<u64, _>> = match order_value_target {
         OrderValueTarget::Value(value_per_grid) => Box::new(move |bid: Fraction| {
             fraction_to_u64((Fraction::from(*value_per_grid.as_u64()) / bid).floor())
@@ -486,53 +1450,171 @@ where
         }
     };
 
-    let owner_ec_point = if let Address::P2Pk(owner_dlog) = &owner_address {
-        Ok(*owner_dlog.h.clone())
-    } else {
-        Err(anyhow!("change address is not P2PK"))
-    }
-    .unwrap();
+    let owner_ec_point = owner_ec_point(&owner_address).unwrap();
 
     let initial_order = new_multi_order(
         grid_range,
         token_id,
-        grid_identity,
+        initial_state,
+        metadata,
         owner_ec_point,
         grid_value_fn,
+        min_box_value,
     )?;
 
-    let (liquidity_state, initial_orders) = match liquidity_box.as_ref() {
-        Some(liquidity_box) => {
-            let (liquidity_state, initial_orders) =
-                fill_orders(liquidity_box.value.clone(), initial_order)?;
+    let initial_orders = match max_entries {
+        Some(max_entries) => initial_order.split(max_entries, min_box_value)?,
+        None => vec![initial_order],
+    };
 
-            (liquidity_state, initial_orders)
+    build_grid_order_data(
+        liquidity_boxes,
+        initial_orders,
+        owner_address,
+        fee_value,
+        wallet_boxes,
+        sponsor_box,
+        change_options,
+    )
+}
+
+/// Build a transaction that creates the given grid orders, filling them by routing ERG to
+/// the grid token through the liquidity boxes if any are given. If `sponsor_box` is given
+/// it pays the fee and the wallet boxes only fund the grid.
+pub(super) fn build_grid_order_data<T: LiquidityProvider>(
+    liquidity_boxes: Vec<TrackedBox<T>>,
+    initial_orders: Vec<MultiGridOrder>,
+    owner_address: Address,
+    fee_value: BoxValue,
+    wallet_boxes: Vec<WalletBox<ErgoBox>>,
+    sponsor_box: Option<WalletBox<ErgoBox>>,
+    change_options: ChangeOptions,
+) -> Result<NewGridTxData<T>, BuildNewGridTxError<RouterError<T::Error>>> {
+    let token_id = initial_orders
+        .first()
+        .map(|o| o.token_id)
+        .ok_or(MultiGridOrderError::NoEntries)?;
+
+    // Orders are filled by swapping ERG for the grid token, so a single pool has to trade
+    // the grid token as asset y
+    if let [liquidity_box] = liquidity_boxes.as_slice() {
+        let asset_x = liquidity_box.value.asset_x().token_id;
+        let asset_y = liquidity_box.value.asset_y().token_id;
+
+        if asset_x != *ERG_TOKEN_ID || asset_y != token_id {
+            return Err(BuildNewGridTxError::PoolAssetMismatch {
+                asset_x,
+                asset_y,
+                token_id,
+            });
         }
-        None => (None, initial_order),
+    }
+
+    let (filled_pools, initial_orders) = if liquidity_boxes.is_empty() {
+        (None, initial_orders)
+    } else {
+        let router = Router::new(
+            liquidity_boxes.iter().map(|b| b.value.clone()).collect(),
+            *ERG_TOKEN_ID,
+            token_id,
+        )?;
+
+        let (router, initial_orders) = fill_orders(router, initial_orders)?;
+        (router.map(Router::into_pools), initial_orders)
     };
 
-    let missing_ergs: Result<BoxValue, _> = once(initial_orders.value.as_i64())
-        .chain(once(fee_value.as_i64()))
+    // Only the pools the swap went through are spent, and none if no order was filled
+    let (liquidity_inputs, liquidity_outputs): (Vec<_>, Vec<_>) = match filled_pools {
+        Some(pools) => liquidity_boxes
+            .into_iter()
+            .zip(pools)
+            .filter(|(input, output)| {
+                input.value.asset_x() != output.asset_x()
+                    || input.value.asset_y() != output.asset_y()
+            })
+            .unzip(),
+        None => (vec![], vec![]),
+    };
+
+    let sponsor = sponsor_box
+        .map(|sponsor_box| SponsorData::new(sponsor_box, fee_value))
+        .transpose()?;
+
+    let wallet_fee = if sponsor.is_some() {
+        0
+    } else {
+        fee_value.as_i64()
+    };
+
+    let missing_ergs: Result<BoxValue, _> = initial_orders
+        .iter()
+        .map(|o| o.value.as_i64())
+        .chain(once(wallet_fee))
         .chain(
-            liquidity_state
+            liquidity_inputs
                 .iter()
-                .map(|s| *s.asset_x().amount.as_u64() as i64),
+                .zip(liquidity_outputs.iter())
+                .map(|(input, output)| reserve_change(&input.value, output, *ERG_TOKEN_ID)),
         )
-        .chain(liquidity_box.iter().map(|lb| -lb.ergo_box.value.as_i64()))
         .sum::<i64>()
         .try_into();
 
     let missing_ergs = missing_ergs.map_err(BuildNewGridTxError::BoxValue)?;
 
-    let selection = SimpleBoxSelector::new().select(wallet_boxes, missing_ergs, &[])?;
+    // Tokens of sell orders that were not bought from the liquidity boxes are funded by
+    // the wallet
+    let liquidity_tokens = liquidity_inputs
+        .iter()
+        .zip(liquidity_outputs.iter())
+        .map(|(input, output)| -reserve_change(&input.value, output, token_id))
+        .sum::<i64>()
+        .max(0) as u64;
 
-    let liquidity_data = liquidity_box
-        .zip(liquidity_state)
-        .map(|(input, output)| LiquidityData::WithLiquidity { input, output })
-        .unwrap_or(LiquidityData::WithoutLiquidity);
+    let missing_tokens = initial_orders
+        .iter()
+        .map(|o| o.entries.token_amount())
+        .sum::<u64>()
+        .saturating_sub(liquidity_tokens);
 
-    let change_boxes = selection
-        .change_boxes
+    if missing_tokens > 0 {
+        let available = wallet_boxes
+            .iter()
+            .flat_map(|b| b.assets.tokens.iter().flat_map(|t| t.iter()))
+            .filter(|t| t.token_id == token_id)
+            .map(|t| *t.amount.as_u64())
+            .sum::<u64>();
+
+        if available < missing_tokens {
+            return Err(BuildNewGridTxError::InsufficientTokens {
+                required: missing_tokens,
+                available,
+            });
+        }
+    }
+
+    let target_tokens: Vec<Token> = if missing_tokens > 0 {
+        let amount =
+            TokenAmount::try_from(missing_tokens).map_err(BuildNewGridTxError::TokenAmount)?;
+        vec![(token_id, amount).into()]
+    } else {
+        vec![]
+    };
+
+    let selection = SimpleBoxSelector::new().select(wallet_boxes, missing_ergs, &target_tokens)?;
+
+    let liquidity_data = if liquidity_inputs.is_empty() {
+        LiquidityData::WithoutLiquidity
+    } else {
+        LiquidityData::WithLiquidity {
+            inputs: liquidity_inputs,
+            outputs: liquidity_outputs,
+        }
+    };
+
+    let (change_boxes, fee_value) =
+        apply_change_options(selection.change_boxes, fee_value, change_options)?;
+
+    let change_boxes = change_boxes
         .into_iter()
         .map(|cb| WalletBox::new(cb, owner_address.clone()))
         .collect();
@@ -541,20 +1623,1270 @@ where
 
     Ok(NewGridTxData {
         liquidity_data,
-        grid_output: initial_orders,
+        grid_outputs: initial_orders,
         selected_boxes: selection.boxes.into(),
         change_boxes,
+        sponsor,
         fee_value,
     })
 }
 
+/// Change of the reserve of `token_id` from `before` to `after`, zero if the pool does not
+/// trade the token
+fn reserve_change<T: LiquidityProvider>(before: &T, after: &T, token_id: TokenId) -> i64 {
+    let reserve = |pool: &T| {
+        [pool.asset_x(), pool.asset_y()]
+            .into_iter()
+            .filter(|asset| asset.token_id == token_id)
+            .map(|asset| *asset.amount.as_u64() as i64)
+            .sum::<i64>()
+    };
+
+    reserve(after) - reserve(before)
+}
+
+/// Merge or fold the change boxes according to `options`, returning the remaining change
+/// boxes and the resulting fee value
+fn apply_change_options<E>(
+    change_boxes: Vec<ErgoBoxAssetsData>,
+    fee_value: BoxValue,
+    options: ChangeOptions,
+) -> Result<(Vec<ErgoBoxAssetsData>, BoxValue), BuildNewGridTxError<E>>
+where
+    E: std::error::Error,
+{
+    if change_boxes.is_empty() {
+        return Ok((change_boxes, fee_value));
+    }
+
+    let change_value = sum_value(&change_boxes);
+    let has_tokens = change_boxes.iter().any(|b| b.tokens.is_some());
+
+    if let Some(fold_below) = options.fold_below {
+        if !has_tokens && change_value < *fold_below.as_u64() {
+            let fee_value = fee_value.checked_add(&change_value.try_into()?)?;
+            return Ok((vec![], fee_value));
+        }
+    }
+
+    if options.single_change && change_boxes.len() > 1 {
+        let tokens = sorted_tokens(sum_tokens_from_boxes(&change_boxes)?);
+        let num_tokens = tokens.len();
+
+        let tokens = if tokens.is_empty() {
+            None
+        } else {
+            Some(
+                tokens
+                    .try_into()
+                    .map_err(|_| BuildNewGridTxError::TooManyChangeTokens(num_tokens))?,
+            )
+        };
+
+        let change_box = ErgoBoxAssetsData {
+            value: change_value.try_into()?,
+            tokens,
+        };
+
+        return Ok((vec![change_box], fee_value));
+    }
+
+    Ok((change_boxes, fee_value))
+}
+
+/// Explain why the liquidity box did not fill any entry of `orders`, either because the
+/// pool price is outside of the price range of the grid or because the pool is too shallow
+/// to fill an entry at a profit
+fn unfilled_explanation<T: LiquidityProvider>(
+    liquidity: &T,
+    orders: &[MultiGridOrder],
+    unit: Unit,
+) -> String {
+    let to_price = |amount: Fraction| Price::new(unit, *ERG_UNIT, amount);
+
+    let pool_price = Fraction::new(
+        *liquidity.asset_x().amount.as_u64(),
+        *liquidity.asset_y().amount.as_u64(),
+    );
+
+    let entries = || orders.iter().flat_map(|o| o.entries.iter());
+
+    let start = entries().map(|e| e.bid()).min();
+    let stop = entries().map(|e| e.ask()).max();
+
+    match start.zip(stop) {
+        Some((start, stop)) if pool_price < start || pool_price > stop => format!(
+            "pool price {} is outside the grid range {}..{}",
+            to_price(pool_price),
+            to_price(start),
+            to_price(stop)
+        ),
+        _ => format!(
+            "pool depth is insufficient to fill an order at pool price {}",
+            to_price(pool_price)
+        ),
+    }
+}
+
+/// Fill `orders` against the liquidity box, returning the new state of the liquidity box
+/// if any order was filled. Orders are returned in the same order, filled or not.
 fn fill_orders<T: LiquidityProvider>(
     liquidity_box: T,
-    order: MultiGridOrder,
-) -> Result<(Option<T>, MultiGridOrder), T::Error> {
-    let (new_pool, filled) = liquidity_box.fill_orders(vec![&order])?;
-    match filled.into_iter().next() {
-        Some((_, filled_order)) => Ok((Some(new_pool), filled_order)),
-        None => Ok((None, order)),
+    orders: Vec<MultiGridOrder>,
+) -> Result<(Option<T>, Vec<MultiGridOrder>), T::Error> {
+    let (new_pool, filled) = liquidity_box.fill_orders(orders.iter().collect())?;
+
+    if filled.is_empty() {
+        return Ok((None, orders));
+    }
+
+    let orders = orders
+        .iter()
+        .map(|order| {
+            filled
+                .iter()
+                .find(|(input, _)| std::ptr::eq(*input, order))
+                .map(|(_, filled_order)| filled_order.clone())
+                .unwrap_or_else(|| order.clone())
+        })
+        .collect();
+
+    Ok((Some(new_pool), orders))
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+    use config::Environment;
+    use ergo_lib::{
+        chain::transaction::TxId,
+        ergo_chain_types::{
+            ec_point::{generator, identity},
+            Digest32,
+        },
+        ergotree_ir::{
+            chain::{
+                address::Address,
+                ergo_box::{ErgoBox, NonMandatoryRegisters},
+                token::{Token, TokenId},
+            },
+            sigma_protocol::sigma_boolean::ProveDlog,
+        },
+    };
+    use off_the_grid::{
+        boxes::{liquidity_box::LiquidityProvider, wallet_box::WalletBox},
+        grid::multigrid_order::{GridMetadata, OrderState},
+        spectrum::pool::{SpectrumPool, SpectrumPoolBuilder, SpectrumSwapError, DEFAULT_FEE_DENOM},
+        units::{Fraction, Price, TokenInfo, TokenStore, Unit, ERG_UNIT},
+    };
+
+    use crate::commands::{
+        grid::{subcommands::group_grids, IntoSummarizedTransaction},
+        test_util::{pool_box, test_pool, tracked_pool},
+    };
+
+    use num_traits::ToPrimitive;
+
+    use ergo_lib::wallet::box_selector::ErgoBoxAssetsData;
+
+    use crate::commands::test_util::order_box;
+
+    use super::{
+        apply_change_options, build_new_grid_data, exclude_wallet_boxes, fee_lines,
+        grid_order_range_from_str, pool_price_impact, two_hop_pools, unfilled_explanation,
+        BuildNewGridTxError, ChangeOptions, CreateOptions, GridOrderRangeError, GridPriceRange,
+        GridSpacing, LiquidityData, OrderValueTarget, CREATE_ENV_PREFIX, DEFAULT_MIN_BOX_VALUE,
+        MAX_ENTRIES_PER_BOX,
+    };
+
+    fn test_wallet_box(
+        owner_address: &Address,
+        value: u64,
+        index: u16,
+        tokens: Option<Vec<Token>>,
+    ) -> WalletBox<ErgoBox> {
+        let ergo_box = ErgoBox::new(
+            value.try_into().unwrap(),
+            owner_address.script().unwrap(),
+            tokens.map(|t| t.try_into().unwrap()),
+            NonMandatoryRegisters::empty(),
+            0,
+            TxId::zero(),
+            index,
+        )
+        .unwrap();
+
+        WalletBox::new(ergo_box, owner_address.clone())
+    }
+
+    #[test]
+    fn range_bounds_validated() {
+        assert_eq!(
+            grid_order_range_from_str("50-100.5").unwrap(),
+            (Fraction::from(50u64), Fraction::new(201u64, 2u64))
+        );
+
+        for (range, reason) in [
+            ("0-100", "positive"),
+            ("50-0", "positive"),
+            ("-50-100", "positive"),
+            ("50--100", "positive"),
+            ("100-50", "below stop"),
+            ("50-50", "below stop"),
+            ("50", "start-stop"),
+            ("abc-100", "Invalid price"),
+        ] {
+            let error = grid_order_range_from_str(range).expect_err(range);
+            assert!(error.contains(reason), "{}: {}", range, error);
+        }
+    }
+
+    #[test]
+    fn zero_orders_rejected() {
+        let error = CreateOptions::try_parse_from(["create", "-i", "test", "-o", "0"])
+            .err()
+            .unwrap();
+        assert!(
+            error.to_string().contains("at least one order"),
+            "{}",
+            error
+        );
+
+        let options = CreateOptions::try_parse_from(["create", "-i", "test", "-o", "1"]).unwrap();
+        assert_eq!(options.num_orders, Some(1));
+
+        let environment = Environment::with_prefix(CREATE_ENV_PREFIX).source(Some(
+            [("OTG_GRID_NUM_ORDERS".to_string(), "0".to_string())]
+                .into_iter()
+                .collect(),
+        ));
+        let mut options = CreateOptions::try_parse_from(["create", "-i", "test"]).unwrap();
+        let error = options.apply_env(environment).unwrap_err();
+        assert!(
+            error.to_string().contains("OTG_GRID_NUM_ORDERS"),
+            "{}",
+            error
+        );
+
+        let erg = *ERG_UNIT;
+        assert!(matches!(
+            GridPriceRange::new(
+                Price::new(erg, erg, Fraction::from(1u64)),
+                Price::new(erg, erg, Fraction::from(2u64)),
+                0,
+            ),
+            Err(GridOrderRangeError::NoOrders)
+        ));
+    }
+
+    #[test]
+    fn geometric_spacing_levels() {
+        let erg = *ERG_UNIT;
+        let range = |start: u64, stop: u64, num_orders| {
+            GridPriceRange::new(
+                Price::new(erg, erg, Fraction::from(start)),
+                Price::new(erg, erg, Fraction::from(stop)),
+                num_orders,
+            )
+            .map(|range| range.with_spacing(GridSpacing::Geometric))
+        };
+
+        let levels: Vec<_> = range(1, 8, 3).unwrap().into_iter().collect();
+        assert_eq!(
+            levels,
+            vec![
+                (Fraction::new(1u64, 2u64), Fraction::from(1u64)),
+                (Fraction::new(1u64, 4u64), Fraction::new(1u64, 2u64)),
+                (Fraction::new(1u64, 8u64), Fraction::new(1u64, 4u64)),
+            ]
+        );
+
+        // A single order spans the whole range
+        let levels: Vec<_> = range(1, 8, 1).unwrap().into_iter().collect();
+        assert_eq!(
+            levels,
+            vec![(Fraction::new(1u64, 8u64), Fraction::from(1u64))]
+        );
+
+        // Every order has the same ratio between its bid and ask, unlike linear spacing
+        let ratios: Vec<f64> = range(100, 200, 4)
+            .unwrap()
+            .into_iter()
+            .map(|(bid, ask)| (ask / bid).to_f64().unwrap())
+            .collect();
+        assert_eq!(ratios.len(), 4);
+        for ratio in ratios {
+            assert!((ratio - 2f64.powf(0.25)).abs() < 1e-9, "{}", ratio);
+        }
+
+        assert!(range(8, 1, 3).is_err());
+        assert!(range(8, 8, 1).is_err());
+    }
+
+    #[test]
+    fn excluded_box_not_selected() {
+        let mut token_bytes = [0u8; 32];
+        token_bytes[0] = 1;
+        let token_id: TokenId = Digest32::from(token_bytes).into();
+
+        let nft_id: TokenId = Digest32::from([2u8; 32]).into();
+
+        let owner_address = Address::P2Pk(ProveDlog::new(generator()));
+
+        let excluded = test_wallet_box(&owner_address, 10_000_000_000, 0, None);
+        let nft_box = test_wallet_box(
+            &owner_address,
+            10_000_000_000,
+            1,
+            Some(vec![(nft_id, 1.try_into().unwrap()).into()]),
+        );
+        let funding = test_wallet_box(&owner_address, 10_000_000_000, 2, None);
+
+        let excluded_id = excluded.assets.box_id();
+        let nft_box_id = nft_box.assets.box_id();
+
+        let wallet_boxes = exclude_wallet_boxes(
+            vec![excluded, nft_box, funding],
+            &[excluded_id],
+            Some(token_id),
+        );
+
+        let range = GridPriceRange::new(
+            Price::new(Unit::Unknown(token_id), *ERG_UNIT, Fraction::from(1000u64)),
+            Price::new(Unit::Unknown(token_id), *ERG_UNIT, Fraction::from(2000u64)),
+            4,
+        )
+        .unwrap();
+
+        let grid_data = build_new_grid_data::<SpectrumPool>(
+            vec![],
+            range,
+            token_id,
+            OrderValueTarget::Value(1_000_000_000u64.try_into().unwrap()),
+            OrderState::Buy,
+            owner_address,
+            1_000_000u64.try_into().unwrap(),
+            wallet_boxes,
+            None,
+            GridMetadata::new("test", None),
+            DEFAULT_MIN_BOX_VALUE,
+            None,
+            ChangeOptions::default(),
+        )
+        .unwrap();
+
+        assert!(!grid_data.selected_boxes.is_empty());
+        assert!(grid_data
+            .selected_boxes
+            .iter()
+            .all(|b| b.assets.box_id() != excluded_id && b.assets.box_id() != nft_box_id));
+    }
+
+    #[test]
+    fn serialize_grid_create_summary() {
+        let mut token_bytes = [0u8; 32];
+        token_bytes[0] = 1;
+        let token_id: TokenId = Digest32::from(token_bytes).into();
+
+        let token_info = TokenInfo {
+            token_id,
+            name: "TEST".to_string(),
+            decimals: 0,
+        };
+        let token_store = TokenStore::with_tokens(vec![token_info.clone()]);
+        let unit = Unit::Known(&token_info);
+
+        let owner_address = Address::P2Pk(ProveDlog::new(generator()));
+
+        let wallet_box = ErgoBox::new(
+            10_000_000_000u64.try_into().unwrap(),
+            owner_address.script().unwrap(),
+            None,
+            NonMandatoryRegisters::empty(),
+            0,
+            TxId::zero(),
+            0,
+        )
+        .unwrap();
+        let wallet_box_id = wallet_box.box_id();
+
+        let range = GridPriceRange::new(
+            Price::new(unit, *ERG_UNIT, Fraction::from(1000u64)),
+            Price::new(unit, *ERG_UNIT, Fraction::from(2000u64)),
+            4,
+        )
+        .unwrap();
+
+        let grid_data = build_new_grid_data::<SpectrumPool>(
+            vec![],
+            range,
+            token_id,
+            OrderValueTarget::Token(100u64.try_into().unwrap()),
+            OrderState::Buy,
+            owner_address.clone(),
+            1_000_000u64.try_into().unwrap(),
+            vec![WalletBox::new(wallet_box, owner_address)],
+            None,
+            GridMetadata::new("test", None),
+            DEFAULT_MIN_BOX_VALUE,
+            None,
+            ChangeOptions::default(),
+        )
+        .unwrap();
+
+        let summary = grid_data.into_summarized_transaction(&token_store).unwrap();
+
+        let json = serde_json::to_value(&summary).unwrap();
+
+        let inputs = json["inputs"].as_array().unwrap();
+        assert_eq!(inputs.len(), 1);
+        assert_eq!(inputs[0]["box_type"], "Wallet");
+        assert_eq!(inputs[0]["box_id"], String::from(wallet_box_id));
+
+        let outputs = json["outputs"].as_array().unwrap();
+        let box_types: Vec<_> = outputs
+            .iter()
+            .map(|o| o["box_type"].as_str().unwrap())
+            .collect();
+        assert_eq!(box_types, vec!["MultiGrid", "Wallet", "Miner fee"]);
+        assert_eq!(outputs[0]["tokens"], "0 TEST");
+        assert_eq!(outputs[2]["value"], "0.001 ERG");
+        assert!(outputs.iter().all(|o| o.get("box_id").is_none()));
+    }
+
+    #[test]
+    fn create_by_token_id_without_store() {
+        let token_store = TokenStore::default();
+        let token_id: TokenId = Digest32::from([1u8; 32]).into();
+
+        let unit = token_store
+            .get_unit_by_id(&String::from(token_id))
+            .expect("Raw token ID should resolve without a token store");
+        assert_eq!(unit, Unit::Unknown(token_id));
+
+        let owner_address = Address::P2Pk(ProveDlog::new(generator()));
+        let wallet_box = test_wallet_box(&owner_address, 10_000_000_000, 0, None);
+
+        let range = GridPriceRange::new(
+            Price::new(unit, *ERG_UNIT, Fraction::from(1000u64)),
+            Price::new(unit, *ERG_UNIT, Fraction::from(2000u64)),
+            4,
+        )
+        .unwrap();
+
+        let grid_data = build_new_grid_data::<SpectrumPool>(
+            vec![],
+            range,
+            token_id,
+            OrderValueTarget::Value(1_000_000_000u64.try_into().unwrap()),
+            OrderState::Buy,
+            owner_address,
+            1_000_000u64.try_into().unwrap(),
+            vec![wallet_box],
+            None,
+            GridMetadata::new("test", None),
+            DEFAULT_MIN_BOX_VALUE,
+            None,
+            ChangeOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(grid_data.grid_outputs[0].token_id, token_id);
+
+        grid_data
+            .into_summarized_transaction(&token_store)
+            .expect("Summary should not require token info");
+    }
+
+    #[test]
+    fn sell_only_funded_with_tokens() {
+        let token_id: TokenId = Digest32::from([1u8; 32]).into();
+        let owner_address = Address::P2Pk(ProveDlog::new(generator()));
+
+        let erg_box = test_wallet_box(&owner_address, 10_000_000_000, 0, None);
+        let token_box = test_wallet_box(
+            &owner_address,
+            1_000_000,
+            1,
+            Some(vec![(token_id, 1000.try_into().unwrap()).into()]),
+        );
+        let token_box_id = token_box.assets.box_id();
+
+        let range = GridPriceRange::new(
+            Price::new(Unit::Unknown(token_id), *ERG_UNIT, Fraction::from(1000u64)),
+            Price::new(Unit::Unknown(token_id), *ERG_UNIT, Fraction::from(2000u64)),
+            4,
+        )
+        .unwrap();
+
+        let grid_data = build_new_grid_data::<SpectrumPool>(
+            vec![],
+            range,
+            token_id,
+            OrderValueTarget::Token(100u64.try_into().unwrap()),
+            OrderState::Sell,
+            owner_address,
+            1_000_000u64.try_into().unwrap(),
+            vec![erg_box, token_box],
+            None,
+            GridMetadata::new("test", None),
+            DEFAULT_MIN_BOX_VALUE,
+            None,
+            ChangeOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            *grid_data.grid_outputs[0].value.as_u64(),
+            DEFAULT_MIN_BOX_VALUE
+        );
+        assert_eq!(grid_data.grid_outputs[0].entries.token_amount(), 400);
+        assert!(grid_data
+            .selected_boxes
+            .iter()
+            .any(|b| b.assets.box_id() == token_box_id));
+
+        let change_tokens: u64 = grid_data
+            .change_boxes
+            .iter()
+            .flat_map(|b| b.assets.tokens.iter().flat_map(|t| t.iter()))
+            .filter(|t| t.token_id == token_id)
+            .map(|t| *t.amount.as_u64())
+            .sum();
+        assert_eq!(change_tokens, 600);
+    }
+
+    #[test]
+    fn sell_only_token_deficit() {
+        let token_id: TokenId = Digest32::from([1u8; 32]).into();
+        let owner_address = Address::P2Pk(ProveDlog::new(generator()));
+
+        let erg_box = test_wallet_box(&owner_address, 10_000_000_000, 0, None);
+        let token_box = test_wallet_box(
+            &owner_address,
+            1_000_000,
+            1,
+            Some(vec![(token_id, 250.try_into().unwrap()).into()]),
+        );
+
+        let range = GridPriceRange::new(
+            Price::new(Unit::Unknown(token_id), *ERG_UNIT, Fraction::from(1000u64)),
+            Price::new(Unit::Unknown(token_id), *ERG_UNIT, Fraction::from(2000u64)),
+            4,
+        )
+        .unwrap();
+
+        let error = build_new_grid_data::<SpectrumPool>(
+            vec![],
+            range,
+            token_id,
+            OrderValueTarget::Token(100u64.try_into().unwrap()),
+            OrderState::Sell,
+            owner_address,
+            1_000_000u64.try_into().unwrap(),
+            vec![erg_box, token_box],
+            None,
+            GridMetadata::new("test", None),
+            DEFAULT_MIN_BOX_VALUE,
+            None,
+            ChangeOptions::default(),
+        )
+        .err()
+        .unwrap();
+
+        assert_eq!(
+            error.to_string(),
+            "Sell orders need 400 tokens but the wallet holds 250, 150 short"
+        );
+    }
+
+    #[test]
+    fn sponsor_box_pays_fee() {
+        let token_id: TokenId = Digest32::from([1u8; 32]).into();
+        let owner_address = Address::P2Pk(ProveDlog::new(generator()));
+        let sponsor_address = Address::P2Pk(ProveDlog::new(identity()));
+
+        let range = || {
+            GridPriceRange::new(
+                Price::new(Unit::Unknown(token_id), *ERG_UNIT, Fraction::from(1000u64)),
+                Price::new(Unit::Unknown(token_id), *ERG_UNIT, Fraction::from(2000u64)),
+                4,
+            )
+            .unwrap()
+        };
+
+        let fee = 1_000_000u64;
+
+        let build = |sponsor_box| {
+            build_new_grid_data::<SpectrumPool>(
+                vec![],
+                range(),
+                token_id,
+                OrderValueTarget::Value(1_000_000_000u64.try_into().unwrap()),
+                OrderState::Buy,
+                owner_address.clone(),
+                fee.try_into().unwrap(),
+                vec![test_wallet_box(&owner_address, 10_000_000_000, 0, None)],
+                sponsor_box,
+                GridMetadata::new("test", None),
+                DEFAULT_MIN_BOX_VALUE,
+                None,
+                ChangeOptions::default(),
+            )
+        };
+
+        let sponsor_box = test_wallet_box(&sponsor_address, 5_000_000, 1, None);
+        let sponsor_box_id = sponsor_box.assets.box_id();
+
+        let grid_data = build(Some(sponsor_box)).unwrap();
+        let grid_value = *grid_data.grid_outputs[0].value.as_u64();
+
+        // The wallet change holds everything that was not locked in the grid
+        let wallet_change: u64 = grid_data
+            .change_boxes
+            .iter()
+            .map(|b| *b.assets.value.as_u64())
+            .sum();
+        assert_eq!(wallet_change, 10_000_000_000 - grid_value);
+
+        let sponsor = grid_data.sponsor.as_ref().unwrap();
+        let sponsor_change = sponsor.change.as_ref().unwrap();
+        assert_eq!(*sponsor_change.assets.value.as_u64(), 5_000_000 - fee);
+        assert_eq!(sponsor_change.address, sponsor_address);
+
+        let summary = grid_data
+            .into_summarized_transaction(&TokenStore::default())
+            .unwrap();
+        let json = serde_json::to_value(&summary).unwrap();
+        assert_eq!(json["inputs"][1]["box_id"], String::from(sponsor_box_id));
+
+        // Without a sponsor the wallet also pays the fee
+        let grid_data = build(None).unwrap();
+        let wallet_change: u64 = grid_data
+            .change_boxes
+            .iter()
+            .map(|b| *b.assets.value.as_u64())
+            .sum();
+        assert_eq!(wallet_change, 10_000_000_000 - grid_value - fee);
+
+        let small_sponsor = test_wallet_box(&sponsor_address, fee - 1, 1, None);
+        assert!(matches!(
+            build(Some(small_sponsor)),
+            Err(BuildNewGridTxError::InsufficientSponsor(_, needed)) if needed == fee
+        ));
+
+        // A sponsor box spent exactly for the fee leaves no change box
+        let exact_sponsor = test_wallet_box(&sponsor_address, fee, 1, None);
+        assert!(build(Some(exact_sponsor))
+            .unwrap()
+            .sponsor
+            .unwrap()
+            .change
+            .is_none());
+
+        // Change below the minimum box value, or tokens left without the value for a box
+        let dust_sponsor = test_wallet_box(&sponsor_address, fee + 1, 1, None);
+        let token_sponsor = test_wallet_box(
+            &sponsor_address,
+            fee,
+            1,
+            Some(vec![(token_id, 5.try_into().unwrap()).into()]),
+        );
+        for sponsor_box in [dust_sponsor, token_sponsor] {
+            assert!(matches!(
+                build(Some(sponsor_box)),
+                Err(BuildNewGridTxError::InsufficientSponsor(_, needed))
+                    if needed == fee + DEFAULT_MIN_BOX_VALUE
+            ));
+        }
+    }
+
+    fn change_box(value: u64, tokens: Option<Vec<Token>>) -> ErgoBoxAssetsData {
+        ErgoBoxAssetsData {
+            value: value.try_into().unwrap(),
+            tokens: tokens.map(|t| t.try_into().unwrap()),
+        }
+    }
+
+    #[test]
+    fn single_change_box() {
+        let token_a: TokenId = Digest32::from([1u8; 32]).into();
+        let token_b: TokenId = Digest32::from([2u8; 32]).into();
+
+        let change_boxes = vec![
+            change_box(
+                2_000_000,
+                Some(vec![
+                    (token_a, 10.try_into().unwrap()).into(),
+                    (token_b, 5.try_into().unwrap()).into(),
+                ]),
+            ),
+            change_box(
+                3_000_000,
+                Some(vec![(token_a, 15.try_into().unwrap()).into()]),
+            ),
+            change_box(4_000_000, None),
+        ];
+
+        let options = ChangeOptions {
+            single_change: true,
+            fold_below: None,
+        };
+
+        let (change, fee) = apply_change_options::<SpectrumSwapError>(
+            change_boxes,
+            1_000_000u64.try_into().unwrap(),
+            options,
+        )
+        .unwrap();
+
+        assert_eq!(change.len(), 1);
+        assert_eq!(*change[0].value.as_u64(), 9_000_000);
+        assert_eq!(*fee.as_u64(), 1_000_000);
+
+        let tokens = change[0].tokens.as_ref().unwrap();
+        let amount = |token_id| {
+            tokens
+                .iter()
+                .find(|t| t.token_id == token_id)
+                .map(|t| *t.amount.as_u64())
+        };
+        assert_eq!(tokens.len(), 2);
+        assert_eq!(amount(token_a), Some(25));
+        assert_eq!(amount(token_b), Some(5));
+    }
+
+    #[test]
+    fn fold_change_into_fee() {
+        let token_id: TokenId = Digest32::from([1u8; 32]).into();
+        let fee_value = 1_000_000u64.try_into().unwrap();
+
+        let options = ChangeOptions {
+            single_change: false,
+            fold_below: Some(5_000_000u64.try_into().unwrap()),
+        };
+
+        let (change, fee) = apply_change_options::<SpectrumSwapError>(
+            vec![change_box(2_000_000, None)],
+            fee_value,
+            options,
+        )
+        .unwrap();
+
+        assert!(change.is_empty());
+        assert_eq!(*fee.as_u64(), 3_000_000);
+
+        // Change above the threshold is kept
+        let (change, fee) = apply_change_options::<SpectrumSwapError>(
+            vec![change_box(6_000_000, None)],
+            fee_value,
+            options,
+        )
+        .unwrap();
+
+        assert_eq!(change.len(), 1);
+        assert_eq!(fee, fee_value);
+
+        // Change holding tokens is never folded
+        let (change, fee) = apply_change_options::<SpectrumSwapError>(
+            vec![change_box(
+                2_000_000,
+                Some(vec![(token_id, 1.try_into().unwrap()).into()]),
+            )],
+            fee_value,
+            options,
+        )
+        .unwrap();
+
+        assert_eq!(change.len(), 1);
+        assert_eq!(fee, fee_value);
+    }
+
+    #[test]
+    fn pool_outside_range_explained() {
+        let token_id: TokenId = Digest32::from([1u8; 32]).into();
+        let owner_address = Address::P2Pk(ProveDlog::new(generator()));
+
+        // 0.01 ERG per token, far above the grid range
+        let pool = test_pool(20, token_id, 1_000_000_000_000, 100_000);
+        let pool_box = pool_box(pool.clone(), 0);
+
+        let range = GridPriceRange::new(
+            Price::new(Unit::Unknown(token_id), *ERG_UNIT, Fraction::from(1000u64)),
+            Price::new(Unit::Unknown(token_id), *ERG_UNIT, Fraction::from(2000u64)),
+            4,
+        )
+        .unwrap();
+
+        let grid_data = build_new_grid_data(
+            vec![pool_box],
+            range,
+            token_id,
+            OrderValueTarget::Token(100u64.try_into().unwrap()),
+            OrderState::Buy,
+            owner_address.clone(),
+            1_000_000u64.try_into().unwrap(),
+            vec![test_wallet_box(&owner_address, 10_000_000_000, 1, None)],
+            None,
+            GridMetadata::new("test", None),
+            DEFAULT_MIN_BOX_VALUE,
+            None,
+            ChangeOptions::default(),
+        )
+        .unwrap();
+
+        assert!(matches!(
+            grid_data.liquidity_data,
+            LiquidityData::WithoutLiquidity
+        ));
+
+        let explanation =
+            unfilled_explanation(&pool, &grid_data.grid_outputs, Unit::Unknown(token_id));
+
+        assert!(
+            explanation.starts_with("pool price 0.01 ")
+                && explanation.contains("is outside the grid range 0.0005 "),
+            "{}",
+            explanation
+        );
+    }
+
+    #[test]
+    fn orders_filled_through_two_hops() {
+        let middle: TokenId = Digest32::from([1u8; 32]).into();
+        let token_id: TokenId = Digest32::from([2u8; 32]).into();
+        let other: TokenId = Digest32::from([3u8; 32]).into();
+        let owner_address = Address::P2Pk(ProveDlog::new(generator()));
+
+        let t2t_pool = |nft: u8, x: (TokenId, u64), y: (TokenId, u64)| {
+            SpectrumPoolBuilder::t2t(
+                Digest32::from([nft; 32]).into(),
+                (
+                    Digest32::from([nft + 1; 32]).into(),
+                    1000.try_into().unwrap(),
+                )
+                    .into(),
+                x.0,
+                y.0,
+                2_000_000u64.try_into().unwrap(),
+            )
+            .reserves(x.1, y.1)
+            .fee(997, DEFAULT_FEE_DENOM)
+            .build()
+            .unwrap()
+        };
+
+        // 1000 nanoERG per middle token and 2 grid tokens per middle token, so 500 nanoERG
+        // per grid token
+        let n2t = tracked_pool(10, middle, 1_000_000_000_000, 1_000_000_000);
+        let t2t = pool_box(
+            t2t_pool(20, (middle, 1_000_000_000), (token_id, 2_000_000_000)),
+            1,
+        );
+        // No N2T pool trades the other token, so there is no route through this pool
+        let unrouted = pool_box(
+            t2t_pool(30, (other, 1_000_000_000), (token_id, 1_000_000_000)),
+            2,
+        );
+
+        let route_pools = two_hop_pools(
+            std::slice::from_ref(&n2t),
+            &[t2t.clone(), unrouted],
+            token_id,
+        );
+        let route_nfts: Vec<_> = route_pools
+            .iter()
+            .map(|p| p.value.pool_nft.token_id)
+            .collect();
+        assert_eq!(
+            route_nfts,
+            vec![n2t.value.pool_nft.token_id, t2t.value.pool_nft.token_id]
+        );
+
+        let range = GridPriceRange::new(
+            Price::new(Unit::Unknown(token_id), *ERG_UNIT, Fraction::from(600u64)),
+            Price::new(Unit::Unknown(token_id), *ERG_UNIT, Fraction::from(800u64)),
+            2,
+        )
+        .unwrap();
+
+        let wallet_box = test_wallet_box(&owner_address, 10_000_000_000, 3, None);
+        let fee_value = 1_000_000u64;
+
+        let grid_data = build_new_grid_data(
+            route_pools,
+            range,
+            token_id,
+            OrderValueTarget::Token(1000u64.try_into().unwrap()),
+            OrderState::Buy,
+            owner_address.clone(),
+            fee_value.try_into().unwrap(),
+            vec![wallet_box],
+            None,
+            GridMetadata::new("test", None),
+            DEFAULT_MIN_BOX_VALUE,
+            None,
+            ChangeOptions::default(),
+        )
+        .unwrap();
+
+        assert!(grid_data
+            .grid_outputs
+            .iter()
+            .flat_map(|o| o.entries.iter())
+            .all(|e| e.state == OrderState::Sell));
+
+        let LiquidityData::WithLiquidity { inputs, outputs } = &grid_data.liquidity_data else {
+            panic!("Orders are filled through the route");
+        };
+        assert_eq!(inputs.len(), 2);
+
+        // The middle tokens bought from the N2T pool are all sold to the T2T pool
+        let middle_bought =
+            *inputs[0].value.asset_y.amount.as_u64() - *outputs[0].asset_y.amount.as_u64();
+        let middle_sold =
+            *outputs[1].asset_x.amount.as_u64() - *inputs[1].value.asset_x.amount.as_u64();
+        assert_eq!(middle_bought, middle_sold);
+        assert_eq!(outputs[1].pool_type, inputs[1].value.pool_type);
+
+        // The grid holds the tokens bought from the T2T pool, up to rounding of the swap
+        let tokens_bought =
+            *inputs[1].value.asset_y.amount.as_u64() - *outputs[1].asset_y.amount.as_u64();
+        let grid_tokens: u64 = grid_data
+            .grid_outputs
+            .iter()
+            .map(|o| o.entries.token_amount())
+            .sum();
+        assert!((grid_tokens..=grid_tokens + 1).contains(&tokens_bought));
+
+        // ERG paid into the N2T pool, the grids, change and fee add up to the inputs
+        let input_value: u64 = inputs
+            .iter()
+            .map(|i| *i.ergo_box.value.as_u64())
+            .chain(
+                grid_data
+                    .selected_boxes
+                    .iter()
+                    .map(|b| *b.assets.value.as_u64()),
+            )
+            .sum();
+        let output_value: u64 = outputs
+            .iter()
+            .map(|o| *o.clone().into_box_candidate(0).unwrap().value.as_u64())
+            .chain(grid_data.grid_outputs.iter().map(|o| *o.value.as_u64()))
+            .chain(
+                grid_data
+                    .change_boxes
+                    .iter()
+                    .map(|b| *b.assets.value.as_u64()),
+            )
+            .chain(std::iter::once(*grid_data.fee_value.0.as_u64()))
+            .sum();
+        assert_eq!(input_value, output_value);
+
+        let summary = grid_data
+            .into_summarized_transaction(&TokenStore::default())
+            .unwrap();
+        let json = serde_json::to_value(&summary).unwrap();
+        let box_types: Vec<_> = json["outputs"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|o| o["box_type"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            &box_types[..2],
+            ["Spectrum N2T".to_string(), "Spectrum T2T".to_string()]
+        );
+    }
+
+    #[test]
+    fn split_grid_listed_as_one() {
+        let token_id: TokenId = Digest32::from([1u8; 32]).into();
+        let owner_address = Address::P2Pk(ProveDlog::new(generator()));
+
+        let range = GridPriceRange::new(
+            Price::new(Unit::Unknown(token_id), *ERG_UNIT, Fraction::from(1000u64)),
+            Price::new(Unit::Unknown(token_id), *ERG_UNIT, Fraction::from(2000u64)),
+            120,
+        )
+        .unwrap();
+
+        let grid_data = build_new_grid_data::<SpectrumPool>(
+            vec![],
+            range,
+            token_id,
+            OrderValueTarget::Token(100u64.try_into().unwrap()),
+            OrderState::Buy,
+            owner_address.clone(),
+            1_000_000u64.try_into().unwrap(),
+            vec![test_wallet_box(&owner_address, 100_000_000_000, 0, None)],
+            None,
+            GridMetadata::new("split", None),
+            DEFAULT_MIN_BOX_VALUE,
+            Some(MAX_ENTRIES_PER_BOX),
+            ChangeOptions::default(),
+        )
+        .unwrap();
+
+        let num_entries: Vec<_> = grid_data
+            .grid_outputs
+            .iter()
+            .map(|o| o.entries.iter().count())
+            .collect();
+        assert_eq!(num_entries, vec![50, 50, 20]);
+        assert!(grid_data
+            .grid_outputs
+            .iter()
+            .all(|o| o.identity() == Some(&b"split"[..])));
+
+        let grid_boxes = grid_data
+            .grid_outputs
+            .iter()
+            .enumerate()
+            .map(|(i, order)| order_box(order.clone(), i as u16))
+            .collect();
+
+        let grids = group_grids(grid_boxes);
+
+        assert_eq!(grids.len(), 1);
+        assert_eq!(grids[0].label, "split (3 boxes)");
+        assert_eq!(grids[0].entries.iter().count(), 120);
+        assert_eq!(
+            grids[0].value,
+            grid_data
+                .grid_outputs
+                .iter()
+                .map(|o| *o.value.as_u64())
+                .sum::<u64>()
+        );
+    }
+
+    #[test]
+    fn fine_spacing_without_spread_rejected() {
+        let token_id: TokenId = Digest32::from([1u8; 32]).into();
+        let owner_address = Address::P2Pk(ProveDlog::new(generator()));
+
+        let range = GridPriceRange::new(
+            Price::new(
+                Unit::Unknown(token_id),
+                *ERG_UNIT,
+                Fraction::from(100_000_000u64),
+            ),
+            Price::new(
+                Unit::Unknown(token_id),
+                *ERG_UNIT,
+                Fraction::from(100_100_000u64),
+            ),
+            10,
+        )
+        .unwrap();
+
+        let result = build_new_grid_data::<SpectrumPool>(
+            vec![],
+            range,
+            token_id,
+            OrderValueTarget::Token(1u64.try_into().unwrap()),
+            OrderState::Buy,
+            owner_address.clone(),
+            1_000_000u64.try_into().unwrap(),
+            vec![test_wallet_box(&owner_address, 10_000_000_000, 0, None)],
+            None,
+            GridMetadata::new("fine", None),
+            DEFAULT_MIN_BOX_VALUE,
+            None,
+            ChangeOptions::default(),
+        );
+
+        assert!(matches!(
+            result,
+            Err(BuildNewGridTxError::NoSpread {
+                level: 2,
+                amount: 1,
+                bid_value: 9,
+                ask_value: 9,
+            })
+        ));
+    }
+
+    #[test]
+    fn pool_of_other_token_rejected() {
+        let token_id: TokenId = Digest32::from([1u8; 32]).into();
+        let other_token_id: TokenId = Digest32::from([2u8; 32]).into();
+        let owner_address = Address::P2Pk(ProveDlog::new(generator()));
+
+        let pool_box = tracked_pool(20, other_token_id, 1_000_000_000_000, 100_000);
+
+        let range = GridPriceRange::new(
+            Price::new(Unit::Unknown(token_id), *ERG_UNIT, Fraction::from(1000u64)),
+            Price::new(Unit::Unknown(token_id), *ERG_UNIT, Fraction::from(2000u64)),
+            4,
+        )
+        .unwrap();
+
+        let result = build_new_grid_data(
+            vec![pool_box],
+            range,
+            token_id,
+            OrderValueTarget::Token(100u64.try_into().unwrap()),
+            OrderState::Buy,
+            owner_address.clone(),
+            1_000_000u64.try_into().unwrap(),
+            vec![test_wallet_box(&owner_address, 10_000_000_000, 1, None)],
+            None,
+            GridMetadata::new("test", None),
+            DEFAULT_MIN_BOX_VALUE,
+            None,
+            ChangeOptions::default(),
+        );
+
+        match result {
+            Err(BuildNewGridTxError::PoolAssetMismatch {
+                asset_x,
+                asset_y,
+                token_id: grid_token_id,
+            }) => {
+                assert_eq!(asset_x, Digest32::zero().into());
+                assert_eq!(asset_y, other_token_id);
+                assert_eq!(grid_token_id, token_id);
+            }
+            _ => panic!("Pool of another token should be rejected"),
+        }
+    }
+
+    #[test]
+    fn options_from_environment() {
+        let environment = || {
+            let variables = [
+                ("OTG_GRID_RANGE", "0.1-0.2"),
+                ("OTG_GRID_NUM_ORDERS", "10"),
+                ("OTG_GRID_TOKEN_ID", "SigUSD"),
+                ("OTG_GRID_TOKEN_AMOUNT", "1000"),
+                ("OTG_GRID_SPLIT", "true"),
+                ("OTG_GRID_EXCLUDE_BOXES", "aa, bb"),
+            ];
+
+            Environment::with_prefix(CREATE_ENV_PREFIX).source(Some(
+                variables
+                    .into_iter()
+                    .map(|(key, value)| (key.to_string(), value.to_string()))
+                    .collect(),
+            ))
+        };
+
+        let mut options =
+            CreateOptions::try_parse_from(["create", "-i", "test", "-t", "COMET"]).unwrap();
+        options.apply_env(environment()).unwrap();
+
+        assert_eq!(
+            options.range,
+            Some((Fraction::new(1u64, 10u64), Fraction::new(2u64, 10u64)))
+        );
+        assert_eq!(options.num_orders, Some(10));
+        assert_eq!(options.token_amount.as_deref(), Some("1000"));
+        assert_eq!(options.token_id.as_deref(), Some("COMET"));
+        assert_eq!(options.grid_identity.as_deref(), Some("test"));
+        assert_eq!(options.exclude_boxes, vec!["aa", "bb"]);
+        assert!(options.split);
+
+        // Flags take precedence, also over the other amount options
+        let mut options = CreateOptions::try_parse_from([
+            "create", "-i", "test", "-r", "1-2", "-o", "4", "-v", "50",
+        ])
+        .unwrap();
+        options.apply_env(environment()).unwrap();
+
+        assert_eq!(
+            options.range,
+            Some((Fraction::from(1u64), Fraction::from(2u64)))
+        );
+        assert_eq!(options.num_orders, Some(4));
+        assert_eq!(options.total_value.as_deref(), Some("50"));
+        assert_eq!(options.token_amount, None);
+        assert_eq!(options.token_id.as_deref(), Some("SigUSD"));
+    }
+
+    #[test]
+    fn environment_conflicts_checked() {
+        let environment = |variables: &[(&str, &str)]| {
+            Environment::with_prefix(CREATE_ENV_PREFIX).source(Some(
+                variables
+                    .iter()
+                    .map(|(key, value)| (key.to_string(), value.to_string()))
+                    .collect(),
+            ))
+        };
+
+        // A grid definition in the environment does not replace the range given as flags
+        let mut options =
+            CreateOptions::try_parse_from(["create", "-t", "COMET", "-r", "1-2", "-o", "4"])
+                .unwrap();
+        options
+            .apply_env(environment(&[
+                ("OTG_GRID_FROM_QR", "definition"),
+                ("OTG_GRID_TOKEN_AMOUNT", "1000"),
+            ]))
+            .unwrap();
+        assert_eq!(options.from_qr, None);
+        assert_eq!(options.token_amount.as_deref(), Some("1000"));
+        assert_eq!(options.num_orders, Some(4));
+
+        // Nor does a pool conflicting with --no-auto-fill
+        let mut options = CreateOptions::try_parse_from(["create", "--no-auto-fill"]).unwrap();
+        options
+            .apply_env(environment(&[("OTG_GRID_POOL_NFT", "pool")]))
+            .unwrap();
+        assert_eq!(options.pool_nft, None);
+
+        // Conflicting variables are rejected like conflicting flags
+        let mut options = CreateOptions::try_parse_from(["create"]).unwrap();
+        let error = options
+            .apply_env(environment(&[
+                ("OTG_GRID_TOKEN_AMOUNT", "1000"),
+                ("OTG_GRID_TOTAL_VALUE", "50"),
+            ]))
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "--token-amount cannot be used with --total-value, given as flags or OTG_GRID_ variables"
+        );
+
+        let mut options = CreateOptions::try_parse_from(["create"]).unwrap();
+        assert!(options
+            .apply_env(environment(&[
+                ("OTG_GRID_FROM_QR", "definition"),
+                ("OTG_GRID_RANGE", "1-2"),
+            ]))
+            .is_err());
+    }
+
+    #[test]
+    fn pool_swap_price_impact() {
+        let token_id: TokenId = Digest32::from([3u8; 32]).into();
+        let pool = test_pool(1, token_id, 1_000_000_000_000, 1_000_000_000);
+
+        assert!(pool_price_impact(&pool, &pool).unwrap().is_none());
+
+        let input: Token = (token_id, 1_000_000.try_into().unwrap()).into();
+        let swapped = pool.clone().with_swap(&input).unwrap();
+
+        let impact = pool_price_impact(&pool, &swapped).unwrap().unwrap();
+        assert_eq!(impact, pool.price_impact(&input).unwrap());
+        assert!(impact > Fraction::new(3u64, 1000u64));
+    }
+
+    #[test]
+    fn creation_and_fill_fees_listed() {
+        let lines = fee_lines(1_500_000);
+
+        assert_eq!(lines.len(), 2);
+        assert_eq!(
+            lines[0],
+            "Creation fee: 0.0015 ERG paid once by this transaction"
+        );
+        assert_eq!(
+            lines[1],
+            "Fill fee: up to 0.002 ERG per fill, paid by the matcher from the surplus of the fill, not by the grid"
+        );
     }
 }
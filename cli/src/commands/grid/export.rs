@@ -0,0 +1,443 @@
+use std::path::PathBuf;
+
+use anyhow::anyhow;
+use clap::{Parser, ValueEnum};
+use ergo_lib::{
+    ergo_chain_types::{Digest32, EcPoint},
+    ergotree_ir::{
+        chain::token::TokenId,
+        mir::constant::{Constant, TryExtractInto},
+        serialization::SigmaSerializable,
+    },
+};
+use off_the_grid::{
+    boxes::tracked_box::TrackedBox,
+    grid::multigrid_order::{
+        EntryTuple, GridMetadata, GridOrderEntries, GridOrderEntry, MultiGridOrder,
+        MultiGridOrderError, OrderState,
+    },
+    node::client::NodeClient,
+    units::{token_id_hex, Price, TokenStore, UnitAmount, ERG_UNIT},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{commands::csv_field, scan_config::ScanConfig};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum ExportFormat {
+    /// Orders of a single grid, to recreate it with `grid create --from-qr`
+    #[default]
+    Definition,
+    /// One row per order of every grid, or of the grid given by `--grid-identity`
+    Csv,
+}
+
+#[derive(Parser)]
+pub struct ExportOptions {
+    #[clap(
+        short = 'i',
+        long,
+        help = "Grid group identity, required for the definition format"
+    )]
+    grid_identity: Option<String>,
+    #[clap(long, value_enum, default_value_t, help = "Format of the export")]
+    format: ExportFormat,
+    #[clap(long, help = "Write the export to a file instead of stdout")]
+    output: Option<PathBuf>,
+    #[clap(
+        long,
+        help = "Encode the entries in the register format of the grid contract instead of JSON"
+    )]
+    binary: bool,
+    #[cfg(feature = "qr")]
+    #[clap(long, help = "Render the grid definition as a QR code")]
+    qr: bool,
+}
+
+impl ExportOptions {
+    /// `--binary` and `--qr` encode a grid definition, they cannot be used with CSV
+    fn check_format(&self) -> anyhow::Result<()> {
+        #[cfg(feature = "qr")]
+        let encoded = self.binary || self.qr;
+        #[cfg(not(feature = "qr"))]
+        let encoded = self.binary;
+
+        if self.format == ExportFormat::Csv && encoded {
+            Err(anyhow!(
+                "--binary and --qr only apply to the definition format, not --format csv"
+            ))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Token amount, bid value and ask value of a grid entry. Serialized as an array to keep
+/// the encoded definition small enough for a QR code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GridDefinitionEntry(pub u64, pub u64, pub u64);
+
+/// Orders of a grid, independent of its owner and current fill state
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GridDefinition {
+    pub token_id: TokenId,
+    pub entries: Vec<GridDefinitionEntry>,
+}
+
+impl GridDefinition {
+    pub fn encode(&self) -> String {
+        serde_json::to_string(self).expect("Grid definition serialization cannot fail")
+    }
+
+    /// Decode a definition from either its JSON or binary encoding
+    pub fn decode(payload: &str) -> anyhow::Result<Self> {
+        let payload = payload.trim();
+
+        if payload.starts_with('{') {
+            serde_json::from_str(payload).map_err(|e| anyhow!("Invalid grid definition: {}", e))
+        } else {
+            Self::decode_binary(payload)
+        }
+    }
+
+    /// Hex encoding of the token ID followed by the entries serialized as the R5 register
+    /// of a grid order box. Entries are stored as buy orders.
+    pub fn encode_binary(&self) -> anyhow::Result<String> {
+        let entries: GridOrderEntries = self
+            .entries
+            .iter()
+            .map(|GridDefinitionEntry(token_amount, bid_value, ask_value)| {
+                Ok(GridOrderEntry::new(
+                    OrderState::Buy,
+                    (*token_amount).try_into()?,
+                    *bid_value,
+                    *ask_value,
+                ))
+            })
+            .collect::<Result<_, MultiGridOrderError>>()?;
+
+        let registers: Constant = entries.to_registers()?.into();
+
+        let mut bytes = Digest32::from(self.token_id).0.to_vec();
+        bytes.extend(registers.sigma_serialize_bytes()?);
+
+        Ok(base16::encode_lower(&bytes))
+    }
+
+    pub fn decode_binary(payload: &str) -> anyhow::Result<Self> {
+        let bytes = base16::decode(payload.trim())
+            .map_err(|e| anyhow!("Invalid grid definition: {}", e))?;
+
+        if bytes.len() < Digest32::SIZE {
+            return Err(anyhow!("Invalid grid definition: missing token ID"));
+        }
+
+        let (token_id, registers) = bytes.split_at(Digest32::SIZE);
+        let token_id: TokenId = Digest32::try_from(token_id.to_vec())?.into();
+
+        let registers: Vec<EntryTuple> = Constant::sigma_parse_bytes(registers)?
+            .try_extract_into()
+            .map_err(|e| anyhow!("Invalid grid definition: {}", e.0))?;
+
+        let entries = GridOrderEntries::from_registers(registers)?
+            .iter()
+            .map(|e| GridDefinitionEntry(*e.token_amount.as_u64(), e.bid_value, e.ask_value))
+            .collect();
+
+        Ok(Self { token_id, entries })
+    }
+
+    /// Create a new grid order from the definition. Every entry starts as a buy order,
+    /// the same state a newly created grid starts in.
+    pub fn into_order(
+        self,
+        owner_ec_point: EcPoint,
+        metadata: GridMetadata,
+        min_box_value: u64,
+    ) -> Result<MultiGridOrder, MultiGridOrderError> {
+        let entries = self
+            .entries
+            .into_iter()
+            .map(|GridDefinitionEntry(token_amount, bid_value, ask_value)| {
+                Ok(GridOrderEntry::new(
+                    OrderState::Buy,
+                    token_amount.try_into()?,
+                    bid_value,
+                    ask_value,
+                ))
+            })
+            .collect::<Result<_, MultiGridOrderError>>()?;
+
+        MultiGridOrder::new(
+            owner_ec_point,
+            self.token_id,
+            entries,
+            Some(metadata.to_bytes()),
+            min_box_value,
+        )
+    }
+}
+
+impl From<&MultiGridOrder> for GridDefinition {
+    fn from(order: &MultiGridOrder) -> Self {
+        let entries = order
+            .entries
+            .iter()
+            .map(|e| GridDefinitionEntry(*e.token_amount.as_u64(), e.bid_value, e.ask_value))
+            .collect();
+
+        Self {
+            token_id: order.token_id,
+            entries,
+        }
+    }
+}
+
+#[cfg(feature = "qr")]
+fn render_qr(payload: &str) -> anyhow::Result<String> {
+    use qrcode::{render::unicode::Dense1x2, QrCode};
+
+    let code = QrCode::new(payload.as_bytes())?;
+
+    Ok(code.render::<Dense1x2>().build())
+}
+
+const CSV_HEADER: [&str; 9] = [
+    "grid_identity",
+    "token_id",
+    "state",
+    "token_amount",
+    "bid_value",
+    "ask_value",
+    "bid_price",
+    "ask_price",
+    "box_value",
+];
+
+/// One row per entry of `orders`, after a header row. Amounts are in the units of the
+/// token store and prices in ERG per token.
+fn grid_csv<'a>(
+    orders: impl IntoIterator<Item = &'a MultiGridOrder>,
+    token_store: &TokenStore,
+) -> String {
+    let header = CSV_HEADER.join(",");
+
+    let rows = orders.into_iter().flat_map(|order| {
+        let unit = token_store.get_unit(&order.token_id);
+        let grid_identity = order
+            .identity()
+            .map(|identity| String::from_utf8_lossy(identity).into_owned())
+            .unwrap_or_default();
+        let token_id = token_id_hex(&order.token_id);
+        let box_value = UnitAmount::new(*ERG_UNIT, *order.value.as_u64());
+
+        order.entries.iter().map(move |entry| {
+            let state = match entry.state {
+                OrderState::Buy => "buy",
+                OrderState::Sell => "sell",
+            };
+
+            [
+                grid_identity.clone(),
+                token_id.clone(),
+                state.to_string(),
+                format!("{:#}", UnitAmount::new(unit, *entry.token_amount.as_u64())),
+                format!("{:#}", UnitAmount::new(*ERG_UNIT, entry.bid_value)),
+                format!("{:#}", UnitAmount::new(*ERG_UNIT, entry.ask_value)),
+                Price::new(unit, *ERG_UNIT, entry.bid()).to_string(),
+                Price::new(unit, *ERG_UNIT, entry.ask()).to_string(),
+                format!("{:#}", box_value),
+            ]
+            .iter()
+            .map(|field| csv_field(field))
+            .collect::<Vec<_>>()
+            .join(",")
+        })
+    });
+
+    std::iter::once(header)
+        .chain(rows)
+        .map(|line| line + "\n")
+        .collect()
+}
+
+pub async fn handle_grid_export(
+    node_client: NodeClient,
+    scan_config: ScanConfig,
+    token_store: &TokenStore,
+    options: ExportOptions,
+) -> anyhow::Result<()> {
+    options.check_format()?;
+
+    let grid_identity = options.grid_identity.map(String::into_bytes);
+
+    let grid_orders: Vec<TrackedBox<MultiGridOrder>> = node_client
+        .get_scan_unspent(scan_config.wallet_multigrid_scan_id)
+        .await?
+        .into_iter()
+        .filter_map(|b| b.try_into().ok())
+        .filter(|b: &TrackedBox<MultiGridOrder>| {
+            grid_identity
+                .as_deref()
+                .is_none_or(|identity| b.value.identity() == Some(identity))
+        })
+        .collect();
+
+    let payload = match options.format {
+        ExportFormat::Csv => grid_csv(grid_orders.iter().map(|b| &b.value), token_store),
+        ExportFormat::Definition => {
+            if grid_identity.is_none() {
+                return Err(anyhow!(
+                    "A grid identity is required to export a grid definition"
+                ));
+            }
+
+            let grid_order = grid_orders
+                .first()
+                .ok_or_else(|| anyhow!("Grid order not found"))?;
+
+            let definition = GridDefinition::from(&grid_order.value);
+
+            let payload = if options.binary {
+                definition.encode_binary()?
+            } else {
+                definition.encode()
+            };
+
+            #[cfg(feature = "qr")]
+            let payload = if options.qr {
+                render_qr(&payload)?
+            } else {
+                payload
+            };
+
+            payload + "\n"
+        }
+    };
+
+    match options.output {
+        Some(path) => std::fs::write(&path, payload)
+            .map_err(|e| anyhow!("Failed to write {}: {}", path.display(), e))?,
+        None => print!("{}", payload),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use ergo_lib::ergo_chain_types::{ec_point::generator, Digest32};
+    use off_the_grid::{
+        grid::multigrid_order::{
+            GridMetadata, GridOrderEntries, GridOrderEntry, OrderState, DEFAULT_MIN_BOX_VALUE,
+        },
+        units::{TokenInfo, TokenStore},
+    };
+
+    use clap::Parser;
+
+    use crate::commands::test_util::test_order;
+
+    use super::{grid_csv, ExportOptions, GridDefinition, GridDefinitionEntry};
+
+    #[test]
+    fn encodings_rejected_with_csv() {
+        let options =
+            |args: &[&str]| ExportOptions::try_parse_from(["export"].iter().chain(args)).unwrap();
+
+        assert!(options(&["--format", "csv"]).check_format().is_ok());
+        assert!(options(&["--binary"]).check_format().is_ok());
+        assert!(options(&["--format", "csv", "--binary"])
+            .check_format()
+            .is_err());
+    }
+
+    #[test]
+    fn definition_round_trip() {
+        let entries = GridOrderEntries::new(vec![
+            GridOrderEntry::new(OrderState::Sell, 10.try_into().unwrap(), 100, 110),
+            GridOrderEntry::new(OrderState::Buy, 12.try_into().unwrap(), 130, 140),
+        ]);
+
+        let order = test_order(Digest32::zero().into(), entries, Some(b"source".to_vec()));
+
+        let definition = GridDefinition::from(&order);
+        let decoded = GridDefinition::decode(&definition.encode()).unwrap();
+
+        assert_eq!(decoded, definition);
+
+        let imported = decoded
+            .into_order(
+                generator(),
+                GridMetadata::new("import", None),
+                DEFAULT_MIN_BOX_VALUE,
+            )
+            .unwrap();
+
+        assert_eq!(imported.token_id, order.token_id);
+
+        for (imported, source) in imported.entries.iter().zip(order.entries.iter()) {
+            assert_eq!(imported.token_amount, source.token_amount);
+            assert_eq!(imported.bid_value, source.bid_value);
+            assert_eq!(imported.ask_value, source.ask_value);
+            assert_eq!(imported.state, OrderState::Buy);
+        }
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let definition = GridDefinition {
+            token_id: Digest32::from([3u8; 32]).into(),
+            entries: vec![
+                GridDefinitionEntry(10, 100_000, 110_000),
+                GridDefinitionEntry(12, 130_000, 140_000),
+            ],
+        };
+
+        let binary = definition.encode_binary().unwrap();
+
+        assert!(binary.len() < definition.encode().len());
+        assert_eq!(GridDefinition::decode_binary(&binary).unwrap(), definition);
+        assert_eq!(GridDefinition::decode(&binary).unwrap(), definition);
+    }
+
+    #[test]
+    fn csv_rows_per_entry() {
+        let token_id = Digest32::from([1u8; 32]).into();
+        let token_store = TokenStore::with_tokens(vec![TokenInfo {
+            token_id,
+            name: "TKN".to_string(),
+            decimals: 1,
+        }]);
+        let header = "grid_identity,token_id,state,token_amount,bid_value,ask_value,bid_price,ask_price,box_value\n";
+
+        assert_eq!(grid_csv([], &token_store), header);
+
+        let entries = GridOrderEntries::new(vec![
+            GridOrderEntry::new(
+                OrderState::Sell,
+                10.try_into().unwrap(),
+                100_000_000,
+                110_000_000,
+            ),
+            GridOrderEntry::new(
+                OrderState::Buy,
+                20.try_into().unwrap(),
+                180_000_000,
+                200_000_000,
+            ),
+        ]);
+
+        let order = test_order(token_id, entries, Some(b"a,b".to_vec()));
+
+        let csv = grid_csv([&order], &token_store);
+        let lines: Vec<_> = csv.lines().collect();
+
+        // Amounts are in token and ERG units, prices in ERG per whole token
+        assert_eq!(lines.len(), 3);
+        assert_eq!(format!("{}\n", lines[0]), header);
+        assert!(lines[1].starts_with("\"a,b\",0101"));
+        assert!(lines[1].ends_with(",sell,1,0.1,0.11,0.1 TKN/ERG,0.11 TKN/ERG,0.181"));
+        assert!(lines[2].ends_with(",buy,2,0.18,0.2,0.09 TKN/ERG,0.1 TKN/ERG,0.181"));
+    }
+}
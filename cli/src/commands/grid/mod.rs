@@ -1,31 +1,48 @@
+mod batch;
+mod clone;
 mod create;
+mod export;
+mod plan;
 mod redeem;
 mod subcommands;
+mod trim;
+mod update;
 
-use std::io::Write;
+use std::{
+    collections::HashSet,
+    future::Future,
+    io::{BufRead, IsTerminal, Write},
+    process::ExitCode,
+};
 
-use clap::{Args, Subcommand};
+use anyhow::{anyhow, Context};
+use clap::{Args, Subcommand, ValueEnum};
 use colored::Colorize;
+use config::Environment;
 use ergo_lib::{
-    chain::transaction::{unsigned::UnsignedTransaction, TransactionError, UnsignedInput},
-    ergotree_ir::{
-        chain::ergo_box::{box_value::BoxValue, ErgoBoxCandidate, NonMandatoryRegisters},
-        serialization::SigmaParsingError,
+    chain::transaction::{unsigned::UnsignedTransaction, TransactionError, TxId, UnsignedInput},
+    ergo_chain_types::Digest32,
+    ergotree_ir::chain::{
+        ergo_box::{box_value::BoxValue, BoxId, ErgoBoxCandidate, NonMandatoryRegisters},
+        token::{Token, TokenAmount, TokenId},
     },
     wallet::{box_selector::ErgoBoxAssets, miner_fee::MINERS_FEE_ADDRESS},
 };
 use off_the_grid::{
     boxes::{
         describe_box::{BoxAssetDisplay, ErgoBoxDescriptors},
-        liquidity_box::LiquidityProvider,
+        min_box_value::check_min_box_value_for,
         wallet_box::WalletBox,
     },
     grid::multigrid_order::{MultiGridOrder, MultiGridOrderError},
     node::client::NodeClient,
     spectrum::pool::{SpectrumPool, SpectrumSwapError},
-    units::{TokenStore, UnitAmount, ERG_UNIT},
+    units::{AmountParser, Price, TokenLookupError, TokenStore, Unit, UnitAmount, ERG_UNIT},
 };
+use reqwest::Url;
+use serde::{Serialize, Serializer};
 use tabled::{
+    builder::Builder,
     row,
     settings::{
         object::{Columns, Rows},
@@ -33,122 +50,741 @@ use tabled::{
     },
     Table, Tabled,
 };
+use thiserror::Error;
 
-use crate::scan_config::ScanConfig;
+use crate::{
+    outbox::{Outbox, OutboxEntry},
+    profile::Profile,
+    scan_config::ScanConfig,
+};
 
 use self::{
-    create::{handle_grid_create, CreateOptions},
-    redeem::{handle_grid_redeem, RedeemOptions},
-    subcommands::{handle_grid_details, handle_grid_list},
+    batch::run_batch,
+    clone::{handle_grid_clone, CloneOptions},
+    create::{handle_grid_create, CreateOptions, CREATE_ENV_PREFIX},
+    export::{handle_grid_export, ExportOptions},
+    plan::{handle_grid_plan, PlanOptions},
+    redeem::{
+        balance_change_lines, handle_grid_redeem, total_proceeds, wait_for_balance_change,
+        RedeemOptions, WAIT_ATTEMPTS, WAIT_INTERVAL,
+    },
+    subcommands::{handle_grid_decode, handle_grid_details, handle_grid_list, handle_grid_verify},
+    trim::{handle_grid_trim, TrimOptions},
+    update::{handle_grid_update, UpdateOptions},
 };
 
-use super::error::CommandResult;
+use super::{
+    address_script,
+    error::CommandResult,
+    tokens::{explorer_url_from_str, update_token_store, DEFAULT_EXPLORER_URL},
+    AddressScriptError,
+};
 
 #[derive(Subcommand)]
+#[allow(clippy::large_enum_variant)]
 pub enum Commands {
+    #[command(flatten)]
+    Tracked(TrackedCommands),
+    /// Compute how many orders fit a range with the given spacing, without creating a grid
+    Plan(PlanOptions),
+}
+
+// Grid commands working on the grid orders and pools tracked by the scans. Not a doc
+// comment, clap would show it as the description of `grid`.
+#[derive(Subcommand)]
+#[allow(clippy::large_enum_variant)]
+pub enum TrackedCommands {
     Create(CreateOptions),
     Redeem(RedeemOptions),
+    /// Create a new grid with the same orders as an existing grid
+    Clone(CloneOptions),
     List {
         #[clap(short = 't', long, help = "TokenID to filter by")]
         token_id: Option<String>,
+        #[clap(short = 's', long, help = "Strategy tag to filter by")]
+        strategy: Option<String>,
+        #[clap(
+            short = 'q',
+            long,
+            help = "Unit to show the total value and profit in, converted using pool prices"
+        )]
+        quote: Option<String>,
+        #[clap(
+            long,
+            help = "Show the mark-to-market value, the ERG of the grid plus its tokens at the pool price"
+        )]
+        mtm: bool,
+        #[clap(
+            long,
+            help = "Show the ERG profit and total value in SigUSD, at the price of the deepest ERG/SigUSD pool"
+        )]
+        fiat: bool,
     },
     Details {
+        #[clap(
+            short = 'i',
+            long,
+            help = "Grid group identity",
+            required_unless_present = "box_id",
+            conflicts_with = "box_id"
+        )]
+        grid_identity: Option<String>,
+        #[clap(
+            long,
+            help = "Show the grid order in this box instead, without looking it up in the scans"
+        )]
+        box_id: Option<String>,
+        #[clap(
+            long,
+            help = "Show the trade volume needed to move the pool price to each order"
+        )]
+        fill_volume: bool,
+    },
+    /// Check that the grid order boxes hold the value and tokens expected from their orders
+    Verify {
         #[clap(short = 'i', long, help = "Grid group identity")]
         grid_identity: String,
     },
+    /// Print the orders of a grid, to recreate them with `grid create --from-qr`, or export
+    /// the orders of every grid as CSV
+    Export(ExportOptions),
+    /// Remove entries from a grid, returning their value and tokens to the wallet
+    Trim(TrimOptions),
+    /// Move the orders of a grid onto a new range in place, keeping their state and the
+    /// collected profit
+    Update(UpdateOptions),
+    /// Decode the registers of a grid order box as far as possible, to diagnose boxes that
+    /// fail to parse as a grid order
+    Decode {
+        #[clap(long, help = "ID of the box to decode")]
+        box_id: String,
+    },
+}
+
+impl TrackedCommands {
+    /// Name or ID of the token the command is run for, if any
+    fn target_token(&self) -> Option<&str> {
+        match self {
+            TrackedCommands::Create(options) => options.token_id(),
+            TrackedCommands::List { token_id, .. } => token_id.as_deref(),
+            _ => None,
+        }
+    }
+}
+
+/// Output format of transaction summaries and the grid list
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum TableFormat {
+    /// Aligned columns, colored in a terminal
+    #[default]
+    Plain,
+    /// GitHub-flavored Markdown tables without colors
+    Markdown,
+}
+
+/// Output of the grid list and details
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Human-readable lines or tables, see `--format`
+    #[default]
+    Text,
+    /// JSON with amounts in base units, for scripts
+    Json,
+}
+
+/// Orientation of the prices shown in the grid list and details
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum PriceOrientation {
+    /// Tokens per ERG, shown as ERG/<token>
+    #[default]
+    Indirect,
+    /// ERG per token, shown as <token>/ERG
+    Direct,
+}
+
+impl PriceOrientation {
+    /// `price` of a token in ERG, in this orientation
+    pub fn orient<'a>(self, price: &'a Price<'a>) -> Price<'a> {
+        match self {
+            PriceOrientation::Indirect => price.indirect(),
+            PriceOrientation::Direct => price.clone(),
+        }
+    }
 }
 
 #[derive(Args)]
 pub struct GridCommand {
-    #[clap(long, help = "Scan configuration file path [default: scan_config]")]
-    scan_config: Option<String>,
+    #[command(flatten)]
+    options: GridOptions,
 
     #[command(subcommand)]
     command: Commands,
 }
 
+// Options of the `TrackedCommands`, a plain comment for the same reason
+#[derive(Args)]
+struct GridOptions {
+    #[clap(long, help = "Scan configuration file path [default: scan_config]")]
+    scan_config: Option<String>,
+
+    #[clap(
+        long,
+        value_enum,
+        default_value_t,
+        help = "Format of transaction summaries and the grid list"
+    )]
+    format: TableFormat,
+
+    #[clap(
+        long,
+        value_enum,
+        default_value_t,
+        help = "Output of the grid list and details"
+    )]
+    output: OutputFormat,
+
+    #[clap(
+        long,
+        value_enum,
+        default_value_t,
+        help = "Orientation of prices in the grid list and details"
+    )]
+    orientation: PriceOrientation,
+
+    #[clap(
+        long,
+        help = "Update the token store before running the command if it is missing or the token is unknown"
+    )]
+    auto_update_tokens: bool,
+
+    #[clap(
+        long,
+        help = "Explorer API URL to update the token store from",
+        default_value = DEFAULT_EXPLORER_URL,
+        value_parser = explorer_url_from_str
+    )]
+    explorer_url: Url,
+
+    #[clap(
+        long,
+        help = "Sign the transaction and queue it in the outbox instead of submitting it"
+    )]
+    outbox: bool,
+
+    #[clap(
+        long,
+        conflicts_with = "outbox",
+        help = "Show the transaction without asking to submit it"
+    )]
+    dry_run: bool,
+
+    #[clap(
+        short = 'y',
+        long,
+        conflicts_with = "dry_run",
+        help = "Submit or queue the transaction without asking for confirmation"
+    )]
+    yes: bool,
+}
+
+/// Exit code of a run whose transaction was cancelled at the prompt
+pub const CANCELLED_EXIT_CODE: u8 = 3;
+
+/// How a transaction shown by [`transaction_query_loop`] was handled
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransactionOutcome {
+    Submitted(TxId),
+    /// Signed and queued in the outbox
+    Queued,
+    /// Declined at the prompt, or the input ended before an answer
+    Cancelled,
+    /// Only shown because of `--dry-run`
+    DryRun,
+}
+
+impl TransactionOutcome {
+    pub fn tx_id(&self) -> Option<TxId> {
+        match self {
+            TransactionOutcome::Submitted(tx_id) => Some(*tx_id),
+            _ => None,
+        }
+    }
+
+    /// Cancelled transactions exit with [`CANCELLED_EXIT_CODE`], so scripts can tell them
+    /// apart from dry runs and errors
+    pub fn exit_code(&self) -> ExitCode {
+        match self {
+            TransactionOutcome::Cancelled => ExitCode::from(CANCELLED_EXIT_CODE),
+            _ => ExitCode::SUCCESS,
+        }
+    }
+}
+
 pub async fn handle_grid_command(
     node_client: NodeClient,
+    profile: &Profile,
+    default_fee: &str,
     orders_command: GridCommand,
-) -> CommandResult<()> {
-    let scan_config = ScanConfig::try_create(orders_command.scan_config, None)?;
-    let token_store = TokenStore::load(None);
-    if token_store.is_err() {
+) -> CommandResult<Option<TransactionOutcome>> {
+    match orders_command.command {
+        Commands::Tracked(command) => {
+            handle_tracked_command(
+                node_client,
+                profile,
+                default_fee,
+                orders_command.options,
+                command,
+            )
+            .await
+        }
+        // Plans need neither the scans nor the token store
+        Commands::Plan(options) => {
+            handle_grid_plan(&node_client, default_fee, options).await?;
+            Ok(None)
+        }
+    }
+}
+
+async fn handle_tracked_command(
+    node_client: NodeClient,
+    profile: &Profile,
+    default_fee: &str,
+    grid_options: GridOptions,
+    mut command: TrackedCommands,
+) -> CommandResult<Option<TransactionOutcome>> {
+    if let TrackedCommands::Create(options) = &mut command {
+        options.apply_env(Environment::with_prefix(CREATE_ENV_PREFIX))?;
+    }
+
+    let scan_config = ScanConfig::try_create(profile, grid_options.scan_config, None)?;
+    let format = grid_options.format;
+    let output = grid_options.output;
+    let orientation = grid_options.orientation;
+    let token_store = TokenStore::load(Some(profile.token_store_path())).ok();
+
+    let token_store = if grid_options.auto_update_tokens
+        && needs_token_update(token_store.as_ref(), command.target_token())
+    {
+        let explorer_url = &grid_options.explorer_url;
+        Some(update_token_store(&node_client, profile, &scan_config, explorer_url).await?)
+    } else {
+        token_store
+    };
+
+    if token_store.is_none() {
         eprintln!("{}", "Warning: No token configuration found".yellow());
 
         eprintln!(
             "{}",
-            "Run `off-the-grid tokens update` to create one".yellow()
+            "Run `off-the-grid tokens update` or pass `--auto-update-tokens` to create one"
+                .yellow()
         );
     }
     let token_store = token_store.unwrap_or_default();
 
-    match orders_command.command {
-        Commands::Create(options) => {
-            let tx = handle_grid_create(&node_client, scan_config, &token_store, options).await?;
-            Ok(transaction_query_loop(&node_client, &token_store, tx).await?)
+    let stale_scans = scan_config.stale_scans();
+    if !stale_scans.is_empty() {
+        let warnings = stale_scan_warnings(stale_scans, |scan_id| {
+            let node_client = &node_client;
+            async move { Ok(node_client.get_scan_unspent(scan_id).await?.len()) }
+        })
+        .await?;
+
+        for warning in warnings {
+            eprintln!("{}", warning.yellow());
+        }
+    }
+
+    let outbox = grid_options
+        .outbox
+        .then(|| Outbox::new(profile.outbox_path()));
+    let confirmation = match (grid_options.dry_run, grid_options.yes) {
+        (true, _) => Confirmation::DryRun,
+        (false, true) => Confirmation::Yes,
+        (false, false) => Confirmation::Prompt,
+    };
+
+    match command {
+        TrackedCommands::Create(options) => {
+            let tx = handle_grid_create(
+                &node_client,
+                scan_config,
+                &token_store,
+                default_fee,
+                options,
+            )
+            .await?;
+            Ok(Some(
+                transaction_query_loop(
+                    &node_client,
+                    &token_store,
+                    tx,
+                    format,
+                    outbox.as_ref(),
+                    confirmation,
+                    "grid create",
+                )
+                .await?,
+            ))
+        }
+        TrackedCommands::Redeem(options) => {
+            let balance_before = if options.wait {
+                Some(node_client.wallet_balances().await?)
+            } else {
+                None
+            };
+
+            let redeem_txs = handle_grid_redeem(
+                &node_client,
+                scan_config,
+                &token_store,
+                default_fee,
+                options,
+            )
+            .await?;
+
+            let num_txs = redeem_txs.len();
+
+            let items = redeem_txs
+                .into_iter()
+                .enumerate()
+                .map(|(index, data)| {
+                    let label = format!("Redeem transaction {} of {}", index + 1, num_txs);
+                    Ok((label, (data.net_proceeds()?, data)))
+                })
+                .collect::<anyhow::Result<_>>()?;
+
+            // The redeem transactions spend disjoint orders, so one failing leaves the
+            // others valid
+            let report = run_batch(items, |label, (proceeds, data)| {
+                if num_txs > 1 {
+                    println!("{}", label);
+                }
+
+                let (node_client, token_store, outbox) = (&node_client, &token_store, &outbox);
+
+                async move {
+                    let outcome = transaction_query_loop(
+                        node_client,
+                        token_store,
+                        data,
+                        format,
+                        outbox.as_ref(),
+                        confirmation,
+                        "grid redeem",
+                    )
+                    .await?;
+                    Ok((outcome, proceeds))
+                }
+            })
+            .await;
+
+            if num_txs > 1 {
+                for line in report.summary_lines() {
+                    println!("{}", line);
+                }
+            }
+
+            let outcomes: Vec<TransactionOutcome> = report
+                .succeeded
+                .iter()
+                .map(|(_, (outcome, _))| *outcome)
+                .collect();
+            let last_tx_id = outcomes.iter().rev().find_map(|o| o.tx_id());
+            let last_outcome = outcomes.last().copied();
+
+            if let (Some(before), Some(_)) = (balance_before, &last_tx_id) {
+                println!("Waiting for the redeem to be confirmed");
+
+                let after = wait_for_balance_change(
+                    &before,
+                    || async { Ok(node_client.wallet_balances().await?) },
+                    WAIT_INTERVAL,
+                    WAIT_ATTEMPTS,
+                )
+                .await?;
+
+                let expected_proceeds = submitted_proceeds(&report.succeeded)?;
+                for line in balance_change_lines(&before, &after, &expected_proceeds, &token_store)
+                {
+                    println!("{}", line);
+                }
+            }
+
+            report.into_result("redeem transactions")?;
+
+            Ok(last_outcome)
+        }
+        TrackedCommands::Clone(options) => {
+            let tx = handle_grid_clone(&node_client, scan_config, default_fee, options).await?;
+            Ok(Some(
+                transaction_query_loop(
+                    &node_client,
+                    &token_store,
+                    tx,
+                    format,
+                    outbox.as_ref(),
+                    confirmation,
+                    "grid clone",
+                )
+                .await?,
+            ))
+        }
+        TrackedCommands::Update(options) => {
+            let data = handle_grid_update(
+                &node_client,
+                scan_config,
+                &token_store,
+                default_fee,
+                options,
+            )
+            .await?;
+            Ok(Some(
+                transaction_query_loop(
+                    &node_client,
+                    &token_store,
+                    data,
+                    format,
+                    outbox.as_ref(),
+                    confirmation,
+                    "grid update",
+                )
+                .await?,
+            ))
         }
-        Commands::Redeem(options) => {
-            let data = handle_grid_redeem(&node_client, scan_config, options).await?;
-            Ok(transaction_query_loop(&node_client, &token_store, data).await?)
+        TrackedCommands::Trim(options) => {
+            let data = handle_grid_trim(&node_client, scan_config, default_fee, options).await?;
+            Ok(Some(
+                transaction_query_loop(
+                    &node_client,
+                    &token_store,
+                    data,
+                    format,
+                    outbox.as_ref(),
+                    confirmation,
+                    "grid trim",
+                )
+                .await?,
+            ))
         }
-        Commands::List { token_id } => {
-            Ok(handle_grid_list(node_client, scan_config, token_id).await?)
+        TrackedCommands::List {
+            token_id,
+            strategy,
+            quote,
+            mtm,
+            fiat,
+        } => {
+            handle_grid_list(
+                node_client,
+                scan_config,
+                &token_store,
+                default_fee,
+                token_id,
+                strategy,
+                quote,
+                mtm,
+                fiat,
+                format,
+                output,
+                orientation,
+            )
+            .await?;
+            Ok(None)
         }
-        Commands::Details { grid_identity } => {
-            Ok(handle_grid_details(node_client, scan_config, grid_identity).await?)
+        TrackedCommands::Details {
+            grid_identity,
+            box_id,
+            fill_volume,
+        } => {
+            handle_grid_details(
+                node_client,
+                scan_config,
+                &token_store,
+                grid_identity,
+                box_id,
+                fill_volume,
+                output,
+                orientation,
+            )
+            .await?;
+            Ok(None)
+        }
+        TrackedCommands::Verify { grid_identity } => {
+            handle_grid_verify(node_client, scan_config, grid_identity).await?;
+            Ok(None)
+        }
+        TrackedCommands::Export(options) => {
+            handle_grid_export(node_client, scan_config, &token_store, options).await?;
+            Ok(None)
+        }
+        TrackedCommands::Decode { box_id } => {
+            handle_grid_decode(node_client, &token_store, box_id).await?;
+            Ok(None)
+        }
+    }
+}
+
+/// Whether the token store has to be updated before running a command for `target_token`,
+/// either because there is no token store yet or the token is not in it
+fn needs_token_update(token_store: Option<&TokenStore>, target_token: Option<&str>) -> bool {
+    match (token_store, target_token) {
+        (None, _) => true,
+        (Some(token_store), Some(token)) => !matches!(
+            token_store.get_unit_by_id(token),
+            Ok(Unit::Known(_)) | Err(TokenLookupError::Ambiguous(..))
+        ),
+        (Some(_), None) => false,
+    }
+}
+
+/// Warnings for the `stale_scans` that find no boxes, as counted by `count_boxes`. Such
+/// scans were created for an older version of the script and track nothing, so they have
+/// to be recreated.
+async fn stale_scan_warnings<F, Fut>(
+    stale_scans: Vec<(&str, i32)>,
+    count_boxes: F,
+) -> anyhow::Result<Vec<String>>
+where
+    F: Fn(i32) -> Fut,
+    Fut: Future<Output = anyhow::Result<usize>>,
+{
+    let mut warnings = Vec::new();
+
+    for (name, scan_id) in stale_scans {
+        if count_boxes(scan_id).await? == 0 {
+            warnings.push(format!(
+                "Warning: Scan {} was created for a different version of the {} script and finds no boxes",
+                scan_id, name
+            ));
         }
     }
+
+    if !warnings.is_empty() {
+        warnings.push("Run `off-the-grid scans create-config` to recreate the scans".to_string());
+    }
+
+    Ok(warnings)
+}
+
+/// How a transaction is confirmed before it is signed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Confirmation {
+    /// Ask on the terminal
+    Prompt,
+    /// Go ahead without asking, with `--yes`
+    Yes,
+    /// Only show the transaction, with `--dry-run`
+    DryRun,
 }
 
+/// Ask on `output` whether to go ahead with a transaction, reading the answer from `input`.
+/// Returns the outcome if the transaction is not to be signed. Nothing is asked for a dry
+/// run or with `--yes`, and asking fails if `input` is not `interactive`, since a script
+/// could otherwise wait for an answer forever.
+fn query_transaction<R, W>(
+    confirmation: Confirmation,
+    queue: bool,
+    interactive: bool,
+    input: &mut R,
+    output: &mut W,
+) -> anyhow::Result<Option<TransactionOutcome>>
+where
+    R: BufRead,
+    W: Write,
+{
+    match confirmation {
+        Confirmation::DryRun => {
+            writeln!(output, "Dry run, transaction not submitted")?;
+            return Ok(Some(TransactionOutcome::DryRun));
+        }
+        Confirmation::Yes => return Ok(None),
+        Confirmation::Prompt if !interactive => {
+            return Err(anyhow!(
+                "Cannot ask for confirmation, stdin is not a terminal. Pass `--yes` to go ahead without asking or `--dry-run` to only show the transaction"
+            ))
+        }
+        Confirmation::Prompt => {}
+    }
+
+    let mut line = String::new();
+
+    loop {
+        if queue {
+            write!(output, "Queue transaction? [Y/n] ")?;
+        } else {
+            write!(output, "Submit transaction? [Y/n] ")?;
+        }
+        output.flush()?;
+
+        line.clear();
+        if input.read_line(&mut line)? == 0 {
+            writeln!(output, "\nNo answer, transaction cancelled!")?;
+            return Ok(Some(TransactionOutcome::Cancelled));
+        }
+
+        match line.trim() {
+            "Y" => return Ok(None),
+            "n" => {
+                writeln!(output, "Transaction cancelled!")?;
+                return Ok(Some(TransactionOutcome::Cancelled));
+            }
+            _ => writeln!(output, "Invalid input, please try again")?,
+        }
+    }
+}
+
+/// Show the transaction and submit it once confirmed. With an `outbox` the signed
+/// transaction is queued under the name of `command` instead.
 async fn transaction_query_loop<T>(
     node_client: &NodeClient,
     token_store: &TokenStore,
     tx_data: T,
-) -> anyhow::Result<()>
+    format: TableFormat,
+    outbox: Option<&Outbox>,
+    confirmation: Confirmation,
+    command: &str,
+) -> anyhow::Result<TransactionOutcome>
 where
     T: IntoSummarizedTransaction,
     T::Error: std::error::Error + Send + Sync + 'static,
 {
     let tx = tx_data.into_summarized_transaction(token_store)?;
-    let stdin = std::io::stdin();
-    let mut stdout = std::io::stdout();
-
-    let mut line = String::new();
+    let parameters = node_client.blockchain_parameters().await?;
+    tx.check_min_box_values(parameters.min_value_per_byte)?;
 
-    let table: Table = (&tx).into();
+    let table = tx.table(format);
 
     println!("{}\n", table);
 
-    loop {
-        print!("Submit transaction? [Y/n] ");
+    let stdin = std::io::stdin();
 
-        stdout.flush()?;
-        stdin.read_line(&mut line)?;
+    let outcome = query_transaction(
+        confirmation,
+        outbox.is_some(),
+        stdin.is_terminal(),
+        &mut stdin.lock(),
+        &mut std::io::stdout(),
+    )?;
 
-        match line.trim() {
-            "Y" => {
-                let tx = tx.try_into()?;
+    if let Some(outcome) = outcome {
+        return Ok(outcome);
+    }
 
-                let signed = node_client.wallet_transaction_sign(&tx).await?;
+    let tx = tx.try_into()?;
 
-                let tx_id = node_client.transaction_submit(&signed).await?;
-                println!("Transaction submitted: {}", String::from(tx_id));
+    let signed = node_client.wallet_transaction_sign(&tx).await?;
 
-                break;
-            }
-            "n" => {
-                println!("Transaction cancelled!");
-                break;
-            }
-            _ => {
-                println!("Invalid input, please try again");
-                line.clear();
-            }
-        }
+    if let Some(outbox) = outbox {
+        let path = outbox.push(&OutboxEntry::new(command, signed))?;
+        println!("Transaction queued: {}", path.display());
+
+        return Ok(TransactionOutcome::Queued);
     }
 
-    Ok(())
+    let tx_id = node_client.transaction_submit(&signed).await?;
+    println!("Transaction submitted: {}", String::from(tx_id));
+
+    Ok(TransactionOutcome::Submitted(tx_id))
 }
 
 pub trait TryIntoErgoBoxCandidate {
@@ -162,7 +798,7 @@ impl<T> TryIntoErgoBoxCandidate for WalletBox<T>
 where
     T: ErgoBoxAssets,
 {
-    type Error = SigmaParsingError;
+    type Error = AddressScriptError;
 
     fn into_ergo_box_candidate(
         self,
@@ -170,7 +806,7 @@ where
     ) -> Result<ErgoBoxCandidate, Self::Error> {
         Ok(ErgoBoxCandidate {
             value: self.assets.value(),
-            ergo_tree: self.address.script()?,
+            ergo_tree: address_script(&self.address)?,
             tokens: self.assets.tokens(),
             additional_registers: NonMandatoryRegisters::empty(),
             creation_height,
@@ -200,13 +836,14 @@ impl TryIntoErgoBoxCandidate for SpectrumPool {
     }
 }
 
-#[derive(Tabled)]
+#[derive(Tabled, Serialize)]
 struct BoxSummary {
     #[tabled(rename = "Box type")]
     box_type: String,
     #[tabled(rename = "Value")]
     value: String,
     #[tabled(rename = "Tokens")]
+    #[serde(rename = "tokens")]
     token: String,
 }
 
@@ -221,8 +858,18 @@ impl BoxSummary {
     }
 }
 
+fn serialize_input_box_id<S>(input: &UnsignedInput, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    input.box_id.serialize(serializer)
+}
+
+#[derive(Serialize)]
 pub struct SummarizedInput {
+    #[serde(flatten)]
     summary: BoxSummary,
+    #[serde(rename = "box_id", serialize_with = "serialize_input_box_id")]
     input: UnsignedInput,
 }
 
@@ -237,8 +884,11 @@ impl SummarizedInput {
     }
 }
 
+#[derive(Serialize)]
 pub struct SummarizedOutput {
+    #[serde(skip)]
     output: ErgoBoxCandidate,
+    #[serde(flatten)]
     summary: BoxSummary,
 }
 
@@ -267,11 +917,51 @@ where
 
 /// A transaction with inputs and outputs that also contain a summary of the
 /// invididual inputs and outputs.
+/// Serializes to the summaries only, so the planned transaction can be logged
+/// before it is signed.
+#[derive(Serialize)]
 pub(super) struct SummarizedTransaction {
     pub inputs: Vec<SummarizedInput>,
     pub outputs: Vec<SummarizedOutput>,
 }
 
+impl SummarizedTransaction {
+    /// Ensure every output holds at least the minimum value accepted by the node, so
+    /// the transaction is rejected locally instead of on submission.
+    pub fn check_min_box_values(&self, min_value_per_byte: u64) -> anyhow::Result<()> {
+        for (index, output) in self.outputs.iter().enumerate() {
+            check_min_box_value_for(&output.output, min_value_per_byte)
+                .with_context(|| format!("Output {} ({})", index, output.summary.box_type))?;
+        }
+
+        Ok(())
+    }
+
+    pub fn table(&self, format: TableFormat) -> Table {
+        match format {
+            TableFormat::Plain => self.into(),
+            TableFormat::Markdown => {
+                let mut builder = Builder::default();
+                builder.set_header(["", "Box type", "Value", "Tokens"]);
+
+                let inputs = self.inputs.iter().map(|i| ("Input", &i.summary));
+                let outputs = self.outputs.iter().map(|o| ("Output", &o.summary));
+
+                for (side, summary) in inputs.chain(outputs) {
+                    builder.push_record([side, &summary.box_type, &summary.value, &summary.token]);
+                }
+
+                let mut table = builder.build();
+                table.with(Style::markdown());
+                table
+            }
+        }
+    }
+}
+
+/// Transaction data that can be summarized and submitted. Outputs are ordered as the
+/// liquidity box (if any), the grid order, the change boxes and the miner fee last. Change
+/// tokens are sorted by token ID, so the same inputs always give the same transaction.
 trait IntoSummarizedTransaction {
     type Error;
 
@@ -299,8 +989,32 @@ impl From<&'_ SummarizedTransaction> for Table {
     }
 }
 
+#[derive(Error, Debug)]
+pub enum AssembleTransactionError {
+    #[error("Box {} is spent by more than one input", String::from(*.0))]
+    DuplicateInput(BoxId),
+    #[error(transparent)]
+    Transaction(#[from] TransactionError),
+}
+
+/// Unsigned transaction spending `inputs`. Fails if a box is spent twice, e.g. a liquidity
+/// box that was also selected from the wallet boxes, which the node would only reject
+/// with an opaque error.
+fn assemble_transaction(
+    inputs: Vec<UnsignedInput>,
+    outputs: Vec<ErgoBoxCandidate>,
+) -> Result<UnsignedTransaction, AssembleTransactionError> {
+    let mut box_ids = HashSet::new();
+
+    if let Some(input) = inputs.iter().find(|input| !box_ids.insert(input.box_id)) {
+        return Err(AssembleTransactionError::DuplicateInput(input.box_id));
+    }
+
+    Ok(UnsignedTransaction::new_from_vec(inputs, vec![], outputs)?)
+}
+
 impl TryFrom<SummarizedTransaction> for UnsignedTransaction {
-    type Error = TransactionError;
+    type Error = AssembleTransactionError;
 
     fn try_from(value: SummarizedTransaction) -> Result<Self, Self::Error> {
         let inputs = value.inputs.into_iter().map(|input| input.input).collect();
@@ -311,10 +1025,43 @@ impl TryFrom<SummarizedTransaction> for UnsignedTransaction {
             .map(|output| output.output)
             .collect();
 
-        UnsignedTransaction::new_from_vec(inputs, vec![], outputs)
+        assemble_transaction(inputs, outputs)
     }
 }
 
+/// Fee value given on the command line, or the configured default if none was given
+fn resolve_fee(fee: Option<&str>, default_fee: &str) -> anyhow::Result<BoxValue> {
+    let fee = fee.unwrap_or(default_fee);
+
+    let fee_amount = AmountParser::new(*ERG_UNIT, "fee").parse(fee)?;
+
+    Ok(fee_amount.amount().try_into()?)
+}
+
+/// Outcome of a redeem transaction with its net proceeds
+type RedeemResult = (TransactionOutcome, (u64, Vec<Token>));
+
+/// Net proceeds of the redeem transactions that were submitted. Failed, cancelled, dry run or
+/// queued transactions do not change the wallet balance.
+fn submitted_proceeds(succeeded: &[(String, RedeemResult)]) -> anyhow::Result<(u64, Vec<Token>)> {
+    total_proceeds(
+        succeeded
+            .iter()
+            .filter(|(_, (outcome, _))| outcome.tx_id().is_some())
+            .map(|(_, (_, proceeds))| proceeds),
+    )
+}
+
+/// Tokens sorted by their ID, independent of the order they were collected in
+fn sorted_tokens<I>(tokens: I) -> Vec<Token>
+where
+    I: IntoIterator<Item = (TokenId, TokenAmount)>,
+{
+    let mut tokens: Vec<Token> = tokens.into_iter().map(Token::from).collect();
+    tokens.sort_by_key(|t| Digest32::from(t.token_id));
+    tokens
+}
+
 /// Wrapper over a box value to describe it as a miner fee
 struct MinerFeeValue(pub BoxValue);
 
@@ -347,3 +1094,365 @@ impl TryIntoErgoBoxCandidate for MinerFeeValue {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use crate::{
+        node_config::NodeConfig,
+        profile::Profile,
+        scan_config::{embedded_fingerprints, ScanConfig},
+    };
+
+    use ergo_lib::{
+        chain::transaction::{unsigned::UnsignedTransaction, TxId},
+        ergo_chain_types::{ec_point::generator, Digest32},
+        ergotree_ir::{
+            chain::{
+                address::{Address, AddressEncoder, NetworkPrefix},
+                ergo_box::{ErgoBox, ErgoBoxCandidate, NonMandatoryRegisters},
+                token::TokenId,
+            },
+            sigma_protocol::sigma_boolean::ProveDlog,
+        },
+        wallet::box_selector::ErgoBoxAssetsData,
+    };
+    use off_the_grid::{
+        boxes::wallet_box::WalletBox,
+        units::{TokenInfo, TokenStore, Unit},
+    };
+
+    use crate::commands::tokens::merge_fetched_tokens;
+
+    use super::{
+        needs_token_update, query_transaction, resolve_fee, stale_scan_warnings,
+        submitted_proceeds, AssembleTransactionError, Confirmation, MinerFeeValue, SummarizedInput,
+        SummarizedOutput, SummarizedTransaction, TableFormat, TransactionOutcome,
+        TryIntoErgoBoxCandidate,
+    };
+
+    #[test]
+    fn only_submitted_redeems_expected() {
+        let token_id: TokenId = Digest32::from([1u8; 32]).into();
+        let tokens = |amount: u64| vec![(token_id, amount.try_into().unwrap()).into()];
+
+        let succeeded = vec![
+            (
+                "Redeem transaction 1 of 3".to_string(),
+                (
+                    TransactionOutcome::Submitted(TxId::zero()),
+                    (1_000_000, tokens(10)),
+                ),
+            ),
+            (
+                "Redeem transaction 2 of 3".to_string(),
+                (TransactionOutcome::DryRun, (2_000_000, tokens(20))),
+            ),
+            (
+                "Redeem transaction 3 of 3".to_string(),
+                (
+                    TransactionOutcome::Submitted(TxId::zero()),
+                    (4_000_000, tokens(40)),
+                ),
+            ),
+        ];
+
+        assert_eq!(
+            submitted_proceeds(&succeeded).unwrap(),
+            (5_000_000, tokens(50))
+        );
+        assert_eq!(submitted_proceeds(&succeeded[1..2]).unwrap(), (0, vec![]));
+    }
+
+    #[test]
+    fn markdown_summary() {
+        let token_store = TokenStore::default();
+        let address = Address::P2Pk(ProveDlog::new(generator()));
+
+        let wallet_box = ErgoBox::new(
+            10_000_000u64.try_into().unwrap(),
+            address.script().unwrap(),
+            None,
+            NonMandatoryRegisters::empty(),
+            0,
+            TxId::zero(),
+            0,
+        )
+        .unwrap();
+
+        let tx = SummarizedTransaction {
+            inputs: vec![SummarizedInput::new(
+                WalletBox::new(wallet_box, address),
+                &token_store,
+            )],
+            outputs: vec![SummarizedOutput::new(
+                MinerFeeValue(1_000_000u64.try_into().unwrap()),
+                &token_store,
+                0,
+            )
+            .unwrap()],
+        };
+
+        let markdown = tx.table(TableFormat::Markdown).to_string();
+        let lines: Vec<_> = markdown.lines().collect();
+
+        assert_eq!(lines.len(), 4);
+        assert!(lines.iter().all(|l| l.starts_with('|') && l.ends_with('|')));
+        assert!(lines[0].contains("Box type"));
+        assert!(lines[2].contains("Input") && lines[2].contains("Wallet"));
+        assert!(lines[3].contains("Output") && lines[3].contains("Miner fee"));
+        assert!(!markdown.contains('\x1b'));
+    }
+
+    #[test]
+    fn duplicate_input_rejected() {
+        let token_store = TokenStore::default();
+        let address = Address::P2Pk(ProveDlog::new(generator()));
+
+        let wallet_box = ErgoBox::new(
+            10_000_000u64.try_into().unwrap(),
+            address.script().unwrap(),
+            None,
+            NonMandatoryRegisters::empty(),
+            0,
+            TxId::zero(),
+            0,
+        )
+        .unwrap();
+
+        let input = || {
+            SummarizedInput::new(
+                WalletBox::new(wallet_box.clone(), address.clone()),
+                &token_store,
+            )
+        };
+        let fee = || {
+            SummarizedOutput::new(
+                MinerFeeValue(1_000_000u64.try_into().unwrap()),
+                &token_store,
+                0,
+            )
+            .unwrap()
+        };
+
+        let tx = SummarizedTransaction {
+            inputs: vec![input()],
+            outputs: vec![fee()],
+        };
+        assert!(UnsignedTransaction::try_from(tx).is_ok());
+
+        let tx = SummarizedTransaction {
+            inputs: vec![input(), input()],
+            outputs: vec![fee()],
+        };
+
+        match UnsignedTransaction::try_from(tx) {
+            Err(AssembleTransactionError::DuplicateInput(box_id)) => {
+                assert_eq!(box_id, wallet_box.box_id())
+            }
+            _ => panic!("Duplicate input should be rejected"),
+        }
+    }
+
+    #[test]
+    fn configured_default_fee() {
+        let profile = Profile::default();
+
+        let default_config =
+            NodeConfig::try_create(&profile, None, None, Some("key".into()), None).unwrap();
+        assert_eq!(
+            *resolve_fee(None, &default_config.default_fee)
+                .unwrap()
+                .as_u64(),
+            1_000_000
+        );
+
+        let config_path = std::env::temp_dir().join(format!("node_config-{}.json", Uuid::new_v4()));
+        std::fs::write(
+            &config_path,
+            r#"{"api_key": "key", "default_fee": "0.002"}"#,
+        )
+        .unwrap();
+
+        let config = NodeConfig::try_create(
+            &profile,
+            Some(config_path.display().to_string()),
+            None,
+            None,
+            None,
+        )
+        .unwrap();
+        std::fs::remove_file(config_path).unwrap();
+
+        assert_eq!(
+            *resolve_fee(None, &config.default_fee).unwrap().as_u64(),
+            2_000_000
+        );
+        assert_eq!(
+            *resolve_fee(Some("0.005"), &config.default_fee)
+                .unwrap()
+                .as_u64(),
+            5_000_000
+        );
+        assert!(resolve_fee(Some("fast"), &config.default_fee).is_err());
+    }
+
+    #[tokio::test]
+    async fn unknown_token_updated() {
+        let token_id: TokenId = Digest32::from([7u8; 32]).into();
+        let token_hex = String::from(token_id);
+
+        let token_store = TokenStore::default();
+
+        assert!(needs_token_update(None, None));
+        assert!(!needs_token_update(Some(&token_store), None));
+        assert!(needs_token_update(Some(&token_store), Some(&token_hex)));
+
+        let token_store =
+            merge_fetched_tokens(token_store, [token_id].into(), |token_id| async move {
+                Some(TokenInfo {
+                    token_id,
+                    name: "TKN".to_string(),
+                    decimals: 2,
+                })
+            })
+            .await;
+
+        assert!(matches!(token_store.get_unit(&token_id), Unit::Known(_)));
+        assert!(!needs_token_update(Some(&token_store), Some(&token_hex)));
+        assert!(!needs_token_update(Some(&token_store), Some("TKN")));
+    }
+
+    #[test]
+    fn cancelled_and_dry_run_distinguished() {
+        let query = |confirmation: Confirmation, input: &str| {
+            let mut output = Vec::new();
+            let outcome = query_transaction(
+                confirmation,
+                false,
+                true,
+                &mut input.as_bytes(),
+                &mut output,
+            )
+            .unwrap();
+            (outcome, String::from_utf8(output).unwrap())
+        };
+
+        let (outcome, output) = query(Confirmation::DryRun, "Y\n");
+        assert_eq!(outcome, Some(TransactionOutcome::DryRun));
+        assert!(!output.contains("Submit transaction?"));
+
+        let (outcome, output) = query(Confirmation::Prompt, "maybe\nn\n");
+        assert_eq!(outcome, Some(TransactionOutcome::Cancelled));
+        assert!(output.contains("Invalid input"));
+
+        let (outcome, _) = query(Confirmation::Prompt, "");
+        assert_eq!(outcome, Some(TransactionOutcome::Cancelled));
+
+        let (outcome, _) = query(Confirmation::Prompt, "Y\n");
+        assert_eq!(outcome, None);
+
+        assert_ne!(
+            TransactionOutcome::Cancelled.exit_code(),
+            TransactionOutcome::DryRun.exit_code()
+        );
+        assert_eq!(
+            TransactionOutcome::DryRun.exit_code(),
+            TransactionOutcome::Submitted(TxId::zero()).exit_code()
+        );
+    }
+
+    #[test]
+    fn prompt_skipped_or_refused_without_terminal() {
+        let query = |confirmation: Confirmation| {
+            let mut output = Vec::new();
+            let outcome = query_transaction(
+                confirmation,
+                true,
+                false,
+                &mut "n\n".as_bytes(),
+                &mut output,
+            );
+            (outcome, String::from_utf8(output).unwrap())
+        };
+
+        // `--yes` goes ahead without reading an answer
+        let (outcome, output) = query(Confirmation::Yes);
+        assert_eq!(outcome.unwrap(), None);
+        assert!(output.is_empty());
+
+        let (outcome, output) = query(Confirmation::Prompt);
+        assert!(outcome.unwrap_err().to_string().contains("`--yes`"));
+        assert!(!output.contains("Queue transaction?"));
+
+        let (outcome, _) = query(Confirmation::DryRun);
+        assert_eq!(outcome.unwrap(), Some(TransactionOutcome::DryRun));
+    }
+
+    #[tokio::test]
+    async fn stale_scan_config_hinted() {
+        let mut scan_config = ScanConfig {
+            n2t_scan_id: 1,
+            t2t_scan_id: None,
+            wallet_multigrid_scan_id: 2,
+            multigrid_scan_id: 3,
+            script_fingerprints: embedded_fingerprints(),
+        };
+
+        assert!(scan_config.stale_scans().is_empty());
+
+        scan_config
+            .script_fingerprints
+            .insert("multigrid".to_string(), "00".repeat(32));
+
+        let stale_scans = scan_config.stale_scans();
+        assert_eq!(stale_scans, vec![("multigrid", 3)]);
+
+        let warnings = stale_scan_warnings(stale_scans.clone(), |_| async { Ok(0) })
+            .await
+            .unwrap();
+
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings[0].contains("Scan 3"));
+        assert!(warnings[1].contains("scans create-config"));
+
+        let warnings = stale_scan_warnings(stale_scans, |_| async { Ok(4) })
+            .await
+            .unwrap();
+
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn malformed_change_address_named() {
+        // Header announcing a tree size that is missing
+        let address = Address::P2S(vec![0x08]);
+        let encoded = AddressEncoder::encode_address_as_string(NetworkPrefix::Mainnet, &address);
+
+        let change_box = WalletBox::new(
+            ErgoBoxAssetsData {
+                value: 1_000_000u64.try_into().unwrap(),
+                tokens: None,
+            },
+            address,
+        );
+
+        let error = change_box.into_ergo_box_candidate(0).unwrap_err();
+
+        assert_eq!(error.address, encoded);
+        assert!(error
+            .to_string()
+            .starts_with(&format!("Invalid script for address {}: ", encoded)));
+
+        let valid: Result<ErgoBoxCandidate, _> = WalletBox::new(
+            ErgoBoxAssetsData {
+                value: 1_000_000u64.try_into().unwrap(),
+                tokens: None,
+            },
+            Address::P2Pk(ProveDlog::new(generator())),
+        )
+        .into_ergo_box_candidate(0);
+        assert!(valid.is_ok());
+    }
+}
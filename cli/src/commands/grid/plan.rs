@@ -0,0 +1,329 @@
+use clap::Parser;
+use fraction::ToPrimitive;
+use off_the_grid::{
+    node::client::NodeClient,
+    units::{AmountParser, Fraction, Price, UnitAmount, ERG_UNIT},
+};
+use thiserror::Error;
+
+use super::{
+    create::{grid_min_box_value, grid_order_range_from_str, GridPriceRange, GridSpacing},
+    resolve_fee,
+};
+
+#[derive(Parser)]
+pub struct PlanOptions {
+    #[clap(short = 'v', long, help = "Total value of the grid")]
+    total_value: String,
+    #[clap(
+        short = 'r',
+        long,
+        help = "Range of the grid, in the form start-stop",
+        value_parser = grid_order_range_from_str
+    )]
+    range: (Fraction, Fraction),
+    #[clap(
+        short = 's',
+        long,
+        help = "Least spacing between the bid and ask of every order, in percent",
+        value_parser = spacing_percent_from_str
+    )]
+    spacing_percent: Fraction,
+    #[clap(
+        long,
+        value_enum,
+        help = "Spacing of the order prices across the range, as in `grid create` [default: linear]"
+    )]
+    spacing: Option<GridSpacing>,
+    #[clap(
+        short,
+        long,
+        help = "Fee of the transaction creating the grid [default: default_fee of the node config]"
+    )]
+    fee: Option<String>,
+    #[clap(
+        long,
+        help = "Base value of the grid order box [default: derived from the node's protocol parameters]"
+    )]
+    min_box_value: Option<String>,
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum GridPlanError {
+    #[error("The range is too narrow for a single order with {0}% spacing")]
+    SpacingTooWide(Fraction),
+    #[error("A total value of {0} nanoERG cannot be split into {1} orders")]
+    ValueTooSmall(u64, u64),
+}
+
+/// Orders fitting a grid range, as computed by `grid plan`
+#[derive(Debug, PartialEq, Eq)]
+struct GridPlanSummary {
+    num_orders: u64,
+    /// Value of each order in nanoERG
+    order_value: u64,
+    /// Value of the orders, the base value of the grid box and the creation fee
+    total_required: u64,
+    /// Profit in nanoERG once every order has been bought and sold
+    profit_per_cycle: u64,
+    /// Base value of the grid box in nanoERG
+    min_box_value: u64,
+}
+
+fn spacing_percent_from_str(s: &str) -> Result<Fraction, String> {
+    let spacing: Fraction = s
+        .trim()
+        .parse()
+        .map_err(|_| format!("Invalid spacing {}", s))?;
+
+    if spacing.is_nan() || spacing.is_infinite() || spacing <= Fraction::from(0u64) {
+        return Err(format!(
+            "Invalid spacing {}: must be a positive percentage",
+            s
+        ));
+    }
+
+    Ok(spacing)
+}
+
+/// Most orders the range fits with every order spaced at least `spacing` apart, as a
+/// fraction of the bid. With linear spacing the top order has the least relative spacing:
+/// (stop - start) / n / (stop - (stop - start) / n). With geometric spacing every order has
+/// the ratio (stop / start)^(1 / n), which is computed in floating point like the levels.
+fn max_orders(range: (Fraction, Fraction), spacing: Fraction, grid_spacing: GridSpacing) -> u64 {
+    let (start, stop) = range;
+
+    match grid_spacing {
+        GridSpacing::Linear => ((stop - start) * (Fraction::from(1u64) + spacing)
+            / (spacing * stop))
+            .floor()
+            .to_u64()
+            .unwrap_or_default(),
+        GridSpacing::Geometric => {
+            let ratio = (stop / start).to_f64().unwrap_or(1.0);
+            let step = (Fraction::from(1u64) + spacing)
+                .to_f64()
+                .unwrap_or(f64::INFINITY);
+
+            // Tolerate rounding when the range fits a whole number of orders exactly
+            (ratio.ln() / step.ln() + 1e-9).floor() as u64
+        }
+    }
+}
+
+/// Orders fitting `range` with at least `spacing_percent` between the bid and ask of every
+/// order. The total includes the base value of the grid box, `min_box_value`, like in
+/// `grid create`.
+fn plan_orders(
+    total_value: u64,
+    range: (Fraction, Fraction),
+    spacing_percent: Fraction,
+    grid_spacing: GridSpacing,
+    fee: u64,
+    min_box_value: u64,
+) -> Result<GridPlanSummary, GridPlanError> {
+    let (start, stop) = range;
+    let spacing = spacing_percent / Fraction::from(100u64);
+
+    let num_orders = max_orders(range, spacing, grid_spacing);
+
+    if num_orders == 0 {
+        return Err(GridPlanError::SpacingTooWide(spacing_percent));
+    }
+
+    let order_value = total_value / num_orders;
+
+    if order_value == 0 {
+        return Err(GridPlanError::ValueTooSmall(total_value, num_orders));
+    }
+
+    // Only the ratio of the bid and ask of each order is used, so the units do not matter
+    let grid_range = GridPriceRange::new(
+        Price::new(*ERG_UNIT, *ERG_UNIT, start),
+        Price::new(*ERG_UNIT, *ERG_UNIT, stop),
+        num_orders,
+    )
+    .expect("Range is validated when parsed")
+    .with_spacing(grid_spacing);
+
+    let profit_per_cycle = grid_range
+        .into_iter()
+        .map(|(bid, ask)| {
+            let ask_value = (Fraction::from(order_value) * ask / bid).floor();
+            ask_value.to_u64().unwrap_or_default() - order_value
+        })
+        .sum();
+
+    Ok(GridPlanSummary {
+        num_orders,
+        order_value,
+        total_required: order_value * num_orders + min_box_value + fee,
+        profit_per_cycle,
+        min_box_value,
+    })
+}
+
+fn plan_lines(plan: &GridPlanSummary, spacing_percent: Fraction, fee: u64) -> Vec<String> {
+    let erg = |amount| UnitAmount::new(*ERG_UNIT, amount);
+
+    vec![
+        format!(
+            "Orders: {} with at least {}% spacing",
+            plan.num_orders, spacing_percent
+        ),
+        format!("Order size: {}", erg(plan.order_value)),
+        format!(
+            "Total required: {}, including the {} base value of the grid box and the {} fee",
+            erg(plan.total_required),
+            erg(plan.min_box_value),
+            erg(fee)
+        ),
+        format!(
+            "Profit per cycle: {} once every order has been bought and sold",
+            erg(plan.profit_per_cycle)
+        ),
+    ]
+}
+
+pub async fn handle_grid_plan(
+    node_client: &NodeClient,
+    default_fee: &str,
+    options: PlanOptions,
+) -> anyhow::Result<()> {
+    let PlanOptions {
+        total_value,
+        range,
+        spacing_percent,
+        spacing,
+        fee,
+        min_box_value,
+    } = options;
+
+    let total_value = AmountParser::new(*ERG_UNIT, "total value").parse(&total_value)?;
+    let fee = *resolve_fee(fee.as_deref(), default_fee)?.as_u64();
+    let min_box_value = grid_min_box_value(node_client, min_box_value).await?;
+
+    let plan = plan_orders(
+        total_value.amount(),
+        range,
+        spacing_percent,
+        spacing.unwrap_or_default(),
+        fee,
+        min_box_value,
+    )?;
+
+    for line in plan_lines(&plan, spacing_percent, fee) {
+        println!("{}", line);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use off_the_grid::{
+        grid::multigrid_order::DEFAULT_MIN_BOX_VALUE,
+        units::{Fraction, UnitAmount, ERG_UNIT},
+    };
+
+    use crate::commands::grid::create::GridSpacing;
+
+    use super::{plan_lines, plan_orders, GridPlanError, GridPlanSummary};
+
+    #[test]
+    fn orders_planned_for_spacing() {
+        let range = (Fraction::from(100u64), Fraction::from(200u64));
+        let fee = 1_000_000;
+
+        // Six orders would leave the top order 183.33-200 with 9.09% spacing
+        let plan = plan_orders(
+            10_000_000_000,
+            range,
+            Fraction::from(10u64),
+            GridSpacing::Linear,
+            fee,
+            DEFAULT_MIN_BOX_VALUE,
+        )
+        .unwrap();
+
+        // 2 ERG per order, earning 20%, 16.7%, 14.3%, 12.5% and 11.1% in turn
+        assert_eq!(
+            plan,
+            GridPlanSummary {
+                num_orders: 5,
+                order_value: 2_000_000_000,
+                total_required: 10_000_000_000 + DEFAULT_MIN_BOX_VALUE + fee,
+                profit_per_cycle: 1_491_269_840,
+                min_box_value: DEFAULT_MIN_BOX_VALUE,
+            }
+        );
+
+        let lines = plan_lines(&plan, Fraction::from(10u64), fee);
+        assert_eq!(lines[0], "Orders: 5 with at least 10% spacing");
+        assert_eq!(lines[1], "Order size: 2 ERG");
+
+        assert_eq!(
+            plan_orders(
+                10_000_000_000,
+                range,
+                Fraction::from(150u64),
+                GridSpacing::Linear,
+                fee,
+                DEFAULT_MIN_BOX_VALUE
+            ),
+            Err(GridPlanError::SpacingTooWide(Fraction::from(150u64)))
+        );
+        assert_eq!(
+            plan_orders(
+                4,
+                range,
+                Fraction::from(10u64),
+                GridSpacing::Linear,
+                fee,
+                DEFAULT_MIN_BOX_VALUE
+            ),
+            Err(GridPlanError::ValueTooSmall(4, 5))
+        );
+    }
+
+    #[test]
+    fn geometric_orders_planned() {
+        let fee = 1_000_000;
+        let min_box_value = 2 * DEFAULT_MIN_BOX_VALUE;
+
+        // 1.1^7 = 1.949 fits 7 orders of 10% between 100 and 200, linear spacing only 5
+        let range = (Fraction::from(100u64), Fraction::from(200u64));
+        let plan = plan_orders(
+            7_000_000_000,
+            range,
+            Fraction::from(10u64),
+            GridSpacing::Geometric,
+            fee,
+            min_box_value,
+        )
+        .unwrap();
+
+        assert_eq!(plan.num_orders, 7);
+        assert_eq!(plan.order_value, 1_000_000_000);
+        assert_eq!(plan.total_required, 7_000_000_000 + min_box_value + fee);
+
+        let lines = plan_lines(&plan, Fraction::from(10u64), fee);
+        assert!(lines[2].contains(&format!(
+            "the {} base value",
+            UnitAmount::new(*ERG_UNIT, min_box_value)
+        )));
+
+        // Exactly two orders of 10% fit between 100 and 121
+        let range = (Fraction::from(100u64), Fraction::from(121u64));
+        let plan = plan_orders(
+            2_000_000_000,
+            range,
+            Fraction::from(10u64),
+            GridSpacing::Geometric,
+            fee,
+            min_box_value,
+        )
+        .unwrap();
+        assert_eq!(plan.num_orders, 2);
+    }
+}
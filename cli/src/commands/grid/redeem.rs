@@ -1,33 +1,174 @@
-use std::collections::{hash_map::Entry, HashMap};
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    future::Future,
+    iter::once,
+    rc::Rc,
+    time::Duration,
+};
 
-use anyhow::anyhow;
+use anyhow::{anyhow, Context};
 use clap::{ArgGroup, Parser};
 use ergo_lib::{
-    ergo_chain_types::Digest32,
+    chain::{
+        ergo_state_context::{ErgoStateContext, Headers},
+        transaction::unsigned::UnsignedTransaction,
+    },
+    ergo_chain_types::{Digest32, Header},
+    ergotree_interpreter::eval::{env::Env, reduce_to_crypto},
     ergotree_ir::{
         chain::{
             address::Address,
-            ergo_box::box_value::BoxValue,
-            token::{Token, TokenAmount, TokenId},
+            ergo_box::{box_value::BoxValue, ErgoBox},
+            token::{Token, TokenAmount, TokenAmountError, TokenId},
         },
-        serialization::SigmaParsingError,
+        sigma_protocol::sigma_boolean::{SigmaBoolean, SigmaProofOfKnowledgeTree},
+    },
+    wallet::{
+        box_selector::{sum_tokens_from_boxes, ErgoBoxAssetsData},
+        signing::{make_context, TransactionContext},
     },
-    wallet::box_selector::ErgoBoxAssetsData,
 };
 use off_the_grid::{
     boxes::{tracked_box::TrackedBox, wallet_box::WalletBox},
-    grid::multigrid_order::MultiGridOrder,
-    node::client::NodeClient,
-    units::{TokenStore, ERG_UNIT},
+    grid::multigrid_order::{GridOrderEntries, MultiGridOrder, MultiGridOrderError},
+    node::{client::NodeClient, wallet::WalletBalance},
+    units::{token_id_hex, Fraction, Price, TokenStore, Unit, UnitAmount, ERG_UNIT},
 };
+use thiserror::Error;
 
-use crate::scan_config::ScanConfig;
+use crate::{
+    commands::{warn_wallet_error, AddressScriptError},
+    scan_config::ScanConfig,
+};
 
 use super::{
+    assemble_transaction, resolve_fee, sorted_tokens,
+    subcommands::{entry_price, group_grids, GridGroup},
     IntoSummarizedTransaction, MinerFeeValue, SummarizedInput, SummarizedOutput,
-    SummarizedTransaction,
+    SummarizedTransaction, TryIntoErgoBoxCandidate,
 };
 
+/// Number of last block headers the spending context is built from
+const NUM_HEADERS: u32 = 10;
+
+/// Maximum number of grid orders spent by a single redeem transaction
+const MAX_REDEEM_INPUTS: usize = 100;
+
+/// Maximum size in bytes of a signed redeem transaction, well below the limit of the node
+const MAX_REDEEM_TX_SIZE: usize = 32 * 1024;
+
+/// Size in bytes of the proof of a P2PK input, including its length prefix
+const P2PK_PROOF_SIZE: usize = 57;
+
+/// Interval between checks of the confirmed wallet balance after a redeem with `--wait`
+pub(super) const WAIT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Number of balance checks before giving up waiting, about 15 blocks
+pub(super) const WAIT_ATTEMPTS: u32 = 180;
+
+/// Average time between two blocks, used to convert ages given as a duration into blocks
+const BLOCK_INTERVAL: Duration = Duration::from_secs(120);
+
+/// Parse the age of a grid, either as a number of blocks or as a number of minutes, hours,
+/// days or weeks with an `m`, `h`, `d` or `w` suffix, e.g. `30d`. Durations are converted
+/// into blocks at the average block interval.
+fn block_age_from_str(s: &str) -> Result<u32, String> {
+    let s = s.trim();
+
+    let (number, unit_secs) = match s.char_indices().last() {
+        Some((index, 'm')) => (&s[..index], 60),
+        Some((index, 'h')) => (&s[..index], 60 * 60),
+        Some((index, 'd')) => (&s[..index], 24 * 60 * 60),
+        Some((index, 'w')) => (&s[..index], 7 * 24 * 60 * 60),
+        _ => (s, 0),
+    };
+
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("Invalid age {}: expected blocks or a duration like 30d", s))?;
+
+    let too_large = || format!("Invalid age {}: too large", s);
+
+    let blocks = match unit_secs {
+        0 => number,
+        unit_secs => {
+            number.checked_mul(unit_secs).ok_or_else(too_large)? / BLOCK_INTERVAL.as_secs()
+        }
+    };
+
+    u32::try_from(blocks).map_err(|_| too_large())
+}
+
+fn price_from_str(s: &str) -> Result<Fraction, String> {
+    let price: Fraction = s
+        .trim()
+        .parse()
+        .map_err(|_| format!("Invalid price {}", s))?;
+
+    if price.is_nan() || price.is_infinite() || price <= Fraction::from(0u64) {
+        return Err(format!("Invalid price {}: must be positive", s));
+    }
+
+    Ok(price)
+}
+
+/// Levels of the grids to redeem by the price they are filled at, in ERG per token. The
+/// other levels stay in a grid order continuing each spent box.
+#[derive(Clone, Copy, Debug, Default)]
+struct PriceFilter {
+    above: Option<Fraction>,
+    below: Option<Fraction>,
+}
+
+impl PriceFilter {
+    fn is_set(&self) -> bool {
+        self.above.is_some() || self.below.is_some()
+    }
+
+    /// Indices of the entries filled above `above` or below `below`
+    fn redeemed_indices(&self, entries: &GridOrderEntries, unit: Unit) -> Vec<usize> {
+        entries
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| {
+                let price = Price::new(unit, *ERG_UNIT, entry_price(entry)).price();
+
+                self.above.is_some_and(|above| price > above)
+                    || self.below.is_some_and(|below| price < below)
+            })
+            .map(|(index, _)| index)
+            .collect()
+    }
+
+    /// How `order` is redeemed, or `None` if none of its entries are matched
+    fn redeem_input(
+        &self,
+        order: TrackedBox<MultiGridOrder>,
+        unit: Unit,
+    ) -> Result<Option<RedeemInput>, MultiGridOrderError> {
+        let indices = self.redeemed_indices(&order.value.entries, unit);
+
+        if indices.is_empty() {
+            return Ok(None);
+        }
+
+        // The kept order holds the base value the grid was created with
+        let kept = if indices.len() == order.value.entries.iter().count() {
+            None
+        } else {
+            let base_value = order.value.base_value();
+            Some(order.value.clone().redeem_entries(&indices, base_value)?)
+        };
+
+        Ok(Some(RedeemInput { order, kept }))
+    }
+}
+
+/// Whether `order` was created at least `age` blocks before `chain_height`
+fn created_before(order: &TrackedBox<MultiGridOrder>, chain_height: u32, age: u32) -> bool {
+    chain_height.saturating_sub(order.ergo_box.creation_height) >= age
+}
+
 #[derive(Parser)]
 #[command(group(
     ArgGroup::new("filter")
@@ -44,17 +185,55 @@ pub struct RedeemOptions {
     #[clap(
         short,
         long,
-        help = "transaction fee value, in nanoERGs",
-        default_value = "0.001"
+        help = "transaction fee value [default: default_fee of the node config]"
+    )]
+    fee: Option<String>,
+    #[clap(
+        long,
+        help = "Verify locally that the wallet can spend the grid orders before submitting"
+    )]
+    verify: bool,
+    #[clap(
+        long,
+        help = "Wait until the redeem is confirmed and report the change of the wallet balance"
+    )]
+    pub wait: bool,
+    #[clap(
+        long,
+        value_parser = block_age_from_str,
+        help = "Only redeem grids created at least this many blocks ago, or this long ago with an m, h, d or w suffix"
+    )]
+    older_than: Option<u32>,
+    #[clap(
+        long,
+        value_parser = price_from_str,
+        help = "Only redeem the levels filled above this price in ERG per token, keeping the other levels in the grid"
     )]
-    fee: String,
+    above: Option<Fraction>,
+    #[clap(
+        long,
+        value_parser = price_from_str,
+        help = "Only redeem the levels filled below this price in ERG per token, keeping the other levels in the grid"
+    )]
+    below: Option<Fraction>,
+}
+
+#[derive(Error, Debug)]
+pub enum RedeemGridError {
+    #[error(transparent)]
+    MultiGridOrder(#[from] MultiGridOrderError),
+
+    #[error(transparent)]
+    AddressScript(#[from] AddressScriptError),
 }
 
 pub async fn handle_grid_redeem(
     node_client: &NodeClient,
     scan_config: ScanConfig,
+    token_store: &TokenStore,
+    default_fee: &str,
     options: RedeemOptions,
-) -> anyhow::Result<RedeemMultiData> {
+) -> anyhow::Result<Vec<RedeemMultiData>> {
     let RedeemOptions {
         token_id,
         grid_identity,
@@ -62,18 +241,34 @@ pub async fn handle_grid_redeem(
         // so the user is forced to choose one of the filters
         all: _,
         fee,
+        verify,
+        wait: _,
+        older_than,
+        above,
+        below,
     } = options;
 
+    let price_filter = PriceFilter { above, below };
+
     let grid_identity = grid_identity.map(|i| i.into_bytes());
 
-    let fee_amount = ERG_UNIT
-        .str_amount(&fee)
-        .ok_or_else(|| anyhow!("Invalid fee value"))?;
+    let fee_value = resolve_fee(fee.as_deref(), default_fee)?;
 
     let token_id = token_id
         .map(|i| Digest32::try_from(i).map(|i| i.into()))
         .transpose()?;
 
+    let chain_height = match older_than {
+        Some(_) => Some(
+            node_client
+                .node_info()
+                .await?
+                .full_height
+                .ok_or_else(|| anyhow!("Node has no full height yet"))?,
+        ),
+        None => None,
+    };
+
     let grid_orders = node_client
         .get_scan_unspent(scan_config.wallet_multigrid_scan_id)
         .await?
@@ -82,7 +277,7 @@ pub async fn handle_grid_redeem(
         .filter(|b: &TrackedBox<MultiGridOrder>| {
             grid_identity
                 .as_ref()
-                .map(|i| b.value.metadata.as_ref().map(|m| *m == *i).unwrap_or(false))
+                .map(|i| b.value.identity() == Some(&i[..]))
                 .unwrap_or(true)
         })
         .filter(|b: &TrackedBox<MultiGridOrder>| {
@@ -91,6 +286,12 @@ pub async fn handle_grid_redeem(
                 .map(|i| b.value.token_id == *i)
                 .unwrap_or(true)
         })
+        .filter(
+            |b: &TrackedBox<MultiGridOrder>| match (chain_height, older_than) {
+                (Some(chain_height), Some(age)) => created_before(b, chain_height, age),
+                _ => true,
+            },
+        )
         .collect::<Vec<_>>();
 
     if grid_orders.is_empty() {
@@ -99,54 +300,378 @@ pub async fn handle_grid_redeem(
 
     let wallet_status = node_client.wallet_status().await?;
     wallet_status.error_if_locked()?;
+    warn_wallet_error(&wallet_status);
+
+    let owner_address = wallet_status.change_address()?;
+
+    let grids = group_grids(grid_orders);
+
+    for grid in grids.iter() {
+        if price_filter.is_set() {
+            let unit = token_store.get_unit(&grid.token_id);
+            println!(
+                "Redeeming {}: {} of {} levels",
+                grid.label,
+                price_filter.redeemed_indices(&grid.entries, unit).len(),
+                grid.entries.iter().count()
+            );
+        } else {
+            println!(
+                "Redeeming {}: {} {}",
+                grid.label,
+                UnitAmount::new(*ERG_UNIT, grid.value),
+                UnitAmount::new(token_store.get_unit(&grid.token_id), grid.tokens)
+            );
+        }
+    }
+
+    let redeem_inputs: Vec<RedeemInput> = if price_filter.is_set() {
+        grouped_orders(grids)
+            .into_iter()
+            .filter_map(|order| {
+                let unit = token_store.get_unit(&order.value.token_id);
+                price_filter.redeem_input(order, unit).transpose()
+            })
+            .collect::<Result<_, _>>()?
+    } else {
+        grouped_orders(grids).into_iter().map(Into::into).collect()
+    };
+
+    if redeem_inputs.is_empty() {
+        return Err(anyhow!("No grid levels found outside the given prices"));
+    }
+
+    let num_orders = redeem_inputs.len();
+
+    let redeem_txs = build_redeem_multi_txs(redeem_inputs, owner_address.clone(), fee_value)?;
+
+    if redeem_txs.len() > 1 {
+        println!(
+            "Redeeming {} grid orders in {} transactions",
+            num_orders,
+            redeem_txs.len()
+        );
+    }
+
+    if verify {
+        let headers = node_client.last_headers(NUM_HEADERS).await?;
+        for redeem_data in redeem_txs.iter() {
+            verify_redeem_spending(redeem_data, &owner_address, headers.clone())
+                .context("Local verification of the redeem transaction failed")?;
+        }
+        println!("Local verification passed");
+    }
+
+    let (value, tokens) = redeem_proceeds(&redeem_txs)?;
+
+    let proceeds: Vec<String> = once(UnitAmount::new(*ERG_UNIT, value))
+        .chain(
+            tokens
+                .iter()
+                .map(|t| UnitAmount::new(token_store.get_unit(&t.token_id), *t.amount.as_u64())),
+        )
+        .map(|amount| amount.to_string())
+        .collect();
+
+    println!("You will receive {}", proceeds.join(", "));
+
+    Ok(redeem_txs)
+}
+
+/// Net proceeds of all `redeem_txs` together, in nanoERG and tokens
+pub(super) fn redeem_proceeds(redeem_txs: &[RedeemMultiData]) -> anyhow::Result<(u64, Vec<Token>)> {
+    let proceeds = redeem_txs
+        .iter()
+        .map(RedeemMultiData::net_proceeds)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    total_proceeds(&proceeds)
+}
+
+/// Sum of the net proceeds of several redeem transactions
+pub(super) fn total_proceeds<'a, I>(proceeds: I) -> anyhow::Result<(u64, Vec<Token>)>
+where
+    I: IntoIterator<Item = &'a (u64, Vec<Token>)>,
+{
+    let mut value = 0;
+    let mut tokens: HashMap<TokenId, TokenAmount> = HashMap::new();
+
+    for (tx_value, tx_tokens) in proceeds {
+        value += tx_value;
+        add_tokens(&mut tokens, tx_tokens)?;
+    }
+
+    Ok((value, sorted_tokens(tokens)))
+}
+
+/// Check the confirmed wallet balance with `fetch_balance` every `interval` until it
+/// differs from `before` at a later height, for at most `attempts` checks
+pub(super) async fn wait_for_balance_change<F, Fut>(
+    before: &WalletBalance,
+    mut fetch_balance: F,
+    interval: Duration,
+    attempts: u32,
+) -> anyhow::Result<WalletBalance>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = anyhow::Result<WalletBalance>>,
+{
+    for _ in 0..attempts {
+        tokio::time::sleep(interval).await;
+
+        let after = fetch_balance().await?;
+
+        if after.height > before.height
+            && (after.balance != before.balance || after.assets != before.assets)
+        {
+            return Ok(after);
+        }
+    }
+
+    Err(anyhow!(
+        "Confirmed wallet balance did not change after {} checks",
+        attempts
+    ))
+}
+
+/// Change of the confirmed wallet balance from `before` to `after`, compared to the
+/// expected proceeds of the redeem
+pub(super) fn balance_change_lines(
+    before: &WalletBalance,
+    after: &WalletBalance,
+    expected: &(u64, Vec<Token>),
+    token_store: &TokenStore,
+) -> Vec<String> {
+    let signed = |unit, before: u64, after: u64| {
+        if after >= before {
+            format!("+{}", UnitAmount::new(unit, after - before))
+        } else {
+            format!("-{}", UnitAmount::new(unit, before - after))
+        }
+    };
+
+    let mut token_ids: Vec<&TokenId> = before.assets.keys().chain(after.assets.keys()).collect();
+    token_ids.sort_by_key(|token_id| token_id_hex(token_id));
+    token_ids.dedup();
+
+    let changes: Vec<String> = once(signed(*ERG_UNIT, before.balance, after.balance))
+        .chain(token_ids.into_iter().filter_map(|token_id| {
+            let before = before.assets.get(token_id).copied().unwrap_or(0);
+            let after = after.assets.get(token_id).copied().unwrap_or(0);
+
+            (before != after).then(|| signed(token_store.get_unit(token_id), before, after))
+        }))
+        .collect();
+
+    let (expected_value, expected_tokens) = expected;
+
+    let received_expected = after.balance.checked_sub(before.balance) == Some(*expected_value)
+        && expected_tokens.iter().all(|token| {
+            let before = before.assets.get(&token.token_id).copied().unwrap_or(0);
+            let after = after.assets.get(&token.token_id).copied().unwrap_or(0);
+
+            after.checked_sub(before) == Some(*token.amount.as_u64())
+        });
+
+    vec![
+        format!(
+            "Confirmed balance change at height {}: {}",
+            after.height,
+            changes.join(", ")
+        ),
+        if received_expected {
+            "Received the expected proceeds".to_string()
+        } else {
+            "Warning: The balance change differs from the expected proceeds, other wallet transactions may have been confirmed in the meantime".to_string()
+        },
+    ]
+}
+
+/// The boxes of every grid, with the boxes of a grid next to each other so that a grid
+/// split across several boxes is redeemed in the same transaction whenever it fits
+fn grouped_orders(grids: Vec<GridGroup>) -> Vec<TrackedBox<MultiGridOrder>> {
+    grids.into_iter().flat_map(|grid| grid.boxes).collect()
+}
+
+/// Reduce the spending condition of every grid order input against the chain state given
+/// by `headers` (oldest first) and check that only a signature of `owner_address` is left
+/// to prove. The transaction is not actually proven as the secret is held by the wallet.
+fn verify_redeem_spending(
+    redeem_data: &RedeemMultiData,
+    owner_address: &Address,
+    mut headers: Vec<Header>,
+) -> anyhow::Result<()> {
+    let owner = match owner_address {
+        Address::P2Pk(owner) => owner.clone(),
+        _ => return Err(anyhow!("Owner address is not a P2PK address")),
+    };
+
+    headers.reverse();
+
+    let pre_header = headers
+        .first()
+        .cloned()
+        .ok_or_else(|| anyhow!("No block headers available"))?
+        .into();
+
+    let headers: Headers = headers
+        .try_into()
+        .map_err(|h: Vec<Header>| anyhow!("Expected {} headers, got {}", NUM_HEADERS, h.len()))?;
+
+    let state_context = ErgoStateContext::new(pre_header, headers);
+
+    let input_boxes: Vec<ErgoBox> = redeem_data
+        .orders
+        .iter()
+        .map(|o| o.ergo_box.clone())
+        .collect();
+
+    let tx = redeem_data.unsigned_transaction()?;
+
+    let tx_context = TransactionContext::new(tx, input_boxes.clone(), vec![])?;
+
+    let expected = SigmaBoolean::ProofOfKnowledge(SigmaProofOfKnowledgeTree::ProveDlog(owner));
+
+    for (index, input_box) in input_boxes.iter().enumerate() {
+        let context = make_context(&state_context, &tx_context, index)?;
+        let reduced = reduce_to_crypto(
+            &input_box.ergo_tree.proposition()?,
+            &Env::empty(),
+            Rc::new(context),
+        )?;
+
+        if reduced.sigma_prop != expected {
+            return Err(anyhow!(
+                "Input {} requires {:?} instead of a signature of the wallet",
+                String::from(input_box.box_id()),
+                reduced.sigma_prop
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Add `tokens` to the amounts in `sum`
+fn add_tokens(
+    sum: &mut HashMap<TokenId, TokenAmount>,
+    tokens: &[Token],
+) -> Result<(), TokenAmountError> {
+    for token in tokens {
+        match sum.entry(token.token_id) {
+            Entry::Occupied(mut e) => {
+                let amount = e.get_mut();
+                *amount = amount.checked_add(&token.amount)?;
+            }
+            Entry::Vacant(e) => {
+                e.insert(token.amount);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// A grid order box spent by a redeem, with the grid order continuing it if only some of
+/// its entries are redeemed
+#[derive(Clone)]
+struct RedeemInput {
+    order: TrackedBox<MultiGridOrder>,
+    kept: Option<MultiGridOrder>,
+}
 
-    let fee_value = fee_amount.amount().try_into()?;
+impl From<TrackedBox<MultiGridOrder>> for RedeemInput {
+    fn from(order: TrackedBox<MultiGridOrder>) -> Self {
+        Self { order, kept: None }
+    }
+}
+
+/// Split the orders into as many redeem transactions as needed to stay within
+/// [`MAX_REDEEM_INPUTS`] and [`MAX_REDEEM_TX_SIZE`]. Each transaction pays `fee_value`.
+fn build_redeem_multi_txs(
+    orders: Vec<RedeemInput>,
+    change_address: Address,
+    fee_value: BoxValue,
+) -> anyhow::Result<Vec<RedeemMultiData>> {
+    let mut redeem_txs = vec![];
+    let mut chunk: Vec<RedeemInput> = vec![];
+
+    for order in orders {
+        chunk.push(order);
+
+        let exceeds_limits = chunk.len() > 1
+            && (chunk.len() > MAX_REDEEM_INPUTS
+                || build_redeem_multi_tx(chunk.clone(), change_address.clone(), fee_value)?
+                    .signed_size()?
+                    > MAX_REDEEM_TX_SIZE);
+
+        if exceeds_limits {
+            let order = chunk.pop().expect("Chunk is not empty");
+            let full_chunk = std::mem::replace(&mut chunk, vec![order]);
+            redeem_txs.push(build_redeem_multi_tx(
+                full_chunk,
+                change_address.clone(),
+                fee_value,
+            )?);
+        }
+    }
 
-    build_redeem_multi_tx(
-        grid_orders,
-        node_client.wallet_status().await?.change_address()?,
-        fee_value,
-    )
+    if !chunk.is_empty() {
+        redeem_txs.push(build_redeem_multi_tx(chunk, change_address, fee_value)?);
+    }
+
+    Ok(redeem_txs)
 }
 
+/// Spend `orders` into their continuing grid orders, if any, and a change box holding
+/// everything else minus the fee
 fn build_redeem_multi_tx(
-    orders: Vec<TrackedBox<MultiGridOrder>>,
+    orders: Vec<RedeemInput>,
     change_address: Address,
     fee_value: BoxValue,
 ) -> anyhow::Result<RedeemMultiData> {
+    let (orders, kept): (Vec<_>, Vec<_>) = orders
+        .into_iter()
+        .map(|RedeemInput { order, kept }| (order, kept))
+        .unzip();
+    let grid_outputs: Vec<MultiGridOrder> = kept.into_iter().flatten().collect();
+
     let change_value = orders
         .iter()
         .map(|o| o.ergo_box.value.as_u64())
         .sum::<u64>()
-        .checked_sub(*fee_value.as_u64())
+        .checked_sub(grid_outputs.iter().map(|g| g.value.as_u64()).sum::<u64>())
+        .and_then(|v| v.checked_sub(*fee_value.as_u64()))
         .ok_or(anyhow!("Not enough funds for fee"))?;
 
     let mut change_tokens: HashMap<TokenId, TokenAmount> = HashMap::new();
 
     for order in orders.iter() {
-        for token in order.ergo_box.tokens.as_ref().iter().flat_map(|b| b.iter()) {
-            match change_tokens.entry(token.token_id) {
-                Entry::Occupied(mut e) => {
-                    let amount = e.get_mut();
-                    *amount = amount.checked_add(&token.amount)?;
-                }
-                Entry::Vacant(e) => {
-                    e.insert(token.amount);
-                }
-            }
+        if let Some(tokens) = order.ergo_box.tokens.as_ref() {
+            add_tokens(&mut change_tokens, tokens.as_slice())?;
+        }
+    }
+
+    for grid_output in grid_outputs.iter() {
+        let kept = grid_output.entries.token_amount();
+        let held = change_tokens
+            .remove(&grid_output.token_id)
+            .map(|amount| *amount.as_u64())
+            .unwrap_or_default();
+
+        let left = held.checked_sub(kept).ok_or(anyhow!(
+            "Grid outputs hold more tokens than the redeemed orders"
+        ))?;
+
+        if left > 0 {
+            change_tokens.insert(grid_output.token_id, left.try_into()?);
         }
     }
 
     let tokens = if change_tokens.is_empty() {
         None
     } else {
-        Some(
-            change_tokens
-                .into_iter()
-                .map(Token::from)
-                .collect::<Vec<_>>()
-                .try_into()?,
-        )
+        Some(sorted_tokens(change_tokens).try_into()?)
     };
 
     let change_asset_data = WalletBox::new(
@@ -159,6 +684,7 @@ fn build_redeem_multi_tx(
 
     Ok(RedeemMultiData {
         orders,
+        grid_outputs,
         change_boxes: vec![change_asset_data],
         fee_value: MinerFeeValue(fee_value),
     })
@@ -166,23 +692,85 @@ fn build_redeem_multi_tx(
 
 pub struct RedeemMultiData {
     orders: Vec<TrackedBox<MultiGridOrder>>,
+    /// Grid orders continuing the orders that are only partially redeemed
+    grid_outputs: Vec<MultiGridOrder>,
     change_boxes: Vec<WalletBox<ErgoBoxAssetsData>>,
     fee_value: MinerFeeValue,
 }
 
+impl RedeemMultiData {
+    /// ERG value and tokens returned to the wallet, after the miner fee
+    pub(super) fn net_proceeds(&self) -> Result<(u64, Vec<Token>), TokenAmountError> {
+        let value = self
+            .change_boxes
+            .iter()
+            .map(|b| *b.assets.value.as_u64())
+            .sum();
+
+        let tokens = sum_tokens_from_boxes(
+            &self
+                .change_boxes
+                .iter()
+                .map(|b| b.assets.clone())
+                .collect::<Vec<_>>(),
+        )?;
+
+        Ok((value, sorted_tokens(tokens)))
+    }
+
+    /// The unsigned redeem transaction, spending every order
+    fn unsigned_transaction(&self) -> anyhow::Result<UnsignedTransaction> {
+        let creation_height = self.creation_height();
+
+        let mut outputs = self
+            .grid_outputs
+            .iter()
+            .cloned()
+            .map(|o| o.into_ergo_box_candidate(creation_height))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        for change_box in self.change_boxes.iter().cloned() {
+            outputs.push(change_box.into_ergo_box_candidate(creation_height)?);
+        }
+
+        outputs.push(
+            MinerFeeValue(self.fee_value.0)
+                .into_ergo_box_candidate(creation_height)
+                .expect("Fee output"),
+        );
+
+        Ok(assemble_transaction(
+            self.orders
+                .iter()
+                .map(|o| o.ergo_box.clone().into())
+                .collect(),
+            outputs,
+        )?)
+    }
+
+    /// Size in bytes of the transaction once the wallet signed every input
+    fn signed_size(&self) -> anyhow::Result<usize> {
+        let unsigned_size = self.unsigned_transaction()?.bytes_to_sign()?.len();
+        Ok(unsigned_size + self.orders.len() * P2PK_PROOF_SIZE)
+    }
+
+    fn creation_height(&self) -> u32 {
+        self.orders
+            .iter()
+            .map(|o| o.ergo_box.creation_height)
+            .max()
+            .unwrap_or(0)
+    }
+}
+
 impl IntoSummarizedTransaction for RedeemMultiData {
-    type Error = SigmaParsingError;
+    type Error = RedeemGridError;
 
     fn into_summarized_transaction(
         self,
         token_store: &TokenStore,
     ) -> Result<SummarizedTransaction, Self::Error> {
-        let creation_height = self
-            .orders
-            .iter()
-            .map(|o| o.ergo_box.creation_height)
-            .max()
-            .unwrap_or(0);
+        let creation_height = self.creation_height();
 
         let inputs = self
             .orders
@@ -190,21 +778,490 @@ impl IntoSummarizedTransaction for RedeemMultiData {
             .map(|i| SummarizedInput::new(i, token_store))
             .collect();
 
-        let change_outputs = self
-            .change_boxes
+        let mut outputs = self
+            .grid_outputs
+            .into_iter()
+            .map(|o| SummarizedOutput::new(o, token_store, creation_height))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        for change_box in self.change_boxes {
+            outputs.push(SummarizedOutput::new(
+                change_box,
+                token_store,
+                creation_height,
+            )?);
+        }
+
+        outputs.push(
+            SummarizedOutput::new(self.fee_value, token_store, creation_height)
+                .expect("Fee output"),
+        );
+
+        Ok(SummarizedTransaction { inputs, outputs })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, time::Duration};
+
+    use ergo_lib::{
+        chain::transaction::TxId,
+        ergo_chain_types::{ec_point::generator, Digest32, Header},
+        ergotree_ir::{
+            chain::{address::Address, ergo_box::ErgoBox},
+            sigma_protocol::sigma_boolean::ProveDlog,
+        },
+        wallet::secret_key::SecretKey,
+    };
+    use off_the_grid::{
+        boxes::tracked_box::TrackedBox,
+        grid::multigrid_order::{
+            GridOrderEntries, GridOrderEntry, MultiGridOrder, OrderState, DEFAULT_MIN_BOX_VALUE,
+        },
+        node::wallet::WalletBalance,
+        units::{Fraction, TokenInfo, TokenStore},
+    };
+
+    use crate::commands::{
+        grid::subcommands::group_grids,
+        test_util::{grid_box, test_order},
+    };
+
+    use super::{
+        balance_change_lines, block_age_from_str, build_redeem_multi_tx, build_redeem_multi_txs,
+        created_before, grouped_orders, redeem_proceeds, verify_redeem_spending,
+        wait_for_balance_change, PriceFilter, MAX_REDEEM_INPUTS, MAX_REDEEM_TX_SIZE,
+    };
+
+    const HEADERS_JSON: &[u8] = include_bytes!("../../../tests/headers.json");
+
+    #[test]
+    fn sell_levels_redeemed_above_price() {
+        let owner_address = Address::P2Pk(ProveDlog::new(generator()));
+        let token_id = Digest32::from([1u8; 32]).into();
+
+        let entry = |state, bid_value| {
+            GridOrderEntry::new(
+                state,
+                10.try_into().unwrap(),
+                bid_value,
+                bid_value + 10_000_000,
+            )
+        };
+
+        // Filled at 0.01, 0.012, 0.015 and 0.017 ERG per token
+        let entries = GridOrderEntries::new(vec![
+            entry(OrderState::Buy, 100_000_000),
+            entry(OrderState::Buy, 120_000_000),
+            entry(OrderState::Sell, 140_000_000),
+            entry(OrderState::Sell, 160_000_000),
+        ]);
+
+        // Profit collected by earlier fills
+        let profit = 5_000_000;
+        let tracked_order = |base_value| -> TrackedBox<MultiGridOrder> {
+            let order = MultiGridOrder::new(
+                generator(),
+                token_id,
+                entries.clone(),
+                Some(b"partial".to_vec()),
+                base_value,
+            )
+            .unwrap();
+            let mut candidate = order.into_box_candidate(0).unwrap();
+            candidate.value = (candidate.value.as_u64() + profit).try_into().unwrap();
+            let order_box = ErgoBox::from_box_candidate(&candidate, TxId::zero(), 0).unwrap();
+            order_box.try_into().unwrap()
+        };
+        let order = tracked_order(DEFAULT_MIN_BOX_VALUE);
+
+        let token_store = TokenStore::default();
+        let unit = token_store.get_unit(&token_id);
+        let above = PriceFilter {
+            above: Some(Fraction::new(13u64, 1000u64)),
+            below: None,
+        };
+
+        let input = above.redeem_input(order.clone(), unit).unwrap().unwrap();
+        let fee = 1_000_000u64;
+        let redeem_data =
+            build_redeem_multi_tx(vec![input], owner_address, fee.try_into().unwrap()).unwrap();
+
+        let grid_output = &redeem_data.grid_outputs[0];
+        let kept: Vec<_> = grid_output.entries.iter().map(|e| e.bid_value).collect();
+        assert_eq!(kept, vec![100_000_000, 120_000_000]);
+        assert_eq!(grid_output.identity(), Some(&b"partial"[..]));
+        assert_eq!(
+            *grid_output.value.as_u64(),
+            DEFAULT_MIN_BOX_VALUE + 220_000_000
+        );
+
+        let (value, tokens) = redeem_data.net_proceeds().unwrap();
+        assert_eq!(value, profit - fee);
+        assert_eq!(*tokens[0].amount.as_u64(), 20);
+
+        let outputs = redeem_data
+            .unsigned_transaction()
+            .unwrap()
+            .output_candidates;
+        assert_eq!(outputs.len(), 3);
+
+        // A grid created with a raised base value keeps it, along with its metadata
+        let base_value = 2 * DEFAULT_MIN_BOX_VALUE;
+        let input = above
+            .redeem_input(tracked_order(base_value), unit)
+            .unwrap()
+            .unwrap();
+        let kept = input.kept.unwrap();
+        assert_eq!(kept.base_value(), base_value);
+        assert_eq!(kept.identity(), Some(&b"partial"[..]));
+        assert_eq!(*kept.value.as_u64(), base_value + 220_000_000);
+
+        // Every level is matched, so the order is redeemed whole
+        let below = PriceFilter {
+            above: None,
+            below: Some(Fraction::from(1u64)),
+        };
+        let input = below.redeem_input(order.clone(), unit).unwrap().unwrap();
+        assert!(input.kept.is_none());
+
+        let above_all = PriceFilter {
+            above: Some(Fraction::from(1u64)),
+            below: None,
+        };
+        assert!(above_all.redeem_input(order, unit).unwrap().is_none());
+    }
+
+    #[test]
+    fn only_old_grids_redeemed() {
+        assert_eq!(block_age_from_str("500"), Ok(500));
+        assert_eq!(block_age_from_str("1h"), Ok(30));
+        assert_eq!(block_age_from_str("30d"), Ok(21_600));
+        assert_eq!(block_age_from_str("2w"), Ok(10_080));
+        assert!(block_age_from_str("30x").is_err());
+        assert!(block_age_from_str("d").is_err());
+        assert_eq!(
+            block_age_from_str("18446744073709551615w"),
+            Err("Invalid age 18446744073709551615w: too large".to_string())
+        );
+        assert!(block_age_from_str("4294967296").is_err());
+
+        let orders: Vec<TrackedBox<MultiGridOrder>> = [1_000, 5_000, 9_000]
             .into_iter()
-            .map(|o| SummarizedOutput::new(o, token_store, creation_height));
+            .map(|creation_height| {
+                let entries = GridOrderEntries::new(vec![GridOrderEntry::new(
+                    OrderState::Buy,
+                    10.try_into().unwrap(),
+                    100_000,
+                    110_000,
+                )]);
+                let metadata = Some(format!("grid-{}", creation_height).into_bytes());
+                let order = test_order(Digest32::zero().into(), entries, metadata);
+                let candidate = order.into_box_candidate(creation_height).unwrap();
+                ErgoBox::from_box_candidate(&candidate, TxId::zero(), 0)
+                    .unwrap()
+                    .try_into()
+                    .unwrap()
+            })
+            .collect();
+
+        // At height 10000, grids created 5000 or more blocks ago
+        let redeemed: Vec<u32> = orders
+            .iter()
+            .filter(|order| created_before(order, 10_000, 5_000))
+            .map(|order| order.ergo_box.creation_height)
+            .collect();
+        assert_eq!(redeemed, vec![1_000, 5_000]);
+    }
+
+    #[test]
+    fn verify_valid_redeem() {
+        let owner_address = Address::P2Pk(ProveDlog::new(generator()));
+        let headers: Vec<Header> = serde_json::from_slice(HEADERS_JSON).unwrap();
+
+        let entries = GridOrderEntries::new(vec![
+            GridOrderEntry::new(OrderState::Buy, 10.try_into().unwrap(), 100_000, 110_000),
+            GridOrderEntry::new(OrderState::Buy, 10.try_into().unwrap(), 120_000, 130_000),
+        ]);
+
+        let order_box = grid_box(Digest32::zero().into(), entries, None, 0);
+
+        let redeem_data = build_redeem_multi_tx(
+            vec![order_box.into()],
+            owner_address.clone(),
+            1_000_000u64.try_into().unwrap(),
+        )
+        .unwrap();
 
-        let fee_output = SummarizedOutput::new(self.fee_value, token_store, creation_height)
-            .expect("Fee output");
+        verify_redeem_spending(&redeem_data, &owner_address, headers.clone())
+            .expect("Redeem should be spendable by the owner");
 
-        let outputs: Result<Vec<_>, _> = change_outputs
-            .chain(std::iter::once(Ok(fee_output)))
+        let other_address = SecretKey::random_dlog().get_address_from_public_image();
+
+        verify_redeem_spending(&redeem_data, &other_address, headers)
+            .expect_err("Redeem should not be spendable by another key");
+    }
+
+    #[test]
+    fn redeem_outputs_deterministic() {
+        let owner_address = Address::P2Pk(ProveDlog::new(generator()));
+
+        let order_boxes: Vec<TrackedBox<MultiGridOrder>> = (1..=5u8)
+            .map(|i| {
+                let entries = GridOrderEntries::new(vec![GridOrderEntry::new(
+                    OrderState::Sell,
+                    10.try_into().unwrap(),
+                    100_000,
+                    110_000,
+                )]);
+
+                grid_box(Digest32::from([i; 32]).into(), entries, None, i as u16)
+            })
             .collect();
 
-        Ok(SummarizedTransaction {
-            inputs,
-            outputs: outputs?,
+        let build = || {
+            let orders = order_boxes.iter().rev().cloned().map(Into::into).collect();
+
+            let redeem_data = build_redeem_multi_tx(
+                orders,
+                owner_address.clone(),
+                1_000_000u64.try_into().unwrap(),
+            )
+            .unwrap();
+
+            redeem_data.change_boxes[0]
+                .assets
+                .tokens
+                .clone()
+                .unwrap()
+                .to_vec()
+        };
+
+        let first = build();
+        let second = build();
+
+        assert_eq!(first, second);
+
+        let token_ids: Vec<Digest32> = first.iter().map(|t| t.token_id.into()).collect();
+        let expected: Vec<Digest32> = (1..=5u8).map(|i| Digest32::from([i; 32])).collect();
+        assert_eq!(token_ids, expected);
+    }
+
+    #[test]
+    fn redeem_net_proceeds() {
+        let owner_address = Address::P2Pk(ProveDlog::new(generator()));
+        let token_id = Digest32::from([1u8; 32]);
+
+        let orders = [
+            GridOrderEntry::new(
+                OrderState::Buy,
+                10.try_into().unwrap(),
+                100_000_000,
+                110_000_000,
+            ),
+            GridOrderEntry::new(
+                OrderState::Sell,
+                20.try_into().unwrap(),
+                200_000_000,
+                220_000_000,
+            ),
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(i, entry)| {
+            let entries = GridOrderEntries::new(vec![entry]);
+            grid_box(token_id.into(), entries, None, i as u16).into()
         })
+        .collect();
+
+        let redeem_data =
+            build_redeem_multi_tx(orders, owner_address, 1_000_000u64.try_into().unwrap()).unwrap();
+
+        let (value, tokens) = redeem_data.net_proceeds().unwrap();
+
+        assert_eq!(value, 2 * DEFAULT_MIN_BOX_VALUE + 100_000_000 - 1_000_000);
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(tokens[0].token_id, token_id.into());
+        assert_eq!(*tokens[0].amount.as_u64(), 20);
+    }
+
+    #[test]
+    fn large_redeem_split_into_multiple_txs() {
+        let owner_address = Address::P2Pk(ProveDlog::new(generator()));
+        let token_id = Digest32::from([1u8; 32]);
+        let num_orders = MAX_REDEEM_INPUTS + 50;
+
+        let orders = (0..num_orders)
+            .map(|i| {
+                let entries = GridOrderEntries::new(vec![GridOrderEntry::new(
+                    OrderState::Sell,
+                    10.try_into().unwrap(),
+                    100_000,
+                    110_000,
+                )]);
+
+                grid_box(token_id.into(), entries, None, i as u16).into()
+            })
+            .collect();
+
+        let redeem_txs =
+            build_redeem_multi_txs(orders, owner_address, 1_000_000u64.try_into().unwrap())
+                .unwrap();
+
+        assert!(redeem_txs.len() > 1);
+        assert_eq!(
+            redeem_txs.iter().map(|r| r.orders.len()).sum::<usize>(),
+            num_orders
+        );
+
+        for redeem_data in redeem_txs.iter() {
+            assert!(redeem_data.orders.len() <= MAX_REDEEM_INPUTS);
+            assert!(redeem_data.signed_size().unwrap() <= MAX_REDEEM_TX_SIZE);
+
+            let (_, tokens) = redeem_data.net_proceeds().unwrap();
+            assert_eq!(
+                *tokens[0].amount.as_u64(),
+                10 * redeem_data.orders.len() as u64
+            );
+        }
+    }
+
+    #[test]
+    fn split_grid_redeemed_as_one() {
+        let owner_address = Address::P2Pk(ProveDlog::new(generator()));
+        let token_id = Digest32::from([1u8; 32]);
+
+        let order = |identity: &str, bid_value: u64, index: u16| -> TrackedBox<MultiGridOrder> {
+            let entries = GridOrderEntries::new(vec![GridOrderEntry::new(
+                OrderState::Sell,
+                10.try_into().unwrap(),
+                bid_value,
+                bid_value + 10_000,
+            )]);
+
+            let metadata = Some(identity.as_bytes().to_vec());
+            grid_box(token_id.into(), entries, metadata, index)
+        };
+
+        let orders = vec![
+            order("comet", 100_000, 0),
+            order("other", 200_000, 1),
+            order("comet", 300_000, 2),
+        ];
+        let comet_ids = [orders[0].ergo_box.box_id(), orders[2].ergo_box.box_id()];
+
+        let grids = group_grids(orders);
+
+        assert_eq!(grids.len(), 2);
+        assert_eq!(grids[0].label, "comet (2 boxes)");
+        assert_eq!(grids[0].value, 2 * DEFAULT_MIN_BOX_VALUE);
+        assert_eq!(grids[0].tokens, 20);
+        assert_eq!(grids[0].entries.iter().count(), 2);
+        assert_eq!(grids[1].label, "other");
+
+        let orders = grouped_orders(grids);
+        let box_ids: Vec<_> = orders.iter().map(|o| o.ergo_box.box_id()).collect();
+        assert_eq!(box_ids[..2], comet_ids);
+
+        let redeem_txs = build_redeem_multi_txs(
+            orders.into_iter().map(Into::into).collect(),
+            owner_address,
+            1_000_000u64.try_into().unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(redeem_txs.len(), 1);
+
+        let (value, tokens) = redeem_txs[0].net_proceeds().unwrap();
+        assert_eq!(value, 3 * DEFAULT_MIN_BOX_VALUE - 1_000_000);
+        assert_eq!(*tokens[0].amount.as_u64(), 30);
+    }
+
+    #[tokio::test]
+    async fn wait_reports_confirmed_balance_change() {
+        let owner_address = Address::P2Pk(ProveDlog::new(generator()));
+        let token_id = Digest32::from([1u8; 32]).into();
+        let tokens = TokenStore::with_tokens(vec![TokenInfo {
+            token_id,
+            name: "TKN".to_string(),
+            decimals: 0,
+        }]);
+
+        let entries = GridOrderEntries::new(vec![GridOrderEntry::new(
+            OrderState::Sell,
+            20.try_into().unwrap(),
+            200_000_000,
+            220_000_000,
+        )]);
+        let order_box = grid_box(token_id, entries, None, 0);
+
+        let redeem_data = build_redeem_multi_tx(
+            vec![order_box.into()],
+            owner_address,
+            100_000u64.try_into().unwrap(),
+        )
+        .unwrap();
+        let expected = redeem_proceeds(&[redeem_data]).unwrap();
+
+        let before = WalletBalance {
+            height: 100,
+            balance: 2_000_000_000,
+            assets: HashMap::new(),
+        };
+        let confirmed = WalletBalance {
+            height: 102,
+            balance: before.balance + expected.0,
+            assets: HashMap::from([(token_id, 20)]),
+        };
+
+        // The redeem is still unconfirmed at the next block
+        let mut balances = vec![
+            before.clone(),
+            WalletBalance {
+                height: 101,
+                ..before.clone()
+            },
+            confirmed.clone(),
+        ]
+        .into_iter();
+
+        let after = wait_for_balance_change(
+            &before,
+            || {
+                let balance = balances.next().unwrap();
+                async move { Ok(balance) }
+            },
+            Duration::ZERO,
+            5,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(after, confirmed);
+        assert_eq!(balances.len(), 0);
+
+        let lines = balance_change_lines(&before, &after, &expected, &tokens);
+
+        assert_eq!(
+            lines,
+            vec![
+                "Confirmed balance change at height 102: +0.0009 ERG, +20 TKN".to_string(),
+                "Received the expected proceeds".to_string(),
+            ]
+        );
+
+        let unchanged = wait_for_balance_change(
+            &before,
+            || {
+                let balance = before.clone();
+                async move { Ok(balance) }
+            },
+            Duration::ZERO,
+            3,
+        )
+        .await;
+
+        assert!(unchanged.is_err());
     }
 }
@@ -1,51 +1,110 @@
-use ergo_lib::ergo_chain_types::Digest32;
+use anyhow::anyhow;
+use ergo_lib::{
+    ergo_chain_types::Digest32,
+    ergotree_ir::{
+        chain::{
+            address::{Address, AddressEncoder, NetworkPrefix},
+            ergo_box::{BoxId, ErgoBox},
+            token::TokenId,
+        },
+        sigma_protocol::sigma_boolean::ProveDlog,
+    },
+};
+use fraction::ToPrimitive;
 use off_the_grid::{
     boxes::tracked_box::TrackedBox,
-    grid::multigrid_order::{MultiGridOrder, OrderState},
+    grid::multigrid_order::{
+        GridOrderEntries, GridOrderEntry, GridRegisters, MultiGridOrder, OrderState,
+    },
     node::client::NodeClient,
-    units::{Price, TokenStore, UnitAmount, ERG_UNIT},
+    spectrum::{
+        oracle::{PriceSource, SpectrumPriceSource},
+        pool::{erg_price, select_pool, SpectrumPool, SpectrumSwapError},
+    },
+    units::{token_id_hex, Price, TokenStore, Unit, UnitAmount, ERG_UNIT},
+};
+use serde::Serialize;
+use tabled::{
+    settings::{locator::ByColumnName, Disable, Style},
+    Table, Tabled,
 };
 
-use crate::scan_config::ScanConfig;
+use crate::{commands::unique_pools, scan_config::ScanConfig};
+
+use super::{resolve_fee, OutputFormat, PriceOrientation, TableFormat};
 use off_the_grid::units::Fraction;
 
+#[allow(clippy::too_many_arguments)]
 pub async fn handle_grid_list(
     node_client: NodeClient,
     scan_config: ScanConfig,
+    tokens: &TokenStore,
+    default_fee: &str,
     token_id: Option<String>,
+    strategy: Option<String>,
+    quote: Option<String>,
+    mtm: bool,
+    fiat: bool,
+    format: TableFormat,
+    output: OutputFormat,
+    orientation: PriceOrientation,
 ) -> Result<(), anyhow::Error> {
+    // Fees of existing grids are not known, assume the default fee for the break-even
+    let owner_fees = grid_owner_fees(*resolve_fee(None, default_fee)?.as_u64());
+
     let token_id = token_id
         .map(|i| Digest32::try_from(i).map(|i| i.into()))
         .transpose()?;
 
+    let quote_unit = quote
+        .map(|quote| tokens.get_unit_by_id(&quote))
+        .transpose()?;
+
+    let pools = if quote_unit.is_some() || mtm || fiat {
+        unique_pools(
+            node_client
+                .get_scan_unspent(scan_config.n2t_scan_id)
+                .await?
+                .into_iter()
+                .filter_map(|b| b.try_into().ok())
+                .collect(),
+        )
+    } else {
+        Vec::new()
+    };
+
+    let fiat_source = fiat.then(|| SpectrumPriceSource::sigusd(&pools)).flatten();
+
+    if fiat && fiat_source.is_none() {
+        eprintln!("Warning: No ERG/SigUSD pool found, the fiat value is not shown");
+    }
+
+    let fiat_price = fiat_source.as_ref().map(|source| source.erg_price());
+
     let grid_orders = node_client
         .get_scan_unspent(scan_config.wallet_multigrid_scan_id)
         .await?
         .into_iter()
         .filter_map(|b| b.try_into().ok())
         .filter(|b: &TrackedBox<MultiGridOrder>| {
-            token_id
-                .as_ref()
-                .map(|i| b.value.token_id == *i)
-                .unwrap_or(true)
+            matches_list_filters(&b.value, token_id.as_ref(), strategy.as_deref())
         })
         .collect::<Vec<_>>();
 
-    if grid_orders.is_empty() {
+    if grid_orders.is_empty() && output == OutputFormat::Text {
         println!("No grid orders found");
         return Ok(());
     }
 
-    let tokens = TokenStore::load(None)?;
+    let grids = group_grids(grid_orders);
 
-    let name_width = grid_orders
-        .iter()
-        .map(|o| o.value.metadata.as_ref().map(|m| m.len()).unwrap_or(0))
-        .max()
-        .unwrap_or(0);
+    let name_width = grids.iter().map(|g| g.label.len()).max().unwrap_or(0);
 
-    for order in grid_orders {
-        let entries = &order.value.entries;
+    let mut rows = Vec::new();
+    let mut json_entries = Vec::new();
+
+    for grid in grids {
+        let entries = &grid.entries;
 
         let num_buy_orders = entries
             .iter()
@@ -57,22 +116,19 @@ pub async fn handle_grid_list(
             .filter(|o| o.state == OrderState::Sell)
             .count();
 
+        let filled = filled_percentage(entries);
+
         let bid = entries.bid_entry().map(|o| o.bid()).unwrap_or_default();
 
         let ask = entries.ask_entry().map(|o| o.ask()).unwrap_or_default();
 
-        let profit = order.value.profit();
+        let profit = grid.profit;
 
-        let total_value = *order.value.value.as_u64();
+        let total_value = grid.value;
 
-        let total_tokens = order
-            .ergo_box
-            .tokens
-            .as_ref()
-            .map(|t| *t.first().amount.as_u64())
-            .unwrap_or(0);
+        let total_tokens = grid.tokens;
 
-        let token_id = order.value.token_id;
+        let token_id = grid.token_id;
 
         let token_info = tokens.get_unit(&token_id);
         let erg_info = *ERG_UNIT;
@@ -88,92 +144,1274 @@ pub async fn handle_grid_list(
         let ask = to_price(ask);
         let profit_in_token = ask.convert_price(&profit).unwrap();
 
-        let grid_identity = if let Some(grid_identity) = order.value.metadata.as_ref() {
-            String::from_utf8(grid_identity.clone())
-                .unwrap_or_else(|_| format!("{:?}", grid_identity))
-        } else {
-            "No identity".to_string()
+        let grid_identity = grid.label;
+
+        let break_even = break_even_text(entries, owner_fees);
+
+        // Grids that cannot be valued, e.g. as their token has no pool, are shown as n/a
+        // instead of failing the whole list
+        let in_quote = quote_unit.map(|quote_unit| {
+            let values = pool_value_in_quote(
+                quote_unit,
+                &pools,
+                tokens,
+                &total_value,
+                &total_tokens,
+                &profit,
+            );
+
+            (quote_unit, values)
+        });
+
+        let mtm = mtm.then(|| {
+            pool_mark_to_market(&pools, tokens, &total_value, &total_tokens)
+                .map(|value| UnitAmount::new(erg_info, value))
+        });
+
+        let mtm_text = match &mtm {
+            Some(Some(mtm)) => format!(", MTM {}", mtm),
+            Some(None) => ", MTM n/a".to_string(),
+            None => String::new(),
         };
 
+        let in_fiat = fiat_price.as_ref().and_then(|price| {
+            Some((
+                price.convert_price(&profit)?,
+                price.convert_price(&total_value)?,
+            ))
+        });
+
+        let fiat_cell = in_fiat
+            .as_ref()
+            .map(|(profit, total)| format!("{} / {}", profit, total))
+            .unwrap_or_default();
+
+        let fiat_text = in_fiat
+            .as_ref()
+            .map(|(profit, total)| format!(", Fiat profit {} total {}", profit, total))
+            .unwrap_or_default();
+
+        if output == OutputFormat::Json {
+            json_entries.push(GridListEntry {
+                grid_identity,
+                token_id: token_id_hex(&token_id),
+                num_buy: num_buy_orders,
+                num_sell: num_sell_orders,
+                bid: entries
+                    .bid_entry()
+                    .and_then(|_| json_price(&bid, orientation)),
+                ask: entries
+                    .ask_entry()
+                    .and_then(|_| json_price(&ask, orientation)),
+                profit: profit.amount(),
+                total_value: total_value.amount(),
+                total_tokens: total_tokens.amount(),
+                quote_profit: in_quote.map(|(_, values)| values.map(|(_, profit)| profit)),
+                quote_total: in_quote.map(|(_, values)| values.map(|(total, _)| total)),
+                mtm: mtm.map(|mtm| mtm.map(|mtm| mtm.amount())),
+                fiat_profit: in_fiat.as_ref().map(|(profit, _)| profit.amount()),
+                fiat_total: in_fiat.as_ref().map(|(_, total)| total.amount()),
+            });
+            continue;
+        }
+
+        if let Some((quote_unit, values)) = in_quote {
+            let (profit, total) = match values {
+                Some((total_in_quote, profit_in_quote)) => (
+                    UnitAmount::new(quote_unit, profit_in_quote).to_string(),
+                    UnitAmount::new(quote_unit, total_in_quote).to_string(),
+                ),
+                None => ("n/a".to_string(), "n/a".to_string()),
+            };
+
+            if format == TableFormat::Markdown {
+                rows.push(GridListRow {
+                    grid: grid_identity,
+                    sell: num_sell_orders,
+                    buy: num_buy_orders,
+                    filled: format!("{}%", filled),
+                    bid: orientation.orient(&bid).to_string(),
+                    ask: orientation.orient(&ask).to_string(),
+                    profit,
+                    total: format!("{}{}", total, mtm_text),
+                    fiat: fiat_cell,
+                    break_even,
+                });
+                continue;
+            }
+
+            println!(
+                "{: <9$} | {} Sell {} Buy, {}% filled, Profit {}, Total {}{}{}, Break-even {}",
+                grid_identity,
+                num_sell_orders,
+                num_buy_orders,
+                filled,
+                profit,
+                total,
+                mtm_text,
+                fiat_text,
+                break_even,
+                name_width
+            );
+
+            continue;
+        }
+
+        if format == TableFormat::Markdown {
+            rows.push(GridListRow {
+                grid: grid_identity,
+                sell: num_sell_orders,
+                buy: num_buy_orders,
+                filled: format!("{}%", filled),
+                bid: orientation.orient(&bid).to_string(),
+                ask: orientation.orient(&ask).to_string(),
+                profit: format!("{} ({})", profit, profit_in_token),
+                total: format!("{} {}{}", total_value, total_tokens, mtm_text),
+                fiat: fiat_cell,
+                break_even,
+            });
+            continue;
+        }
+
         println!(
-            "{: <9$} | {} Sell {} Buy, Bid {} Ask {}, Profit {} ({}), Total {} {}",
+            "{: <13$} | {} Sell {} Buy, {}% filled, Bid {} Ask {}, Profit {} ({}), Total {} {}{}{}, Break-even {}",
             grid_identity,
             num_sell_orders,
             num_buy_orders,
-            bid.indirect(),
-            ask.indirect(),
+            filled,
+            orientation.orient(&bid),
+            orientation.orient(&ask),
             profit,
             profit_in_token,
             total_value,
             total_tokens,
+            mtm_text,
+            fiat_text,
+            break_even,
             name_width
         );
     }
 
+    if output == OutputFormat::Json {
+        println!("{}", serde_json::to_string_pretty(&json_entries)?);
+    } else if format == TableFormat::Markdown {
+        println!("{}", markdown_table(rows, fiat_price.is_some()));
+    }
+
     Ok(())
 }
 
+/// Grid order boxes sharing an identity and token, listed as a single grid. Boxes without
+/// an identity are never grouped.
+pub(super) struct GridGroup {
+    pub label: String,
+    pub token_id: TokenId,
+    pub strategy: Option<String>,
+    pub boxes: Vec<TrackedBox<MultiGridOrder>>,
+    /// Entries of all boxes of the grid
+    pub entries: GridOrderEntries,
+    pub value: u64,
+    pub tokens: u64,
+    pub profit: u64,
+}
+
+/// Group the grid order boxes by identity and token, keeping the order in which each
+/// grid was first seen
+pub(super) fn group_grids(grid_orders: Vec<TrackedBox<MultiGridOrder>>) -> Vec<GridGroup> {
+    let mut groups: Vec<Vec<TrackedBox<MultiGridOrder>>> = Vec::new();
+
+    for order in grid_orders {
+        let group = groups.iter_mut().find(|orders| {
+            let first = &orders[0].value;
+            first.identity().is_some()
+                && first.identity() == order.value.identity()
+                && first.token_id == order.value.token_id
+        });
+
+        match group {
+            Some(orders) => orders.push(order),
+            None => groups.push(vec![order]),
+        }
+    }
+
+    groups
+        .into_iter()
+        .map(|orders| {
+            let num_boxes = orders.len();
+
+            let label = match num_boxes {
+                1 => grid_label(&orders[0].value),
+                _ => format!("{} ({} boxes)", grid_label(&orders[0].value), num_boxes),
+            };
+
+            let tokens = orders
+                .iter()
+                .flat_map(|o| o.ergo_box.tokens.iter().flat_map(|t| t.iter()))
+                .filter(|t| t.token_id == orders[0].value.token_id)
+                .map(|t| *t.amount.as_u64())
+                .sum();
+
+            GridGroup {
+                label,
+                token_id: orders[0].value.token_id,
+                strategy: orders[0].value.strategy(),
+                entries: orders
+                    .iter()
+                    .flat_map(|o| o.value.entries.iter().copied())
+                    .collect(),
+                value: orders.iter().map(|o| *o.value.value.as_u64()).sum(),
+                tokens,
+                profit: orders.iter().map(|o| o.value.profit()).sum(),
+                boxes: orders,
+            }
+        })
+        .collect()
+}
+
+/// Row of the grid list in table formats
+#[derive(Tabled)]
+struct GridListRow {
+    #[tabled(rename = "Grid")]
+    grid: String,
+    #[tabled(rename = "Sell")]
+    sell: usize,
+    #[tabled(rename = "Buy")]
+    buy: usize,
+    #[tabled(rename = "Filled")]
+    filled: String,
+    #[tabled(rename = "Bid")]
+    bid: String,
+    #[tabled(rename = "Ask")]
+    ask: String,
+    #[tabled(rename = "Profit")]
+    profit: String,
+    #[tabled(rename = "Total")]
+    total: String,
+    #[tabled(rename = "Fiat")]
+    fiat: String,
+    #[tabled(rename = "Break-even")]
+    break_even: String,
+}
+
+/// Grid in the JSON output of the grid list. Amounts are in base units of the ERG and the
+/// grid token, prices in the chosen orientation.
+#[derive(Debug, Serialize)]
+struct GridListEntry {
+    grid_identity: String,
+    token_id: String,
+    num_buy: usize,
+    num_sell: usize,
+    /// Best bid and ask prices, null without such an order or if the price is not a
+    /// finite number
+    bid: Option<f64>,
+    ask: Option<f64>,
+    profit: u64,
+    total_value: u64,
+    total_tokens: u64,
+    /// Profit in base units of the `--quote` unit, null if the grid cannot be valued
+    #[serde(skip_serializing_if = "Option::is_none")]
+    quote_profit: Option<Option<u64>>,
+    /// Total value and tokens in base units of the `--quote` unit, null if the grid cannot
+    /// be valued
+    #[serde(skip_serializing_if = "Option::is_none")]
+    quote_total: Option<Option<u64>>,
+    /// Mark-to-market value in nanoERG with `--mtm`, null if the tokens cannot be valued
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mtm: Option<Option<u64>>,
+    /// Profit in base units of SigUSD with `--fiat`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fiat_profit: Option<u64>,
+    /// Total value in base units of SigUSD with `--fiat`, excluding the tokens
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fiat_total: Option<u64>,
+}
+
+/// Grid in the JSON output of the grid details
+#[derive(Debug, Serialize)]
+struct GridDetails {
+    grid_identity: String,
+    token_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    strategy: Option<String>,
+    entries: Vec<GridEntryDetails>,
+}
+
+/// Entry of a grid in the JSON output of the grid details. The amount is in base units of
+/// the grid token and the price is the bid of buy orders and the ask of sell orders, null
+/// if it is not a finite number.
+#[derive(Debug, PartialEq, Serialize)]
+struct GridEntryDetails {
+    state: OrderState,
+    amount: u64,
+    price: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fill_volume: Option<String>,
+}
+
+/// `price` in `orientation` as a plain number of display units, `None` if it is not a
+/// finite number, e.g. the inverse of a zero price
+fn json_price(price: &Price, orientation: PriceOrientation) -> Option<f64> {
+    orientation
+        .orient(price)
+        .price()
+        .to_f64()
+        .filter(|price| price.is_finite())
+}
+
+/// Miner fees the owner of a grid pays over its life, one transaction creating it and one
+/// redeeming it at `fee` each
+pub(super) fn grid_owner_fees(fee: u64) -> u64 {
+    2 * fee
+}
+
+/// Number of orders that have to complete a buy-sell cycle to cover `fees`, see
+/// [`GridOrderEntries::break_even_orders`]
+pub(super) fn break_even_text(entries: &GridOrderEntries, fees: u64) -> String {
+    match entries.break_even_orders(fees) {
+        Some(1) => "1 order".to_string(),
+        Some(count) => format!("{} orders", count),
+        None => "never".to_string(),
+    }
+}
+
+/// Grid list as a Markdown table, without the fiat column unless `show_fiat` is set
+fn markdown_table(rows: Vec<GridListRow>, show_fiat: bool) -> Table {
+    let mut table = Table::new(rows);
+    table.with(Style::markdown());
+
+    if !show_fiat {
+        table.with(Disable::column(ByColumnName::new("Fiat")));
+    }
+
+    table
+}
+
+/// Total value and profit of a grid, converted into the base unit of `quote_price`.
+/// `token_price` and `quote_price` are the ERG prices of the grid token and the quote unit,
+/// so tokens are converted into the quote unit through ERG.
+fn grid_value_in_quote(
+    token_price: &Price,
+    quote_price: &Price,
+    total_value: &UnitAmount,
+    total_tokens: &UnitAmount,
+    profit: &UnitAmount,
+) -> Option<(u64, u64)> {
+    let ergs_per_quote = quote_price.indirect();
+    let token_to_quote = token_price.chain(&ergs_per_quote)?;
+
+    let value_in_quote = quote_price.convert_price(total_value)?.amount();
+    let tokens_in_quote = token_to_quote.convert_price(total_tokens)?.amount();
+    let profit_in_quote = quote_price.convert_price(profit)?.amount();
+
+    Some((
+        value_in_quote.checked_add(tokens_in_quote)?,
+        profit_in_quote,
+    ))
+}
+
+/// Mark-to-market value of a grid in nanoERG: its ERG plus its tokens valued at
+/// `token_price`, the ERG price of the grid token
+fn mark_to_market(
+    token_price: &Price,
+    total_value: &UnitAmount,
+    total_tokens: &UnitAmount,
+) -> Option<u64> {
+    let tokens_value = token_price.convert_price(total_tokens)?;
+
+    (*tokens_value.unit() == *ERG_UNIT)
+        .then(|| total_value.amount().checked_add(tokens_value.amount()))
+        .flatten()
+}
+
+/// [`grid_value_in_quote`] at the prices of the deepest pools of the grid token and
+/// `quote_unit`, `None` if either has no pool
+fn pool_value_in_quote(
+    quote_unit: Unit,
+    pools: &[TrackedBox<SpectrumPool>],
+    tokens: &TokenStore,
+    total_value: &UnitAmount,
+    total_tokens: &UnitAmount,
+    profit: &UnitAmount,
+) -> Option<(u64, u64)> {
+    let token_price = erg_price(*total_tokens.unit(), pools, tokens).ok()?;
+    let quote_price = erg_price(quote_unit, pools, tokens).ok()?;
+
+    grid_value_in_quote(
+        &token_price,
+        &quote_price,
+        total_value,
+        total_tokens,
+        profit,
+    )
+}
+
+/// [`mark_to_market`] at the price of the deepest pool of the grid token, `None` if it has
+/// no pool
+fn pool_mark_to_market(
+    pools: &[TrackedBox<SpectrumPool>],
+    tokens: &TokenStore,
+    total_value: &UnitAmount,
+    total_tokens: &UnitAmount,
+) -> Option<u64> {
+    let token_price = erg_price(*total_tokens.unit(), pools, tokens).ok()?;
+
+    mark_to_market(&token_price, total_value, total_tokens)
+}
+
+fn matches_list_filters(
+    order: &MultiGridOrder,
+    token_id: Option<&TokenId>,
+    strategy: Option<&str>,
+) -> bool {
+    let token_matches = token_id.map(|i| order.token_id == *i).unwrap_or(true);
+    let strategy_matches = strategy
+        .map(|s| order.strategy().as_deref() == Some(s))
+        .unwrap_or(true);
+
+    token_matches && strategy_matches
+}
+
+/// Identity of the grid followed by its strategy tag, if any
+fn grid_label(order: &MultiGridOrder) -> String {
+    let identity = match order.identity() {
+        Some(identity) => {
+            String::from_utf8(identity.to_vec()).unwrap_or_else(|_| format!("{:?}", identity))
+        }
+        None => "No identity".to_string(),
+    };
+
+    match order.strategy() {
+        Some(strategy) => format!("{} [{}]", identity, strategy),
+        None => identity,
+    }
+}
+
+/// Percentage of the grid's entries that are sell orders. New grids start with only buy
+/// orders, so this is how much of the grid has been filled by the market.
+fn filled_percentage(entries: &GridOrderEntries) -> u64 {
+    let (num_sell, total) = entries.iter().fold((0, 0), |(num_sell, total), e| {
+        let is_sell = (e.state == OrderState::Sell) as u64;
+        (num_sell + is_sell, total + 1)
+    });
+
+    (num_sell * 100).checked_div(total).unwrap_or(0)
+}
+
+#[allow(clippy::too_many_arguments)]
 pub async fn handle_grid_details(
+    node_client: NodeClient,
+    scan_config: ScanConfig,
+    tokens: &TokenStore,
+    grid_identity: Option<String>,
+    box_id: Option<String>,
+    fill_volume: bool,
+    output: OutputFormat,
+    orientation: PriceOrientation,
+) -> Result<(), anyhow::Error> {
+    let grid_orders: Vec<TrackedBox<MultiGridOrder>> = match (grid_identity, box_id) {
+        (_, Some(box_id)) => {
+            let box_id: BoxId = box_id.try_into()?;
+            node_client
+                .box_by_id(&box_id)
+                .await?
+                .map(TrackedBox::try_from)
+                .transpose()?
+                .into_iter()
+                .collect()
+        }
+        (Some(grid_identity), None) => {
+            let grid_identity = grid_identity.into_bytes();
+
+            node_client
+                .get_scan_unspent(scan_config.wallet_multigrid_scan_id)
+                .await?
+                .into_iter()
+                .filter_map(|b| b.try_into().ok())
+                .filter(|b: &TrackedBox<MultiGridOrder>| {
+                    b.value.identity() == Some(&grid_identity[..])
+                })
+                .collect()
+        }
+        (None, None) => return Err(anyhow!("Either a grid identity or a box ID is required")),
+    };
+
+    if grid_orders.is_empty() && output == OutputFormat::Text {
+        println!("No grid order found");
+        return Ok(());
+    }
+
+    let pools = if fill_volume {
+        Some(unique_pools(
+            node_client
+                .get_scan_unspent(scan_config.n2t_scan_id)
+                .await?
+                .into_iter()
+                .filter_map(|b| b.try_into().ok())
+                .collect(),
+        ))
+    } else {
+        None
+    };
+
+    let mut json_grids = Vec::new();
+
+    // Boxes sharing the identity are shown as one grid
+    for grid in group_grids(grid_orders) {
+        let pool = pools
+            .as_ref()
+            .map(|pools| select_pool(pools, grid.token_id, None).map(|p| p.value.clone()))
+            .transpose()?;
+
+        if output == OutputFormat::Json {
+            json_grids.push(GridDetails {
+                grid_identity: grid.label,
+                token_id: token_id_hex(&grid.token_id),
+                strategy: grid.strategy,
+                entries: grid_entry_details(
+                    grid.token_id,
+                    &grid.entries,
+                    pool.as_ref(),
+                    tokens,
+                    orientation,
+                )?,
+            });
+            continue;
+        }
+
+        if grid.boxes.len() > 1 {
+            println!("Grid {}", grid.label);
+        }
+
+        if let Some(strategy) = &grid.strategy {
+            println!("Strategy: {}", strategy);
+        }
+
+        for line in grid_details_lines(
+            grid.token_id,
+            &grid.entries,
+            pool.as_ref(),
+            tokens,
+            orientation,
+        )? {
+            println!("{}", line);
+        }
+    }
+
+    if output == OutputFormat::Json {
+        println!("{}", serde_json::to_string_pretty(&json_grids)?);
+    }
+
+    Ok(())
+}
+
+/// Price an entry is filled at: the bid of buy orders and the ask of sell orders
+pub(super) fn entry_price(entry: &GridOrderEntry) -> Fraction {
+    match entry.state {
+        OrderState::Buy => entry.bid(),
+        OrderState::Sell => entry.ask(),
+    }
+}
+
+/// Entries of the grid for the JSON output, with the trade volume needed to fill each if a
+/// `pool` is given
+fn grid_entry_details(
+    token_id: TokenId,
+    entries: &GridOrderEntries,
+    pool: Option<&SpectrumPool>,
+    tokens: &TokenStore,
+    orientation: PriceOrientation,
+) -> Result<Vec<GridEntryDetails>, SpectrumSwapError> {
+    let token_info = tokens.get_unit(&token_id);
+
+    entries
+        .iter()
+        .map(|entry| {
+            let price = entry_price(entry);
+
+            let fill_volume = pool
+                .map(|pool| fill_volume_description(pool, entry.state, price, tokens))
+                .transpose()?;
+
+            Ok(GridEntryDetails {
+                state: entry.state,
+                amount: *entry.token_amount.as_u64(),
+                price: json_price(&Price::new(token_info, *ERG_UNIT, price), orientation),
+                fill_volume,
+            })
+        })
+        .collect()
+}
+
+/// One line per entry of the grid, with the trade volume needed to fill it if a `pool`
+/// is given
+fn grid_details_lines(
+    token_id: TokenId,
+    entries: &GridOrderEntries,
+    pool: Option<&SpectrumPool>,
+    tokens: &TokenStore,
+    orientation: PriceOrientation,
+) -> Result<Vec<String>, SpectrumSwapError> {
+    let token_info = tokens.get_unit(&token_id);
+    let erg_info = *ERG_UNIT;
+
+    entries
+        .iter()
+        .map(|entry| {
+            let to_price = |amount: Fraction| Price::new(token_info, erg_info, amount);
+
+            let price = entry_price(entry);
+
+            let volume = pool
+                .map(|pool| fill_volume_description(pool, entry.state, price, tokens))
+                .transpose()?;
+
+            let price = to_price(price);
+
+            let amount = UnitAmount::new(token_info, *entry.token_amount.as_u64());
+
+            let state_str = match entry.state {
+                OrderState::Buy => "Buy",
+                OrderState::Sell => "Sell",
+            };
+
+            Ok(match volume {
+                Some(volume) => format!(
+                    "{:>4} {:>8} @ {:>15} | {}",
+                    state_str,
+                    amount.to_string(),
+                    orientation.orient(&price).to_string(),
+                    volume,
+                ),
+                None => format!(
+                    "{:>4} {:>8} @ {:>15}",
+                    state_str,
+                    amount.to_string(),
+                    orientation.orient(&price).to_string(),
+                ),
+            })
+        })
+        .collect()
+}
+
+/// Describe the trade volume needed to move the pool price to the price of an order.
+/// Buy orders are filled when the price drops, sell orders when it rises.
+fn fill_volume_description(
+    pool: &SpectrumPool,
+    state: OrderState,
+    price: Fraction,
+    tokens: &TokenStore,
+) -> Result<String, SpectrumSwapError> {
+    let input = pool.input_to_price(price)?;
+
+    let side = match (&input, state) {
+        (None, _) => None,
+        (Some(input), OrderState::Buy) if input.token_id == pool.asset_y.token_id => {
+            Some("selling")
+        }
+        (Some(input), OrderState::Sell) if input.token_id == pool.asset_x.token_id => {
+            Some("spending")
+        }
+        _ => None,
+    };
+
+    Ok(match (input, side) {
+        (Some(input), Some(side)) => {
+            let unit = tokens.get_unit(&input.token_id);
+            format!(
+                "fills after {} {}",
+                side,
+                UnitAmount::new(unit, *input.amount.as_u64())
+            )
+        }
+        _ => "fillable at current price".to_string(),
+    })
+}
+
+pub async fn handle_grid_verify(
     node_client: NodeClient,
     scan_config: ScanConfig,
     grid_identity: String,
 ) -> Result<(), anyhow::Error> {
     let grid_identity = grid_identity.into_bytes();
 
-    let grid_order = node_client
+    // Parse without validation so that boxes which fail the checks are still found
+    let grid_orders: Vec<_> = node_client
         .get_scan_unspent(scan_config.wallet_multigrid_scan_id)
         .await?
         .into_iter()
-        .filter_map(|b| b.try_into().ok())
-        .find(|b: &TrackedBox<MultiGridOrder>| {
-            b.value
-                .metadata
-                .as_ref()
-                .map(|i| *i == *grid_identity)
-                .unwrap_or(false)
-        });
+        .filter_map(|b| {
+            MultiGridOrder::from_box_unchecked(&b)
+                .ok()
+                .map(|order| (b, order))
+        })
+        .filter(|(_, order)| order.identity() == Some(&grid_identity[..]))
+        .collect();
 
-    match grid_order {
-        Some(grid_order) => {
-            let tokens = TokenStore::load(None)?;
+    if grid_orders.is_empty() {
+        println!("No grid order found");
+        return Ok(());
+    }
 
-            let token_id = grid_order.value.token_id;
+    let mut num_failed = 0;
 
-            let token_info = tokens.get_unit(&token_id);
-            let erg_info = *ERG_UNIT;
+    for (ergo_box, order) in grid_orders {
+        let box_id = String::from(ergo_box.box_id());
+        let discrepancies = order.box_discrepancies(&ergo_box);
 
-            for entry in grid_order.value.entries.iter() {
-                let bid = entry.bid();
-                let ask = entry.ask();
+        if discrepancies.is_empty() {
+            println!("{}: OK", box_id);
+        } else {
+            num_failed += 1;
+            println!("{}: FAILED", box_id);
+            for discrepancy in discrepancies {
+                println!("  {}", discrepancy);
+            }
+        }
+    }
 
-                let to_price = |amount: Fraction| Price::new(token_info, erg_info, amount);
+    if num_failed > 0 {
+        Err(anyhow!(
+            "{} grid order box(es) failed verification",
+            num_failed
+        ))
+    } else {
+        Ok(())
+    }
+}
 
-                let price = match entry.state {
-                    OrderState::Buy => bid,
-                    OrderState::Sell => ask,
-                };
+pub async fn handle_grid_decode(
+    node_client: NodeClient,
+    tokens: &TokenStore,
+    box_id: String,
+) -> Result<(), anyhow::Error> {
+    let box_id: BoxId = box_id.try_into()?;
+
+    let ergo_box = node_client
+        .box_by_id(&box_id)
+        .await?
+        .ok_or_else(|| anyhow!("Box {} not found", String::from(box_id)))?;
+
+    for line in grid_register_lines(&ergo_box, tokens, node_client.network_prefix()) {
+        println!("{}", line);
+    }
+
+    Ok(())
+}
 
-                let price = to_price(price);
+/// One line per grid register of the box, decoded as far as possible, followed by the
+/// result of parsing the whole box as a grid order
+fn grid_register_lines(
+    ergo_box: &ErgoBox,
+    tokens: &TokenStore,
+    network_prefix: NetworkPrefix,
+) -> Vec<String> {
+    let registers = GridRegisters::decode(ergo_box);
+    let erg_info = *ERG_UNIT;
 
-                let amount = UnitAmount::new(token_info, *entry.token_amount.as_u64());
+    let mut lines = vec![match &registers.owner_ec_point {
+        Ok(owner) => format!(
+            "R4 owner: {}",
+            AddressEncoder::encode_address_as_string(
+                network_prefix,
+                &Address::P2Pk(ProveDlog::new(owner.clone()))
+            )
+        ),
+        Err(e) => format!("R4 owner: {}", e),
+    }];
+
+    match &registers.entries {
+        Ok(entries) => {
+            lines.push(format!("R5 entries: {}", entries.iter().count()));
+
+            lines.extend(entries.iter().map(|entry| {
+                let amount = match &registers.token_id {
+                    Ok(token_id) => {
+                        UnitAmount::new(tokens.get_unit(token_id), entry.order_amount()).to_string()
+                    }
+                    Err(_) => entry.order_amount().to_string(),
+                };
 
                 let state_str = match entry.state {
                     OrderState::Buy => "Buy",
                     OrderState::Sell => "Sell",
                 };
 
-                println!(
-                    "{:>4} {:>8} @ {:>15}",
+                format!(
+                    "  {} {}, bid {}, ask {}",
                     state_str,
-                    amount.to_string(),
-                    price.indirect().to_string(),
-                );
-            }
-            Ok(())
-        }
-        None => {
-            println!("No grid order found");
-            Ok(())
+                    amount,
+                    UnitAmount::new(erg_info, entry.bid_value),
+                    UnitAmount::new(erg_info, entry.ask_value)
+                )
+            }));
         }
+        Err(e) => lines.push(format!("R5 entries: {}", e)),
+    }
+
+    lines.push(match &registers.token_id {
+        Ok(token_id) => match tokens.get_unit(token_id) {
+            Unit::Known(info) => format!("R6 token: {} ({})", info.name, String::from(*token_id)),
+            Unit::Unknown(_) => format!("R6 token: {}", String::from(*token_id)),
+        },
+        Err(e) => format!("R6 token: {}", e),
+    });
+
+    lines.push(match &registers.metadata {
+        Ok(metadata) => format!(
+            "R7 metadata: identity {}, strategy {}",
+            String::from_utf8_lossy(&metadata.identity),
+            metadata.strategy.as_deref().unwrap_or("-")
+        ),
+        Err(e) => format!("R7 metadata: {}", e),
+    });
+
+    lines.push(match MultiGridOrder::try_from(ergo_box) {
+        Ok(_) => "Grid order: valid".to_string(),
+        Err(e) => format!("Grid order: {}", e),
+    });
+
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use ergo_lib::{
+        chain::transaction::TxId,
+        ergo_chain_types::Digest32,
+        ergotree_ir::chain::{
+            address::NetworkPrefix,
+            ergo_box::{ErgoBox, NonMandatoryRegisterId, NonMandatoryRegisters},
+            token::TokenId,
+        },
+    };
+    use off_the_grid::{
+        boxes::tracked_box::TrackedBox,
+        grid::multigrid_order::{
+            GridMetadata, GridOrderEntries, GridOrderEntry, MultiGridOrder, OrderState,
+        },
+        spectrum::pool::{erg_price, SpectrumPool},
+        units::{token_id_hex, Fraction, Price, TokenInfo, TokenStore, UnitAmount, ERG_UNIT},
+    };
+
+    use crate::commands::test_util::{grid_box, test_order, tracked_pool};
+
+    use super::{
+        filled_percentage, grid_details_lines, grid_entry_details, grid_label, grid_register_lines,
+        grid_value_in_quote, json_price, mark_to_market, markdown_table, matches_list_filters,
+        pool_mark_to_market, pool_value_in_quote, GridListEntry, GridListRow, PriceOrientation,
+    };
+
+    fn entries(states: &[OrderState]) -> GridOrderEntries {
+        states
+            .iter()
+            .map(|state| GridOrderEntry::new(*state, 10.try_into().unwrap(), 100, 110))
+            .collect()
+    }
+
+    #[test]
+    fn filled_percentage_of_grids() {
+        use OrderState::{Buy, Sell};
+
+        assert_eq!(filled_percentage(&entries(&[Buy, Buy, Buy, Buy])), 0);
+        assert_eq!(filled_percentage(&entries(&[Sell, Buy, Buy, Buy])), 25);
+        assert_eq!(filled_percentage(&entries(&[Sell, Sell, Sell, Buy])), 75);
+        assert_eq!(filled_percentage(&entries(&[Sell, Sell, Buy])), 66);
+        assert_eq!(filled_percentage(&entries(&[Sell, Sell])), 100);
+        assert_eq!(filled_percentage(&entries(&[])), 0);
+    }
+
+    #[test]
+    fn fiat_column_only_with_price_source() {
+        let row = || GridListRow {
+            grid: "comet".to_string(),
+            sell: 1,
+            buy: 1,
+            filled: "50%".to_string(),
+            bid: "1 ERG/COMET".to_string(),
+            ask: "2 ERG/COMET".to_string(),
+            profit: "1 ERG".to_string(),
+            total: "10 ERG".to_string(),
+            fiat: "1.5 SigUSD / 15 SigUSD".to_string(),
+            break_even: "1 order".to_string(),
+        };
+
+        let with_fiat = markdown_table(vec![row()], true).to_string();
+        assert!(with_fiat.contains("| Fiat "));
+        assert!(with_fiat.contains("1.5 SigUSD / 15 SigUSD"));
+
+        let without_fiat = markdown_table(vec![row()], false).to_string();
+        assert!(!without_fiat.contains("Fiat"));
+        assert!(without_fiat.contains("| Break-even |"));
+    }
+
+    #[test]
+    fn details_from_box_by_id() {
+        let token_id = Digest32::from([1u8; 32]).into();
+        let tokens = TokenStore::with_tokens(vec![TokenInfo {
+            token_id,
+            name: "TKN".to_string(),
+            decimals: 0,
+        }]);
+
+        let entries = GridOrderEntries::new(vec![
+            GridOrderEntry::new(
+                OrderState::Sell,
+                10.try_into().unwrap(),
+                1_000_000_000,
+                1_100_000_000,
+            ),
+            GridOrderEntry::new(
+                OrderState::Buy,
+                20.try_into().unwrap(),
+                1_800_000_000,
+                2_000_000_000,
+            ),
+        ]);
+
+        let order_box = grid_box(token_id, entries, Some(b"by-id".to_vec()), 0).ergo_box;
+
+        // The box as returned by the node's utxo/byId endpoint
+        let response = serde_json::to_string(&order_box).unwrap();
+        let fetched: ErgoBox = serde_json::from_str(&response).unwrap();
+        assert_eq!(fetched.box_id(), order_box.box_id());
+
+        let order = MultiGridOrder::try_from(&fetched).unwrap();
+        assert_eq!(order.metadata.as_deref(), Some(&b"by-id"[..]));
+
+        let lines = grid_details_lines(
+            order.token_id,
+            &order.entries,
+            None,
+            &tokens,
+            PriceOrientation::Indirect,
+        )
+        .unwrap();
+        assert_eq!(
+            lines,
+            vec![
+                "Sell   10 TKN @       9 ERG/TKN",
+                " Buy   20 TKN @      11 ERG/TKN"
+            ]
+        );
+
+        let lines = grid_details_lines(
+            order.token_id,
+            &order.entries,
+            None,
+            &tokens,
+            PriceOrientation::Direct,
+        )
+        .unwrap();
+        assert_eq!(
+            lines,
+            vec![
+                "Sell   10 TKN @    0.11 TKN/ERG",
+                " Buy   20 TKN @    0.09 TKN/ERG"
+            ]
+        );
+    }
+
+    #[test]
+    fn details_as_json() {
+        let token_id = Digest32::from([1u8; 32]).into();
+        let tokens = TokenStore::with_tokens(vec![TokenInfo {
+            token_id,
+            name: "TKN".to_string(),
+            decimals: 0,
+        }]);
+
+        let entries = GridOrderEntries::new(vec![
+            GridOrderEntry::new(
+                OrderState::Sell,
+                10.try_into().unwrap(),
+                1_000_000_000,
+                1_100_000_000,
+            ),
+            GridOrderEntry::new(
+                OrderState::Buy,
+                20.try_into().unwrap(),
+                1_800_000_000,
+                2_000_000_000,
+            ),
+        ]);
+
+        let details =
+            grid_entry_details(token_id, &entries, None, &tokens, PriceOrientation::Direct)
+                .unwrap();
+
+        assert_eq!(
+            serde_json::to_value(details).unwrap(),
+            serde_json::json!([
+                { "state": "sell", "amount": 10, "price": 0.11 },
+                { "state": "buy", "amount": 20, "price": 0.09 },
+            ])
+        );
+    }
+
+    #[test]
+    fn grid_list_json_prices() {
+        let token_id: TokenId = Digest32::from([1u8; 32]).into();
+        let tokens = TokenStore::with_tokens(vec![TokenInfo {
+            token_id,
+            name: "TKN".to_string(),
+            decimals: 0,
+        }]);
+        let token_unit = tokens.get_unit(&token_id);
+        let price = |value: u64| Price::new(token_unit, *ERG_UNIT, Fraction::new(value, 1u64));
+
+        // A bid of 0 nanoERG per token has no finite price in tokens per ERG
+        let entry = GridListEntry {
+            grid_identity: "grid".to_string(),
+            token_id: token_id_hex(&token_id),
+            num_buy: 1,
+            num_sell: 1,
+            bid: json_price(&price(0), PriceOrientation::Indirect),
+            ask: json_price(&price(500_000_000), PriceOrientation::Indirect),
+            profit: 0,
+            total_value: 1_000_000,
+            total_tokens: 10,
+            quote_profit: None,
+            quote_total: None,
+            mtm: Some(None),
+            fiat_profit: None,
+            fiat_total: None,
+        };
+
+        assert_eq!(
+            serde_json::to_value(&entry).unwrap(),
+            serde_json::json!({
+                "grid_identity": "grid",
+                "token_id": token_id_hex(&token_id),
+                "num_buy": 1,
+                "num_sell": 1,
+                "bid": null,
+                "ask": 2.0,
+                "profit": 0,
+                "total_value": 1_000_000,
+                "total_tokens": 10,
+                "mtm": null,
+            })
+        );
+    }
+
+    #[test]
+    fn filter_by_strategy() {
+        let token_id = Digest32::from([1u8; 32]).into();
+
+        let grids: Vec<MultiGridOrder> = [
+            ("comet-1", Some("dca")),
+            ("comet-2", Some("range")),
+            ("comet-3", Some("dca")),
+            ("comet-4", None),
+        ]
+        .into_iter()
+        .map(|(identity, strategy)| {
+            let metadata = GridMetadata::new(identity, strategy.map(String::from)).to_bytes();
+            let entries = entries(&[OrderState::Buy, OrderState::Buy]);
+            grid_box(token_id, entries, Some(metadata), 0).value
+        })
+        .collect();
+
+        let filtered: Vec<String> = grids
+            .iter()
+            .filter(|g| matches_list_filters(g, None, Some("dca")))
+            .map(grid_label)
+            .collect();
+        assert_eq!(filtered, vec!["comet-1 [dca]", "comet-3 [dca]"]);
+
+        let other_token = Digest32::zero().into();
+        assert!(!grids
+            .iter()
+            .any(|g| matches_list_filters(g, Some(&other_token), Some("dca"))));
+
+        assert_eq!(
+            grids
+                .iter()
+                .filter(|g| matches_list_filters(g, Some(&token_id), None))
+                .count(),
+            4
+        );
+        assert_eq!(grid_label(&grids[3]), "comet-4");
+    }
+
+    #[test]
+    fn grid_value_in_third_token() {
+        let grid_token: TokenId = Digest32::from([1u8; 32]).into();
+        let quote_token: TokenId = Digest32::from([2u8; 32]).into();
+
+        let tokens = TokenStore::with_tokens(vec![
+            TokenInfo {
+                token_id: grid_token,
+                name: "GRID".to_string(),
+                decimals: 0,
+            },
+            TokenInfo {
+                token_id: quote_token,
+                name: "QUOTE".to_string(),
+                decimals: 0,
+            },
+        ]);
+
+        // 1 ERG per GRID and 0.002 ERG per QUOTE
+        let pools = vec![
+            tracked_pool(10, grid_token, 1_000_000_000_000, 1_000),
+            tracked_pool(20, quote_token, 1_000_000_000_000, 500_000),
+        ];
+
+        let grid_unit = tokens.get_unit(&grid_token);
+        let quote_unit = tokens.get_unit(&quote_token);
+
+        let token_price = erg_price(grid_unit, &pools, &tokens).unwrap();
+        let quote_price = erg_price(quote_unit, &pools, &tokens).unwrap();
+
+        // 3 ERG and 10 GRID, of which 0.5 ERG is profit
+        let total_value = UnitAmount::new(*ERG_UNIT, 3_000_000_000);
+        let total_tokens = UnitAmount::new(grid_unit, 10);
+        let profit = UnitAmount::new(*ERG_UNIT, 500_000_000);
+
+        assert_eq!(
+            grid_value_in_quote(
+                &token_price,
+                &quote_price,
+                &total_value,
+                &total_tokens,
+                &profit
+            ),
+            Some((1_500 + 5_000, 250))
+        );
+
+        let erg_quote = erg_price(*ERG_UNIT, &pools, &tokens).unwrap();
+
+        assert_eq!(
+            grid_value_in_quote(
+                &token_price,
+                &erg_quote,
+                &total_value,
+                &total_tokens,
+                &profit
+            ),
+            Some((13_000_000_000, 500_000_000))
+        );
+    }
+
+    #[test]
+    fn mark_to_market_at_pool_price() {
+        let grid_token: TokenId = Digest32::from([1u8; 32]).into();
+        let tokens = TokenStore::with_tokens(vec![TokenInfo {
+            token_id: grid_token,
+            name: "GRID".to_string(),
+            decimals: 2,
+        }]);
+
+        // 0.5 ERG per GRID
+        let pools = vec![tracked_pool(10, grid_token, 1_000_000_000_000, 200_000)];
+        let grid_unit = tokens.get_unit(&grid_token);
+        let token_price = erg_price(grid_unit, &pools, &tokens).unwrap();
+
+        // 3 ERG and 10 GRID
+        let total_value = UnitAmount::new(*ERG_UNIT, 3_000_000_000);
+        let total_tokens = UnitAmount::new(grid_unit, 1_000);
+
+        assert_eq!(
+            mark_to_market(&token_price, &total_value, &total_tokens),
+            Some(8_000_000_000)
+        );
+
+        // Values in another unit than ERG are not mixed in
+        let other_price = erg_price(*ERG_UNIT, &pools, &tokens).unwrap();
+        assert_eq!(
+            mark_to_market(&other_price, &total_value, &total_tokens),
+            None
+        );
+    }
+
+    #[test]
+    fn grid_without_pool_not_valued() {
+        let grid_token: TokenId = Digest32::from([1u8; 32]).into();
+        let quote_token: TokenId = Digest32::from([2u8; 32]).into();
+        let tokens = TokenStore::default();
+
+        let grid_unit = tokens.get_unit(&grid_token);
+        let quote_unit = tokens.get_unit(&quote_token);
+
+        let total_value = UnitAmount::new(*ERG_UNIT, 3_000_000_000);
+        let total_tokens = UnitAmount::new(grid_unit, 10);
+        let profit = UnitAmount::new(*ERG_UNIT, 500_000_000);
+
+        let value = |pools: &[TrackedBox<SpectrumPool>]| {
+            (
+                pool_value_in_quote(
+                    quote_unit,
+                    pools,
+                    &tokens,
+                    &total_value,
+                    &total_tokens,
+                    &profit,
+                ),
+                pool_mark_to_market(pools, &tokens, &total_value, &total_tokens),
+            )
+        };
+
+        // Only the quote token has a pool
+        let quote_pool = tracked_pool(20, quote_token, 1_000_000_000_000, 500_000);
+        assert_eq!(value(std::slice::from_ref(&quote_pool)), (None, None));
+
+        // Only the grid token has a pool
+        let grid_pool = tracked_pool(10, grid_token, 1_000_000_000_000, 1_000);
+        let (in_quote, mtm) = value(std::slice::from_ref(&grid_pool));
+        assert_eq!(in_quote, None);
+        assert_eq!(mtm, Some(13_000_000_000));
+
+        let (in_quote, _) = value(&[grid_pool, quote_pool]);
+        assert_eq!(in_quote, Some((1_500 + 5_000, 250)));
+    }
+
+    #[test]
+    fn decode_box_with_missing_register() {
+        let token_id = Digest32::from([1u8; 32]).into();
+
+        let order = test_order(
+            token_id,
+            entries(&[OrderState::Sell, OrderState::Buy]),
+            Some(b"decode".to_vec()),
+        );
+        let mut candidate = order.into_box_candidate(0).unwrap();
+
+        // Registers must be densely packed, so drop everything after R5
+        let registers = &candidate.additional_registers;
+        candidate.additional_registers = NonMandatoryRegisters::new(HashMap::from([
+            (
+                NonMandatoryRegisterId::R4,
+                registers
+                    .get_constant(NonMandatoryRegisterId::R4)
+                    .unwrap()
+                    .clone(),
+            ),
+            (
+                NonMandatoryRegisterId::R5,
+                registers
+                    .get_constant(NonMandatoryRegisterId::R5)
+                    .unwrap()
+                    .clone(),
+            ),
+        ]))
+        .unwrap();
+
+        let ergo_box = ErgoBox::from_box_candidate(&candidate, TxId::zero(), 0).unwrap();
+
+        let lines = grid_register_lines(&ergo_box, &TokenStore::default(), NetworkPrefix::Mainnet);
+
+        assert!(lines[0].starts_with("R4 owner: 9"));
+        assert_eq!(lines[1], "R5 entries: 2");
+        assert_eq!(lines[2], "  Sell 10, bid 0.0000001 ERG, ask 0.00000011 ERG");
+        assert_eq!(lines[4], "R6 token: Missing register value at R6");
+        assert_eq!(lines[5], "R7 metadata: Missing register value at R7");
+        assert_eq!(lines[6], "Grid order: Missing register value at R6");
+
+        let lines = grid_register_lines(&ergo_box, &TokenStore::default(), NetworkPrefix::Testnet);
+
+        assert!(lines[0].starts_with("R4 owner: 3"));
     }
 }
@@ -0,0 +1,421 @@
+use anyhow::anyhow;
+use clap::Parser;
+use ergo_lib::{
+    ergotree_ir::chain::{
+        address::Address,
+        ergo_box::{box_value::BoxValue, ErgoBox},
+        token::Token,
+    },
+    wallet::box_selector::{BoxSelector, ErgoBoxAssetsData, SimpleBoxSelector},
+};
+use off_the_grid::{
+    boxes::{tracked_box::TrackedBox, wallet_box::WalletBox},
+    grid::multigrid_order::{MultiGridOrder, MultiGridOrderError, DEFAULT_MIN_BOX_VALUE},
+    node::client::NodeClient,
+    units::TokenStore,
+};
+use thiserror::Error;
+use tokio::try_join;
+
+use crate::{
+    commands::{warn_wallet_error, AddressScriptError},
+    scan_config::ScanConfig,
+};
+
+use super::{
+    resolve_fee, IntoSummarizedTransaction, MinerFeeValue, SummarizedInput, SummarizedOutput,
+    SummarizedTransaction,
+};
+
+#[derive(Parser)]
+pub struct TrimOptions {
+    #[clap(short = 'i', long, help = "Grid group identity")]
+    grid_identity: String,
+    #[clap(
+        short,
+        long,
+        required = true,
+        value_delimiter = ',',
+        help = "Indices of the entries to remove, starting at 0 in the order shown by `grid details`"
+    )]
+    remove: Vec<usize>,
+    #[clap(
+        short,
+        long,
+        help = "transaction fee value [default: default_fee of the node config]"
+    )]
+    fee: Option<String>,
+}
+
+#[derive(Error, Debug)]
+pub enum TrimGridError {
+    #[error(transparent)]
+    MultiGridOrder(#[from] MultiGridOrderError),
+
+    #[error(transparent)]
+    AddressScript(#[from] AddressScriptError),
+}
+
+pub async fn handle_grid_trim(
+    node_client: &NodeClient,
+    scan_config: ScanConfig,
+    default_fee: &str,
+    options: TrimOptions,
+) -> anyhow::Result<TrimGridData> {
+    let TrimOptions {
+        grid_identity,
+        remove,
+        fee,
+    } = options;
+
+    let grid_identity = grid_identity.into_bytes();
+
+    let fee_value = resolve_fee(fee.as_deref(), default_fee)?;
+
+    let grid_order = node_client
+        .get_scan_unspent(scan_config.wallet_multigrid_scan_id)
+        .await?
+        .into_iter()
+        .filter_map(|b| b.try_into().ok())
+        .find(|b: &TrackedBox<MultiGridOrder>| b.value.identity() == Some(&grid_identity[..]))
+        .ok_or_else(|| anyhow!("Grid order not found"))?;
+
+    let (wallet_boxes, wallet_status) = try_join!(
+        node_client.wallet_boxes_unspent(),
+        node_client.wallet_status()
+    )?;
+
+    wallet_status.error_if_locked()?;
+    warn_wallet_error(&wallet_status);
+
+    let owner_address = wallet_status.change_address()?;
+
+    build_trim_tx(grid_order, &remove, wallet_boxes, owner_address, fee_value)
+}
+
+/// Spend `order` into a grid without the entries at `remove`. The value and tokens that
+/// were locked by the removed entries, minus the fee, are returned to `change_address`.
+/// If the freed value does not cover the fee and the minimum value of a box for the freed
+/// tokens, the rest is taken from `wallet_boxes`. Freed value too small for a box of its
+/// own is added to the fee.
+fn build_trim_tx(
+    order: TrackedBox<MultiGridOrder>,
+    remove: &[usize],
+    wallet_boxes: Vec<WalletBox<ErgoBox>>,
+    change_address: Address,
+    fee_value: BoxValue,
+) -> anyhow::Result<TrimGridData> {
+    let grid_output = order.value.clone().without_entries(remove)?;
+
+    let freed_value = order
+        .ergo_box
+        .value
+        .as_u64()
+        .checked_sub(*grid_output.value.as_u64())
+        .ok_or(anyhow!("Value overflow"))?;
+
+    let freed_tokens = order.value.entries.token_amount() - grid_output.entries.token_amount();
+
+    let tokens = if freed_tokens > 0 {
+        let token: Token = (grid_output.token_id, freed_tokens.try_into()?).into();
+        Some(vec![token].try_into()?)
+    } else {
+        None
+    };
+
+    // Freed tokens need a box of their own, which has to hold at least the minimum value
+    let token_box_value = if tokens.is_some() {
+        DEFAULT_MIN_BOX_VALUE
+    } else {
+        0
+    };
+
+    let required_value = fee_value
+        .as_u64()
+        .checked_add(token_box_value)
+        .ok_or(anyhow!("Value overflow"))?;
+
+    let (selected_boxes, mut change_boxes, change_value, fee_value) =
+        match required_value.checked_sub(freed_value) {
+            Some(missing) if missing > 0 => {
+                let selection =
+                    SimpleBoxSelector::new().select(wallet_boxes, missing.try_into()?, &[])?;
+
+                let change_boxes = selection
+                    .change_boxes
+                    .into_iter()
+                    .map(|cb| WalletBox::new(cb, change_address.clone()))
+                    .collect();
+
+                (
+                    selection.boxes.into(),
+                    change_boxes,
+                    token_box_value,
+                    fee_value,
+                )
+            }
+            _ => {
+                let change_value = freed_value - *fee_value.as_u64();
+
+                if tokens.is_none() && change_value < DEFAULT_MIN_BOX_VALUE {
+                    (
+                        vec![],
+                        vec![],
+                        0,
+                        fee_value.as_u64().saturating_add(change_value).try_into()?,
+                    )
+                } else {
+                    (vec![], vec![], change_value, fee_value)
+                }
+            }
+        };
+
+    if change_value > 0 {
+        change_boxes.insert(
+            0,
+            WalletBox::new(
+                ErgoBoxAssetsData {
+                    value: change_value.try_into()?,
+                    tokens,
+                },
+                change_address,
+            ),
+        );
+    }
+
+    Ok(TrimGridData {
+        order,
+        grid_output,
+        selected_boxes,
+        change_boxes,
+        fee_value: MinerFeeValue(fee_value),
+    })
+}
+
+pub struct TrimGridData {
+    order: TrackedBox<MultiGridOrder>,
+    grid_output: MultiGridOrder,
+    selected_boxes: Vec<WalletBox<ErgoBox>>,
+    change_boxes: Vec<WalletBox<ErgoBoxAssetsData>>,
+    fee_value: MinerFeeValue,
+}
+
+impl IntoSummarizedTransaction for TrimGridData {
+    type Error = TrimGridError;
+
+    fn into_summarized_transaction(
+        self,
+        token_store: &TokenStore,
+    ) -> Result<SummarizedTransaction, Self::Error> {
+        let creation_height = self
+            .selected_boxes
+            .iter()
+            .map(|b| b.assets.creation_height)
+            .chain(std::iter::once(self.order.ergo_box.creation_height))
+            .max()
+            .unwrap_or(0);
+
+        let inputs = std::iter::once(SummarizedInput::new(self.order, token_store))
+            .chain(
+                self.selected_boxes
+                    .into_iter()
+                    .map(|b| SummarizedInput::new(b, token_store)),
+            )
+            .collect();
+
+        let mut outputs = vec![SummarizedOutput::new(
+            self.grid_output,
+            token_store,
+            creation_height,
+        )?];
+
+        for change_box in self.change_boxes {
+            outputs.push(SummarizedOutput::new(
+                change_box,
+                token_store,
+                creation_height,
+            )?);
+        }
+
+        outputs.push(
+            SummarizedOutput::new(self.fee_value, token_store, creation_height)
+                .expect("Fee output"),
+        );
+
+        Ok(SummarizedTransaction { inputs, outputs })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ergo_lib::{
+        chain::transaction::TxId,
+        ergo_chain_types::{ec_point::generator, Digest32},
+        ergotree_ir::{
+            chain::{
+                address::Address,
+                ergo_box::{ErgoBox, ErgoBoxCandidate, NonMandatoryRegisters},
+            },
+            sigma_protocol::sigma_boolean::ProveDlog,
+        },
+    };
+    use off_the_grid::{
+        boxes::wallet_box::WalletBox,
+        grid::multigrid_order::{
+            GridOrderEntries, GridOrderEntry, OrderState, DEFAULT_MIN_BOX_VALUE,
+        },
+    };
+
+    use crate::commands::test_util::grid_box;
+
+    use super::build_trim_tx;
+
+    fn wallet_box(owner_address: &Address, value: u64) -> WalletBox<ErgoBox> {
+        let candidate = ErgoBoxCandidate {
+            value: value.try_into().unwrap(),
+            ergo_tree: owner_address.script().unwrap(),
+            tokens: None,
+            additional_registers: NonMandatoryRegisters::empty(),
+            creation_height: 0,
+        };
+
+        WalletBox::new(
+            ErgoBox::from_box_candidate(&candidate, TxId::zero(), 1).unwrap(),
+            owner_address.clone(),
+        )
+    }
+
+    #[test]
+    fn trim_two_entries() {
+        let owner_address = Address::P2Pk(ProveDlog::new(generator()));
+
+        let entries = GridOrderEntries::new(vec![
+            GridOrderEntry::new(
+                OrderState::Buy,
+                10.try_into().unwrap(),
+                100_000_000,
+                110_000_000,
+            ),
+            GridOrderEntry::new(
+                OrderState::Buy,
+                10.try_into().unwrap(),
+                120_000_000,
+                130_000_000,
+            ),
+            GridOrderEntry::new(
+                OrderState::Sell,
+                10.try_into().unwrap(),
+                140_000_000,
+                150_000_000,
+            ),
+            GridOrderEntry::new(
+                OrderState::Sell,
+                10.try_into().unwrap(),
+                160_000_000,
+                170_000_000,
+            ),
+        ]);
+
+        let order_box = grid_box(Digest32::zero().into(), entries, Some(b"trim".to_vec()), 0);
+
+        let fee = 1_000_000u64;
+
+        assert!(build_trim_tx(
+            order_box.clone(),
+            &[0, 4],
+            vec![],
+            owner_address.clone(),
+            fee.try_into().unwrap(),
+        )
+        .is_err());
+
+        let trim_data = build_trim_tx(
+            order_box.clone(),
+            &[1, 3],
+            vec![],
+            owner_address.clone(),
+            fee.try_into().unwrap(),
+        )
+        .unwrap();
+
+        let remaining: Vec<_> = trim_data.grid_output.entries.iter().collect();
+        assert_eq!(remaining.len(), 2);
+        assert_eq!(remaining[0].bid_value, 100_000_000);
+        assert_eq!(remaining[1].bid_value, 140_000_000);
+
+        assert_eq!(
+            *trim_data.grid_output.value.as_u64(),
+            DEFAULT_MIN_BOX_VALUE + 100_000_000
+        );
+        assert_eq!(trim_data.grid_output.entries.token_amount(), 10);
+
+        assert!(trim_data.selected_boxes.is_empty());
+
+        let change = &trim_data.change_boxes[0].assets;
+        assert_eq!(*change.value.as_u64(), 120_000_000 - fee);
+
+        let change_tokens = change.tokens.as_ref().unwrap();
+        assert_eq!(*change_tokens.first().amount.as_u64(), 10);
+
+        // Removing only sell entries frees no value, so the fee and the value of the box
+        // for the freed tokens come from the wallet
+        let wallet_value = 1_000_000_000;
+        let trim_data = build_trim_tx(
+            order_box,
+            &[3],
+            vec![wallet_box(&owner_address, wallet_value)],
+            owner_address,
+            fee.try_into().unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(trim_data.selected_boxes.len(), 1);
+        assert_eq!(*trim_data.fee_value.0.as_u64(), fee);
+
+        let change_values: Vec<_> = trim_data
+            .change_boxes
+            .iter()
+            .map(|b| *b.assets.value.as_u64())
+            .collect();
+        assert_eq!(
+            change_values,
+            vec![
+                DEFAULT_MIN_BOX_VALUE,
+                wallet_value - DEFAULT_MIN_BOX_VALUE - fee
+            ]
+        );
+        assert!(trim_data.change_boxes[0].assets.tokens.is_some());
+    }
+
+    #[test]
+    fn freed_dust_added_to_fee() {
+        let owner_address = Address::P2Pk(ProveDlog::new(generator()));
+        let fee = 1_000_000u64;
+        let dust = 1_000;
+
+        let entries = GridOrderEntries::new(vec![
+            GridOrderEntry::new(OrderState::Buy, 10.try_into().unwrap(), fee + dust, fee * 2),
+            GridOrderEntry::new(
+                OrderState::Buy,
+                10.try_into().unwrap(),
+                100_000_000,
+                110_000_000,
+            ),
+        ]);
+
+        let token_id = Digest32::from([1u8; 32]).into();
+        let order_box = grid_box(token_id, entries, Some(b"trim".to_vec()), 0);
+
+        let trim_data = build_trim_tx(
+            order_box,
+            &[0],
+            vec![],
+            owner_address,
+            fee.try_into().unwrap(),
+        )
+        .unwrap();
+
+        assert!(trim_data.change_boxes.is_empty());
+        assert_eq!(*trim_data.fee_value.0.as_u64(), fee + dust);
+    }
+}
@@ -0,0 +1,405 @@
+use anyhow::anyhow;
+use clap::Parser;
+use ergo_lib::{
+    ergotree_ir::chain::{
+        address::Address,
+        ergo_box::{box_value::BoxValue, ErgoBox},
+    },
+    wallet::box_selector::{BoxSelector, ErgoBoxAssetsData, SimpleBoxSelector},
+};
+use off_the_grid::{
+    boxes::{tracked_box::TrackedBox, wallet_box::WalletBox},
+    grid::multigrid_order::{MultiGridOrder, MultiGridOrderError, DEFAULT_MIN_BOX_VALUE},
+    node::client::NodeClient,
+    units::{Fraction, Price, TokenStore, ERG_UNIT},
+};
+use thiserror::Error;
+use tokio::try_join;
+
+use crate::{
+    commands::{
+        error::{CommandResult, Hint},
+        warn_wallet_error, AddressScriptError,
+    },
+    scan_config::ScanConfig,
+};
+
+use super::{
+    create::{grid_order_range_from_str, GridPriceRange, GridSpacing},
+    resolve_fee, IntoSummarizedTransaction, MinerFeeValue, SummarizedInput, SummarizedOutput,
+    SummarizedTransaction,
+};
+
+#[derive(Parser)]
+pub struct UpdateOptions {
+    #[clap(short = 'i', long, help = "Grid group identity")]
+    grid_identity: String,
+    #[clap(
+        short = 'r',
+        long,
+        help = "New range of the grid, in the form start-stop like `grid create --range`",
+        value_parser = grid_order_range_from_str
+    )]
+    range: (Fraction, Fraction),
+    #[clap(
+        long,
+        value_enum,
+        help = "Spacing of the new order prices across the range [default: linear]"
+    )]
+    spacing: Option<GridSpacing>,
+    #[clap(
+        short,
+        long,
+        help = "transaction fee value [default: default_fee of the node config]"
+    )]
+    fee: Option<String>,
+}
+
+#[derive(Error, Debug)]
+pub enum UpdateGridError {
+    #[error(transparent)]
+    MultiGridOrder(#[from] MultiGridOrderError),
+
+    #[error(transparent)]
+    AddressScript(#[from] AddressScriptError),
+}
+
+pub async fn handle_grid_update(
+    node_client: &NodeClient,
+    scan_config: ScanConfig,
+    token_store: &TokenStore,
+    default_fee: &str,
+    options: UpdateOptions,
+) -> CommandResult<UpdateGridData> {
+    let UpdateOptions {
+        grid_identity,
+        range: (start, stop),
+        spacing,
+        fee,
+    } = options;
+
+    let fee_value = resolve_fee(fee.as_deref(), default_fee)?;
+
+    let mut grid_boxes: Vec<TrackedBox<MultiGridOrder>> = node_client
+        .get_scan_unspent(scan_config.wallet_multigrid_scan_id)
+        .await?
+        .into_iter()
+        .filter_map(|b| b.try_into().ok())
+        .filter(|b: &TrackedBox<MultiGridOrder>| {
+            b.value.identity() == Some(grid_identity.as_bytes())
+        })
+        .collect();
+
+    let order = match grid_boxes.len() {
+        0 => return Err(anyhow!("no grid order found with identity `{}`", grid_identity).into()),
+        1 => grid_boxes.remove(0),
+        num_boxes => {
+            return Err(anyhow!(
+                "grid `{}` is split across {} boxes",
+                grid_identity,
+                num_boxes
+            ))
+            .hint("Only grids in a single box can be updated, redeem and recreate split grids")
+        }
+    };
+
+    let unit = token_store.get_unit(&order.value.token_id);
+    let num_entries = order.value.entries.iter().count() as u64;
+
+    let levels: Vec<(Fraction, Fraction)> = GridPriceRange::new(
+        Price::new(unit, *ERG_UNIT, start),
+        Price::new(unit, *ERG_UNIT, stop),
+        num_entries,
+    )?
+    .with_spacing(spacing.unwrap_or_default())
+    .into_iter()
+    .collect();
+
+    let (wallet_boxes, wallet_status) = try_join!(
+        node_client.wallet_boxes_unspent(),
+        node_client.wallet_status()
+    )?;
+
+    wallet_status.error_if_locked()?;
+    warn_wallet_error(&wallet_status);
+
+    let owner_address = wallet_status.change_address()?;
+
+    Ok(build_update_tx(
+        order,
+        &levels,
+        wallet_boxes,
+        owner_address,
+        fee_value,
+    )?)
+}
+
+/// Spend `order` into a grid order with the same entries moved onto `levels`. If the buy
+/// entries need more value at the new prices, it is taken from `wallet_boxes` together
+/// with the fee. Otherwise the fee is paid from the freed value and the rest is returned
+/// to `change_address`, or added to the fee if it is too small for a box of its own.
+fn build_update_tx(
+    order: TrackedBox<MultiGridOrder>,
+    levels: &[(Fraction, Fraction)],
+    wallet_boxes: Vec<WalletBox<ErgoBox>>,
+    change_address: Address,
+    fee_value: BoxValue,
+) -> anyhow::Result<UpdateGridData> {
+    let grid_output = order.value.clone().with_prices(levels)?;
+
+    // Any other token in the box, or tokens beyond those of the entries, would be burned
+    let box_tokens: Vec<_> = order
+        .ergo_box
+        .tokens
+        .iter()
+        .flat_map(|tokens| tokens.iter())
+        .map(|t| (t.token_id, *t.amount.as_u64()))
+        .collect();
+
+    let output_tokens = grid_output.entries.token_amount();
+    let expected_tokens: Vec<_> = if output_tokens > 0 {
+        vec![(grid_output.token_id, output_tokens)]
+    } else {
+        vec![]
+    };
+
+    if box_tokens != expected_tokens {
+        return Err(anyhow!(
+            "Tokens are not conserved: the grid box holds {:?} but the updated grid would hold {} tokens",
+            box_tokens,
+            output_tokens
+        ));
+    }
+
+    let input_value = *order.ergo_box.value.as_u64();
+    let required_value = grid_output
+        .value
+        .as_u64()
+        .checked_add(*fee_value.as_u64())
+        .ok_or(anyhow!("Value overflow"))?;
+
+    let (selected_boxes, change_boxes, fee_value) = match required_value.checked_sub(input_value) {
+        Some(missing) if missing > 0 => {
+            let selection =
+                SimpleBoxSelector::new().select(wallet_boxes, missing.try_into()?, &[])?;
+
+            let change_boxes = selection
+                .change_boxes
+                .into_iter()
+                .map(|cb| WalletBox::new(cb, change_address.clone()))
+                .collect();
+
+            (selection.boxes.into(), change_boxes, fee_value)
+        }
+        _ => {
+            let freed = input_value - required_value;
+
+            if freed >= DEFAULT_MIN_BOX_VALUE {
+                let change_box = WalletBox::new(
+                    ErgoBoxAssetsData {
+                        value: freed.try_into()?,
+                        tokens: None,
+                    },
+                    change_address,
+                );
+
+                (vec![], vec![change_box], fee_value)
+            } else {
+                (
+                    vec![],
+                    vec![],
+                    fee_value.as_u64().saturating_add(freed).try_into()?,
+                )
+            }
+        }
+    };
+
+    Ok(UpdateGridData {
+        order,
+        grid_output,
+        selected_boxes,
+        change_boxes,
+        fee_value: MinerFeeValue(fee_value),
+    })
+}
+
+pub struct UpdateGridData {
+    order: TrackedBox<MultiGridOrder>,
+    grid_output: MultiGridOrder,
+    selected_boxes: Vec<WalletBox<ErgoBox>>,
+    change_boxes: Vec<WalletBox<ErgoBoxAssetsData>>,
+    fee_value: MinerFeeValue,
+}
+
+impl IntoSummarizedTransaction for UpdateGridData {
+    type Error = UpdateGridError;
+
+    fn into_summarized_transaction(
+        self,
+        token_store: &TokenStore,
+    ) -> Result<SummarizedTransaction, Self::Error> {
+        let creation_height = self
+            .selected_boxes
+            .iter()
+            .map(|b| b.assets.creation_height)
+            .chain(std::iter::once(self.order.ergo_box.creation_height))
+            .max()
+            .unwrap_or(0);
+
+        let inputs = std::iter::once(SummarizedInput::new(self.order, token_store))
+            .chain(
+                self.selected_boxes
+                    .into_iter()
+                    .map(|b| SummarizedInput::new(b, token_store)),
+            )
+            .collect();
+
+        let mut outputs = vec![SummarizedOutput::new(
+            self.grid_output,
+            token_store,
+            creation_height,
+        )?];
+
+        for change_box in self.change_boxes {
+            outputs.push(SummarizedOutput::new(
+                change_box,
+                token_store,
+                creation_height,
+            )?);
+        }
+
+        outputs.push(
+            SummarizedOutput::new(self.fee_value, token_store, creation_height)
+                .expect("Fee output"),
+        );
+
+        Ok(SummarizedTransaction { inputs, outputs })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ergo_lib::{
+        chain::transaction::TxId,
+        ergo_chain_types::{ec_point::generator, Digest32},
+        ergotree_ir::{
+            chain::{
+                address::Address,
+                ergo_box::{ErgoBox, ErgoBoxCandidate, NonMandatoryRegisters},
+            },
+            sigma_protocol::sigma_boolean::ProveDlog,
+        },
+    };
+    use off_the_grid::{
+        boxes::wallet_box::WalletBox,
+        grid::multigrid_order::{
+            GridOrderEntries, GridOrderEntry, OrderState, DEFAULT_MIN_BOX_VALUE,
+        },
+        units::Fraction,
+    };
+
+    use crate::commands::test_util::test_order;
+
+    use super::build_update_tx;
+
+    #[test]
+    fn update_keeps_profit_and_tokens() {
+        let owner_address = Address::P2Pk(ProveDlog::new(generator()));
+        let fee = 1_000_000u64;
+        let profit = 3_000_000;
+
+        let entries = GridOrderEntries::new(vec![
+            GridOrderEntry::new(
+                OrderState::Sell,
+                10.try_into().unwrap(),
+                110_000_000,
+                120_000_000,
+            ),
+            GridOrderEntry::new(
+                OrderState::Buy,
+                10.try_into().unwrap(),
+                100_000_000,
+                110_000_000,
+            ),
+        ]);
+
+        let token_id = Digest32::from([1u8; 32]).into();
+        let order = test_order(token_id, entries, Some(b"update".to_vec()));
+        let mut candidate = order.into_box_candidate(0).unwrap();
+        candidate.value = (candidate.value.as_u64() + profit).try_into().unwrap();
+        let order_box = ErgoBox::from_box_candidate(&candidate, TxId::zero(), 0).unwrap();
+
+        let wallet_candidate = ErgoBoxCandidate {
+            value: 1_000_000_000u64.try_into().unwrap(),
+            ergo_tree: owner_address.script().unwrap(),
+            tokens: None,
+            additional_registers: NonMandatoryRegisters::empty(),
+            creation_height: 0,
+        };
+        let wallet_box = WalletBox::new(
+            ErgoBox::from_box_candidate(&wallet_candidate, TxId::zero(), 1).unwrap(),
+            owner_address.clone(),
+        );
+
+        let levels = |bid: u64| {
+            vec![
+                (
+                    Fraction::from(bid + 1_000_000),
+                    Fraction::from(bid + 2_000_000),
+                ),
+                (Fraction::from(bid), Fraction::from(bid + 1_000_000)),
+            ]
+        };
+
+        // Higher prices need more value for the buy entry, taken from the wallet
+        let up = build_update_tx(
+            order_box.clone().try_into().unwrap(),
+            &levels(20_000_000),
+            vec![wallet_box.clone()],
+            owner_address.clone(),
+            fee.try_into().unwrap(),
+        )
+        .unwrap();
+
+        let prices: Vec<_> = up
+            .grid_output
+            .entries
+            .iter()
+            .map(|e| (e.state, e.bid_value))
+            .collect();
+        assert_eq!(
+            prices,
+            vec![
+                (OrderState::Sell, 210_000_000),
+                (OrderState::Buy, 200_000_000)
+            ]
+        );
+        assert_eq!(up.grid_output.identity(), Some(&b"update"[..]));
+        assert_eq!(
+            *up.grid_output.value.as_u64(),
+            DEFAULT_MIN_BOX_VALUE + 200_000_000 + profit
+        );
+        assert_eq!(up.grid_output.entries.token_amount(), 10);
+        assert_eq!(up.selected_boxes.len(), 1);
+        assert_eq!(
+            *up.change_boxes[0].assets.value.as_u64(),
+            1_000_000_000 - 100_000_000 - fee
+        );
+
+        // Lower prices free value, which pays the fee without wallet inputs
+        let down = build_update_tx(
+            order_box.try_into().unwrap(),
+            &levels(5_000_000),
+            vec![wallet_box],
+            owner_address,
+            fee.try_into().unwrap(),
+        )
+        .unwrap();
+
+        assert!(down.selected_boxes.is_empty());
+        assert_eq!(
+            *down.change_boxes[0].assets.value.as_u64(),
+            100_000_000 - 50_000_000 - fee
+        );
+    }
+}
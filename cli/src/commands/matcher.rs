@@ -1,12 +1,20 @@
-use crate::{matcher_config::MatcherConfig, scan_config::ScanConfig};
+use crate::{
+    commands::{address_script, unique_pools, warn_wallet_error},
+    matcher_config::MatcherConfig,
+    profile::Profile,
+    scan_config::ScanConfig,
+};
+use anyhow::Context;
 use clap::Args;
 use ergo_lib::{
     chain::transaction::{Input, Transaction, TxId},
+    ergo_chain_types::Digest32,
     ergotree_interpreter::sigma_protocol::prover::ProofBytes,
     ergotree_ir::{
         chain::{
-            address::{AddressEncoder, NetworkPrefix},
+            address::AddressEncoder,
             ergo_box::{BoxId, ErgoBox, ErgoBoxCandidate, NonMandatoryRegisters},
+            token::TokenId,
         },
         ergo_tree::ErgoTree,
     },
@@ -14,18 +22,37 @@ use ergo_lib::{
 };
 use itertools::Itertools;
 use off_the_grid::{
-    boxes::{liquidity_box::LiquidityProvider, tracked_box::TrackedBox},
-    grid::multigrid_order::{FillMultiGridOrders, MultiGridOrder, MAX_FEE},
+    boxes::{min_box_value::check_min_box_value, tracked_box::TrackedBox},
+    grid::multigrid_order::{FillMultiGridOrders, MultiGridOrder, OrderState, MAX_FEE},
     node::client::NodeClient,
-    spectrum::pool::SpectrumPool,
+    spectrum::pool::{select_pool, PoolSelectionError, SpectrumPool},
+    units::token_id_hex,
 };
+use serde::Serialize;
 use std::{
     collections::{hash_map::Values, HashMap, HashSet},
     iter::once,
-    time::Duration,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 use tokio::try_join;
 
+/// File in the profile holding the box ids of the gate, if `persist_gate` is set in the
+/// matcher config
+const GATE_FILE: &str = "matcher_gate.json";
+
+/// Write the current unix time to `path`, updating its modification time
+fn touch_file(path: &str) -> anyhow::Result<()> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+
+    std::fs::write(path, timestamp.to_string())
+        .with_context(|| format!("Failed to write {}", path))?;
+
+    Ok(())
+}
+
 pub struct BoxIdGate {
     current_ids: HashSet<BoxId>,
 }
@@ -37,6 +64,26 @@ impl BoxIdGate {
         }
     }
 
+    /// Load the box ids saved by [`BoxIdGate::save`]. A missing file gives an empty gate.
+    pub fn load(path: &str) -> anyhow::Result<Self> {
+        let current_ids: Vec<BoxId> = match std::fs::read_to_string(path) {
+            Ok(contents) => serde_json::from_str(&contents)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => vec![],
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(Self {
+            current_ids: current_ids.into_iter().collect(),
+        })
+    }
+
+    pub fn save(&self, path: &str) -> anyhow::Result<()> {
+        let current_ids: Vec<_> = self.current_ids.iter().collect();
+        std::fs::write(path, serde_json::to_string(&current_ids)?)?;
+
+        Ok(())
+    }
+
     /// Returns true if there are new box ids and updates the current ids
     /// to the new ids.
     pub fn check_box_ids(&mut self, box_ids: &[BoxId]) -> Option<(Vec<BoxId>, Vec<BoxId>)> {
@@ -61,6 +108,11 @@ pub struct MempoolOverlay {
 }
 
 impl MempoolOverlay {
+    /// Whether `box_id` is spent by a transaction in the mempool
+    pub fn is_spent(&self, box_id: &BoxId) -> bool {
+        self.spent_boxes.contains(box_id)
+    }
+
     pub fn add_transaction(&mut self, tx: Transaction) {
         for input in tx.inputs {
             self.spent_boxes.insert(input.box_id);
@@ -159,49 +211,156 @@ pub struct MatcherCommand {
         help = "Matcher configuration file path [default: matcher_config]"
     )]
     matcher_config: Option<String>,
+    #[clap(short = 't', long, help = "Only match orders for the given TokenID")]
+    token_id: Option<String>,
+    #[clap(
+        short,
+        long,
+        help = "Print why the orders of each token were filled or not, as JSON lines"
+    )]
+    verbose: bool,
+    #[clap(
+        long,
+        help = "Print the matching transactions instead of submitting them"
+    )]
+    dry_run: bool,
+}
+
+/// When a fill is profitable enough to submit, and whether it is submitted at all
+#[derive(Clone, Copy, Debug, Default)]
+struct FillPolicy {
+    /// Least matcher reward in nanoERG, on top of the miner fee
+    min_profit: u64,
+    /// Print a [`FillDecision`] for the orders of every token
+    log_decisions: bool,
+    /// Print the matching transactions instead of submitting them
+    dry_run: bool,
 }
 
 pub async fn handle_matcher_command(
     node_client: NodeClient,
+    profile: &Profile,
     matcher_command: MatcherCommand,
 ) -> anyhow::Result<()> {
-    let scan_config = ScanConfig::try_create(matcher_command.scan_config, None)?;
-    let matcher_config = MatcherConfig::try_create(matcher_command.matcher_config)?;
+    let scan_config = ScanConfig::try_create(profile, matcher_command.scan_config, None)?;
+    let matcher_config = MatcherConfig::try_create(profile, matcher_command.matcher_config)?;
     let matcher_interval = Duration::from_secs_f64(matcher_config.interval.unwrap_or(10.0));
-    let address_encoder = AddressEncoder::new(NetworkPrefix::Mainnet);
+    let metrics_interval = matcher_config.metrics_interval.map(Duration::from_secs_f64);
+    let address_encoder = AddressEncoder::new(node_client.network_prefix());
+
+    let token_filter: Option<TokenId> = matcher_command
+        .token_id
+        .map(|i| Digest32::try_from(i).map(|i| i.into()))
+        .transpose()?;
 
     let reward_address = match matcher_config.reward_address {
         Some(address) => address_encoder.parse_address_from_str(&address)?,
         None => {
             let wallet_status = node_client.wallet_status().await?;
             wallet_status.error_if_locked()?;
+            warn_wallet_error(&wallet_status);
             wallet_status.change_address()?
         }
     };
 
-    let reward_script = reward_address.script()?;
+    let reward_script = address_script(&reward_address)?;
 
     println!(
         "Using reward address: {}",
         address_encoder.address_to_str(&reward_address)
     );
 
-    matcher_loop(&node_client, &scan_config, matcher_interval, &reward_script).await;
+    if matcher_command.dry_run {
+        println!("Dry run: matching transactions are printed, not submitted");
+    }
+
+    if let Some(token_id) = token_filter {
+        println!(
+            "Only matching orders for token: {}",
+            token_id_hex(&token_id)
+        );
+    }
+
+    let gate_path = matcher_config
+        .persist_gate
+        .unwrap_or(false)
+        .then(|| profile.path(GATE_FILE));
+
+    let box_id_gate = match &gate_path {
+        Some(path) => BoxIdGate::load(path)?,
+        None => BoxIdGate::new(),
+    };
+
+    let use_chain_height = matcher_config.use_chain_height.unwrap_or(false);
+
+    let pinned_pools: HashMap<TokenId, TokenId> = matcher_config
+        .pinned_pools
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(token_id, pool_nft)| {
+            Ok((
+                Digest32::try_from(token_id)?.into(),
+                Digest32::try_from(pool_nft)?.into(),
+            ))
+        })
+        .collect::<anyhow::Result<_>>()?;
+
+    let fill_policy = FillPolicy {
+        min_profit: matcher_config.min_profit.unwrap_or(0),
+        log_decisions: matcher_command.verbose,
+        dry_run: matcher_command.dry_run,
+    };
+
+    if let Some(path) = &matcher_config.readiness_file {
+        touch_file(path)?;
+    }
+
+    matcher_loop(
+        &node_client,
+        &scan_config,
+        matcher_interval,
+        &reward_script,
+        token_filter,
+        &pinned_pools,
+        metrics_interval,
+        box_id_gate,
+        gate_path.as_deref(),
+        use_chain_height,
+        matcher_config.liveness_file.as_deref(),
+        fill_policy,
+    )
+    .await;
 
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn matcher_loop(
     node_client: &NodeClient,
     scan_config: &ScanConfig,
     matcher_interval: Duration,
     reward_script: &ErgoTree,
+    token_filter: Option<TokenId>,
+    pinned_pools: &HashMap<TokenId, TokenId>,
+    metrics_interval: Option<Duration>,
+    mut box_id_gate: BoxIdGate,
+    gate_path: Option<&str>,
+    use_chain_height: bool,
+    liveness_path: Option<&str>,
+    fill_policy: FillPolicy,
 ) {
-    let mut box_id_gate = BoxIdGate::new();
+    let mut metrics_printed = Instant::now();
 
     loop {
         tokio::time::sleep(matcher_interval).await;
 
+        if let Some(metrics_interval) = metrics_interval {
+            if metrics_printed.elapsed() >= metrics_interval {
+                print_request_metrics(node_client);
+                metrics_printed = Instant::now();
+            }
+        }
+
         let state_result = try_join!(
             node_client.get_scan_unspent(scan_config.multigrid_scan_id),
             node_client.get_scan_unspent(scan_config.n2t_scan_id),
@@ -216,123 +375,1066 @@ async fn matcher_loop(
             }
         };
 
+        let chain_height = if use_chain_height {
+            match node_client.node_info().await.map(|info| info.full_height) {
+                Ok(Some(height)) => Some(height),
+                Ok(None) => {
+                    println!("Node has no full height yet");
+                    continue;
+                }
+                Err(e) => {
+                    println!("Error getting chain height: {}", e);
+                    continue;
+                }
+            }
+        } else {
+            None
+        };
+
         let overlay: MempoolOverlay = mempool_txs.into_iter().collect();
 
         let grid_orders: Vec<TrackedBox<MultiGridOrder>> = grid_orders
             .into_iter()
             .filter_map(|b| b.try_into().ok())
-            .overlay(&overlay)
             .collect();
 
-        let n2t_pools: Vec<TrackedBox<SpectrumPool>> = n2t_pools
-            .into_iter()
-            .filter_map(|b| b.try_into().ok())
-            .overlay(&overlay)
-            .collect();
+        let mempool_excluded = grid_orders
+            .iter()
+            .filter(|b| overlay.is_spent(&b.ergo_box.box_id()))
+            .counts_by(|b| b.value.token_id);
+
+        let grid_orders: Vec<TrackedBox<MultiGridOrder>> =
+            grid_orders.into_iter().overlay(&overlay).collect();
 
-        if box_id_gate
-            .check_box_ids(
-                &grid_orders
-                    .iter()
-                    .map(|b| b.ergo_box.box_id())
-                    .chain(n2t_pools.iter().map(|b| b.ergo_box.box_id()))
-                    .collect::<Vec<_>>(),
-            )
-            .is_some()
-        {
-            let grouped_orders = grid_orders
+        let n2t_pools = unique_pools(
+            n2t_pools
                 .into_iter()
-                .into_group_map_by(|b| b.value.token_id);
+                .filter_map(|b| b.try_into().ok())
+                .overlay(&overlay)
+                .collect(),
+        );
+
+        let box_ids: Vec<_> = grid_orders
+            .iter()
+            .map(|b| b.ergo_box.box_id())
+            .chain(n2t_pools.iter().map(|b| b.ergo_box.box_id()))
+            .collect();
+
+        if update_gate(&mut box_id_gate, &box_ids, gate_path, fill_policy) {
+            let grouped_orders = group_orders_by_token(grid_orders, token_filter);
+
+            if fill_policy.log_decisions {
+                for decision in mempool_excluded_decisions(
+                    &mempool_excluded,
+                    &grouped_orders,
+                    token_filter,
+                    fill_policy,
+                ) {
+                    decision.log();
+                }
+            }
 
             for (token_id, orders) in grouped_orders {
-                let pool = n2t_pools
-                    .iter()
-                    .filter(|p| p.value.asset_y.token_id == token_id)
-                    .max_by_key(|p| p.value.asset_x.amount.as_u64())
-                    .cloned();
+                let mut decision = FillDecision::new(
+                    token_id,
+                    orders.len(),
+                    mempool_excluded.get(&token_id).copied().unwrap_or(0),
+                    fill_policy,
+                );
+
+                let pool_nft = pinned_pools.get(&token_id).cloned();
+                let pool = match select_pool(&n2t_pools, token_id, pool_nft) {
+                    Ok(pool) => Some(pool.clone()),
+                    // Tokens without any pool are expected, only report pinned pools
+                    Err(PoolSelectionError::NoPool(_)) => None,
+                    Err(e) => {
+                        println!("Error selecting pool: {}", e);
+                        None
+                    }
+                };
 
                 if let Some(pool) = pool {
-                    let match_result =
-                        try_fill_orders(node_client, reward_script, pool, orders).await;
+                    decision.pool_nft = Some(token_id_hex(&pool.value.pool_nft.token_id));
+
+                    let match_result = try_fill_orders(
+                        node_client,
+                        reward_script,
+                        pool,
+                        orders,
+                        chain_height,
+                        fill_policy,
+                    )
+                    .await;
 
                     match match_result {
-                        Ok(Some(tx_id)) => println!("Filled orders with tx {}", tx_id),
-                        Err(e) => println!("Error filling orders: {}", e),
-                        Ok(None) => (),
+                        Ok((attempt, tx_id)) => {
+                            decision.record(&attempt);
+
+                            if let (Some(tx_id), Some((_, split))) = (tx_id, attempt.transaction) {
+                                println!("Filled orders with tx {}: {}", tx_id, split)
+                            }
+                        }
+                        Err(e) => {
+                            println!("Error filling orders: {}", e);
+                            decision.record_error(&e);
+                        }
                     }
                 }
+
+                if fill_policy.log_decisions {
+                    decision.log();
+                }
+            }
+        }
+
+        if let Some(path) = liveness_path {
+            if let Err(e) = touch_file(path) {
+                println!("Error writing liveness file: {}", e);
             }
         }
     }
 }
 
+/// Check `box_ids` against the gate, returning true if any box is new. The updated gate is
+/// saved to `gate_path` unless it is a dry run, which submits nothing, so that the next
+/// run still matches the boxes seen by the dry run.
+fn update_gate(
+    box_id_gate: &mut BoxIdGate,
+    box_ids: &[BoxId],
+    gate_path: Option<&str>,
+    fill_policy: FillPolicy,
+) -> bool {
+    if box_id_gate.check_box_ids(box_ids).is_none() {
+        return false;
+    }
+
+    if let Some(path) = gate_path.filter(|_| !fill_policy.dry_run) {
+        if let Err(e) = box_id_gate.save(path) {
+            println!("Error saving box id gate: {}", e);
+        }
+    }
+
+    true
+}
+
+/// Print the mean latency of every node endpoint requested so far
+fn print_request_metrics(node_client: &NodeClient) {
+    for (endpoint, histogram) in node_client.request_metrics().snapshot() {
+        if let Some(mean) = histogram.mean() {
+            println!(
+                "Node {}: {} requests, mean {} ms",
+                endpoint,
+                histogram.count,
+                mean.as_millis()
+            );
+        }
+    }
+}
+
+/// Group grid orders by their token, keeping only orders for `token_filter`
+/// if it is set.
+fn group_orders_by_token(
+    grid_orders: Vec<TrackedBox<MultiGridOrder>>,
+    token_filter: Option<TokenId>,
+) -> HashMap<TokenId, Vec<TrackedBox<MultiGridOrder>>> {
+    grid_orders
+        .into_iter()
+        .filter(|b| {
+            token_filter
+                .as_ref()
+                .map(|i| b.value.token_id == *i)
+                .unwrap_or(true)
+        })
+        .into_group_map_by(|b| b.value.token_id)
+}
+
+/// Decisions for the tokens in `mempool_excluded` that have no orders left in
+/// `grouped_orders`, which are never matched and so not otherwise reported
+fn mempool_excluded_decisions(
+    mempool_excluded: &HashMap<TokenId, usize>,
+    grouped_orders: &HashMap<TokenId, Vec<TrackedBox<MultiGridOrder>>>,
+    token_filter: Option<TokenId>,
+    fill_policy: FillPolicy,
+) -> Vec<FillDecision> {
+    mempool_excluded
+        .iter()
+        .filter(|(token_id, _)| !grouped_orders.contains_key(*token_id))
+        .filter(|(token_id, _)| token_filter.map(|i| i == **token_id).unwrap_or(true))
+        .map(|(token_id, excluded)| {
+            FillDecision::mempool_excluded(*token_id, *excluded, fill_policy)
+        })
+        .collect()
+}
+
+/// Where the ERG of a matching transaction ends up. Values are in nanoERG, changes are
+/// relative to the inputs of the transaction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FillSplit {
+    /// Net value change of the filled grid order boxes
+    pub grid_value: i64,
+    /// Spread earned by grid owners on sell orders that were filled
+    pub owner_profit: u64,
+    /// Net value change of the liquidity pool
+    pub pool_value: i64,
+    /// Value of the reward box paid to the matcher
+    pub matcher_reward: u64,
+    pub miner_fee: u64,
+}
+
+impl FillSplit {
+    /// Whether the value changes of all outputs add up, i.e. no ERG is created or lost
+    pub fn reconciles(&self) -> bool {
+        self.grid_value + self.pool_value + self.matcher_reward as i64 + self.miner_fee as i64 == 0
+    }
+}
+
+impl std::fmt::Display for FillSplit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "grid owners {:+} nanoERG (profit {}), pool {:+}, matcher reward {}, miner fee {}",
+            self.grid_value,
+            self.owner_profit,
+            self.pool_value,
+            self.matcher_reward,
+            self.miner_fee
+        )
+    }
+}
+
+/// Why the orders of a token were filled or not
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum FillOutcome {
+    Filled,
+    /// No pool trades the token
+    NoPool,
+    /// The pool price fills none of the orders
+    NothingFillable,
+    /// The fill accounting is off, see [`build_fill_transaction`]
+    NegativeSurplus,
+    /// The surplus does not cover the miner fee
+    BelowFee,
+    /// The reward is below the `min_profit` of the matcher config
+    BelowMinProfit,
+    /// The reward cannot pay for the minimum value of the reward box
+    BelowMinBoxValue,
+    /// Every order of the token is spent by a mempool transaction
+    MempoolExcluded,
+    /// Building or submitting the transaction failed, see `error`
+    Error,
+}
+
+/// Outcome of filling orders against a pool, with the transaction if the fill is made
+struct FillAttempt {
+    /// ERG left over by the fill for the miner fee and the matcher, if anything was filled
+    surplus: Option<i64>,
+    outcome: FillOutcome,
+    transaction: Option<(Transaction, FillSplit)>,
+}
+
+impl FillAttempt {
+    fn skipped(surplus: Option<i64>, outcome: FillOutcome) -> Self {
+        Self {
+            surplus,
+            outcome,
+            transaction: None,
+        }
+    }
+}
+
+/// Record of the matcher's decision for the orders of one token, printed with `--verbose`
+#[derive(Debug, PartialEq, Eq, Serialize)]
+struct FillDecision {
+    token_id: String,
+    /// NFT ID of the pool the orders were matched against
+    pool_nft: Option<String>,
+    orders: usize,
+    /// Orders left out because a mempool transaction already spends them
+    mempool_excluded: usize,
+    surplus: Option<i64>,
+    miner_fee: u64,
+    min_profit: u64,
+    outcome: FillOutcome,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl FillDecision {
+    fn new(
+        token_id: TokenId,
+        orders: usize,
+        mempool_excluded: usize,
+        fill_policy: FillPolicy,
+    ) -> Self {
+        Self {
+            token_id: token_id_hex(&token_id),
+            pool_nft: None,
+            orders,
+            mempool_excluded,
+            surplus: None,
+            miner_fee: MAX_FEE,
+            min_profit: fill_policy.min_profit,
+            outcome: FillOutcome::NoPool,
+            error: None,
+        }
+    }
+
+    /// Decision for a token whose orders are all spent by mempool transactions, so none
+    /// are left to match
+    fn mempool_excluded(
+        token_id: TokenId,
+        mempool_excluded: usize,
+        fill_policy: FillPolicy,
+    ) -> Self {
+        Self {
+            outcome: FillOutcome::MempoolExcluded,
+            ..Self::new(token_id, 0, mempool_excluded, fill_policy)
+        }
+    }
+
+    fn record(&mut self, attempt: &FillAttempt) {
+        self.surplus = attempt.surplus;
+        self.outcome = attempt.outcome;
+    }
+
+    fn record_error(&mut self, error: &anyhow::Error) {
+        self.outcome = FillOutcome::Error;
+        self.error = Some(error.to_string());
+    }
+
+    fn log(&self) {
+        match serde_json::to_string(self) {
+            Ok(line) => println!("{}", line),
+            Err(e) => println!("Error serializing fill decision: {}", e),
+        }
+    }
+}
+
+/// Build the matching transaction and submit it if the fill is made. With
+/// [`FillPolicy::dry_run`] the transaction is only printed.
 async fn try_fill_orders(
     node_client: &NodeClient,
     reward_script: &ErgoTree,
     pool: TrackedBox<SpectrumPool>,
     orders: Vec<TrackedBox<MultiGridOrder>>,
-) -> Result<Option<TxId>, anyhow::Error> {
+    chain_height: Option<u32>,
+    fill_policy: FillPolicy,
+) -> Result<(FillAttempt, Option<TxId>), anyhow::Error> {
+    let attempt = build_fill_transaction(reward_script, pool, orders, chain_height, fill_policy)?;
+
+    let tx_id = match &attempt.transaction {
+        Some((tx, split)) if fill_policy.dry_run => {
+            for line in dry_run_lines(tx, split) {
+                println!("{}", line);
+            }
+            None
+        }
+        Some((tx, _)) => Some(node_client.transaction_submit(tx).await?),
+        None => None,
+    };
+
+    Ok((attempt, tx_id))
+}
+
+/// The matching transaction that would have been submitted, with the split of its surplus
+fn dry_run_lines(tx: &Transaction, split: &FillSplit) -> Vec<String> {
+    let inputs = tx
+        .inputs
+        .iter()
+        .map(|input| format!("  Input {}", input.box_id));
+
+    let outputs = tx.outputs.iter().map(|output| {
+        let num_tokens = output.tokens.as_ref().map(|t| t.len()).unwrap_or(0);
+        format!(
+            "  Output {} nanoERG, {} tokens",
+            output.value.as_u64(),
+            num_tokens
+        )
+    });
+
+    once(format!("Dry run, not submitting tx {}", tx.id()))
+        .chain(inputs)
+        .chain(outputs)
+        .chain(once(format!(
+            "  Surplus {} nanoERG: {}",
+            split.matcher_reward + split.miner_fee,
+            split
+        )))
+        .collect()
+}
+
+/// Spread of the entries that were filled from sell orders into buy orders
+fn filled_sell_profit(input: &MultiGridOrder, output: &MultiGridOrder) -> u64 {
+    input
+        .entries
+        .iter()
+        .zip(output.entries.iter())
+        .filter(|(i, o)| i.state == OrderState::Sell && o.state == OrderState::Buy)
+        .map(|(i, _)| i.ask_value - i.bid_value)
+        .sum()
+}
+
+/// Fill the grid orders against `pool` and build the matching transaction. No transaction
+/// is built if nothing can be filled or the surplus does not cover the miner fee, the
+/// reward box and the least reward of `fill_policy`. Outputs are created at `chain_height` if set,
+/// otherwise at the highest creation height of the inputs.
+fn build_fill_transaction(
+    reward_script: &ErgoTree,
+    pool: TrackedBox<SpectrumPool>,
+    orders: Vec<TrackedBox<MultiGridOrder>>,
+    chain_height: Option<u32>,
+    fill_policy: FillPolicy,
+) -> Result<FillAttempt, anyhow::Error> {
     let (new_pool, filled) = pool.value.clone().fill_orders(orders)?;
 
-    let input_value = filled
+    if filled.is_empty() {
+        return Ok(FillAttempt::skipped(None, FillOutcome::NothingFillable));
+    }
+
+    let grid_input_value = filled
         .iter()
         .map(|(b, _)| b.value.value.as_i64())
-        .sum::<i64>()
-        + *pool.value.asset_x.amount.as_u64() as i64;
+        .sum::<i64>();
+    let grid_output_value = filled.iter().map(|(_, o)| o.value.as_i64()).sum::<i64>();
+
+    let pool_value =
+        *new_pool.asset_x.amount.as_u64() as i64 - *pool.value.asset_x.amount.as_u64() as i64;
+    let grid_value = grid_output_value - grid_input_value;
 
-    let output_value = filled.iter().map(|(_, o)| o.value.as_i64()).sum::<i64>()
-        + *new_pool.asset_x.amount.as_u64() as i64;
+    let surplus = -(grid_value + pool_value);
 
-    let surplus = input_value - output_value;
+    // Filling orders can never create ERG. A negative surplus means the fill accounting is
+    // off, so the orders are skipped with the full accounting instead of failing obscurely
+    // on the reward box value.
+    if surplus < 0 {
+        println!(
+            "Skipping fill with negative surplus {} nanoERG: grid orders in {} out {} ({:+}), pool in {} out {} ({:+})",
+            surplus,
+            grid_input_value,
+            grid_output_value,
+            grid_value,
+            pool.value.asset_x.amount.as_u64(),
+            new_pool.asset_x.amount.as_u64(),
+            pool_value
+        );
+        return Ok(FillAttempt::skipped(
+            Some(surplus),
+            FillOutcome::NegativeSurplus,
+        ));
+    }
 
-    if !filled.is_empty() && surplus > MAX_FEE as i64 {
-        let creation_height = once(pool.ergo_box.creation_height)
+    let reward = surplus - MAX_FEE as i64;
+
+    // The surplus must cover the miner fee and leave a reward for the matcher
+    if reward <= 0 {
+        return Ok(FillAttempt::skipped(Some(surplus), FillOutcome::BelowFee));
+    }
+
+    if reward < fill_policy.min_profit as i64 {
+        return Ok(FillAttempt::skipped(
+            Some(surplus),
+            FillOutcome::BelowMinProfit,
+        ));
+    }
+
+    let creation_height = chain_height.unwrap_or_else(|| {
+        once(pool.ergo_box.creation_height)
             .chain(filled.iter().map(|(tb, _)| tb.ergo_box.creation_height))
             .max()
-            .unwrap_or(0);
+            .unwrap_or(0)
+    });
 
-        let pool_input = Input::from_unsigned_input(pool.ergo_box.into(), ProofBytes::Empty);
+    let split = FillSplit {
+        grid_value,
+        owner_profit: filled
+            .iter()
+            .map(|(tb, order)| filled_sell_profit(&tb.value, order))
+            .sum(),
+        pool_value,
+        matcher_reward: u64::try_from(reward)?,
+        miner_fee: MAX_FEE,
+    };
 
-        let pool_candidate = new_pool.into_box_candidate(creation_height)?;
+    if !split.reconciles() {
+        return Err(anyhow::anyhow!(
+            "Matching transaction values do not add up: {}",
+            split
+        ));
+    }
 
-        let (order_inputs, order_outputs): (Vec<Input>, Vec<ErgoBoxCandidate>) = filled
-            .into_iter()
-            .map(|(tb, order)| {
-                let input = Input::from_unsigned_input(tb.ergo_box.into(), ProofBytes::Empty);
-                (input, order.into_box_candidate(creation_height).unwrap())
-            })
-            .unzip();
-
-        let change_candidate = ErgoBoxCandidate {
-            value: (surplus - MAX_FEE as i64).try_into()?,
-            ergo_tree: reward_script.clone(),
-            tokens: None,
-            additional_registers: NonMandatoryRegisters::empty(),
-            creation_height,
+    let pool_input = Input::from_unsigned_input(pool.ergo_box.into(), ProofBytes::Empty);
+
+    let pool_candidate = new_pool.into_box_candidate(creation_height)?;
+
+    let (order_inputs, order_outputs): (Vec<Input>, Vec<ErgoBoxCandidate>) = filled
+        .into_iter()
+        .map(|(tb, order)| {
+            let input = Input::from_unsigned_input(tb.ergo_box.into(), ProofBytes::Empty);
+            (input, order.into_box_candidate(creation_height).unwrap())
+        })
+        .unzip();
+
+    let change_candidate = ErgoBoxCandidate {
+        value: split.matcher_reward.try_into()?,
+        ergo_tree: reward_script.clone(),
+        tokens: None,
+        additional_registers: NonMandatoryRegisters::empty(),
+        creation_height,
+    };
+
+    // A surplus that cannot cover the minimum value of the reward box is not worth
+    // matching.
+    if check_min_box_value(&change_candidate).is_err() {
+        return Ok(FillAttempt::skipped(
+            Some(surplus),
+            FillOutcome::BelowMinBoxValue,
+        ));
+    }
+
+    let fee_candidate = ErgoBoxCandidate {
+        value: MAX_FEE.try_into().unwrap(),
+        ergo_tree: MINERS_FEE_ADDRESS.script()?,
+        tokens: None,
+        additional_registers: NonMandatoryRegisters::empty(),
+        creation_height,
+    };
+
+    let outputs: Vec<_> = once(pool_candidate)
+        .chain(order_outputs)
+        .chain(once(change_candidate))
+        .chain(once(fee_candidate))
+        .collect();
+
+    for output in outputs.iter() {
+        check_min_box_value(output)?;
+    }
+
+    let tx = Transaction::new_from_vec(
+        once(pool_input).chain(order_inputs).collect(),
+        vec![],
+        outputs,
+    )?;
+
+    Ok(FillAttempt {
+        surplus: Some(surplus),
+        outcome: FillOutcome::Filled,
+        transaction: Some((tx, split)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use ergo_lib::{
+        ergo_chain_types::{ec_point::generator, Digest32},
+        ergotree_ir::{
+            chain::{address::Address, token::TokenId},
+            sigma_protocol::sigma_boolean::ProveDlog,
+        },
+    };
+    use off_the_grid::{
+        boxes::{liquidity_box::LiquidityProvider, tracked_box::TrackedBox},
+        grid::multigrid_order::{GridOrderEntries, GridOrderEntry, MultiGridOrder, OrderState},
+        units::token_id_hex,
+    };
+
+    use uuid::Uuid;
+
+    use crate::commands::test_util::{grid_box, tracked_pool};
+
+    use super::{
+        build_fill_transaction, dry_run_lines, group_orders_by_token, mempool_excluded_decisions,
+        touch_file, update_gate, BoxIdGate, FillDecision, FillOutcome, FillPolicy, MAX_FEE,
+    };
+
+    fn test_token_id(id: u8) -> TokenId {
+        let mut token_bytes = [0u8; 32];
+        token_bytes[0] = id;
+        Digest32::from(token_bytes).into()
+    }
+
+    fn test_order_with_entry(
+        token_id: TokenId,
+        entry: GridOrderEntry,
+        index: u16,
+    ) -> TrackedBox<MultiGridOrder> {
+        grid_box(token_id, GridOrderEntries::new(vec![entry]), None, index)
+    }
+
+    fn test_order(token_id: TokenId) -> TrackedBox<MultiGridOrder> {
+        let entry = GridOrderEntry::new(OrderState::Buy, 1.try_into().unwrap(), 10, 20);
+        test_order_with_entry(token_id, entry, 0)
+    }
+
+    #[test]
+    fn reloaded_gate_suppresses_unchanged_boxes() {
+        let token_id = test_token_id(1);
+        let order_id = test_order(token_id).ergo_box.box_id();
+        let pool_id = tracked_pool(10, token_id, 1_000_000, 1_000)
+            .ergo_box
+            .box_id();
+
+        let path = std::env::temp_dir()
+            .join(format!("matcher-gate-{}.json", Uuid::new_v4()))
+            .display()
+            .to_string();
+
+        // A missing file starts an empty gate
+        let mut gate = BoxIdGate::load(&path).unwrap();
+        assert!(gate.check_box_ids(&[order_id, pool_id]).is_some());
+        gate.save(&path).unwrap();
+
+        let mut reloaded = BoxIdGate::load(&path).unwrap();
+        assert!(reloaded.check_box_ids(&[order_id, pool_id]).is_none());
+
+        let new_id = test_order_with_entry(
+            token_id,
+            GridOrderEntry::new(OrderState::Buy, 2.try_into().unwrap(), 10, 20),
+            3,
+        )
+        .ergo_box
+        .box_id();
+        assert_eq!(
+            reloaded.check_box_ids(&[new_id, pool_id]),
+            Some((vec![order_id], vec![new_id]))
+        );
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn dry_run_gate_not_saved() {
+        let token_id = test_token_id(1);
+        let order_id = test_order(token_id).ergo_box.box_id();
+
+        let path = std::env::temp_dir()
+            .join(format!("matcher-gate-{}.json", Uuid::new_v4()))
+            .display()
+            .to_string();
+
+        let dry_run = FillPolicy {
+            dry_run: true,
+            ..FillPolicy::default()
         };
 
-        let fee_candidate = ErgoBoxCandidate {
-            value: MAX_FEE.try_into().unwrap(),
-            ergo_tree: MINERS_FEE_ADDRESS.script()?,
-            tokens: None,
-            additional_registers: NonMandatoryRegisters::empty(),
-            creation_height,
+        let mut gate = BoxIdGate::new();
+        assert!(update_gate(&mut gate, &[order_id], Some(&path), dry_run));
+        assert!(!update_gate(&mut gate, &[order_id], Some(&path), dry_run));
+        assert!(!std::path::Path::new(&path).exists());
+
+        let mut gate = BoxIdGate::new();
+        assert!(update_gate(
+            &mut gate,
+            &[order_id],
+            Some(&path),
+            FillPolicy::default()
+        ));
+        assert!(BoxIdGate::load(&path)
+            .unwrap()
+            .check_box_ids(&[order_id])
+            .is_none());
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn liveness_file_touched_every_round() {
+        let path = std::env::temp_dir()
+            .join(format!("matcher-liveness-{}", Uuid::new_v4()))
+            .display()
+            .to_string();
+
+        let modified = || std::fs::metadata(&path).unwrap().modified().unwrap();
+
+        touch_file(&path).unwrap();
+        let first = modified();
+
+        std::thread::sleep(std::time::Duration::from_millis(20));
+
+        touch_file(&path).unwrap();
+        let second = modified();
+
+        assert!(second > first);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn group_orders_token_filter() {
+        let token_a = test_token_id(1);
+        let token_b = test_token_id(2);
+
+        let orders = vec![test_order(token_a), test_order(token_b)];
+
+        let grouped = group_orders_by_token(orders.clone(), None);
+        assert_eq!(grouped.len(), 2);
+
+        let grouped = group_orders_by_token(orders, Some(token_b));
+        assert_eq!(grouped.len(), 1);
+        assert_eq!(grouped[&token_b].len(), 1);
+        assert!(!grouped.contains_key(&token_a));
+    }
+
+    #[test]
+    fn fillable_multigrid_order_matched() {
+        let token_id = test_token_id(1);
+        let reward_script = Address::P2Pk(ProveDlog::new(generator())).script().unwrap();
+
+        // Pool price is 1 ERG per token, the order sells 100 tokens for 0.6 ERG each
+        let pool = tracked_pool(10, token_id, 1_000_000_000_000, 1_000_000);
+        let entry = GridOrderEntry::new(
+            OrderState::Sell,
+            100.try_into().unwrap(),
+            50_000_000,
+            60_000_000,
+        );
+        let order = test_order_with_entry(token_id, entry, 2);
+        let order_id = order.ergo_box.box_id();
+
+        let (tx, _) = build_fill_transaction(
+            &reward_script,
+            pool.clone(),
+            vec![order],
+            None,
+            FillPolicy::default(),
+        )
+        .unwrap()
+        .transaction
+        .expect("Order should be matched");
+
+        let input_ids: Vec<_> = tx.inputs.iter().map(|i| i.box_id).collect();
+        assert_eq!(input_ids, vec![pool.ergo_box.box_id(), order_id]);
+
+        let outputs = &tx.outputs;
+        assert_eq!(outputs.len(), 4);
+
+        let filled = MultiGridOrder::try_from(&outputs[1]).unwrap();
+        assert!(filled.entries.iter().all(|e| e.state == OrderState::Buy));
+        assert_eq!(outputs[2].ergo_tree, reward_script);
+    }
+
+    #[test]
+    fn dry_run_prints_transaction() {
+        let token_id = test_token_id(1);
+        let reward_script = Address::P2Pk(ProveDlog::new(generator())).script().unwrap();
+
+        let pool = tracked_pool(10, token_id, 1_000_000_000_000, 1_000_000);
+        let entry = GridOrderEntry::new(
+            OrderState::Sell,
+            100.try_into().unwrap(),
+            50_000_000,
+            60_000_000,
+        );
+        let order = test_order_with_entry(token_id, entry, 2);
+        let order_id = order.ergo_box.box_id();
+
+        let fill_policy = FillPolicy {
+            dry_run: true,
+            ..Default::default()
         };
+        let (tx, split) =
+            build_fill_transaction(&reward_script, pool, vec![order], None, fill_policy)
+                .unwrap()
+                .transaction
+                .expect("Order should be matched");
 
-        let tx = Transaction::new_from_vec(
-            once(pool_input).chain(order_inputs).collect(),
-            vec![],
-            once(pool_candidate)
-                .chain(order_outputs)
-                .chain(once(change_candidate))
-                .chain(once(fee_candidate))
-                .collect(),
-        )?;
+        let lines = dry_run_lines(&tx, &split);
+
+        assert_eq!(lines.len(), 1 + 2 + 4 + 1);
+        assert_eq!(lines[0], format!("Dry run, not submitting tx {}", tx.id()));
+        assert_eq!(lines[2], format!("  Input {}", order_id));
+        assert_eq!(lines[6], format!("  Output {} nanoERG, 0 tokens", MAX_FEE));
+        assert!(lines[7].starts_with(&format!(
+            "  Surplus {} nanoERG: ",
+            split.matcher_reward + MAX_FEE
+        )));
+    }
+
+    #[test]
+    fn unprofitable_multigrid_order_not_matched() {
+        let token_id = test_token_id(1);
+        let reward_script = Address::P2Pk(ProveDlog::new(generator())).script().unwrap();
+
+        // The order asks more than the pool pays
+        let pool = tracked_pool(10, token_id, 1_000_000_000_000, 1_000_000);
+        let entry = GridOrderEntry::new(
+            OrderState::Sell,
+            100.try_into().unwrap(),
+            150_000_000,
+            160_000_000,
+        );
+        let order = test_order_with_entry(token_id, entry, 2);
+
+        assert!(build_fill_transaction(
+            &reward_script,
+            pool,
+            vec![order],
+            None,
+            FillPolicy::default()
+        )
+        .unwrap()
+        .transaction
+        .is_none());
+    }
+
+    #[test]
+    fn fill_decisions_recorded() {
+        let token_id = test_token_id(1);
+        let reward_script = Address::P2Pk(ProveDlog::new(generator())).script().unwrap();
+        let fill_policy = FillPolicy {
+            min_profit: 1_000,
+            log_decisions: true,
+            dry_run: false,
+        };
+
+        let decide = |ask_value: u64| {
+            let pool = tracked_pool(10, token_id, 1_000_000_000_000, 1_000_000);
+            let entry = GridOrderEntry::new(
+                OrderState::Sell,
+                100.try_into().unwrap(),
+                ask_value - 10_000_000,
+                ask_value,
+            );
+            let order = test_order_with_entry(token_id, entry, 2);
+
+            let attempt =
+                build_fill_transaction(&reward_script, pool, vec![order], None, fill_policy)
+                    .unwrap();
+
+            let mut decision = FillDecision::new(token_id, 1, 2, fill_policy);
+            decision.pool_nft = Some("pool".to_string());
+            decision.record(&attempt);
+            decision
+        };
+
+        // The pool pays about 0.0997 ERG for the 100 tokens
+        let filled = decide(60_000_000);
+        assert_eq!(filled.outcome, FillOutcome::Filled);
+        assert!(filled.surplus.unwrap() > MAX_FEE as i64);
+
+        let json = serde_json::to_value(&filled).unwrap();
+        assert_eq!(json["outcome"], "filled");
+        assert_eq!(json["pool_nft"], "pool");
+        assert_eq!(json["orders"], 1);
+        assert_eq!(json["mempool_excluded"], 2);
+        assert_eq!(json["miner_fee"], MAX_FEE);
+        assert_eq!(json["min_profit"], 1_000);
+
+        let not_filled = decide(160_000_000);
+        assert_eq!(not_filled.outcome, FillOutcome::NothingFillable);
+        assert_eq!(
+            serde_json::to_value(&not_filled).unwrap()["surplus"],
+            serde_json::Value::Null
+        );
+    }
+
+    #[test]
+    fn fill_errors_and_mempool_exclusions_recorded() {
+        let fill_policy = FillPolicy {
+            min_profit: 0,
+            log_decisions: true,
+            dry_run: false,
+        };
+
+        let mut decision = FillDecision::new(test_token_id(1), 1, 0, fill_policy);
+        decision.record_error(&anyhow::anyhow!("node unavailable"));
+        let json = serde_json::to_value(&decision).unwrap();
+        assert_eq!(json["outcome"], "error");
+        assert_eq!(json["error"], "node unavailable");
+
+        // Token 2 still has an order to match, all orders of tokens 3 and 4 are spent
+        let mempool_excluded = [
+            (test_token_id(2), 1),
+            (test_token_id(3), 2),
+            (test_token_id(4), 1),
+        ]
+        .into_iter()
+        .collect();
+        let grouped = group_orders_by_token(vec![test_order(test_token_id(2))], None);
+
+        let mut decisions =
+            mempool_excluded_decisions(&mempool_excluded, &grouped, None, fill_policy);
+        decisions.sort_by(|a, b| a.token_id.cmp(&b.token_id));
+        assert_eq!(decisions.len(), 2);
+        assert_eq!(decisions[0].token_id, token_id_hex(&test_token_id(3)));
+        assert_eq!(decisions[0].orders, 0);
+        assert_eq!(decisions[0].mempool_excluded, 2);
+        assert_eq!(decisions[0].outcome, FillOutcome::MempoolExcluded);
+        assert!(serde_json::to_value(&decisions[0])
+            .unwrap()
+            .get("error")
+            .is_none());
+
+        let filtered = mempool_excluded_decisions(
+            &mempool_excluded,
+            &grouped,
+            Some(test_token_id(4)),
+            fill_policy,
+        );
+        assert_eq!(filtered.len(), 1);
+        assert_eq!(filtered[0].token_id, token_id_hex(&test_token_id(4)));
+    }
+
+    #[test]
+    fn fill_netting_exact_fee_not_matched() {
+        let token_id = test_token_id(1);
+        let reward_script = Address::P2Pk(ProveDlog::new(generator())).script().unwrap();
+
+        let pool = tracked_pool(10, token_id, 1_000_000_000_000, 1_000_000);
+        let pool_output = pool
+            .value
+            .output_amount(&(token_id, 100.try_into().unwrap()).into())
+            .unwrap();
+        let pool_pays = *pool_output.amount.as_u64();
+
+        // The order asks exactly what the pool pays minus the miner fee
+        let order_asking = |ask_value: u64| {
+            let entry = GridOrderEntry::new(
+                OrderState::Sell,
+                100.try_into().unwrap(),
+                ask_value - 10_000_000,
+                ask_value,
+            );
+            test_order_with_entry(token_id, entry, 2)
+        };
+
+        assert!(build_fill_transaction(
+            &reward_script,
+            pool.clone(),
+            vec![order_asking(pool_pays - MAX_FEE)],
+            None,
+            FillPolicy::default()
+        )
+        .unwrap()
+        .transaction
+        .is_none());
+
+        let (_, split) = build_fill_transaction(
+            &reward_script,
+            pool.clone(),
+            vec![order_asking(pool_pays - MAX_FEE - 1_000_000)],
+            None,
+            FillPolicy::default(),
+        )
+        .unwrap()
+        .transaction
+        .expect("Order should be matched");
+        assert_eq!(split.matcher_reward, 1_000_000);
+
+        // A reward below the configured minimum is skipped, one at the minimum is filled
+        let min_profit = |min_profit| FillPolicy {
+            min_profit,
+            log_decisions: true,
+            dry_run: false,
+        };
+        let order = order_asking(pool_pays - MAX_FEE - 1_000_000);
+
+        assert!(build_fill_transaction(
+            &reward_script,
+            pool.clone(),
+            vec![order.clone()],
+            None,
+            min_profit(1_000_001)
+        )
+        .unwrap()
+        .transaction
+        .is_none());
+
+        assert!(build_fill_transaction(
+            &reward_script,
+            pool,
+            vec![order],
+            None,
+            min_profit(1_000_000)
+        )
+        .unwrap()
+        .transaction
+        .is_some());
+    }
+
+    #[test]
+    fn fill_split_reconciles() {
+        let token_id = test_token_id(1);
+        let reward_script = Address::P2Pk(ProveDlog::new(generator())).script().unwrap();
+
+        let pool = tracked_pool(10, token_id, 1_000_000_000_000, 1_000_000);
+        let entry = GridOrderEntry::new(
+            OrderState::Sell,
+            100.try_into().unwrap(),
+            50_000_000,
+            60_000_000,
+        );
+        let order = test_order_with_entry(token_id, entry, 2);
+        let order_value = *order.ergo_box.value.as_u64() as i64;
+        let pool_value = *pool.ergo_box.value.as_u64() as i64;
+
+        let (tx, split) = build_fill_transaction(
+            &reward_script,
+            pool,
+            vec![order],
+            None,
+            FillPolicy::default(),
+        )
+        .unwrap()
+        .transaction
+        .expect("Order should be matched");
+
+        assert!(split.reconciles());
+        assert_eq!(split.owner_profit, 10_000_000);
+        assert_eq!(split.miner_fee, MAX_FEE);
+
+        let outputs = &tx.outputs;
+        assert_eq!(
+            *outputs[0].value.as_u64() as i64 - pool_value,
+            split.pool_value
+        );
+        assert_eq!(
+            *outputs[1].value.as_u64() as i64 - order_value,
+            split.grid_value
+        );
+        assert_eq!(*outputs[2].value.as_u64(), split.matcher_reward);
+        assert_eq!(*outputs[3].value.as_u64(), split.miner_fee);
+
+        let total_out: u64 = outputs.iter().map(|o| *o.value.as_u64()).sum();
+        assert_eq!(total_out as i64, pool_value + order_value);
+    }
+
+    #[test]
+    fn outputs_created_at_chain_height() {
+        let token_id = test_token_id(1);
+        let reward_script = Address::P2Pk(ProveDlog::new(generator())).script().unwrap();
+
+        let pool = tracked_pool(10, token_id, 1_000_000_000_000, 1_000_000);
+        let entry = GridOrderEntry::new(
+            OrderState::Sell,
+            100.try_into().unwrap(),
+            50_000_000,
+            60_000_000,
+        );
+        let order = test_order_with_entry(token_id, entry, 2);
 
-        let tx_id = node_client.transaction_submit(&tx).await?;
+        let (tx, _) = build_fill_transaction(
+            &reward_script,
+            pool.clone(),
+            vec![order.clone()],
+            None,
+            FillPolicy::default(),
+        )
+        .unwrap()
+        .transaction
+        .expect("Order should be matched");
+        assert!(tx.outputs.iter().all(|o| o.creation_height == 0));
 
-        Ok(Some(tx_id))
-    } else {
-        Ok(None)
+        let (tx, _) = build_fill_transaction(
+            &reward_script,
+            pool,
+            vec![order],
+            Some(1_126_400),
+            FillPolicy::default(),
+        )
+        .unwrap()
+        .transaction
+        .expect("Order should be matched");
+        assert!(tx.outputs.iter().all(|o| o.creation_height == 1_126_400));
     }
 }
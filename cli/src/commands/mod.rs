@@ -1,5 +1,126 @@
+pub mod arb;
+pub mod convert;
 pub mod error;
 pub mod grid;
 pub mod matcher;
+pub mod outbox;
+pub mod pool;
 pub mod scans;
+pub mod script;
+pub mod status;
+#[cfg(test)]
+mod test_util;
 pub mod tokens;
+
+use std::{collections::HashSet, sync::Mutex};
+
+use colored::Colorize;
+use ergo_lib::ergotree_ir::{
+    chain::{
+        address::{Address, AddressEncoder, NetworkPrefix},
+        token::TokenId,
+    },
+    ergo_tree::ErgoTree,
+    serialization::SigmaParsingError,
+};
+use lazy_static::lazy_static;
+use off_the_grid::{
+    boxes::tracked_box::TrackedBox,
+    node::wallet::WalletStatus,
+    spectrum::pool::{dedup_pools, SpectrumPool},
+};
+use thiserror::Error;
+
+lazy_static! {
+    /// Pool NFTs [`unique_pools`] already warned about, so that commands running in a loop,
+    /// such as the matcher, warn only once per NFT
+    static ref WARNED_POOL_NFTS: Mutex<HashSet<TokenId>> = Mutex::new(HashSet::new());
+}
+
+/// The script of an address could not be built, e.g. a change address holding a
+/// malformed P2S script
+#[derive(Error, Debug)]
+#[error("Invalid script for address {address}: {source}")]
+pub struct AddressScriptError {
+    pub address: String,
+    pub source: SigmaParsingError,
+}
+
+/// Script of `address`, naming the address if the script cannot be built
+pub fn address_script(address: &Address) -> Result<ErgoTree, AddressScriptError> {
+    address.script().map_err(|source| AddressScriptError {
+        address: AddressEncoder::encode_address_as_string(NetworkPrefix::Mainnet, address),
+        source,
+    })
+}
+
+/// Quote a CSV field if it contains a separator, quote or line break
+pub fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Pools with at most one box per pool NFT, warning once about every NFT claimed by more
+/// than one box
+pub fn unique_pools(pools: Vec<TrackedBox<SpectrumPool>>) -> Vec<TrackedBox<SpectrumPool>> {
+    let (pools, duplicates) = dedup_pools(pools);
+
+    let new_duplicates = not_yet_warned(
+        duplicates,
+        &mut WARNED_POOL_NFTS.lock().expect("Pool warning lock poisoned"),
+    );
+
+    for nft in new_duplicates {
+        eprintln!(
+            "{}",
+            format!(
+                "Warning: Multiple boxes found for pool NFT {}, using the most recent one",
+                String::from(nft)
+            )
+            .yellow()
+        );
+    }
+
+    pools
+}
+
+/// The NFTs of `duplicates` missing from `warned`, which are added to it
+fn not_yet_warned(duplicates: Vec<TokenId>, warned: &mut HashSet<TokenId>) -> Vec<TokenId> {
+    duplicates
+        .into_iter()
+        .filter(|nft| warned.insert(*nft))
+        .collect()
+}
+
+/// Print the wallet error reported by the node, if any. Called once the wallet is known
+/// to be unlocked.
+pub fn warn_wallet_error(wallet_status: &WalletStatus) {
+    if let Some(warning) = wallet_status.warn_if_error() {
+        eprintln!("{}", warning.yellow());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use ergo_lib::{ergo_chain_types::Digest32, ergotree_ir::chain::token::TokenId};
+
+    use super::not_yet_warned;
+
+    #[test]
+    fn duplicate_pool_warned_once() {
+        let nft = |id: u8| -> TokenId { Digest32::from([id; 32]).into() };
+        let mut warned = HashSet::new();
+
+        assert_eq!(not_yet_warned(vec![nft(1)], &mut warned), vec![nft(1)]);
+        assert_eq!(
+            not_yet_warned(vec![nft(1), nft(2)], &mut warned),
+            vec![nft(2)]
+        );
+        assert!(not_yet_warned(vec![nft(2), nft(1)], &mut warned).is_empty());
+    }
+}
@@ -0,0 +1,59 @@
+use clap::{Args, Subcommand};
+use off_the_grid::node::client::NodeClient;
+
+use crate::{outbox::Outbox, profile::Profile};
+
+#[derive(Subcommand)]
+pub enum Commands {
+    /// List the transactions waiting to be submitted
+    List,
+    /// Submit all queued transactions in the order they were queued, removing each one
+    /// once it is submitted
+    SubmitAll,
+}
+
+#[derive(Args)]
+pub struct OutboxCommand {
+    #[command(subcommand)]
+    pub command: Commands,
+}
+
+pub async fn handle_outbox_command(
+    node_client: NodeClient,
+    profile: &Profile,
+    outbox_command: OutboxCommand,
+) -> anyhow::Result<()> {
+    let outbox = Outbox::new(profile.outbox_path());
+
+    match outbox_command.command {
+        Commands::List => {
+            let pending = outbox.pending()?;
+
+            if pending.is_empty() {
+                println!("No queued transactions");
+            }
+
+            for (path, entry) in pending {
+                println!("{} | {} | {}", entry.tx_id, entry.command, path.display());
+            }
+        }
+        Commands::SubmitAll => {
+            let submitted = outbox
+                .submit_all(|tx| {
+                    let node_client = &node_client;
+                    async move { Ok(node_client.transaction_submit(&tx).await?) }
+                })
+                .await?;
+
+            for tx_id in &submitted {
+                println!("Transaction submitted: {}", String::from(*tx_id));
+            }
+
+            if submitted.is_empty() {
+                println!("No queued transactions");
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,199 @@
+use clap::{Args, Subcommand};
+use ergo_lib::ergotree_ir::chain::token::TokenAmount;
+use off_the_grid::{
+    boxes::{liquidity_box::LiquidityProvider, tracked_box::TrackedBox},
+    node::client::NodeClient,
+    spectrum::pool::{select_pool, SpectrumPool},
+    units::{token_id_hex, AmountParser, TokenStore, Unit, UnitAmount, ERG_UNIT},
+};
+use tabled::{settings::Style, Table, Tabled};
+
+use crate::{commands::unique_pools, profile::Profile, scan_config::ScanConfig};
+
+#[derive(Subcommand)]
+pub enum Commands {
+    /// Quote the amount of each token received for an amount of ERG
+    Quote {
+        #[clap(
+            long,
+            required = true,
+            value_delimiter = ',',
+            help = "Tokens to quote, by name or token ID"
+        )]
+        tokens: Vec<String>,
+        #[clap(long, help = "Amount of ERG to swap")]
+        erg: String,
+        #[clap(long, help = "Scan configuration file path [default: scan_config]")]
+        scan_config: Option<String>,
+    },
+}
+
+#[derive(Args)]
+pub struct PoolCommand {
+    #[command(subcommand)]
+    pub command: Commands,
+}
+
+/// Expected output of swapping ERG for a token in its deepest pool
+#[derive(Tabled, Debug, PartialEq, Eq)]
+struct QuoteRow {
+    #[tabled(rename = "Token")]
+    token: String,
+    #[tabled(rename = "Pool")]
+    pool: String,
+    #[tabled(rename = "Output")]
+    output: String,
+}
+
+pub async fn handle_pool_command(
+    node_client: NodeClient,
+    profile: &Profile,
+    pool_command: PoolCommand,
+) -> anyhow::Result<()> {
+    match pool_command.command {
+        Commands::Quote {
+            tokens: token_names,
+            erg,
+            scan_config,
+        } => {
+            let scan_config = ScanConfig::try_create(profile, scan_config, None)?;
+            let tokens = TokenStore::load(Some(profile.token_store_path())).unwrap_or_default();
+
+            let units = token_names
+                .iter()
+                .map(|name| tokens.get_unit_by_id(name))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            let erg_amount = AmountParser::new(*ERG_UNIT, "ERG amount").parse(&erg)?;
+
+            let n2t_pools = unique_pools(
+                node_client
+                    .get_scan_unspent(scan_config.n2t_scan_id)
+                    .await?
+                    .into_iter()
+                    .filter_map(|b| b.try_into().ok())
+                    .collect(),
+            );
+
+            let rows = quote_rows(erg_amount.amount(), &units, &n2t_pools);
+
+            let mut table = Table::new(rows);
+            table.with(Style::empty());
+
+            println!("{}", table);
+
+            Ok(())
+        }
+    }
+}
+
+/// One row per unit with the output of swapping `erg_amount` nanoERG in the unit's
+/// deepest pool. Units without a pool, or a pool that cannot fill the swap, are listed
+/// with the reason instead of an output.
+fn quote_rows(
+    erg_amount: u64,
+    units: &[Unit<'_>],
+    pools: &[TrackedBox<SpectrumPool>],
+) -> Vec<QuoteRow> {
+    units
+        .iter()
+        .map(|unit| {
+            let (pool, output) = match select_pool(pools, unit.token_id(), None) {
+                Ok(pool) => {
+                    let output = TokenAmount::try_from(erg_amount)
+                        .map_err(|e| e.to_string())
+                        .and_then(|amount| {
+                            let input = (pool.value.asset_x.token_id, amount).into();
+                            pool.value.output_amount(&input).map_err(|e| e.to_string())
+                        })
+                        .map(|output| UnitAmount::new(*unit, *output.amount.as_u64()).to_string())
+                        .unwrap_or_else(|e| e);
+
+                    (token_id_hex(&pool.value.pool_nft.token_id), output)
+                }
+                Err(e) => ("-".to_string(), e.to_string()),
+            };
+
+            QuoteRow {
+                token: unit.name(),
+                pool,
+                output,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use ergo_lib::{ergo_chain_types::Digest32, ergotree_ir::chain::token::TokenId};
+    use off_the_grid::{
+        boxes::{liquidity_box::LiquidityProvider, tracked_box::TrackedBox},
+        spectrum::pool::SpectrumPool,
+        units::{token_id_hex, TokenInfo, TokenStore},
+    };
+
+    use crate::commands::test_util::tracked_pool;
+
+    use super::quote_rows;
+
+    #[test]
+    fn quote_two_tokens() {
+        let token_a: TokenId = Digest32::from([1u8; 32]).into();
+        let token_b: TokenId = Digest32::from([2u8; 32]).into();
+        let tokens = TokenStore::with_tokens(vec![
+            TokenInfo {
+                token_id: token_a,
+                name: "AAA".to_string(),
+                decimals: 0,
+            },
+            TokenInfo {
+                token_id: token_b,
+                name: "BBB".to_string(),
+                decimals: 2,
+            },
+        ]);
+
+        let pools = vec![
+            tracked_pool(20, token_a, 1_000_000_000_000, 1_000_000),
+            tracked_pool(21, token_b, 1_000_000_000_000, 2_000_000),
+        ];
+
+        let units = vec![
+            tokens.get_unit_by_id("AAA").unwrap(),
+            tokens.get_unit_by_id("BBB").unwrap(),
+        ];
+
+        let expected_output = |pool: &TrackedBox<SpectrumPool>| {
+            let input = (
+                pool.value.asset_x.token_id,
+                1_000_000_000.try_into().unwrap(),
+            )
+                .into();
+            *pool.value.output_amount(&input).unwrap().amount.as_u64()
+        };
+
+        let rows = quote_rows(1_000_000_000, &units, &pools);
+
+        assert_eq!(rows.len(), 2);
+
+        assert_eq!(rows[0].token, "AAA");
+        assert_eq!(
+            rows[0].pool,
+            token_id_hex(&pools[0].value.pool_nft.token_id)
+        );
+        assert_eq!(
+            rows[0].output,
+            format!("{} AAA", expected_output(&pools[0]))
+        );
+
+        assert_eq!(rows[1].token, "BBB");
+        assert_eq!(
+            rows[1].pool,
+            token_id_hex(&pools[1].value.pool_nft.token_id)
+        );
+        assert!(rows[1].output.ends_with(" BBB"));
+
+        let no_pool = quote_rows(1_000_000_000, &units[..1], &pools[1..]);
+        assert_eq!(no_pool[0].pool, "-");
+    }
+}
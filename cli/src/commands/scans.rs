@@ -1,7 +1,7 @@
 use clap::{Args, Subcommand};
 use ergo_lib::ergotree_ir::{
-    chain::address::Address, mir::constant::Constant, serialization::SigmaSerializable,
-    sigma_protocol::sigma_boolean::ProveDlog,
+    chain::address::Address, ergo_tree::ErgoTree, mir::constant::Constant,
+    serialization::SigmaSerializable, sigma_protocol::sigma_boolean::ProveDlog,
 };
 use off_the_grid::{
     grid::multigrid_order::MULTIGRID_ORDER_SCRIPT,
@@ -12,7 +12,11 @@ use off_the_grid::{
     spectrum::pool,
 };
 
-use crate::scan_config::ScanConfig;
+use crate::{
+    commands::warn_wallet_error,
+    profile::Profile,
+    scan_config::{embedded_fingerprints, ScanConfig},
+};
 
 #[derive(Clone, Debug)]
 pub enum RescanHeight {
@@ -55,14 +59,14 @@ pub struct ScansCommand {
     command: Commands,
 }
 
-fn n2t_tracking_rule() -> TrackingRule {
+fn pool_tracking_rule(pool_script: &ErgoTree) -> TrackingRule {
     // We assume the pool script is always valid
-    let n2t_scan_script = pool::N2T_POOL_SCRIPT.sigma_serialize_bytes().unwrap();
-    let n2t_scan_value = Constant::from(n2t_scan_script);
-    let n2t_scan_value_bytes = n2t_scan_value.sigma_serialize_bytes().unwrap();
+    let pool_scan_script = pool_script.sigma_serialize_bytes().unwrap();
+    let pool_scan_value = Constant::from(pool_scan_script);
+    let pool_scan_value_bytes = pool_scan_value.sigma_serialize_bytes().unwrap();
 
     TrackingRule::Equals {
-        value: n2t_scan_value_bytes,
+        value: pool_scan_value_bytes,
         register: "R1".to_string(),
     }
 }
@@ -125,6 +129,7 @@ async fn get_or_create_scan(
 
 pub async fn handle_scan_command(
     node_client: NodeClient,
+    profile: &Profile,
     scan_command: ScansCommand,
 ) -> anyhow::Result<()> {
     match scan_command.command {
@@ -134,6 +139,7 @@ pub async fn handle_scan_command(
         } => {
             let wallet_status = node_client.wallet_status().await?;
             wallet_status.error_if_locked()?;
+            warn_wallet_error(&wallet_status);
             let change_address = wallet_status.change_address()?;
 
             let owner_dlog = if let Address::P2Pk(owner_dlog) = change_address {
@@ -142,13 +148,15 @@ pub async fn handle_scan_command(
                 Err(anyhow::anyhow!("Change address is not a P2PK address"))
             }?;
 
-            let n2t_tracking_rule = n2t_tracking_rule();
+            let n2t_tracking_rule = pool_tracking_rule(&pool::N2T_POOL_SCRIPT);
+            let t2t_tracking_rule = pool_tracking_rule(&pool::T2T_POOL_SCRIPT);
             let wallet_multigrid_tracking_rule = wallet_multigrid_tracking_rule(owner_dlog);
             let multigrid_tracking_rule = multigrid_tracking_rule();
 
             let scans = node_client.list_scans().await?;
 
             let n2t_scan = scans.iter().find(|s| s.tracking_rule == n2t_tracking_rule);
+            let t2t_scan = scans.iter().find(|s| s.tracking_rule == t2t_tracking_rule);
             let wallet_multigrid_scan = scans
                 .iter()
                 .find(|s| s.tracking_rule == wallet_multigrid_tracking_rule);
@@ -160,6 +168,9 @@ pub async fn handle_scan_command(
             let n2t_scan_id =
                 get_or_create_scan(&node_client, n2t_tracking_rule, n2t_scan, "N2T Pool").await?;
 
+            let t2t_scan_id =
+                get_or_create_scan(&node_client, t2t_tracking_rule, t2t_scan, "T2T Pool").await?;
+
             let wallet_multigrid_scan_id = get_or_create_scan(
                 &node_client,
                 wallet_multigrid_tracking_rule,
@@ -178,12 +189,14 @@ pub async fn handle_scan_command(
 
             let scan_config = ScanConfig {
                 n2t_scan_id,
+                t2t_scan_id: Some(t2t_scan_id),
                 wallet_multigrid_scan_id,
                 multigrid_scan_id,
+                script_fingerprints: embedded_fingerprints(),
             };
 
-            let output_path = output_path.unwrap_or_else(|| "scan_config.json".to_string());
-            std::fs::write(&output_path, serde_json::to_string_pretty(&scan_config)?)?;
+            let output_path = output_path.unwrap_or_else(|| profile.path("scan_config.json"));
+            scan_config.save(&output_path)?;
 
             if let Some(rescan_height) = rescan_height {
                 let height = match rescan_height {
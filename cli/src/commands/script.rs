@@ -0,0 +1,80 @@
+use clap::{Args, Subcommand};
+use colored::Colorize;
+
+use crate::{
+    profile::Profile,
+    scan_config::{embedded_fingerprints, ScanConfig, ScriptFingerprint},
+};
+
+#[derive(Subcommand)]
+pub enum Commands {
+    /// Print the fingerprint of each embedded script and compare it against the scan config
+    Version {
+        #[clap(long, help = "Scan configuration file path [default: scan_config]")]
+        scan_config: Option<String>,
+    },
+}
+
+#[derive(Args)]
+pub struct ScriptCommand {
+    #[command(subcommand)]
+    command: Commands,
+}
+
+pub async fn handle_script_command(
+    profile: &Profile,
+    script_command: ScriptCommand,
+) -> anyhow::Result<()> {
+    match script_command.command {
+        Commands::Version { scan_config } => {
+            match ScanConfig::try_create(profile, scan_config, None) {
+                Ok(scan_config) => {
+                    for line in fingerprint_lines(&scan_config.script_fingerprints()) {
+                        println!("{}", line);
+                    }
+                }
+                Err(e) => {
+                    for (name, fingerprint) in embedded_fingerprints() {
+                        println!("{}: {}", name, fingerprint);
+                    }
+                    eprintln!(
+                        "{}",
+                        format!("Warning: Could not load scan config to compare: {}", e).yellow()
+                    );
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn fingerprint_lines(fingerprints: &[ScriptFingerprint]) -> Vec<String> {
+    let mut lines: Vec<String> = fingerprints
+        .iter()
+        .map(|fingerprint| {
+            let status = match &fingerprint.recorded {
+                None => "not recorded in scan config".to_string(),
+                Some(_) if !fingerprint.is_mismatch() => "matches scan config".to_string(),
+                Some(recorded) => format!("scan config records {}", recorded)
+                    .yellow()
+                    .to_string(),
+            };
+
+            format!(
+                "{}: {} ({})",
+                fingerprint.name, fingerprint.embedded, status
+            )
+        })
+        .collect();
+
+    if fingerprints.iter().any(|f| f.is_mismatch()) {
+        lines.push(
+            "Warning: The scans track a different script version, rerun `scans create-config`"
+                .yellow()
+                .to_string(),
+        );
+    }
+
+    lines
+}
@@ -0,0 +1,247 @@
+use std::collections::HashMap;
+
+use clap::Args;
+use ergo_lib::ergotree_ir::chain::{ergo_box::ErgoBox, token::TokenId};
+use off_the_grid::{
+    boxes::{tracked_box::TrackedBox, wallet_box::WalletBox},
+    grid::multigrid_order::MultiGridOrder,
+    node::client::NodeClient,
+    units::{token_id_hex, TokenStore, UnitAmount, ERG_UNIT},
+};
+use tokio::try_join;
+
+use crate::{profile::Profile, scan_config::ScanConfig};
+
+#[derive(Args)]
+pub struct StatusCommand {
+    #[clap(long, help = "Scan configuration file path [default: scan_config]")]
+    scan_config: Option<String>,
+}
+
+/// Grids trading a single token
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+struct MarketSummary {
+    num_grids: usize,
+    locked_value: u64,
+    locked_tokens: u64,
+    profit: u64,
+}
+
+/// Wallet balance and the totals of all grids owned by the wallet
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+struct PortfolioSummary {
+    wallet_value: u64,
+    wallet_tokens: Vec<(TokenId, u64)>,
+    markets: Vec<(TokenId, MarketSummary)>,
+}
+
+impl PortfolioSummary {
+    fn new(wallet_boxes: &[WalletBox<ErgoBox>], grids: &[TrackedBox<MultiGridOrder>]) -> Self {
+        let wallet_value = wallet_boxes.iter().map(|b| *b.assets.value.as_u64()).sum();
+
+        let mut wallet_tokens: HashMap<TokenId, u64> = HashMap::new();
+        for token in wallet_boxes
+            .iter()
+            .flat_map(|b| b.assets.tokens.iter().flat_map(|t| t.iter()))
+        {
+            *wallet_tokens.entry(token.token_id).or_default() += *token.amount.as_u64();
+        }
+
+        let mut markets: HashMap<TokenId, MarketSummary> = HashMap::new();
+        for grid in grids {
+            let market = markets.entry(grid.value.token_id).or_default();
+
+            market.num_grids += 1;
+            market.locked_value += *grid.value.value.as_u64();
+            market.locked_tokens += grid.value.entries.token_amount();
+            market.profit += grid.value.profit();
+        }
+
+        Self {
+            wallet_value,
+            wallet_tokens: sorted_by_token(wallet_tokens),
+            markets: sorted_by_token(markets),
+        }
+    }
+
+    fn num_grids(&self) -> usize {
+        self.markets.iter().map(|(_, m)| m.num_grids).sum()
+    }
+
+    fn locked_value(&self) -> u64 {
+        self.markets.iter().map(|(_, m)| m.locked_value).sum()
+    }
+
+    fn profit(&self) -> u64 {
+        self.markets.iter().map(|(_, m)| m.profit).sum()
+    }
+
+    fn lines(&self, tokens: &TokenStore) -> Vec<String> {
+        let erg = |amount| UnitAmount::new(*ERG_UNIT, amount);
+
+        let mut lines = vec![format!("Wallet: {}", erg(self.wallet_value))];
+
+        lines.extend(self.wallet_tokens.iter().map(|(token_id, amount)| {
+            format!("  {}", UnitAmount::new(tokens.get_unit(token_id), *amount))
+        }));
+
+        lines.push(format!(
+            "Grids: {}, Locked {}, Profit {}",
+            self.num_grids(),
+            erg(self.locked_value()),
+            erg(self.profit())
+        ));
+
+        if !self.markets.is_empty() {
+            lines.push("Markets:".to_string());
+        }
+
+        lines.extend(self.markets.iter().map(|(token_id, market)| {
+            let unit = tokens.get_unit(token_id);
+
+            format!(
+                "  {} | {} grids, Locked {} and {}, Profit {}",
+                unit.name(),
+                market.num_grids,
+                erg(market.locked_value),
+                UnitAmount::new(unit, market.locked_tokens),
+                erg(market.profit)
+            )
+        }));
+
+        lines
+    }
+}
+
+/// Entries sorted by token ID, so the summary is printed in a stable order
+fn sorted_by_token<T>(entries: HashMap<TokenId, T>) -> Vec<(TokenId, T)> {
+    let mut entries: Vec<_> = entries.into_iter().collect();
+    entries.sort_by_key(|(token_id, _)| token_id_hex(token_id));
+    entries
+}
+
+pub async fn handle_status_command(
+    node_client: NodeClient,
+    profile: &Profile,
+    status_command: StatusCommand,
+) -> anyhow::Result<()> {
+    let scan_config = ScanConfig::try_create(profile, status_command.scan_config, None)?;
+    let tokens = TokenStore::load(Some(profile.token_store_path())).unwrap_or_default();
+
+    let (wallet_boxes, grid_boxes) = try_join!(
+        node_client.wallet_boxes_unspent(),
+        node_client.get_scan_unspent(scan_config.wallet_multigrid_scan_id)
+    )?;
+
+    let grids: Vec<TrackedBox<MultiGridOrder>> = grid_boxes
+        .into_iter()
+        .filter_map(|b| b.try_into().ok())
+        .collect();
+
+    for line in PortfolioSummary::new(&wallet_boxes, &grids).lines(&tokens) {
+        println!("{}", line);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use ergo_lib::{
+        chain::transaction::TxId,
+        ergo_chain_types::{ec_point::generator, Digest32},
+        ergotree_ir::{
+            chain::{
+                address::Address,
+                ergo_box::{ErgoBox, NonMandatoryRegisters},
+                token::{Token, TokenId},
+            },
+            sigma_protocol::sigma_boolean::ProveDlog,
+        },
+    };
+    use off_the_grid::{
+        boxes::{tracked_box::TrackedBox, wallet_box::WalletBox},
+        grid::multigrid_order::{
+            GridOrderEntries, GridOrderEntry, MultiGridOrder, OrderState, DEFAULT_MIN_BOX_VALUE,
+        },
+        units::{TokenInfo, TokenStore},
+    };
+
+    use crate::commands::test_util::grid_box;
+
+    use super::PortfolioSummary;
+
+    fn wallet_box(value: u64, tokens: Option<Vec<Token>>, index: u16) -> WalletBox<ErgoBox> {
+        let address = Address::P2Pk(ProveDlog::new(generator()));
+        let ergo_box = ErgoBox::new(
+            value.try_into().unwrap(),
+            address.script().unwrap(),
+            tokens.map(|t| t.try_into().unwrap()),
+            NonMandatoryRegisters::empty(),
+            0,
+            TxId::zero(),
+            index,
+        )
+        .unwrap();
+
+        WalletBox::new(ergo_box, address)
+    }
+
+    fn grid(token_id: TokenId, index: u16) -> TrackedBox<MultiGridOrder> {
+        let entries = GridOrderEntries::new(vec![
+            GridOrderEntry::new(
+                OrderState::Buy,
+                10.try_into().unwrap(),
+                100_000_000,
+                110_000_000,
+            ),
+            GridOrderEntry::new(
+                OrderState::Sell,
+                10.try_into().unwrap(),
+                120_000_000,
+                130_000_000,
+            ),
+        ]);
+
+        grid_box(token_id, entries, None, index)
+    }
+
+    #[test]
+    fn summary_includes_grids_and_wallet() {
+        let token_id: TokenId = Digest32::from([1u8; 32]).into();
+        let tokens = TokenStore::with_tokens(vec![TokenInfo {
+            token_id,
+            name: "TKN".to_string(),
+            decimals: 0,
+        }]);
+
+        let wallet_boxes = vec![
+            wallet_box(2_000_000_000, None, 0),
+            wallet_box(
+                500_000_000,
+                Some(vec![(token_id, 25.try_into().unwrap()).into()]),
+                1,
+            ),
+        ];
+        let grids = vec![grid(token_id, 2), grid(token_id, 3)];
+
+        let summary = PortfolioSummary::new(&wallet_boxes, &grids);
+
+        assert_eq!(summary.wallet_value, 2_500_000_000);
+        assert_eq!(summary.wallet_tokens, vec![(token_id, 25)]);
+        assert_eq!(summary.num_grids(), 2);
+        assert_eq!(
+            summary.locked_value(),
+            2 * (DEFAULT_MIN_BOX_VALUE + 100_000_000)
+        );
+
+        let lines = summary.lines(&tokens);
+
+        assert_eq!(lines[0], "Wallet: 2.5 ERG");
+        assert_eq!(lines[1], "  25 TKN");
+        assert!(lines[2].starts_with("Grids: 2, Locked"));
+        assert_eq!(lines[3], "Markets:");
+        assert!(lines[4].starts_with("  TKN | 2 grids"));
+        assert!(lines[4].contains("20 TKN"));
+    }
+}
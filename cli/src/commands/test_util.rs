@@ -0,0 +1,88 @@
+//! Fixtures shared by the tests of the commands
+
+use ergo_lib::{
+    chain::transaction::TxId,
+    ergo_chain_types::{ec_point::generator, Digest32},
+    ergotree_ir::chain::{ergo_box::ErgoBox, token::TokenId},
+};
+use off_the_grid::{
+    boxes::tracked_box::TrackedBox,
+    grid::multigrid_order::{GridOrderEntries, MultiGridOrder, DEFAULT_MIN_BOX_VALUE},
+    spectrum::pool::{SpectrumPool, SpectrumPoolBuilder, DEFAULT_FEE_DENOM},
+};
+
+/// N2T pool with a 0.3% fee, identified by `nft`, which also sets its LP token
+pub fn test_pool(nft: u8, token_id: TokenId, erg_amount: u64, token_amount: u64) -> SpectrumPool {
+    SpectrumPoolBuilder::new(
+        Digest32::from([nft; 32]).into(),
+        (
+            Digest32::from([nft.wrapping_add(1); 32]).into(),
+            1000.try_into().unwrap(),
+        )
+            .into(),
+        token_id,
+    )
+    .reserves(erg_amount, token_amount)
+    .fee(997, DEFAULT_FEE_DENOM)
+    .build()
+    .unwrap()
+}
+
+/// `pool` as the output `index` of a transaction
+pub fn pool_box(pool: SpectrumPool, index: u16) -> TrackedBox<SpectrumPool> {
+    let candidate = pool.into_box_candidate(0).unwrap();
+
+    ErgoBox::from_box_candidate(&candidate, TxId::zero(), index)
+        .unwrap()
+        .try_into()
+        .unwrap()
+}
+
+/// Pool box of [`test_pool`], output at the index `nft`
+pub fn tracked_pool(
+    nft: u8,
+    token_id: TokenId,
+    erg_amount: u64,
+    token_amount: u64,
+) -> TrackedBox<SpectrumPool> {
+    pool_box(
+        test_pool(nft, token_id, erg_amount, token_amount),
+        nft as u16,
+    )
+}
+
+/// Grid order of the generator key trading `token_id`, with the default base value
+pub fn test_order(
+    token_id: TokenId,
+    entries: GridOrderEntries,
+    metadata: Option<Vec<u8>>,
+) -> MultiGridOrder {
+    MultiGridOrder::new(
+        generator(),
+        token_id,
+        entries,
+        metadata,
+        DEFAULT_MIN_BOX_VALUE,
+    )
+    .unwrap()
+}
+
+/// `order` as the output `index` of a transaction
+pub fn order_box(order: MultiGridOrder, index: u16) -> TrackedBox<MultiGridOrder> {
+    let candidate = order.into_box_candidate(0).unwrap();
+
+    ErgoBox::from_box_candidate(&candidate, TxId::zero(), index)
+        .unwrap()
+        .try_into()
+        .unwrap()
+}
+
+/// Grid order box of [`test_order`], output at `index`
+pub fn grid_box(
+    token_id: TokenId,
+    entries: GridOrderEntries,
+    metadata: Option<Vec<u8>>,
+    index: u16,
+) -> TrackedBox<MultiGridOrder> {
+    order_box(test_order(token_id, entries, metadata), index)
+}
@@ -1,15 +1,24 @@
-use std::collections::HashSet;
+use std::{collections::HashSet, future::Future, iter::once};
 
-use clap::{Args, Subcommand};
+use clap::{Args, Subcommand, ValueEnum};
+use ergo_lib::ergotree_ir::chain::token::TokenId;
 use futures::future::join_all;
 use off_the_grid::{
     boxes::tracked_box::TrackedBox,
     node::client::NodeClient,
     spectrum::pool::SpectrumPool,
-    units::{TokenInfo, TokenStore, Unit},
+    units::{token_id_hex, TokenInfo, TokenStore, Unit},
 };
+use reqwest::Url;
+use tabled::{settings::Style, Table, Tabled};
 
-use crate::scan_config::ScanConfig;
+use crate::{
+    commands::{csv_field, unique_pools},
+    profile::Profile,
+    scan_config::ScanConfig,
+};
+
+pub const DEFAULT_EXPLORER_URL: &str = "https://api.ergoplatform.com/api/v1";
 
 #[derive(Subcommand)]
 pub enum Commands {
@@ -20,10 +29,53 @@ pub enum Commands {
         #[clap(
             long,
             help = "Explorer API URL",
-            default_value = "https://api.ergoplatform.com/api/v1"
+            default_value = DEFAULT_EXPLORER_URL,
+            value_parser = explorer_url_from_str
         )]
-        explorer_url: String,
+        explorer_url: Url,
     },
+    /// List the tokens in the token store
+    List {
+        #[clap(long, value_enum, default_value_t)]
+        format: TokenListFormat,
+    },
+}
+
+/// Output format of `tokens list`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum TokenListFormat {
+    /// Aligned columns
+    #[default]
+    Table,
+    /// JSON array in the format of the token store file
+    Json,
+    /// Comma separated values with a header row
+    Csv,
+}
+
+#[derive(Tabled)]
+struct TokenRow<'a> {
+    #[tabled(rename = "Name")]
+    name: &'a str,
+    #[tabled(rename = "Decimals")]
+    decimals: u32,
+    #[tabled(rename = "Token ID")]
+    token_id: String,
+}
+
+/// Parse the explorer API base URL, normalized to end with a slash so that endpoint paths
+/// can be joined onto it
+pub fn explorer_url_from_str(s: &str) -> Result<Url, String> {
+    let url = Url::parse(&format!("{}/", s.trim().trim_end_matches('/')))
+        .map_err(|e| format!("Invalid explorer URL {}: {}", s, e))?;
+
+    match url.scheme() {
+        "http" | "https" => Ok(url),
+        scheme => Err(format!(
+            "Invalid explorer URL {}: expected an http or https URL, got {}",
+            s, scheme
+        )),
+    }
 }
 
 #[derive(Args)]
@@ -34,6 +86,7 @@ pub struct TokensCommand {
 
 pub async fn handle_tokens_command(
     node_client: NodeClient,
+    profile: &Profile,
     units_command: TokensCommand,
 ) -> anyhow::Result<()> {
     match units_command.command {
@@ -41,88 +94,233 @@ pub async fn handle_tokens_command(
             scan_config,
             explorer_url,
         } => {
-            let scan_config = ScanConfig::try_create(scan_config, None)?;
-
-            let n2t_pools: Vec<TrackedBox<SpectrumPool>> = node_client
-                .get_scan_unspent(scan_config.n2t_scan_id)
-                .await?
-                .into_iter()
-                .filter_map(|b| b.try_into().ok())
-                .collect();
-
-            let current_tokens = TokenStore::load(None).unwrap_or_default();
-
-            let token_ids: HashSet<_> = n2t_pools
-                .iter()
-                .map(|b| b.value.asset_y.token_id)
-                .filter(|token_id| match current_tokens.get_unit(token_id) {
-                    Unit::Known(_) => false,
-                    Unit::Unknown(_) => true,
-                })
-                .collect();
-
-            if token_ids.is_empty() {
-                println!("No new tokens to add");
-                return Ok(());
-            }
+            let scan_config = ScanConfig::try_create(profile, scan_config, None)?;
 
-            let explorer_client = reqwest::Client::new();
-
-            let urls = token_ids
-                .iter()
-                .map(|token_id| {
-                    format!(
-                        "{}/tokens/{}",
-                        explorer_url.trim_end_matches('/'),
-                        String::from(*token_id)
-                    )
-                })
-                .collect::<Vec<_>>();
-
-            let responses = join_all(urls.into_iter().map(|url| {
-                let client = &explorer_client;
-                async move {
-                    let resp = client.get(url).send().await;
-                    match resp {
-                        Ok(resp) => {
-                            if resp.status().is_success() {
-                                resp.json::<TokenInfo>().await.ok()
-                            } else {
-                                None
-                            }
-                        }
-                        Err(_) => None,
-                    }
-                }
+            update_token_store(&node_client, profile, &scan_config, &explorer_url).await?;
+        }
+        Commands::List { format } => {
+            let token_store =
+                TokenStore::load(Some(profile.token_store_path())).unwrap_or_default();
+
+            println!("{}", token_list(&token_store, format)?);
+        }
+    }
+    Ok(())
+}
+
+/// Every token of `token_store` sorted by name, formatted as `format`
+fn token_list(token_store: &TokenStore, format: TokenListFormat) -> anyhow::Result<String> {
+    let mut tokens: Vec<&TokenInfo> = token_store.tokens().collect();
+    tokens.sort_by(|a, b| {
+        a.name
+            .cmp(&b.name)
+            .then_with(|| token_id_hex(&a.token_id).cmp(&token_id_hex(&b.token_id)))
+    });
+
+    let list = match format {
+        TokenListFormat::Table => {
+            let rows = tokens.iter().map(|token| TokenRow {
+                name: &token.name,
+                decimals: token.decimals,
+                token_id: token_id_hex(&token.token_id),
+            });
+
+            let mut table = Table::new(rows);
+            table.with(Style::empty());
+            table.to_string()
+        }
+        TokenListFormat::Json => serde_json::to_string_pretty(&tokens)?,
+        TokenListFormat::Csv => once("id,name,decimals".to_string())
+            .chain(tokens.iter().map(|token| {
+                format!(
+                    "{},{},{}",
+                    token_id_hex(&token.token_id),
+                    csv_field(&token.name),
+                    token.decimals
+                )
             }))
-            .await;
+            .collect::<Vec<_>>()
+            .join("\n"),
+    };
 
-            let errors = responses.iter().filter(|resp| resp.is_none()).count();
+    Ok(list)
+}
 
-            if errors > 0 {
-                eprintln!(
-                    "Error: Failed to fetch {} out of {} tokens from explorer API",
-                    errors,
-                    token_ids.len()
-                );
-            }
+/// Add the tokens of all N2T pools missing from the profile's token store, fetching their
+/// information from the explorer API. Returns the updated token store.
+pub async fn update_token_store(
+    node_client: &NodeClient,
+    profile: &Profile,
+    scan_config: &ScanConfig,
+    explorer_url: &Url,
+) -> anyhow::Result<TokenStore> {
+    let n2t_pools = unique_pools(
+        node_client
+            .get_scan_unspent(scan_config.n2t_scan_id)
+            .await?
+            .into_iter()
+            .filter_map(|b| b.try_into().ok())
+            .collect(),
+    );
 
-            let successes = responses.iter().filter(|resp| resp.is_some()).count();
+    let current_tokens = TokenStore::load(Some(profile.token_store_path())).unwrap_or_default();
 
-            if successes > 0 {
-                println!("{} new tokens added", successes);
-            }
+    let token_ids = unknown_pool_tokens(&n2t_pools, &current_tokens);
+
+    if token_ids.is_empty() {
+        println!("No new tokens to add");
+        return Ok(current_tokens);
+    }
+
+    let explorer_client = reqwest::Client::new();
 
-            let unitsystem = TokenStore::with_tokens(
-                responses
-                    .into_iter()
-                    .flatten()
-                    .chain(current_tokens.tokens().cloned())
-                    .collect(),
-            );
+    let token_store = merge_fetched_tokens(current_tokens, token_ids, |token_id| {
+        let client = &explorer_client;
+        let url = explorer_url.join(&format!("tokens/{}", String::from(token_id)));
 
-            unitsystem.save(None)?;
+        async move {
+            let resp = client.get(url.ok()?).send().await;
+            match resp {
+                Ok(resp) => {
+                    if resp.status().is_success() {
+                        resp.json::<TokenInfo>().await.ok()
+                    } else {
+                        None
+                    }
+                }
+                Err(_) => None,
+            }
         }
+    })
+    .await;
+
+    token_store.save(Some(profile.token_store_path()))?;
+
+    Ok(token_store)
+}
+
+/// Tokens traded in `pools` that are not in `current_tokens`
+fn unknown_pool_tokens(
+    pools: &[TrackedBox<SpectrumPool>],
+    current_tokens: &TokenStore,
+) -> HashSet<TokenId> {
+    pools
+        .iter()
+        .map(|b| b.value.asset_y.token_id)
+        .filter(|token_id| match current_tokens.get_unit(token_id) {
+            Unit::Known(_) => false,
+            Unit::Unknown(_) => true,
+        })
+        .collect()
+}
+
+/// Fetch the information of every token in `token_ids` with `fetch` and add it to
+/// `current_tokens`, printing how many tokens were added or failed to fetch
+pub(super) async fn merge_fetched_tokens<F, Fut>(
+    current_tokens: TokenStore,
+    token_ids: HashSet<TokenId>,
+    fetch: F,
+) -> TokenStore
+where
+    F: Fn(TokenId) -> Fut,
+    Fut: Future<Output = Option<TokenInfo>>,
+{
+    let responses = join_all(token_ids.iter().map(|token_id| fetch(*token_id))).await;
+
+    let errors = responses.iter().filter(|resp| resp.is_none()).count();
+
+    if errors > 0 {
+        eprintln!(
+            "Error: Failed to fetch {} out of {} tokens from explorer API",
+            errors,
+            token_ids.len()
+        );
+    }
+
+    let successes = responses.iter().filter(|resp| resp.is_some()).count();
+
+    if successes > 0 {
+        println!("{} new tokens added", successes);
+    }
+
+    let token_store = TokenStore::with_tokens(
+        responses
+            .into_iter()
+            .flatten()
+            .chain(current_tokens.tokens().cloned())
+            .collect(),
+    );
+
+    for (name, token_ids) in token_store.ambiguous_names() {
+        eprintln!(
+            "Warning: {} tokens are named `{}`, refer to them by token ID",
+            token_ids.len(),
+            name
+        );
+    }
+
+    token_store
+}
+
+#[cfg(test)]
+mod tests {
+    use ergo_lib::ergo_chain_types::Digest32;
+    use off_the_grid::units::{TokenInfo, TokenStore};
+
+    use super::{explorer_url_from_str, token_list, TokenListFormat};
+
+    #[test]
+    fn token_list_formats() {
+        let token_store = TokenStore::with_tokens(vec![
+            TokenInfo {
+                token_id: Digest32::from([1u8; 32]).into(),
+                name: "SigUSD".to_string(),
+                decimals: 2,
+            },
+            TokenInfo {
+                token_id: Digest32::from([2u8; 32]).into(),
+                name: "Comet, the token".to_string(),
+                decimals: 0,
+            },
+        ]);
+
+        let json = token_list(&token_store, TokenListFormat::Json).unwrap();
+        let listed: Vec<TokenInfo> = serde_json::from_str(&json).unwrap();
+
+        let names: Vec<_> = listed.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, vec!["Comet, the token", "ERG", "SigUSD"]);
+        assert_eq!(listed[1].token_id, Digest32::zero().into());
+        assert_eq!(listed[1].decimals, 9);
+        assert_eq!(listed[2].token_id, Digest32::from([1u8; 32]).into());
+        assert_eq!(listed[2].decimals, 2);
+
+        let csv = token_list(&token_store, TokenListFormat::Csv).unwrap();
+        let lines: Vec<_> = csv.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], "id,name,decimals");
+        assert!(lines[1].ends_with(",\"Comet, the token\",0"));
+        assert!(lines[2].ends_with(",ERG,9"));
+
+        let table = token_list(&token_store, TokenListFormat::Table).unwrap();
+        assert!(table
+            .lines()
+            .any(|l| l.contains("SigUSD") && l.contains(" 2 ")));
+    }
+
+    #[test]
+    fn explorer_url_validated() {
+        let url = explorer_url_from_str("https://api.ergoplatform.com/api/v1/").unwrap();
+        assert_eq!(url.as_str(), "https://api.ergoplatform.com/api/v1/");
+
+        let url = explorer_url_from_str("https://api.ergoplatform.com/api/v1").unwrap();
+        assert_eq!(
+            url.join("tokens/abc").unwrap().as_str(),
+            "https://api.ergoplatform.com/api/v1/tokens/abc"
+        );
+
+        let error = explorer_url_from_str("api.ergoplatform.com/api/v1").unwrap_err();
+        assert!(error.starts_with("Invalid explorer URL api.ergoplatform.com/api/v1"));
+
+        assert!(explorer_url_from_str("ftp://api.ergoplatform.com").is_err());
     }
-    Ok(())
 }
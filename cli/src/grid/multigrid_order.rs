@@ -15,7 +15,9 @@ use ergo_lib::{
 };
 
 use lazy_static::lazy_static;
-use std::collections::HashMap;
+use num_traits::ToPrimitive;
+use serde::Serialize;
+use std::{cmp::Ordering, collections::HashMap};
 use thiserror::Error;
 
 use crate::{
@@ -23,12 +25,23 @@ use crate::{
         describe_box::{BoxAssetDisplay, ErgoBoxDescriptors},
         tracked_box::TrackedBox,
     },
-    units::{Fraction, TokenStore, UnitAmount, ERG_UNIT},
+    units::{token_id_hex, Fraction, TokenStore, UnitAmount, ERG_UNIT},
 };
 
-const MIN_BOX_VALUE: u64 = 1000000;
+/// Default base value of a grid order box, held on top of the value of its buy orders
+pub const DEFAULT_MIN_BOX_VALUE: u64 = 1000000;
 pub const MAX_FEE: u64 = 2000000;
 
+/// Scale the default base value of grid order boxes with the protocol's minimum value per
+/// byte. The default was chosen for a minimum of `BoxValue::MIN_VALUE_PER_BOX_BYTE`, so it
+/// is only ever raised.
+pub fn min_box_value_for_parameters(min_value_per_byte: u64) -> u64 {
+    let scaled = (DEFAULT_MIN_BOX_VALUE as u128 * min_value_per_byte as u128)
+        / BoxValue::MIN_VALUE_PER_BOX_BYTE as u128;
+
+    DEFAULT_MIN_BOX_VALUE.max(scaled.try_into().unwrap_or(u64::MAX))
+}
+
 pub const MULTIGRID_ORDER_BASE16_BYTES: &[u8] = include_bytes!("../../grid_multi.ergotree");
 
 lazy_static! {
@@ -43,7 +56,7 @@ lazy_static! {
 
 #[derive(Error, Debug)]
 pub enum MultiGridConfigurationError {
-    #[error("TokenId {0:?} expected, got {1:?}")]
+    #[error("TokenId {} expected, got {}", token_id_hex(.0), token_id_hex(.1))]
     TokenId(TokenId, TokenId),
 
     #[error("Exactly {0} tokens expected, got {1}")]
@@ -85,14 +98,36 @@ pub enum MultiGridOrderError {
     #[error("Invalid register value at {0:?}: {1}")]
     InvalidRegisterValue(NonMandatoryRegisterId, String),
 
+    #[error("Unexpected R5 structure, expected Coll[((Long, Boolean), (Long, Long))], got {0}")]
+    UnexpectedEntryStructure(String),
+
     #[error("{0} when converting number")]
     TryFromIntError(#[from] std::num::TryFromIntError),
 
+    #[error("Negative {0} {1} in grid entry register")]
+    NegativeRegisterValue(&'static str, i64),
+
+    #[error("{0} {1} does not fit the i64 of a grid entry register")]
+    RegisterValueOverflow(&'static str, u64),
+
     #[error("Value overflow")]
     ValueOverflow,
+
+    #[error("Entry index {0} out of range for {1} entries")]
+    EntryIndex(usize, usize),
+
+    #[error("Grid order has no entries")]
+    NoEntries,
+
+    #[error("{0} price levels given for {1} entries")]
+    LevelCount(usize, usize),
+
+    #[error("No spread between the bid and ask of level {0} at {1} tokens")]
+    NoSpread(usize, u64),
 }
 
-#[derive(Clone, Copy, PartialEq, Debug)]
+#[derive(Clone, Copy, PartialEq, Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum OrderState {
     Buy,
     Sell,
@@ -106,7 +141,18 @@ pub struct GridOrderEntry {
     pub ask_value: u64,
 }
 
-type EntryTuple = ((i64, bool), (i64, i64));
+/// Register representation of a grid entry: ((token amount, is buy order), (bid value, ask value)).
+/// The contract stores amounts and values as signed i64, so they are limited to
+/// `i64::MAX` and a negative value in a register is never a valid entry.
+pub type EntryTuple = ((i64, bool), (i64, i64));
+
+fn to_register_value(field: &'static str, value: u64) -> Result<i64, MultiGridOrderError> {
+    i64::try_from(value).map_err(|_| MultiGridOrderError::RegisterValueOverflow(field, value))
+}
+
+fn from_register_value(field: &'static str, value: i64) -> Result<u64, MultiGridOrderError> {
+    u64::try_from(value).map_err(|_| MultiGridOrderError::NegativeRegisterValue(field, value))
+}
 
 impl GridOrderEntry {
     pub fn new(
@@ -142,8 +188,14 @@ impl GridOrderEntry {
         };
 
         Ok((
-            (self.order_amount().try_into()?, state_bool),
-            (self.bid_value.try_into()?, self.ask_value.try_into()?),
+            (
+                to_register_value("token amount", self.order_amount())?,
+                state_bool,
+            ),
+            (
+                to_register_value("bid value", self.bid_value)?,
+                to_register_value("ask value", self.ask_value)?,
+            ),
         ))
     }
 
@@ -155,9 +207,9 @@ impl GridOrderEntry {
             false => OrderState::Sell,
         };
 
-        let order_amount: u64 = amount.try_into()?;
-        let bid_value = bid_value.try_into()?;
-        let ask_value = ask_value.try_into()?;
+        let order_amount = from_register_value("token amount", amount)?;
+        let bid_value = from_register_value("bid value", bid_value)?;
+        let ask_value = from_register_value("ask value", ask_value)?;
 
         Ok(Self {
             state,
@@ -168,22 +220,56 @@ impl GridOrderEntry {
     }
 }
 
+/// Entries of a grid order. The best bid and ask entries are tracked when the entries are
+/// created or filled, so looking them up does not scan the entries.
 #[derive(Clone, Debug)]
-pub struct GridOrderEntries(Vec<GridOrderEntry>);
+pub struct GridOrderEntries {
+    entries: Vec<GridOrderEntry>,
+    best_bid: Option<usize>,
+    best_ask: Option<usize>,
+}
 
 impl GridOrderEntries {
     pub fn new(entries: Vec<GridOrderEntry>) -> Self {
-        Self(entries)
+        let mut entries = Self {
+            entries,
+            best_bid: None,
+            best_ask: None,
+        };
+        entries.update_best();
+        entries
+    }
+
+    fn update_best(&mut self) {
+        self.best_bid = self
+            .entries
+            .iter()
+            .enumerate()
+            .filter(|(_, e)| e.state == OrderState::Buy)
+            .max_by_key(|(_, e)| e.bid())
+            .map(|(i, _)| i);
+
+        self.best_ask = self
+            .entries
+            .iter()
+            .enumerate()
+            .filter(|(_, e)| e.state == OrderState::Sell)
+            .min_by_key(|(_, e)| e.ask())
+            .map(|(i, _)| i);
     }
 
     pub fn to_registers(self) -> Result<Vec<EntryTuple>, MultiGridOrderError> {
-        self.0
+        self.entries
             .into_iter()
             .map(GridOrderEntry::to_register)
             .collect()
     }
 
     pub fn from_registers(registers: Vec<EntryTuple>) -> Result<Self, MultiGridOrderError> {
+        if registers.is_empty() {
+            return Err(MultiGridOrderError::NoEntries);
+        }
+
         let entries = registers
             .into_iter()
             .map(GridOrderEntry::from_register)
@@ -193,7 +279,7 @@ impl GridOrderEntries {
     }
 
     pub fn token_amount(&self) -> u64 {
-        self.0
+        self.entries
             .iter()
             .filter_map(|e| {
                 if e.state == OrderState::Sell {
@@ -205,66 +291,173 @@ impl GridOrderEntries {
             .sum()
     }
 
-    pub fn bid_entry(&self) -> Option<&GridOrderEntry> {
-        self.0
+    /// Number of orders that have to complete a full buy-sell cycle before their margins,
+    /// `ask_value - bid_value`, cover `fees`, such as the miner fees the owner pays to create
+    /// and redeem the grid. Fills cost the owner nothing, the matcher pays their miner fee
+    /// from the surplus of the fill. Orders with the smallest margins are counted first, so
+    /// the count is an upper bound. `None` if the margins of all orders together do not
+    /// cover the fees.
+    pub fn break_even_orders(&self, fees: u64) -> Option<usize> {
+        let mut margins: Vec<u64> = self
+            .entries
             .iter()
-            .filter(|e| e.state == OrderState::Buy)
-            .max_by_key(|e| e.bid())
+            .map(|e| e.ask_value.saturating_sub(e.bid_value))
+            .collect();
+        margins.sort_unstable();
+
+        let mut covered = 0;
+
+        for (count, margin) in margins.into_iter().enumerate() {
+            covered += margin;
+
+            if covered >= fees {
+                return Some(count + 1);
+            }
+        }
+
+        None
     }
 
-    pub fn bid_entry_mut(&mut self) -> Option<&mut GridOrderEntry> {
-        self.0
-            .iter_mut()
-            .filter(|e| e.state == OrderState::Buy)
-            .max_by_key(|e| e.bid())
+    pub fn bid_entry(&self) -> Option<&GridOrderEntry> {
+        self.best_bid.map(|i| &self.entries[i])
     }
 
     pub fn ask_entry(&self) -> Option<&GridOrderEntry> {
-        self.0
-            .iter()
-            .filter(|e| e.state == OrderState::Sell)
-            .min_by_key(|e| e.ask())
+        self.best_ask.map(|i| &self.entries[i])
     }
 
-    pub fn ask_entry_mut(&mut self) -> Option<&mut GridOrderEntry> {
-        self.0
-            .iter_mut()
-            .filter(|e| e.state == OrderState::Sell)
-            .min_by_key(|e| e.ask())
+    /// Highest bid price of the buy entries
+    pub fn best_bid(&self) -> Option<Fraction> {
+        self.bid_entry().map(|e| e.bid())
+    }
+
+    /// Lowest ask price of the sell entries
+    pub fn best_ask(&self) -> Option<Fraction> {
+        self.ask_entry().map(|e| e.ask())
     }
 
     pub fn iter(&self) -> impl Iterator<Item = &GridOrderEntry> {
-        self.0.iter()
+        self.entries.iter()
+    }
+
+    /// Fill the best bid, turning it into a sell order
+    pub fn fill_bid(&mut self) -> Result<(), GridOrderEntriesError> {
+        let index = self.best_bid.ok_or(GridOrderEntriesError::NoBidOrders)?;
+        self.entries[index].state = OrderState::Sell;
+        self.update_best();
+        Ok(())
+    }
+
+    /// Fill the best ask, turning it into a buy order
+    pub fn fill_ask(&mut self) -> Result<(), GridOrderEntriesError> {
+        let index = self.best_ask.ok_or(GridOrderEntriesError::NoAskOrders)?;
+        self.entries[index].state = OrderState::Buy;
+        self.update_best();
+        Ok(())
     }
 
     pub fn into_fill_ask(mut self) -> Result<Self, GridOrderEntriesError> {
-        if let Some(order) = self.ask_entry_mut() {
-            order.state = OrderState::Buy;
-            Ok(self)
-        } else {
-            Err(GridOrderEntriesError::NoAskOrders)
-        }
+        self.fill_ask()?;
+        Ok(self)
     }
 
     pub fn into_fill_bid(mut self) -> Result<Self, GridOrderEntriesError> {
-        if let Some(order) = self.bid_entry_mut() {
-            order.state = OrderState::Sell;
-            Ok(self)
-        } else {
-            Err(GridOrderEntriesError::NoBidOrders)
-        }
+        self.fill_bid()?;
+        Ok(self)
     }
 }
 
 impl FromIterator<GridOrderEntry> for GridOrderEntries {
     fn from_iter<I: IntoIterator<Item = GridOrderEntry>>(iter: I) -> Self {
-        Self(iter.into_iter().collect())
+        Self::new(iter.into_iter().collect())
     }
 }
 
 impl From<Vec<GridOrderEntry>> for GridOrderEntries {
     fn from(entries: Vec<GridOrderEntry>) -> Self {
-        Self(entries)
+        Self::new(entries)
+    }
+}
+
+/// Separates the grid identity from its strategy tag in the metadata register
+const STRATEGY_SEPARATOR: u8 = 0;
+
+/// Structured contents of the metadata register, which the contract keeps unchanged when
+/// the order is filled. Grids without a strategy tag store only their identity. A base
+/// value other than [`DEFAULT_MIN_BOX_VALUE`] follows the strategy tag, possibly empty, as
+/// decimal digits after another separator.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GridMetadata {
+    pub identity: Vec<u8>,
+    pub strategy: Option<String>,
+    /// Value held by the box on top of its buy entries when it was created, if not the
+    /// default
+    pub base_value: Option<u64>,
+}
+
+impl GridMetadata {
+    pub fn new(identity: impl Into<Vec<u8>>, strategy: Option<String>) -> Self {
+        Self {
+            identity: identity.into(),
+            strategy,
+            base_value: None,
+        }
+    }
+
+    /// Record `base_value` as the base value of the grid, unless it is the default
+    pub fn with_base_value(mut self, base_value: u64) -> Self {
+        self.base_value = (base_value != DEFAULT_MIN_BOX_VALUE).then_some(base_value);
+        self
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        let Some(separator) = bytes.iter().position(|b| *b == STRATEGY_SEPARATOR) else {
+            return Self::new(bytes, None);
+        };
+
+        let identity = bytes[..separator].to_vec();
+        let rest = &bytes[separator + 1..];
+
+        let base_value = rest
+            .iter()
+            .rposition(|b| *b == STRATEGY_SEPARATOR)
+            .and_then(|position| {
+                let value = std::str::from_utf8(&rest[position + 1..])
+                    .ok()?
+                    .parse()
+                    .ok()?;
+                Some((position, value))
+            });
+
+        match base_value {
+            Some((position, base_value)) => Self {
+                identity,
+                strategy: (position > 0)
+                    .then(|| String::from_utf8_lossy(&rest[..position]).into_owned()),
+                base_value: Some(base_value),
+            },
+            None => Self {
+                identity,
+                strategy: Some(String::from_utf8_lossy(rest).into_owned()),
+                base_value: None,
+            },
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.identity.clone();
+
+        if self.strategy.is_some() || self.base_value.is_some() {
+            bytes.push(STRATEGY_SEPARATOR);
+            bytes.extend_from_slice(self.strategy.as_deref().unwrap_or_default().as_bytes());
+        }
+
+        if let Some(base_value) = self.base_value {
+            bytes.push(STRATEGY_SEPARATOR);
+            bytes.extend_from_slice(base_value.to_string().as_bytes());
+        }
+
+        bytes
     }
 }
 
@@ -278,20 +471,40 @@ pub struct MultiGridOrder {
 }
 
 impl MultiGridOrder {
+    /// Create a new grid order. Buy entries are funded with ERG, adding their bid value to
+    /// `min_box_value`, while sell entries are funded with tokens. A grid with only buy
+    /// entries holds no tokens and a grid with only sell entries holds only the base value.
+    /// A `min_box_value` other than the default is recorded in the metadata, if there is
+    /// any, so that it is not mistaken for profit.
     pub fn new(
         owner_ec_point: EcPoint,
         token_id: TokenId,
         entries: GridOrderEntries,
         metadata: Option<Vec<u8>>,
+        min_box_value: u64,
     ) -> Result<Self, MultiGridOrderError> {
+        if entries.iter().next().is_none() {
+            return Err(MultiGridOrderError::NoEntries);
+        }
+
         let value = entries
-            .0
             .iter()
             .filter(|e| e.state == OrderState::Buy)
-            .try_fold(MIN_BOX_VALUE, |acc, e| acc.checked_add(e.bid_value))
+            .try_fold(min_box_value, |acc, e| acc.checked_add(e.bid_value))
             .ok_or(MultiGridOrderError::ValueOverflow)?
             .try_into()?;
 
+        let metadata = metadata.map(|bytes| {
+            let parsed = GridMetadata::from_bytes(&bytes);
+            let recorded = parsed.clone().with_base_value(min_box_value);
+
+            if recorded == parsed {
+                bytes
+            } else {
+                recorded.to_bytes()
+            }
+        });
+
         Ok(Self {
             owner_ec_point,
             token_id,
@@ -301,6 +514,28 @@ impl MultiGridOrder {
         })
     }
 
+    /// Grid identity, without the strategy tag
+    pub fn identity(&self) -> Option<&[u8]> {
+        self.metadata
+            .as_deref()
+            .and_then(|m| m.split(|b| *b == STRATEGY_SEPARATOR).next())
+    }
+
+    pub fn strategy(&self) -> Option<String> {
+        self.metadata
+            .as_deref()
+            .and_then(|m| GridMetadata::from_bytes(m).strategy)
+    }
+
+    /// Value the box held on top of its buy entries when it was created, as recorded in
+    /// the metadata. Grids without a recorded base value were created with the default.
+    pub fn base_value(&self) -> u64 {
+        self.metadata
+            .as_deref()
+            .and_then(|m| GridMetadata::from_bytes(m).base_value)
+            .unwrap_or(DEFAULT_MIN_BOX_VALUE)
+    }
+
     pub fn bid_entry(&self) -> Option<&GridOrderEntry> {
         self.entries.bid_entry()
     }
@@ -310,15 +545,15 @@ impl MultiGridOrder {
     }
 
     pub fn bid(&self) -> Option<Fraction> {
-        self.bid_entry().map(|e| e.bid())
+        self.entries.best_bid()
     }
 
     pub fn ask(&self) -> Option<Fraction> {
-        self.ask_entry().map(|e| e.ask())
+        self.entries.best_ask()
     }
 
     pub fn with_entries(self, entries: GridOrderEntries) -> Result<Self, MultiGridOrderError> {
-        let value = self.entries.0.iter().zip(entries.0.iter()).fold(
+        let value = self.entries.iter().zip(entries.iter()).fold(
             self.value.as_i64(),
             |value, (old, new)| match (old.state, new.state) {
                 (OrderState::Buy, OrderState::Sell) => value - old.bid_value as i64,
@@ -338,18 +573,190 @@ impl MultiGridOrder {
         Ok(new_order)
     }
 
-    /// Amount of ergs that have been collected for this order.
-    /// Assumes the box was created with either MIN_BOX_VALUE or MIN_BOX_VALUE + bid_value,
-    /// depending on the initial order state.
+    /// Remove the entries at `indices`. The bid value of removed buy entries is taken out
+    /// of the box value, tokens of removed sell entries are no longer held by the order.
+    /// Collected profit stays in the box.
+    pub fn without_entries(self, indices: &[usize]) -> Result<Self, MultiGridOrderError> {
+        let num_entries = self.entries.iter().count();
+
+        if let Some(index) = indices.iter().find(|i| **i >= num_entries) {
+            return Err(MultiGridOrderError::EntryIndex(*index, num_entries));
+        }
+
+        let (removed, kept): (Vec<_>, Vec<_>) = self
+            .entries
+            .iter()
+            .enumerate()
+            .partition(|(i, _)| indices.contains(i));
+
+        if kept.is_empty() {
+            return Err(MultiGridOrderError::NoEntries);
+        }
+
+        let removed_value = removed
+            .iter()
+            .filter(|(_, e)| e.state == OrderState::Buy)
+            .map(|(_, e)| e.bid_value)
+            .sum::<u64>();
+
+        let value = self
+            .value
+            .as_u64()
+            .checked_sub(removed_value)
+            .ok_or(MultiGridOrderError::ValueOverflow)?
+            .try_into()?;
+
+        Ok(Self {
+            owner_ec_point: self.owner_ec_point,
+            token_id: self.token_id,
+            entries: kept.into_iter().map(|(_, e)| *e).collect(),
+            value,
+            metadata: self.metadata,
+        })
+    }
+
+    /// Move the entries onto new price `levels`, each a bid and ask per token in nanoERG.
+    /// Entries are matched to levels by the rank of their bid, so the highest entry takes
+    /// the highest level. Every entry keeps its state and token amount, so the grid holds
+    /// the same tokens, and the value beyond the bids of the buy entries, such as the
+    /// collected profit, stays in the box.
+    pub fn with_prices(self, levels: &[(Fraction, Fraction)]) -> Result<Self, MultiGridOrderError> {
+        let mut entries: Vec<GridOrderEntry> = self.entries.iter().copied().collect();
+
+        if levels.len() != entries.len() {
+            return Err(MultiGridOrderError::LevelCount(levels.len(), entries.len()));
+        }
+
+        let by_bid_desc = |a: &Fraction, b: &Fraction| b.partial_cmp(a).unwrap_or(Ordering::Equal);
+
+        entries.sort_by(|a, b| by_bid_desc(&a.bid(), &b.bid()));
+
+        let mut level_order: Vec<usize> = (0..levels.len()).collect();
+        level_order.sort_by(|a, b| by_bid_desc(&levels[*a].0, &levels[*b].0));
+
+        let mut repriced = vec![None; levels.len()];
+
+        for (entry, level) in entries.iter().zip(level_order) {
+            let (bid, ask) = levels[level];
+            let amount = Fraction::from(entry.order_amount());
+
+            let to_value = |price: Fraction| {
+                (price * amount)
+                    .floor()
+                    .to_u64()
+                    .ok_or(MultiGridOrderError::ValueOverflow)
+            };
+
+            let bid_value = to_value(bid)?;
+            let ask_value = to_value(ask)?;
+
+            if bid_value == 0 || ask_value <= bid_value {
+                return Err(MultiGridOrderError::NoSpread(
+                    level + 1,
+                    entry.order_amount(),
+                ));
+            }
+
+            repriced[level] = Some(GridOrderEntry::new(
+                entry.state,
+                entry.token_amount,
+                bid_value,
+                ask_value,
+            ));
+        }
+
+        let entries: GridOrderEntries = repriced.into_iter().flatten().collect();
+
+        let buy_value = |entries: &GridOrderEntries| {
+            entries
+                .iter()
+                .filter(|e| e.state == OrderState::Buy)
+                .try_fold(0u64, |acc, e| acc.checked_add(e.bid_value))
+                .ok_or(MultiGridOrderError::ValueOverflow)
+        };
+
+        let value = self
+            .value
+            .as_u64()
+            .checked_sub(buy_value(&self.entries)?)
+            .and_then(|v| v.checked_add(buy_value(&entries).ok()?))
+            .ok_or(MultiGridOrderError::ValueOverflow)?
+            .try_into()?;
+
+        Ok(Self {
+            owner_ec_point: self.owner_ec_point,
+            token_id: self.token_id,
+            entries,
+            value,
+            metadata: self.metadata,
+        })
+    }
+
+    /// The grid order left after redeeming the entries at `indices`, rebuilt like a new
+    /// grid with `min_box_value` as its base value. Unlike [`Self::without_entries`] the
+    /// collected profit does not stay in the box but is redeemed with the entries.
+    pub fn redeem_entries(
+        self,
+        indices: &[usize],
+        min_box_value: u64,
+    ) -> Result<Self, MultiGridOrderError> {
+        let num_entries = self.entries.iter().count();
+
+        if let Some(index) = indices.iter().find(|i| **i >= num_entries) {
+            return Err(MultiGridOrderError::EntryIndex(*index, num_entries));
+        }
+
+        let kept = self
+            .entries
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| !indices.contains(i))
+            .map(|(_, e)| *e)
+            .collect();
+
+        Self::new(
+            self.owner_ec_point,
+            self.token_id,
+            kept,
+            self.metadata,
+            min_box_value,
+        )
+    }
+
+    /// Split the entries into grid orders of at most `max_entries` entries each, keeping
+    /// the order of the entries. Every order shares the owner, token and metadata of this
+    /// order and holds `min_box_value` on top of its own buy entries.
+    pub fn split(
+        self,
+        max_entries: usize,
+        min_box_value: u64,
+    ) -> Result<Vec<Self>, MultiGridOrderError> {
+        let entries: Vec<GridOrderEntry> = self.entries.iter().copied().collect();
+
+        entries
+            .chunks(max_entries.max(1))
+            .map(|chunk| {
+                Self::new(
+                    self.owner_ec_point.clone(),
+                    self.token_id,
+                    chunk.iter().copied().collect(),
+                    self.metadata.clone(),
+                    min_box_value,
+                )
+            })
+            .collect()
+    }
+
+    /// Amount of ergs that have been collected for this order: the value of the box beyond
+    /// its [base value](Self::base_value) and the bids of its buy entries.
     pub fn profit(&self) -> u64 {
         let expected_value = self
             .entries
-            .0
             .iter()
             .filter(|e| e.state == OrderState::Buy)
-            .fold(MIN_BOX_VALUE, |acc, e| acc + e.bid_value);
+            .fold(self.base_value(), |acc, e| acc.saturating_add(e.bid_value));
 
-        self.value.as_u64() - expected_value
+        self.value.as_u64().saturating_sub(expected_value)
     }
 
     pub fn into_box_candidate(
@@ -389,98 +796,148 @@ impl MultiGridOrder {
     }
 }
 
-impl TryFrom<&ErgoBox> for MultiGridOrder {
-    type Error = MultiGridOrderError;
+fn get_register_extract<T>(
+    value: &ErgoBox,
+    register: NonMandatoryRegisterId,
+) -> Result<T, MultiGridOrderError>
+where
+    T: TryExtractFrom<Literal>,
+{
+    value
+        .additional_registers
+        .get_constant(register)
+        .ok_or(MultiGridOrderError::MissingRegisterValue(register))
+        .and_then(|c| {
+            c.clone()
+                .try_extract_into::<T>()
+                .map_err(|e| MultiGridOrderError::InvalidRegisterValue(register, e.0))
+        })
+}
 
-    fn try_from(ergo_box: &ErgoBox) -> Result<Self, Self::Error> {
-        fn get_register_extract<T>(
-            value: &ErgoBox,
-            register: NonMandatoryRegisterId,
-        ) -> Result<T, MultiGridOrderError>
-        where
-            T: TryExtractFrom<Literal>,
-        {
-            value
-                .additional_registers
-                .get_constant(register)
-                .ok_or(MultiGridOrderError::MissingRegisterValue(register))
-                .and_then(|c| {
-                    c.clone()
-                        .try_extract_into::<T>()
-                        .map_err(|e| MultiGridOrderError::InvalidRegisterValue(register, e.0))
-                })
+/// Extract the entry tuples of R5. Any other shape of the register is reported with its
+/// type, rather than the extraction error of the first mismatching tuple element.
+fn get_entries_register(ergo_box: &ErgoBox) -> Result<Vec<EntryTuple>, MultiGridOrderError> {
+    let register = NonMandatoryRegisterId::R5;
+
+    let constant = ergo_box
+        .additional_registers
+        .get_constant(register)
+        .ok_or(MultiGridOrderError::MissingRegisterValue(register))?;
+
+    constant
+        .clone()
+        .try_extract_into::<Vec<EntryTuple>>()
+        .map_err(|_| MultiGridOrderError::UnexpectedEntryStructure(format!("{:?}", constant.tpe)))
+}
+
+/// Registers of a grid order box, each decoded on its own so that the readable registers
+/// of a malformed box can still be inspected
+#[derive(Debug)]
+pub struct GridRegisters {
+    pub owner_ec_point: Result<EcPoint, MultiGridOrderError>,
+    pub entries: Result<GridOrderEntries, MultiGridOrderError>,
+    pub token_id: Result<TokenId, MultiGridOrderError>,
+    pub metadata: Result<GridMetadata, MultiGridOrderError>,
+}
+
+impl GridRegisters {
+    pub fn decode(ergo_box: &ErgoBox) -> Self {
+        Self {
+            owner_ec_point: get_register_extract(ergo_box, NonMandatoryRegisterId::R4),
+            entries: get_entries_register(ergo_box).and_then(GridOrderEntries::from_registers),
+            token_id: get_register_extract(ergo_box, NonMandatoryRegisterId::R6),
+            metadata: get_register_extract::<Vec<u8>>(ergo_box, NonMandatoryRegisterId::R7)
+                .map(|bytes| GridMetadata::from_bytes(&bytes)),
         }
+    }
+}
 
+impl MultiGridOrder {
+    /// Parse the order from a box without checking that the box holds the value and
+    /// tokens required by the order entries.
+    pub fn from_box_unchecked(ergo_box: &ErgoBox) -> Result<Self, MultiGridOrderError> {
         let owner_ec_point: EcPoint = get_register_extract(ergo_box, NonMandatoryRegisterId::R4)?;
-        let orders: Vec<_> = get_register_extract(ergo_box, NonMandatoryRegisterId::R5)?;
+        let entries = GridOrderEntries::from_registers(get_entries_register(ergo_box)?)?;
 
         let token_id: TokenId = get_register_extract(ergo_box, NonMandatoryRegisterId::R6)?;
 
         let metadata: Option<Vec<u8>> =
             get_register_extract(ergo_box, NonMandatoryRegisterId::R7).ok();
 
-        let entries = orders
-            .into_iter()
-            .map(GridOrderEntry::from_register)
-            .collect::<Result<Vec<_>, _>>()?
-            .into();
-
-        let order = Self {
+        Ok(Self {
             owner_ec_point,
             token_id,
             entries,
             metadata,
             value: ergo_box.value,
-        };
+        })
+    }
 
-        let current_value = *ergo_box.value.as_u64();
-        let min_value = order.entries.0.iter().map(|e| e.bid_value).sum::<u64>() + MIN_BOX_VALUE;
+    /// Compare the value and tokens expected from the order entries with the contents of
+    /// the box, returning every discrepancy found.
+    pub fn box_discrepancies(&self, ergo_box: &ErgoBox) -> Vec<MultiGridConfigurationError> {
+        let mut discrepancies = Vec::new();
 
-        let expected_token_amount = order
+        let current_value = *ergo_box.value.as_u64();
+        let min_value = self
             .entries
-            .0
             .iter()
-            .filter_map(|e| {
-                if e.state == OrderState::Sell {
-                    Some(e.token_amount.as_u64())
-                } else {
-                    None
-                }
-            })
-            .sum::<u64>();
+            .filter(|e| e.state == OrderState::Buy)
+            .fold(self.base_value(), |acc, e| acc.saturating_add(e.bid_value));
+
+        let expected_token_amount = self.entries.token_amount();
 
-        // Validate order state
         match &ergo_box.tokens {
-            None if expected_token_amount > 0 => Err(MultiGridConfigurationError::TokenLength(0)),
-            Some(_) if expected_token_amount == 0 => {
-                Err(MultiGridConfigurationError::TokenLengthNonZero(1))
+            None if expected_token_amount > 0 => {
+                discrepancies.push(MultiGridConfigurationError::TokenLength(0))
+            }
+            None => (),
+            Some(v) if expected_token_amount == 0 => {
+                discrepancies.push(MultiGridConfigurationError::TokenLengthNonZero(v.len()))
             }
             Some(v) => {
                 if let [token] = v.as_slice() {
-                    if token.token_id != token_id {
-                        Err(MultiGridConfigurationError::TokenId(
-                            token_id,
+                    if token.token_id != self.token_id {
+                        discrepancies.push(MultiGridConfigurationError::TokenId(
+                            self.token_id,
                             token.token_id,
-                        ))
-                    } else if *token.amount.as_u64() != expected_token_amount {
-                        Err(MultiGridConfigurationError::TokenAmount(
+                        ));
+                    }
+
+                    if *token.amount.as_u64() != expected_token_amount {
+                        discrepancies.push(MultiGridConfigurationError::TokenAmount(
                             expected_token_amount,
                             *token.amount.as_u64(),
-                        ))
-                    } else {
-                        Ok(order)
+                        ));
                     }
                 } else {
-                    Err(MultiGridConfigurationError::TokenLength(v.len()))
+                    discrepancies.push(MultiGridConfigurationError::TokenLength(v.len()));
                 }
             }
-            _ if current_value < min_value => Err(MultiGridConfigurationError::BidValue(
+        }
+
+        if current_value < min_value {
+            discrepancies.push(MultiGridConfigurationError::BidValue(
                 min_value,
                 current_value,
-            )),
-            _ => Ok(order),
+            ));
+        }
+
+        discrepancies
+    }
+}
+
+impl TryFrom<&ErgoBox> for MultiGridOrder {
+    type Error = MultiGridOrderError;
+
+    fn try_from(ergo_box: &ErgoBox) -> Result<Self, Self::Error> {
+        let order = Self::from_box_unchecked(ergo_box)?;
+
+        // Validate order state
+        match order.box_discrepancies(ergo_box).into_iter().next() {
+            Some(discrepancy) => Err(discrepancy.into()),
+            None => Ok(order),
         }
-        .map_err(|e| e.into())
     }
 }
 
@@ -587,8 +1044,8 @@ pub mod arbitrary {
         fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
             const MAX_ENTRIES: u64 = 50;
 
-            const MAX_VALUE: u64 = std::i64::MAX as u64;
-            const MAX_TOKENS: u64 = std::i64::MAX as u64;
+            const MAX_VALUE: u64 = i64::MAX as u64;
+            const MAX_TOKENS: u64 = i64::MAX as u64;
 
             // The value of a multigrid order is determined by the sum of the bid values for all
             // orders in the BUY state. To prevent overflow when generating random values, we
@@ -623,7 +1080,7 @@ pub mod arbitrary {
 #[cfg(test)]
 pub mod tests {
     use ergo_lib::{
-        ergo_chain_types::Digest32,
+        chain::transaction::TxId, ergo_chain_types::Digest32,
         ergotree_interpreter::sigma_protocol::private_input::PrivateInput,
         wallet::secret_key::SecretKey,
     };
@@ -645,13 +1102,26 @@ pub mod tests {
         };
     }
 
+    /// `order` as parsed back from its box
+    fn parsed_from_box(order: MultiGridOrder) -> MultiGridOrder {
+        let candidate = order.into_box_candidate(0).unwrap();
+        let ergo_box = ErgoBox::from_box_candidate(&candidate, TxId::zero(), 0).unwrap();
+        MultiGridOrder::try_from(&ergo_box).unwrap()
+    }
+
     prop_compose! {
         fn multigrid()(entries in any::<GridOrderEntries>()) -> MultiGridOrder {
             let mut asset_y_id = [0u8; 32];
             asset_y_id[0] = 3;
 
             let token_id: TokenId = Digest32::from(asset_y_id).into();
-            MultiGridOrder::new(GROUP_ELEMENT.clone(), token_id, entries, None).unwrap()
+            MultiGridOrder::new(
+                GROUP_ELEMENT.clone(),
+                token_id,
+                entries,
+                None,
+                DEFAULT_MIN_BOX_VALUE,
+            ).unwrap()
         }
     }
 
@@ -666,13 +1136,456 @@ pub mod tests {
 
         let token_id: TokenId = Digest32::from(asset_y_id).into();
 
-        let order = MultiGridOrder::new(GROUP_ELEMENT.clone(), token_id, entries, None).unwrap();
+        let order = MultiGridOrder::new(
+            GROUP_ELEMENT.clone(),
+            token_id,
+            entries,
+            None,
+            DEFAULT_MIN_BOX_VALUE,
+        )
+        .unwrap();
 
         let refs = vec![&order];
 
         let _ = pool.fill_orders(refs).expect("Failed to fill orders");
     }
 
+    #[test]
+    fn box_discrepancies() {
+        let mut asset_y_id = [0u8; 32];
+        asset_y_id[0] = 3;
+        let token_id: TokenId = Digest32::from(asset_y_id).into();
+
+        let entries = test_entries(100, 200, 4, 2, vec![10, 20, 30, 40]);
+        let order = MultiGridOrder::new(
+            GROUP_ELEMENT.clone(),
+            token_id,
+            entries,
+            None,
+            DEFAULT_MIN_BOX_VALUE,
+        )
+        .unwrap();
+
+        let candidate = order.into_box_candidate(0).unwrap();
+        let ergo_box = ErgoBox::from_box_candidate(&candidate, TxId::zero(), 0).unwrap();
+
+        let parsed = MultiGridOrder::from_box_unchecked(&ergo_box).unwrap();
+        assert!(parsed.box_discrepancies(&ergo_box).is_empty());
+        MultiGridOrder::try_from(&ergo_box).expect("Well-formed box");
+
+        let mut tampered_candidate = candidate.clone();
+        tampered_candidate.value = DEFAULT_MIN_BOX_VALUE.try_into().unwrap();
+        let tampered_box =
+            ErgoBox::from_box_candidate(&tampered_candidate, TxId::zero(), 0).unwrap();
+
+        let parsed = MultiGridOrder::from_box_unchecked(&tampered_box).unwrap();
+        let discrepancies = parsed.box_discrepancies(&tampered_box);
+
+        assert_eq!(discrepancies.len(), 1);
+        assert!(matches!(
+            discrepancies[0],
+            MultiGridConfigurationError::BidValue(_, value) if value == DEFAULT_MIN_BOX_VALUE
+        ));
+        MultiGridOrder::try_from(&tampered_box).expect_err("Value mismatch");
+    }
+
+    #[test]
+    fn negative_register_value() {
+        let token_id: TokenId = Digest32::from([3u8; 32]).into();
+
+        let entries = test_entries(100, 200, 2, 1, vec![10, 20]);
+        let order = MultiGridOrder::new(
+            GROUP_ELEMENT.clone(),
+            token_id,
+            entries,
+            None,
+            DEFAULT_MIN_BOX_VALUE,
+        )
+        .unwrap();
+
+        let mut candidate = order.into_box_candidate(0).unwrap();
+
+        // An entry written by another tool with the high bit of the bid value set
+        let registers: Vec<EntryTuple> = vec![((10, true), (-5, 200)), ((20, false), (100, 200))];
+        candidate.additional_registers = NonMandatoryRegisters::new(HashMap::from([
+            (NonMandatoryRegisterId::R4, GROUP_ELEMENT.clone().into()),
+            (NonMandatoryRegisterId::R5, registers.into()),
+            (NonMandatoryRegisterId::R6, token_id.into()),
+        ]))
+        .unwrap();
+        let ergo_box = ErgoBox::from_box_candidate(&candidate, TxId::zero(), 0).unwrap();
+
+        let error = MultiGridOrder::from_box_unchecked(&ergo_box).unwrap_err();
+        assert!(matches!(
+            error,
+            MultiGridOrderError::NegativeRegisterValue("bid value", -5)
+        ));
+        assert_eq!(
+            error.to_string(),
+            "Negative bid value -5 in grid entry register"
+        );
+
+        let too_large = GridOrderEntry::new(OrderState::Buy, 1.try_into().unwrap(), u64::MAX, 1);
+        assert!(matches!(
+            too_large.to_register(),
+            Err(MultiGridOrderError::RegisterValueOverflow(
+                "bid value",
+                u64::MAX
+            ))
+        ));
+    }
+
+    #[test]
+    fn break_even_orders() {
+        let entry = |bid_value, ask_value| {
+            GridOrderEntry::new(
+                OrderState::Buy,
+                10.try_into().unwrap(),
+                bid_value,
+                ask_value,
+            )
+        };
+
+        // Margins of 1, 1.5, 2 and 3 mERG against 4 mERG of fees to create and redeem the grid
+        let fees = 4_000_000;
+        let entries = GridOrderEntries::new(vec![
+            entry(100_000_000, 103_000_000),
+            entry(90_000_000, 91_000_000),
+            entry(80_000_000, 82_000_000),
+            entry(70_000_000, 71_500_000),
+        ]);
+
+        assert_eq!(entries.break_even_orders(fees), Some(3));
+
+        let wide = GridOrderEntries::new(vec![entry(100_000_000, 105_000_000)]);
+        assert_eq!(wide.break_even_orders(fees), Some(1));
+
+        let narrow = GridOrderEntries::new(vec![
+            entry(100_000_000, 101_000_000),
+            entry(90_000_000, 91_000_000),
+        ]);
+        assert_eq!(narrow.break_even_orders(fees), None);
+    }
+
+    #[test]
+    fn malformed_entries_register() {
+        let token_id: TokenId = Digest32::from([3u8; 32]).into();
+
+        let entries = test_entries(100, 200, 2, 1, vec![10, 20]);
+        let order = MultiGridOrder::new(
+            GROUP_ELEMENT.clone(),
+            token_id,
+            entries,
+            None,
+            DEFAULT_MIN_BOX_VALUE,
+        )
+        .unwrap();
+
+        let candidate = order.into_box_candidate(0).unwrap();
+
+        let with_r5 = |r5: Constant| {
+            let mut candidate = candidate.clone();
+            candidate.additional_registers = NonMandatoryRegisters::new(HashMap::from([
+                (NonMandatoryRegisterId::R4, GROUP_ELEMENT.clone().into()),
+                (NonMandatoryRegisterId::R5, r5),
+                (NonMandatoryRegisterId::R6, token_id.into()),
+            ]))
+            .unwrap();
+            ErgoBox::from_box_candidate(&candidate, TxId::zero(), 0).unwrap()
+        };
+
+        let flat_r5 = with_r5(vec![10i64, 100, 200].into());
+        let error = MultiGridOrder::from_box_unchecked(&flat_r5).unwrap_err();
+        assert!(matches!(
+            error,
+            MultiGridOrderError::UnexpectedEntryStructure(_)
+        ));
+        assert!(error.to_string().starts_with("Unexpected R5 structure"));
+
+        let empty_r5 = with_r5(Vec::<EntryTuple>::new().into());
+        assert!(matches!(
+            MultiGridOrder::from_box_unchecked(&empty_r5),
+            Err(MultiGridOrderError::NoEntries)
+        ));
+    }
+
+    #[test]
+    fn best_entries_after_fills() {
+        let num_entries = 1000;
+        let mut entries = test_entries(
+            1000,
+            1000 + num_entries as u64,
+            num_entries,
+            500,
+            vec![7; num_entries],
+        );
+
+        let scan_best_bid = |entries: &GridOrderEntries| {
+            entries
+                .iter()
+                .filter(|e| e.state == OrderState::Buy)
+                .map(|e| e.bid())
+                .max()
+        };
+        let scan_best_ask = |entries: &GridOrderEntries| {
+            entries
+                .iter()
+                .filter(|e| e.state == OrderState::Sell)
+                .map(|e| e.ask())
+                .min()
+        };
+
+        for i in 0..num_entries {
+            let filled = if i % 3 == 0 {
+                entries.fill_ask()
+            } else {
+                entries.fill_bid()
+            };
+
+            if filled.is_err() {
+                continue;
+            }
+
+            let best_bid = entries.best_bid();
+            let best_ask = entries.best_ask();
+
+            assert_eq!(best_bid, scan_best_bid(&entries));
+            assert_eq!(best_ask, scan_best_ask(&entries));
+
+            // Repeated lookups return the same entries
+            assert_eq!(entries.best_bid(), best_bid);
+            assert_eq!(entries.best_ask(), best_ask);
+        }
+    }
+
+    #[test]
+    fn min_box_value_raises_base_value() {
+        let token_id: TokenId = Digest32::zero().into();
+        let entries = test_entries(100, 200, 4, 2, vec![10, 20, 30, 40]);
+
+        let default_order = MultiGridOrder::new(
+            GROUP_ELEMENT.clone(),
+            token_id,
+            entries.clone(),
+            None,
+            DEFAULT_MIN_BOX_VALUE,
+        )
+        .unwrap();
+
+        let min_box_value =
+            min_box_value_for_parameters(BoxValue::MIN_VALUE_PER_BOX_BYTE as u64 * 2);
+        assert_eq!(min_box_value, DEFAULT_MIN_BOX_VALUE * 2);
+
+        let order = MultiGridOrder::new(
+            GROUP_ELEMENT.clone(),
+            token_id,
+            entries,
+            None,
+            min_box_value,
+        )
+        .unwrap();
+
+        assert_eq!(
+            order.value.as_u64() - default_order.value.as_u64(),
+            DEFAULT_MIN_BOX_VALUE
+        );
+
+        // A lower minimum never reduces the base value
+        assert_eq!(min_box_value_for_parameters(1), DEFAULT_MIN_BOX_VALUE);
+    }
+
+    #[test]
+    fn single_sided_grids() {
+        let token_id: TokenId = Digest32::zero().into();
+
+        let buy_only = MultiGridOrder::new(
+            GROUP_ELEMENT.clone(),
+            token_id,
+            test_entries(100, 200, 4, 0, vec![10, 20, 30, 40]),
+            None,
+            DEFAULT_MIN_BOX_VALUE,
+        )
+        .unwrap();
+
+        let bid_values: u64 = buy_only.entries.iter().map(|e| e.bid_value).sum();
+        let candidate = buy_only.into_box_candidate(0).unwrap();
+
+        assert_eq!(
+            *candidate.value.as_u64(),
+            DEFAULT_MIN_BOX_VALUE + bid_values
+        );
+        assert!(candidate.tokens.is_none());
+
+        let sell_only = MultiGridOrder::new(
+            GROUP_ELEMENT.clone(),
+            token_id,
+            test_entries(100, 200, 4, 4, vec![10, 20, 30, 40]),
+            None,
+            DEFAULT_MIN_BOX_VALUE,
+        )
+        .unwrap();
+
+        let candidate = sell_only.into_box_candidate(0).unwrap();
+        let tokens = candidate.tokens.expect("Sell orders hold tokens");
+
+        assert_eq!(*candidate.value.as_u64(), DEFAULT_MIN_BOX_VALUE);
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(tokens.first().token_id, token_id);
+        assert_eq!(*tokens.first().amount.as_u64(), 100);
+
+        assert!(matches!(
+            MultiGridOrder::new(
+                GROUP_ELEMENT.clone(),
+                token_id,
+                GridOrderEntries::new(vec![]),
+                None,
+                DEFAULT_MIN_BOX_VALUE,
+            ),
+            Err(MultiGridOrderError::NoEntries)
+        ));
+    }
+
+    #[test]
+    fn repriced_entries_keep_state_and_profit() {
+        let token_id: TokenId = Digest32::zero().into();
+        let profit = 500;
+
+        // Entries at 100-125, 125-150, 150-175 and 175-200 with the top one sold
+        let order = MultiGridOrder::new(
+            GROUP_ELEMENT.clone(),
+            token_id,
+            test_entries(100, 200, 4, 0, vec![10, 10, 10, 10]),
+            None,
+            DEFAULT_MIN_BOX_VALUE,
+        )
+        .unwrap();
+        let mut entries: Vec<GridOrderEntry> = order.entries.iter().copied().collect();
+        entries[3].state = OrderState::Sell;
+        let order = MultiGridOrder {
+            value: (DEFAULT_MIN_BOX_VALUE + 100 + 125 + 150 + profit)
+                .try_into()
+                .unwrap(),
+            entries: entries.into_iter().collect(),
+            ..order
+        };
+
+        let levels: Vec<(Fraction, Fraction)> = [(20u64, 22u64), (22, 24), (24, 26), (26, 28)]
+            .iter()
+            .rev()
+            .map(|(bid, ask)| (Fraction::from(*bid), Fraction::from(*ask)))
+            .collect();
+
+        let repriced = order.clone().with_prices(&levels).unwrap();
+
+        let states: Vec<_> = repriced
+            .entries
+            .iter()
+            .map(|e| (e.state, e.bid_value))
+            .collect();
+        assert_eq!(
+            states,
+            vec![
+                (OrderState::Sell, 260),
+                (OrderState::Buy, 240),
+                (OrderState::Buy, 220),
+                (OrderState::Buy, 200)
+            ]
+        );
+        assert_eq!(
+            repriced.entries.token_amount(),
+            order.entries.token_amount()
+        );
+        assert_eq!(
+            *repriced.value.as_u64(),
+            DEFAULT_MIN_BOX_VALUE + 200 + 220 + 240 + profit
+        );
+
+        assert!(matches!(
+            order.clone().with_prices(&levels[..3]),
+            Err(MultiGridOrderError::LevelCount(3, 4))
+        ));
+
+        let flat = vec![(Fraction::new(1u64, 20u64), Fraction::new(1u64, 20u64)); 4];
+        assert!(matches!(
+            order.with_prices(&flat),
+            Err(MultiGridOrderError::NoSpread(..))
+        ));
+    }
+
+    #[test]
+    fn strategy_metadata() {
+        let token_id: TokenId = Digest32::zero().into();
+
+        let order = |metadata: Option<Vec<u8>>| {
+            let order = MultiGridOrder::new(
+                GROUP_ELEMENT.clone(),
+                token_id,
+                test_entries(100, 200, 2, 0, vec![10, 20]),
+                metadata,
+                DEFAULT_MIN_BOX_VALUE,
+            )
+            .unwrap();
+
+            parsed_from_box(order)
+        };
+
+        let tagged = order(Some(
+            GridMetadata::new("comet", Some("dca".to_string())).to_bytes(),
+        ));
+        assert_eq!(tagged.identity(), Some(&b"comet"[..]));
+        assert_eq!(tagged.strategy(), Some("dca".to_string()));
+
+        let untagged = order(Some(GridMetadata::new("comet", None).to_bytes()));
+        assert_eq!(untagged.metadata, Some(b"comet".to_vec()));
+        assert_eq!(untagged.identity(), Some(&b"comet"[..]));
+        assert_eq!(untagged.strategy(), None);
+
+        let anonymous = order(None);
+        assert_eq!(anonymous.identity(), None);
+        assert_eq!(anonymous.strategy(), None);
+    }
+
+    #[test]
+    fn raised_base_value_not_profit() {
+        let token_id: TokenId = Digest32::zero().into();
+        let base_value = DEFAULT_MIN_BOX_VALUE * 2;
+
+        let order = |metadata: GridMetadata| {
+            let order = MultiGridOrder::new(
+                GROUP_ELEMENT.clone(),
+                token_id,
+                test_entries(100, 200, 2, 0, vec![10, 20]),
+                Some(metadata.to_bytes()),
+                base_value,
+            )
+            .unwrap();
+
+            parsed_from_box(order)
+        };
+
+        let tagged = order(GridMetadata::new("comet", Some("dca".to_string())));
+        assert_eq!(tagged.identity(), Some(&b"comet"[..]));
+        assert_eq!(tagged.strategy(), Some("dca".to_string()));
+        assert_eq!(tagged.base_value(), base_value);
+        assert_eq!(tagged.profit(), 0);
+
+        let untagged = order(GridMetadata::new("comet", None));
+        assert_eq!(untagged.identity(), Some(&b"comet"[..]));
+        assert_eq!(untagged.strategy(), None);
+        assert_eq!(untagged.profit(), 0);
+
+        // The box holding less than the recorded base value is a discrepancy
+        let mut candidate = untagged.clone().into_box_candidate(0).unwrap();
+        candidate.value = (candidate.value.as_u64() - 1).try_into().unwrap();
+        let ergo_box = ErgoBox::from_box_candidate(&candidate, TxId::zero(), 0).unwrap();
+        assert!(!untagged.box_discrepancies(&ergo_box).is_empty());
+
+        // Grids rebuilt with the default base value drop the recorded one
+        let rebuilt = untagged.split(10, DEFAULT_MIN_BOX_VALUE).unwrap().remove(0);
+        assert_eq!(rebuilt.metadata, Some(b"comet".to_vec()));
+        assert_eq!(rebuilt.base_value(), DEFAULT_MIN_BOX_VALUE);
+    }
+
     proptest!(
         #[test]
         fn fill_orders(pool in any::<SpectrumPool>(), orders in proptest::collection::vec(multigrid(), 1..=5)) {
@@ -0,0 +1,186 @@
+use std::{
+    fs::OpenOptions,
+    io::Write,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use clap::ArgMatches;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::commands::{error::CommandResult, grid::TransactionOutcome};
+
+/// Arguments whose values are replaced before being written to the journal
+const REDACTED_ARGS: &[&str] = &["--api-key", "--api_key"];
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum JournalResult {
+    Ok,
+    Error,
+    /// The transaction was declined at the prompt
+    Cancelled,
+    /// The transaction was only shown because of `--dry-run`
+    DryRun,
+}
+
+/// A single command invocation, written as one JSON line to the journal file
+#[derive(Debug, Serialize, Deserialize)]
+pub struct JournalRecord {
+    pub run_id: String,
+    pub timestamp: u64,
+    /// Subcommands that were run, e.g. `grid create`
+    pub command: String,
+    pub args: Vec<String>,
+    pub result: JournalResult,
+    pub error: Option<String>,
+    pub tx_id: Option<String>,
+}
+
+impl JournalRecord {
+    pub fn new(
+        run_id: Uuid,
+        command: &str,
+        args: Vec<String>,
+        command_result: &CommandResult<Option<TransactionOutcome>>,
+    ) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        let (result, error, tx_id) = match command_result {
+            Ok(Some(TransactionOutcome::Cancelled)) => (JournalResult::Cancelled, None, None),
+            Ok(Some(TransactionOutcome::DryRun)) => (JournalResult::DryRun, None, None),
+            Ok(outcome) => (
+                JournalResult::Ok,
+                None,
+                outcome.and_then(|o| o.tx_id()).map(String::from),
+            ),
+            Err(e) => (JournalResult::Error, Some(e.error.to_string()), None),
+        };
+
+        Self {
+            run_id: run_id.to_string(),
+            timestamp,
+            command: command.to_string(),
+            args: redact_args(args),
+            result,
+            error,
+            tx_id,
+        }
+    }
+
+    /// Append the record to the journal file, creating it if it does not exist
+    pub fn append(&self, path: &str) -> anyhow::Result<()> {
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        let line = serde_json::to_string(self)?;
+        writeln!(file, "{}", line)?;
+        Ok(())
+    }
+}
+
+/// Names of the subcommands in `matches`, separated by spaces
+pub fn command_path(matches: &ArgMatches) -> String {
+    let mut names = vec![];
+    let mut current = matches.subcommand();
+
+    while let Some((name, sub_matches)) = current {
+        names.push(name);
+        current = sub_matches.subcommand();
+    }
+
+    names.join(" ")
+}
+
+fn redact_args(args: Vec<String>) -> Vec<String> {
+    let mut redact_next = false;
+
+    args.into_iter()
+        .map(|arg| {
+            if redact_next {
+                redact_next = false;
+                return "<redacted>".to_string();
+            }
+
+            match arg.split_once('=') {
+                Some((name, _)) if REDACTED_ARGS.contains(&name) => format!("{}=<redacted>", name),
+                _ => {
+                    redact_next = REDACTED_ARGS.contains(&arg.as_str());
+                    arg
+                }
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::CommandFactory;
+    use uuid::Uuid;
+
+    use crate::{commands::grid::TransactionOutcome, GridArgs};
+
+    use super::{command_path, JournalRecord, JournalResult};
+
+    #[test]
+    fn append_create_record() {
+        let path = std::env::temp_dir().join(format!("journal-{}.jsonl", Uuid::new_v4()));
+        let path = path.to_str().unwrap();
+
+        let run_id = Uuid::new_v4();
+        let args: Vec<String> = [
+            "--api-key",
+            "secret",
+            "grid",
+            "--dry-run",
+            "create",
+            "-t",
+            "SigUSD",
+            "-i",
+            "test",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+
+        // Recorded like `main` does for a dry run of `grid create`
+        let matches = GridArgs::command()
+            .try_get_matches_from(std::iter::once("off-the-grid".to_string()).chain(args.clone()))
+            .unwrap();
+        let command = command_path(&matches);
+        assert_eq!(command, "grid create");
+
+        let dry_run = Ok(Some(TransactionOutcome::DryRun));
+        JournalRecord::new(run_id, &command, args.clone(), &dry_run)
+            .append(path)
+            .unwrap();
+        JournalRecord::new(Uuid::new_v4(), &command, args, &Ok(None))
+            .append(path)
+            .unwrap();
+
+        let contents = std::fs::read_to_string(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        let records: Vec<JournalRecord> = contents
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+
+        assert_eq!(records.len(), 2);
+
+        let record = &records[0];
+        assert_eq!(record.run_id, run_id.to_string());
+        assert_eq!(record.command, "grid create");
+        assert_eq!(
+            record.args[0..5],
+            ["--api-key", "<redacted>", "grid", "--dry-run", "create"]
+        );
+        assert_eq!(record.result, JournalResult::DryRun);
+        assert!(record.error.is_none());
+        assert!(record.tx_id.is_none());
+        assert!(record.timestamp > 0);
+
+        assert_eq!(records[1].result, JournalResult::Ok);
+    }
+}
@@ -1,3 +1,4 @@
+pub mod arbitrage;
 pub mod boxes;
 pub mod grid;
 pub mod node;
@@ -1,22 +1,36 @@
 mod commands;
+mod journal;
 mod matcher_config;
 mod node_config;
+mod outbox;
+mod profile;
 mod scan_config;
 
-use node_config::NodeConfig;
+use journal::{command_path, JournalRecord};
+use node_config::{Network, NodeConfig};
 use off_the_grid::node::client::NodeClient;
+use profile::Profile;
+
+use std::process::ExitCode;
 
 use anyhow::Context;
-use clap::{arg, command, ArgAction, Parser, Subcommand};
+use clap::{arg, ArgAction, CommandFactory, FromArgMatches, Parser, Subcommand};
 use commands::{
+    arb::{handle_arb_command, ArbCommand},
+    convert::{handle_convert_command, ConvertCommand},
     error::CommandError,
     grid::{handle_grid_command, GridCommand},
     matcher::{handle_matcher_command, MatcherCommand},
+    outbox::{handle_outbox_command, OutboxCommand},
+    pool::{handle_pool_command, PoolCommand},
     scans::{handle_scan_command, ScansCommand},
+    script::{handle_script_command, ScriptCommand},
+    status::{handle_status_command, StatusCommand},
     tokens::{handle_tokens_command, TokensCommand},
 };
 
 #[derive(Subcommand)]
+#[allow(clippy::large_enum_variant)]
 pub enum Commands {
     #[command(author, version, about, long_about = None)]
     Scans(ScansCommand),
@@ -26,6 +40,18 @@ pub enum Commands {
     Matcher(MatcherCommand),
     #[command(author, version, about, long_about = None)]
     Tokens(TokensCommand),
+    #[command(author, version, about, long_about = None)]
+    Arb(ArbCommand),
+    /// Convert an amount between units using pool prices
+    Convert(ConvertCommand),
+    /// Query liquidity pools
+    Pool(PoolCommand),
+    /// Summarize the wallet balance and all grids
+    Status(StatusCommand),
+    /// Submit transactions queued with `grid --outbox`
+    Outbox(OutboxCommand),
+    /// Inspect the scripts compiled into this binary
+    Script(ScriptCommand),
 }
 
 #[derive(Parser)]
@@ -48,12 +74,34 @@ struct GridArgs {
     #[arg(long, help = "Ergo node API key", global(true))]
     api_key: Option<String>,
 
+    #[arg(
+        long,
+        value_enum,
+        help = "Network the node runs on, used to encode and parse addresses [default: mainnet]",
+        global(true)
+    )]
+    network: Option<Network>,
+
+    #[arg(
+        long,
+        help = "Name of the profile in the profiles directory to read configs and tokens from",
+        global(true)
+    )]
+    profile: Option<String>,
+
+    #[arg(
+        long,
+        help = "Append a record of the command run to this journal file",
+        global(true)
+    )]
+    journal: Option<String>,
+
     #[command(subcommand)]
     command: Commands,
 }
 
 #[tokio::main]
-async fn main() -> anyhow::Result<()> {
+async fn main() -> anyhow::Result<ExitCode> {
     let config_matches = clap::Command::new("Config")
         .arg(
             arg!(--node_config <VALUE>)
@@ -64,30 +112,84 @@ async fn main() -> anyhow::Result<()> {
         .try_get_matches()
         .ok();
 
-    let args = GridArgs::parse();
+    let matches = GridArgs::command().get_matches();
+    let args = GridArgs::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+
+    // The run ID is only needed to find the run in the journal
+    let run_id = uuid::Uuid::new_v4();
+    if args.journal.is_some() {
+        eprintln!("Run ID: {}", run_id);
+    }
 
     let node_config_path: Option<String> = config_matches
         .as_ref()
         .and_then(|matches| matches.get_one("node_config").cloned());
 
-    let node_config = NodeConfig::try_create(node_config_path, args.api_url, args.api_key)
-        .context("Failed to parse node configuration")?;
+    let profile = Profile::resolve(args.profile.as_deref(), &std::env::current_dir()?)?;
+
+    let node_config = NodeConfig::try_create(
+        &profile,
+        node_config_path,
+        args.api_url,
+        args.api_key,
+        args.network,
+    )
+    .context("Failed to parse node configuration")?;
 
     let node = NodeClient::new(
         node_config.api_url.as_str().try_into()?,
         node_config.api_key.as_bytes(),
-    )?;
+    )?
+    .with_network_prefix(node_config.network.prefix())
+    .with_retry_policy(
+        node_config
+            .retry_policy()
+            .context("Invalid retry_delay in node configuration")?,
+    );
 
     let result = match args.command {
-        Commands::Scans(scan_command) => handle_scan_command(node, scan_command)
+        Commands::Scans(scan_command) => handle_scan_command(node, &profile, scan_command)
+            .await
+            .map(|_| None)
+            .map_err(CommandError::from),
+        Commands::Grid(grid_command) => {
+            handle_grid_command(node, &profile, &node_config.default_fee, grid_command).await
+        }
+        Commands::Matcher(executor_command) => {
+            handle_matcher_command(node, &profile, executor_command)
+                .await
+                .map(|_| None)
+                .map_err(CommandError::from)
+        }
+        Commands::Tokens(units_command) => handle_tokens_command(node, &profile, units_command)
+            .await
+            .map(|_| None)
+            .map_err(CommandError::from),
+        Commands::Arb(arb_command) => handle_arb_command(node, &profile, arb_command)
             .await
+            .map(|_| None)
             .map_err(CommandError::from),
-        Commands::Grid(grid_command) => handle_grid_command(node, grid_command).await,
-        Commands::Matcher(executor_command) => handle_matcher_command(node, executor_command)
+        Commands::Convert(convert_command) => {
+            handle_convert_command(node, &profile, convert_command)
+                .await
+                .map(|_| None)
+                .map_err(CommandError::from)
+        }
+        Commands::Pool(pool_command) => handle_pool_command(node, &profile, pool_command)
             .await
+            .map(|_| None)
             .map_err(CommandError::from),
-        Commands::Tokens(units_command) => handle_tokens_command(node, units_command)
+        Commands::Status(status_command) => handle_status_command(node, &profile, status_command)
             .await
+            .map(|_| None)
+            .map_err(CommandError::from),
+        Commands::Outbox(outbox_command) => handle_outbox_command(node, &profile, outbox_command)
+            .await
+            .map(|_| None)
+            .map_err(CommandError::from),
+        Commands::Script(script_command) => handle_script_command(&profile, script_command)
+            .await
+            .map(|_| None)
             .map_err(CommandError::from),
     };
 
@@ -95,5 +197,20 @@ async fn main() -> anyhow::Result<()> {
         println!("{command_error}");
     }
 
-    result.map_err(|e| e.error)
+    if let Some(journal_path) = args.journal {
+        let record = JournalRecord::new(
+            run_id,
+            &command_path(&matches),
+            std::env::args().skip(1).collect(),
+            &result,
+        );
+
+        if let Err(e) = record.append(&journal_path) {
+            eprintln!("Failed to write journal record to {}: {}", journal_path, e);
+        }
+    }
+
+    result
+        .map(|outcome| outcome.map_or(ExitCode::SUCCESS, |o| o.exit_code()))
+        .map_err(|e| e.error)
 }
@@ -1,21 +1,47 @@
+use std::collections::HashMap;
+
 use config::Config;
 use serde::{Deserialize, Serialize};
 
+use crate::profile::Profile;
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct MatcherConfig {
     pub reward_address: Option<String>,
     pub interval: Option<f64>,
+    /// Pool NFT IDs keyed by the TokenID of the token traded in the pool. Orders for these
+    /// tokens are only matched against the pinned pool.
+    pub pinned_pools: Option<HashMap<String, String>>,
+    /// Seconds between printing the latencies of node requests. Not printed if unset.
+    pub metrics_interval: Option<f64>,
+    /// Save the box ids seen by the matcher, so after a restart it only reacts to boxes
+    /// that changed while it was stopped.
+    pub persist_gate: Option<bool>,
+    /// Use the height of the chain tip, fetched once per matching round, as the creation
+    /// height of the outputs instead of the highest creation height of the inputs.
+    pub use_chain_height: Option<bool>,
+    /// File written after every completed matching round, so a supervisor can detect a
+    /// stalled matcher by the age of its modification time.
+    pub liveness_file: Option<String>,
+    /// File written once the matcher is set up and starts matching.
+    pub readiness_file: Option<String>,
+    /// Least reward in nanoERG, on top of the miner fee, for which orders are filled. Fills
+    /// earning less are skipped.
+    pub min_profit: Option<u64>,
 }
 
 impl MatcherConfig {
-    pub fn try_create(config_path: Option<String>) -> Result<Self, config::ConfigError> {
+    pub fn try_create(
+        profile: &Profile,
+        config_path: Option<String>,
+    ) -> Result<Self, config::ConfigError> {
         let config_required = config_path.is_some();
 
         let scan_config_reader = Config::builder()
             .add_source(config::Environment::with_prefix("MATCHER"))
             .add_source(
                 config::File::with_name(
-                    &config_path.unwrap_or_else(|| "matcher_config".to_string()),
+                    &config_path.unwrap_or_else(|| profile.path("matcher_config")),
                 )
                 .required(config_required),
             )
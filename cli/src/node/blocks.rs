@@ -0,0 +1,14 @@
+use ergo_lib::ergo_chain_types::Header;
+
+use crate::node::client::NodeClient;
+
+use super::client::ErgoNodeError;
+
+impl NodeClient {
+    /// Headers of the last `count` blocks, oldest first
+    pub async fn last_headers(&self, count: u32) -> Result<Vec<Header>, ErgoNodeError> {
+        let path = format!("blocks/lastHeaders/{count}");
+        let result = self.request_get(&path).await?;
+        Ok(result)
+    }
+}
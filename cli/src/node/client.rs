@@ -1,11 +1,17 @@
+use ergo_lib::ergotree_ir::chain::address::NetworkPrefix;
 use reqwest::{
     header::{HeaderMap, HeaderValue, InvalidHeaderValue},
     Client, ClientBuilder, RequestBuilder, Url,
 };
 use serde::{Deserialize, Serialize};
-use std::fmt::{Debug, Display};
+use std::{
+    fmt::{Debug, Display},
+    time::{Duration, Instant},
+};
 use thiserror::Error;
 
+use super::metrics::{endpoint_label, RequestMetrics};
+
 #[derive(Serialize, Deserialize, Debug, Error)]
 pub struct ApiError {
     error: i32,
@@ -47,15 +53,96 @@ pub enum ErgoNodeError {
     },
 }
 
+impl ErgoNodeError {
+    /// The node responded that the requested resource does not exist
+    pub fn is_not_found(&self) -> bool {
+        matches!(self, ErgoNodeError::ApiError { api_error, .. } if api_error.error == 404)
+    }
+
+    /// The request may succeed if sent again: the node could not be reached, timed out or
+    /// responded with a server error. Client errors such as 404 are never retried.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            ErgoNodeError::ReqwestError(reqwest_error)
+            | ErgoNodeError::ReqwestErrorPath { reqwest_error, .. } => {
+                reqwest_error.is_connect()
+                    || reqwest_error.is_timeout()
+                    || reqwest_error.is_request()
+                    || reqwest_error
+                        .status()
+                        .is_some_and(|status| status.is_server_error())
+            }
+            ErgoNodeError::ApiError { api_error, .. } => api_error.error >= 500,
+            ErgoNodeError::InvalidHeaderValue(_) => false,
+        }
+    }
+
+    /// The connection to the node could not be established, so the request never reached
+    /// it. Only such errors are retried for requests that are not idempotent.
+    pub fn is_connect_error(&self) -> bool {
+        match self {
+            ErgoNodeError::ReqwestError(reqwest_error)
+            | ErgoNodeError::ReqwestErrorPath { reqwest_error, .. } => reqwest_error.is_connect(),
+            _ => false,
+        }
+    }
+}
+
+/// How often a request failing with a retryable error is sent before the error is returned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Attempts per request including the first one. A request is always sent at least once.
+    pub max_attempts: u32,
+    /// Delay before the first retry, doubled for every retry after it
+    pub base_delay: Duration,
+}
+
+impl RetryPolicy {
+    /// Send every request once
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            base_delay: Duration::ZERO,
+        }
+    }
+
+    /// Delay before sending a request again after `attempt` failed attempts
+    fn delay(&self, attempt: u32) -> Duration {
+        self.base_delay
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(500),
+        }
+    }
+}
+
 pub struct NodeClient {
     client: Client,
     base_url: Url,
+    metrics: RequestMetrics,
+    network_prefix: NetworkPrefix,
+    retry_policy: RetryPolicy,
 }
 
-async fn send_request<T>(request: RequestBuilder, request_url: String) -> Result<T, ErgoNodeError>
+/// Send `request` and parse its response. The time until the response body is parsed is
+/// recorded in `metrics` under `endpoint`, unless the request could not be sent.
+async fn send_request<T>(
+    request: RequestBuilder,
+    request_url: String,
+    metrics: &RequestMetrics,
+    endpoint: String,
+) -> Result<T, ErgoNodeError>
 where
     for<'a> T: Deserialize<'a> + Debug,
 {
+    let start = Instant::now();
+
     let response_result = request.send().await;
 
     let response = match response_result {
@@ -68,13 +155,19 @@ where
         }
     };
 
+    // Reported instead of the parse error if the body is not an API error, e.g. the 502 of a
+    // proxy in front of the node, so that it can be retried
+    let status_error = response.error_for_status_ref().err();
+
     let parsed_result = response.json::<ApiResponse<T>>().await;
 
+    metrics.record(endpoint, start.elapsed());
+
     let parsed = match parsed_result {
         Ok(x) => x,
         Err(error) => {
             return Err(ErgoNodeError::ReqwestErrorPath {
-                reqwest_error: error,
+                reqwest_error: status_error.unwrap_or(error),
                 request_url,
             })
         }
@@ -95,7 +188,35 @@ impl NodeClient {
         headers.insert("api_key", HeaderValue::from_bytes(api_key)?);
         let client = ClientBuilder::new().default_headers(headers).build()?;
 
-        Ok(Self { client, base_url })
+        Ok(Self {
+            client,
+            base_url,
+            metrics: RequestMetrics::default(),
+            network_prefix: NetworkPrefix::Mainnet,
+            retry_policy: RetryPolicy::default(),
+        })
+    }
+
+    /// Retry failed requests according to `retry_policy` instead of the default policy
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Encode and parse addresses for the network the node runs on instead of mainnet
+    pub fn with_network_prefix(mut self, network_prefix: NetworkPrefix) -> Self {
+        self.network_prefix = network_prefix;
+        self
+    }
+
+    /// Network prefix used for addresses read from and shown for this node
+    pub fn network_prefix(&self) -> NetworkPrefix {
+        self.network_prefix
+    }
+
+    /// Latencies of the requests sent by this client
+    pub fn request_metrics(&self) -> &RequestMetrics {
+        &self.metrics
     }
 
     pub(super) async fn request_get<T>(&self, path: &str) -> Result<T, ErgoNodeError>
@@ -104,7 +225,13 @@ impl NodeClient {
     {
         let request_url = format!("{}{}", self.base_url, path);
 
-        send_request(self.client.get(&request_url), request_url).await
+        self.send_with_retries(
+            || self.client.get(&request_url),
+            &request_url,
+            endpoint_label("GET", path),
+            ErgoNodeError::is_retryable,
+        )
+        .await
     }
 
     pub(super) async fn request_post<Req, Resp>(
@@ -118,6 +245,129 @@ impl NodeClient {
     {
         let request_url = format!("{}{}", self.base_url, path);
 
-        send_request(self.client.post(&request_url).json(body), request_url).await
+        // POSTs such as transaction submits and scan registrations must not be repeated
+        // once they may have reached the node
+        self.send_with_retries(
+            || self.client.post(&request_url).json(body),
+            &request_url,
+            endpoint_label("POST", path),
+            ErgoNodeError::is_connect_error,
+        )
+        .await
+    }
+
+    /// Send the request built by `request` until it succeeds, fails with an error for which
+    /// `retryable` is false or the attempts of the retry policy run out
+    async fn send_with_retries<T>(
+        &self,
+        request: impl Fn() -> RequestBuilder,
+        request_url: &str,
+        endpoint: String,
+        retryable: fn(&ErgoNodeError) -> bool,
+    ) -> Result<T, ErgoNodeError>
+    where
+        for<'a> T: Deserialize<'a> + Debug,
+    {
+        let mut attempt = 1;
+
+        loop {
+            let result = send_request(
+                request(),
+                request_url.to_string(),
+                &self.metrics,
+                endpoint.clone(),
+            )
+            .await;
+
+            match result {
+                Err(error) if retryable(&error) && attempt < self.retry_policy.max_attempts => {
+                    tokio::time::sleep(self.retry_policy.delay(attempt)).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::node::mock::flaky_mock_node;
+
+    use super::{NodeClient, RetryPolicy};
+
+    #[tokio::test]
+    async fn requests_retried_on_server_errors() {
+        let routes = vec![("/blocks/lastHeaders/1".to_string(), "[]".to_string())];
+        let retry_policy = RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(1),
+        };
+
+        let node_client = NodeClient::new(flaky_mock_node(routes.clone(), 2).await, b"hello")
+            .unwrap()
+            .with_retry_policy(retry_policy);
+
+        let headers: Vec<serde_json::Value> = node_client
+            .request_get("blocks/lastHeaders/1")
+            .await
+            .unwrap();
+        assert!(headers.is_empty());
+
+        let node_client = NodeClient::new(flaky_mock_node(routes.clone(), 3).await, b"hello")
+            .unwrap()
+            .with_retry_policy(retry_policy);
+
+        let error = node_client
+            .request_get::<Vec<serde_json::Value>>("blocks/lastHeaders/1")
+            .await
+            .unwrap_err();
+        assert!(error.is_retryable());
+        assert!(error.to_string().ends_with("blocks/lastHeaders/1"));
+
+        // The 503 is retried, the 404 answered after it is not
+        let node_client = NodeClient::new(flaky_mock_node(routes, 1).await, b"hello")
+            .unwrap()
+            .with_retry_policy(retry_policy);
+
+        let error = node_client
+            .request_get::<Vec<serde_json::Value>>("blocks/lastHeaders/2")
+            .await
+            .unwrap_err();
+        assert!(error.is_not_found());
+        assert!(!error.is_retryable());
+
+        assert_eq!(retry_policy.delay(1), Duration::from_millis(1));
+        assert_eq!(retry_policy.delay(3), Duration::from_millis(4));
+    }
+
+    #[tokio::test]
+    async fn posts_not_retried_on_server_errors() {
+        let routes = vec![("/transactions".to_string(), "\"tx\"".to_string())];
+        let retry_policy = RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(1),
+        };
+
+        // A submit answered with a 503 may still have been accepted by the node
+        let node_client = NodeClient::new(flaky_mock_node(routes.clone(), 1).await, b"hello")
+            .unwrap()
+            .with_retry_policy(retry_policy);
+
+        let error = node_client
+            .request_post::<_, String>("transactions", &serde_json::json!({}))
+            .await
+            .unwrap_err();
+        assert!(error.is_retryable());
+        assert!(!error.is_connect_error());
+
+        // The failure was not retried, so the next request is the first to succeed
+        let tx_id: String = node_client
+            .request_post("transactions", &serde_json::json!({}))
+            .await
+            .unwrap();
+        assert_eq!(tx_id, "tx");
     }
 }
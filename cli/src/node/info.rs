@@ -0,0 +1,68 @@
+use serde::Deserialize;
+
+use crate::node::client::NodeClient;
+
+use super::client::ErgoNodeError;
+
+/// Subset of the node status returned by the `info` endpoint
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct NodeInfo {
+    pub name: String,
+    pub app_version: String,
+    /// Height of the last fully validated block, `None` while the node has not synced any
+    pub full_height: Option<u32>,
+    pub headers_height: Option<u32>,
+    pub peers_count: u32,
+    pub unconfirmed_count: u32,
+}
+
+impl NodeClient {
+    pub async fn node_info(&self) -> Result<NodeInfo, ErgoNodeError> {
+        let path = "info";
+        let result = self.request_get(path).await?;
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::NodeInfo;
+
+    #[test]
+    fn parse_info_response() {
+        let response = r#"{
+            "currentTime": 1700000000000,
+            "network": "mainnet",
+            "name": "ergo-node",
+            "stateType": "utxo",
+            "difficulty": 1000000,
+            "bestFullHeaderId": "1f2a",
+            "bestHeaderId": "1f2a",
+            "peersCount": 30,
+            "unconfirmedCount": 12,
+            "appVersion": "5.0.22",
+            "headersHeight": 1126401,
+            "fullHeight": 1126400,
+            "isMining": false
+        }"#;
+
+        let info: NodeInfo = serde_json::from_str(response).unwrap();
+
+        assert_eq!(info.full_height, Some(1126400));
+        assert_eq!(info.headers_height, Some(1126401));
+        assert_eq!(info.peers_count, 30);
+
+        let syncing = r#"{
+            "name": "ergo-node",
+            "appVersion": "5.0.22",
+            "headersHeight": null,
+            "fullHeight": null,
+            "peersCount": 0,
+            "unconfirmedCount": 0
+        }"#;
+
+        let info: NodeInfo = serde_json::from_str(syncing).unwrap();
+        assert_eq!(info.full_height, None);
+    }
+}
@@ -0,0 +1,146 @@
+use std::{collections::HashMap, sync::Mutex, time::Duration};
+
+/// Upper bounds in milliseconds of the latency histogram buckets. Requests slower than
+/// the last bound are only counted in the total.
+pub const LATENCY_BUCKETS_MS: [u64; 10] = [5, 10, 25, 50, 100, 250, 500, 1000, 2500, 5000];
+
+/// Cumulative latency histogram of the requests to a single endpoint
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LatencyHistogram {
+    /// Number of requests that completed within the bound of the same index in
+    /// [`LATENCY_BUCKETS_MS`]
+    pub buckets: [u64; LATENCY_BUCKETS_MS.len()],
+    pub count: u64,
+    pub sum: Duration,
+}
+
+impl LatencyHistogram {
+    pub fn observe(&mut self, latency: Duration) {
+        let millis = latency.as_millis();
+
+        for (bucket, bound) in self.buckets.iter_mut().zip(LATENCY_BUCKETS_MS) {
+            if millis <= bound as u128 {
+                *bucket += 1;
+            }
+        }
+
+        self.count += 1;
+        self.sum += latency;
+    }
+
+    pub fn mean(&self) -> Option<Duration> {
+        (self.count > 0).then(|| self.sum / self.count as u32)
+    }
+}
+
+/// Round-trip times of node requests, from sending the request until the response body
+/// is parsed, grouped by endpoint
+#[derive(Debug, Default)]
+pub struct RequestMetrics {
+    endpoints: Mutex<HashMap<String, LatencyHistogram>>,
+}
+
+impl RequestMetrics {
+    pub fn record(&self, endpoint: String, latency: Duration) {
+        self.endpoints
+            .lock()
+            .expect("Metrics lock poisoned")
+            .entry(endpoint)
+            .or_default()
+            .observe(latency);
+    }
+
+    /// Histograms of every endpoint that was requested, sorted by endpoint
+    pub fn snapshot(&self) -> Vec<(String, LatencyHistogram)> {
+        let endpoints = self.endpoints.lock().expect("Metrics lock poisoned");
+
+        let mut snapshot: Vec<_> = endpoints
+            .iter()
+            .map(|(endpoint, histogram)| (endpoint.clone(), histogram.clone()))
+            .collect();
+        snapshot.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        snapshot
+    }
+}
+
+/// Label of the endpoint requested with `method` at `path`. Query parameters are dropped
+/// and path segments holding IDs or numbers are replaced by `{id}`, so requests for
+/// different boxes or scans are grouped together.
+pub fn endpoint_label(method: &str, path: &str) -> String {
+    let path = path.split('?').next().unwrap_or_default();
+
+    let segments: Vec<_> = path
+        .split('/')
+        .map(|segment| {
+            let is_number = !segment.is_empty() && segment.chars().all(|c| c.is_ascii_digit());
+            let is_id = segment.len() >= 32 && segment.chars().all(|c| c.is_ascii_hexdigit());
+
+            if is_number || is_id {
+                "{id}"
+            } else {
+                segment
+            }
+        })
+        .collect();
+
+    format!("{} {}", method, segments.join("/"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::node::{client::NodeClient, mock::mock_node};
+
+    use super::{endpoint_label, LatencyHistogram};
+
+    #[test]
+    fn histogram_buckets() {
+        let mut histogram = LatencyHistogram::default();
+
+        histogram.observe(Duration::from_millis(3));
+        histogram.observe(Duration::from_millis(70));
+        histogram.observe(Duration::from_secs(10));
+
+        assert_eq!(histogram.count, 3);
+        assert_eq!(histogram.buckets[0], 1);
+        assert_eq!(histogram.buckets[4], 2);
+        assert_eq!(histogram.buckets[9], 2);
+        assert_eq!(histogram.mean().unwrap().as_millis(), 3357);
+    }
+
+    #[tokio::test]
+    async fn latency_recorded_for_request() {
+        let routes = vec![("/blocks/lastHeaders/1".to_string(), "[]".to_string())];
+
+        let node_client = NodeClient::new(mock_node(routes).await, b"hello").unwrap();
+
+        assert!(node_client.request_metrics().snapshot().is_empty());
+
+        let _: Vec<serde_json::Value> = node_client
+            .request_get("blocks/lastHeaders/1")
+            .await
+            .unwrap();
+
+        let snapshot = node_client.request_metrics().snapshot();
+        assert_eq!(snapshot.len(), 1);
+
+        let (endpoint, histogram) = &snapshot[0];
+        assert_eq!(endpoint, "GET blocks/lastHeaders/{id}");
+        assert_eq!(histogram.count, 1);
+        assert!(histogram.sum > Duration::ZERO);
+
+        assert_eq!(
+            endpoint_label(
+                "GET",
+                "utxo/byId/3e1bd0f26c1f4e2b45c1fd9a7cd1d4e4fc98bba2d67e4bcbd1c10a4c4e1c2e3f"
+            ),
+            "GET utxo/byId/{id}"
+        );
+        assert_eq!(
+            endpoint_label("POST", "scan/unspentBoxes/12?minConfirmations=0"),
+            "POST scan/unspentBoxes/{id}"
+        );
+    }
+}
@@ -0,0 +1,66 @@
+use reqwest::Url;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+
+/// Serve `routes` as a node would, answering 404 for every other path
+pub(crate) async fn mock_node(routes: Vec<(String, String)>) -> Url {
+    flaky_mock_node(routes, 0).await
+}
+
+/// Like `mock_node`, but answering the first `failures` requests with 503
+pub(crate) async fn flaky_mock_node(routes: Vec<(String, String)>, failures: usize) -> Url {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/", listener.local_addr().unwrap());
+
+    tokio::spawn(async move {
+        let mut failures_left = failures;
+
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+
+            let mut request = Vec::new();
+            let mut buf = [0u8; 1024];
+            // The body of a POST can arrive together with the headers
+            while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                let n = stream.read(&mut buf).await.unwrap();
+                if n == 0 {
+                    break;
+                }
+                request.extend_from_slice(&buf[..n]);
+            }
+
+            let request = String::from_utf8_lossy(&request);
+            let path = request.split_whitespace().nth(1).unwrap_or_default();
+
+            let route = routes.iter().find(|(route, _)| route == path);
+
+            let (status, body) = match route {
+                _ if failures_left > 0 => {
+                    failures_left -= 1;
+                    (
+                        "503 Service Unavailable",
+                        r#"{"error": 503, "reason": "unavailable", "detail": "try again"}"#
+                            .to_string(),
+                    )
+                }
+                Some((_, body)) => ("200 OK", body.clone()),
+                None => (
+                    "404 Not Found",
+                    r#"{"error": 404, "reason": "not-found", "detail": "not found"}"#.to_string(),
+                ),
+            };
+
+            let response = format!(
+                "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            );
+            stream.write_all(response.as_bytes()).await.unwrap();
+        }
+    });
+
+    Url::parse(&url).unwrap()
+}
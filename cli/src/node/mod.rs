@@ -1,4 +1,11 @@
+pub mod blocks;
 pub mod client;
+pub mod info;
+pub mod metrics;
+#[cfg(test)]
+mod mock;
+pub mod parameters;
 pub mod scan;
 pub mod transactions;
+pub mod utxo;
 pub mod wallet;
@@ -0,0 +1,68 @@
+use serde::Deserialize;
+
+use crate::node::client::NodeClient;
+
+use super::client::ErgoNodeError;
+
+/// Protocol parameters voted on by the miners
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct BlockchainParameters {
+    pub height: u32,
+    pub storage_fee_factor: u64,
+    pub min_value_per_byte: u64,
+    pub max_block_size: u64,
+    pub max_block_cost: u64,
+    pub block_version: u8,
+    pub token_access_cost: u64,
+    pub input_cost: u64,
+    pub data_input_cost: u64,
+    pub output_cost: u64,
+}
+
+impl NodeClient {
+    pub async fn blockchain_parameters(&self) -> Result<BlockchainParameters, ErgoNodeError> {
+        let path = "blockchain/parameters";
+        let result = self.request_get(path).await?;
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BlockchainParameters;
+
+    #[test]
+    fn parse_parameters_response() {
+        let response = r#"{
+            "height": 1126400,
+            "storageFeeFactor": 1250000,
+            "minValuePerByte": 360,
+            "maxBlockSize": 1271009,
+            "maxBlockCost": 8001091,
+            "blockVersion": 3,
+            "tokenAccessCost": 100,
+            "inputCost": 2000,
+            "dataInputCost": 100,
+            "outputCost": 100
+        }"#;
+
+        let parameters: BlockchainParameters = serde_json::from_str(response).unwrap();
+
+        assert_eq!(
+            parameters,
+            BlockchainParameters {
+                height: 1126400,
+                storage_fee_factor: 1250000,
+                min_value_per_byte: 360,
+                max_block_size: 1271009,
+                max_block_cost: 8001091,
+                block_version: 3,
+                token_access_cost: 100,
+                input_cost: 2000,
+                data_input_cost: 100,
+                output_cost: 100,
+            }
+        );
+    }
+}
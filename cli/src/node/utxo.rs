@@ -0,0 +1,113 @@
+use ergo_lib::ergotree_ir::chain::ergo_box::{BoxId, ErgoBox};
+use serde::Deserialize;
+
+use crate::node::client::NodeClient;
+
+use super::client::ErgoNodeError;
+
+/// Box as returned by the blockchain indexer, which also knows about spent boxes
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct IndexedErgoBox {
+    #[serde(flatten)]
+    ergo_box: ErgoBox,
+    spent_transaction_id: Option<String>,
+}
+
+impl NodeClient {
+    /// Unspent box with the given ID, independent of any scan. Nodes without a UTXO set
+    /// cannot serve the box directly, so the blockchain indexer is queried as a fallback.
+    /// Returns `None` if the box does not exist or is already spent.
+    pub async fn box_by_id(&self, box_id: &BoxId) -> Result<Option<ErgoBox>, ErgoNodeError> {
+        let path = format!("utxo/byId/{box_id}");
+
+        match self.request_get::<ErgoBox>(&path).await {
+            Ok(ergo_box) => return Ok(Some(ergo_box)),
+            Err(ErgoNodeError::ApiError { .. }) => (),
+            Err(e) => return Err(e),
+        }
+
+        let path = format!("blockchain/box/byId/{box_id}");
+
+        match self.request_get::<IndexedErgoBox>(&path).await {
+            Ok(indexed) if indexed.spent_transaction_id.is_none() => Ok(Some(indexed.ergo_box)),
+            Ok(_) => Ok(None),
+            Err(e) if e.is_not_found() => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ergo_lib::{
+        chain::transaction::TxId,
+        ergo_chain_types::ec_point::generator,
+        ergotree_ir::{
+            chain::{
+                address::Address,
+                ergo_box::{ErgoBox, ErgoBoxCandidate, NonMandatoryRegisters},
+            },
+            sigma_protocol::sigma_boolean::ProveDlog,
+        },
+    };
+
+    use crate::node::{client::NodeClient, mock::mock_node};
+
+    fn test_box(index: u16) -> ErgoBox {
+        let candidate = ErgoBoxCandidate {
+            value: 1_000_000u64.try_into().unwrap(),
+            ergo_tree: Address::P2Pk(ProveDlog::new(generator())).script().unwrap(),
+            tokens: None,
+            additional_registers: NonMandatoryRegisters::empty(),
+            creation_height: 0,
+        };
+
+        ErgoBox::from_box_candidate(&candidate, TxId::zero(), index).unwrap()
+    }
+
+    #[tokio::test]
+    async fn box_by_id_from_mock_node() {
+        let unspent = test_box(0);
+        let indexed = test_box(1);
+        let spent = test_box(2);
+        let missing = test_box(3);
+
+        let indexed_json = |ergo_box: &ErgoBox, spent_tx: Option<&str>| {
+            let mut json = serde_json::to_value(ergo_box).unwrap();
+            json["spentTransactionId"] = serde_json::to_value(spent_tx).unwrap();
+            json.to_string()
+        };
+
+        let routes = vec![
+            (
+                format!("/utxo/byId/{}", unspent.box_id()),
+                serde_json::to_string(&unspent).unwrap(),
+            ),
+            (
+                format!("/blockchain/box/byId/{}", indexed.box_id()),
+                indexed_json(&indexed, None),
+            ),
+            (
+                format!("/blockchain/box/byId/{}", spent.box_id()),
+                indexed_json(&spent, Some(&String::from(TxId::zero()))),
+            ),
+        ];
+
+        let node_client = NodeClient::new(mock_node(routes).await, b"hello").unwrap();
+
+        assert_eq!(
+            node_client.box_by_id(&unspent.box_id()).await.unwrap(),
+            Some(unspent)
+        );
+        assert_eq!(
+            node_client.box_by_id(&indexed.box_id()).await.unwrap(),
+            Some(indexed)
+        );
+        assert_eq!(node_client.box_by_id(&spent.box_id()).await.unwrap(), None);
+        assert_eq!(
+            node_client.box_by_id(&missing.box_id()).await.unwrap(),
+            None
+        );
+    }
+}
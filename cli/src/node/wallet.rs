@@ -1,8 +1,11 @@
+use std::collections::HashMap;
+
 use ergo_lib::{
     chain::transaction::{unsigned::UnsignedTransaction, Transaction},
     ergotree_ir::chain::{
-        address::{Address, AddressEncoder, NetworkPrefix},
+        address::{Address, AddressEncoder},
         ergo_box::ErgoBox,
+        token::TokenId,
     },
 };
 use serde::{Deserialize, Serialize};
@@ -40,6 +43,15 @@ struct WalletRescanDto {
     from_height: i32,
 }
 
+/// Confirmed balance of the wallet at `height`, excluding unconfirmed transactions
+#[derive(Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct WalletBalance {
+    pub height: u32,
+    /// Value in nanoERG
+    pub balance: u64,
+    pub assets: HashMap<TokenId, u64>,
+}
+
 #[derive(Error, Debug)]
 pub enum WalletStatusError {
     // #[error("Wallet not initialized")]
@@ -73,6 +85,14 @@ impl WalletStatus {
             .clone()
             .ok_or(WalletStatusError::NoChangeAddress)
     }
+
+    /// Warning for the user when the node reports a wallet error that does not prevent
+    /// using the wallet, e.g. while the wallet is still scanning
+    pub fn warn_if_error(&self) -> Option<String> {
+        let error = self.error.trim();
+
+        (!error.is_empty()).then(|| format!("Warning: Wallet reported an error: {}", error))
+    }
 }
 
 impl NodeClient {
@@ -106,7 +126,7 @@ impl NodeClient {
     pub async fn wallet_status(&self) -> Result<WalletStatus, ErgoNodeError> {
         let path = "wallet/status";
         let result: WalletStatusDto = self.request_get(path).await?;
-        let change_address = AddressEncoder::new(NetworkPrefix::Mainnet)
+        let change_address = AddressEncoder::new(self.network_prefix())
             .parse_address_from_str(&result.change_address)
             .ok();
 
@@ -119,6 +139,12 @@ impl NodeClient {
         })
     }
 
+    pub async fn wallet_balances(&self) -> Result<WalletBalance, ErgoNodeError> {
+        let path = "wallet/balances";
+        let result = self.request_get(path).await?;
+        Ok(result)
+    }
+
     pub async fn wallet_rescan(&self, from_height: i32) -> Result<(), ErgoNodeError> {
         let path = "wallet/rescan";
         let body = WalletRescanDto { from_height };
@@ -128,3 +154,53 @@ impl NodeClient {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use ergo_lib::{ergo_chain_types::Digest32, ergotree_ir::chain::token::TokenId};
+
+    use crate::node::{client::NodeClient, mock::mock_node};
+
+    use super::WalletStatus;
+
+    fn wallet_status(error: &str) -> WalletStatus {
+        WalletStatus {
+            is_initialized: true,
+            is_unlocked: true,
+            change_address: None,
+            wallet_height: 100,
+            error: error.to_string(),
+        }
+    }
+
+    #[test]
+    fn wallet_error_warning() {
+        let status = wallet_status("Wallet is still scanning");
+
+        assert!(status.error_if_locked().is_ok());
+        assert_eq!(
+            status.warn_if_error().as_deref(),
+            Some("Warning: Wallet reported an error: Wallet is still scanning")
+        );
+
+        assert_eq!(wallet_status("").warn_if_error(), None);
+        assert_eq!(wallet_status("  ").warn_if_error(), None);
+    }
+
+    #[tokio::test]
+    async fn wallet_balances() {
+        let token_id: TokenId = Digest32::from([1u8; 32]).into();
+        let response = format!(
+            r#"{{"height": 1126400, "balance": 2500000000, "assets": {{"{}": 25}}}}"#,
+            String::from(token_id)
+        );
+        let routes = vec![("/wallet/balances".to_string(), response)];
+
+        let node_client = NodeClient::new(mock_node(routes).await, b"hello").unwrap();
+        let balance = node_client.wallet_balances().await.unwrap();
+
+        assert_eq!(balance.height, 1126400);
+        assert_eq!(balance.balance, 2_500_000_000);
+        assert_eq!(balance.assets.get(&token_id), Some(&25));
+    }
+}
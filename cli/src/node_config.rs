@@ -1,35 +1,130 @@
+use clap::ValueEnum;
 use config::Config;
+use ergo_lib::ergotree_ir::chain::address::NetworkPrefix;
+use off_the_grid::node::client::RetryPolicy;
 use serde::Deserialize;
+use std::time::{Duration, TryFromFloatSecsError};
+
+use crate::profile::Profile;
 
 fn api_url_default() -> String {
     "http://127.0.0.1:9053".into()
 }
 
+fn default_fee_default() -> String {
+    "0.001".into()
+}
+
+fn retry_attempts_default() -> u32 {
+    RetryPolicy::default().max_attempts
+}
+
+fn retry_delay_default() -> f64 {
+    RetryPolicy::default().base_delay.as_secs_f64()
+}
+
+/// Ergo network the node runs on, deciding the prefix of encoded addresses
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Network {
+    #[default]
+    Mainnet,
+    Testnet,
+}
+
+impl Network {
+    pub fn prefix(self) -> NetworkPrefix {
+        match self {
+            Network::Mainnet => NetworkPrefix::Mainnet,
+            Network::Testnet => NetworkPrefix::Testnet,
+        }
+    }
+}
+
 #[derive(Debug, Deserialize)]
 pub struct NodeConfig {
     #[serde(default = "api_url_default")]
     pub api_url: String,
     pub api_key: String,
+    /// Transaction fee in ERG used by commands when `--fee` is not given
+    #[serde(default = "default_fee_default")]
+    pub default_fee: String,
+    #[serde(default)]
+    pub network: Network,
+    /// Attempts per node request when the node cannot be reached or fails with a server error
+    #[serde(default = "retry_attempts_default")]
+    pub retry_attempts: u32,
+    /// Seconds to wait before the first retry of a request, doubled for every later retry
+    #[serde(default = "retry_delay_default")]
+    pub retry_delay: f64,
 }
 
 impl NodeConfig {
+    pub fn retry_policy(&self) -> Result<RetryPolicy, TryFromFloatSecsError> {
+        Ok(RetryPolicy {
+            max_attempts: self.retry_attempts,
+            base_delay: Duration::try_from_secs_f64(self.retry_delay)?,
+        })
+    }
+
     pub fn try_create(
+        profile: &Profile,
         config_path: Option<String>,
         api_url: Option<String>,
         api_key: Option<String>,
+        network: Option<Network>,
     ) -> Result<Self, config::ConfigError> {
         let config_required = config_path.is_some();
 
         let scan_config_reader = Config::builder()
             .add_source(config::Environment::with_prefix("NODE"))
             .add_source(
-                config::File::with_name(&config_path.unwrap_or_else(|| "node_config".to_string()))
-                    .required(config_required),
+                config::File::with_name(
+                    &config_path.unwrap_or_else(|| profile.path("node_config")),
+                )
+                .required(config_required),
             )
             .set_override_option("api_url", api_url)?
             .set_override_option("api_key", api_key)?
             .build()?;
 
-        scan_config_reader.try_deserialize()
+        let mut node_config: Self = scan_config_reader.try_deserialize()?;
+
+        if let Some(network) = network {
+            node_config.network = network;
+        }
+
+        Ok(node_config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use config::Config;
+    use ergo_lib::ergotree_ir::chain::address::NetworkPrefix;
+    use off_the_grid::node::client::RetryPolicy;
+
+    use super::{Network, NodeConfig};
+
+    #[test]
+    fn node_config_defaults() {
+        let parse = |network: Option<&str>| -> NodeConfig {
+            Config::builder()
+                .set_override("api_key", "hello")
+                .unwrap()
+                .set_override_option("network", network)
+                .unwrap()
+                .build()
+                .unwrap()
+                .try_deserialize()
+                .unwrap()
+        };
+
+        assert_eq!(parse(None).network, Network::Mainnet);
+        assert_eq!(
+            parse(Some("testnet")).network.prefix(),
+            NetworkPrefix::Testnet
+        );
+        assert_eq!(parse(None).retry_policy().unwrap(), RetryPolicy::default());
     }
 }
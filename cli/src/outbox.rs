@@ -0,0 +1,212 @@
+use std::{
+    future::Future,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use ergo_lib::chain::transaction::{Transaction, TxId};
+use serde::{Deserialize, Serialize};
+
+/// A signed transaction waiting in the outbox to be submitted
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OutboxEntry {
+    pub timestamp: u64,
+    /// Command that built the transaction, e.g. `grid create`
+    pub command: String,
+    pub tx_id: String,
+    pub transaction: Transaction,
+}
+
+impl OutboxEntry {
+    pub fn new(command: &str, transaction: Transaction) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        Self {
+            timestamp,
+            command: command.to_string(),
+            tx_id: String::from(transaction.id()),
+            transaction,
+        }
+    }
+}
+
+/// Directory of signed transactions, one JSON file per transaction. File names start with
+/// a sequence number so the transactions are submitted in the order they were queued,
+/// which matters when a transaction spends the outputs of an earlier one.
+pub struct Outbox {
+    dir: PathBuf,
+}
+
+impl Outbox {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// Queue `entry` after every pending transaction, returning the path it was written to
+    pub fn push(&self, entry: &OutboxEntry) -> anyhow::Result<PathBuf> {
+        std::fs::create_dir_all(&self.dir)
+            .with_context(|| format!("Failed to create outbox {}", self.dir.display()))?;
+
+        let sequence = self
+            .entry_paths()?
+            .last()
+            .and_then(|path| sequence_number(path))
+            .map_or(0, |sequence| sequence + 1);
+
+        let path = self
+            .dir
+            .join(format!("{:06}-{}.json", sequence, entry.tx_id));
+
+        std::fs::write(&path, serde_json::to_vec_pretty(entry)?)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+
+        Ok(path)
+    }
+
+    /// Pending transactions in the order they were queued
+    pub fn pending(&self) -> anyhow::Result<Vec<(PathBuf, OutboxEntry)>> {
+        self.entry_paths()?
+            .into_iter()
+            .map(|path| {
+                let contents = std::fs::read(&path)
+                    .with_context(|| format!("Failed to read {}", path.display()))?;
+                let entry = serde_json::from_slice(&contents)
+                    .with_context(|| format!("Invalid outbox entry {}", path.display()))?;
+                Ok((path, entry))
+            })
+            .collect()
+    }
+
+    /// Submit every pending transaction in order with `submit`, removing each one once it
+    /// is submitted. Stops at the first failure so that later transactions, which may
+    /// depend on it, stay queued.
+    pub async fn submit_all<F, Fut>(&self, mut submit: F) -> anyhow::Result<Vec<TxId>>
+    where
+        F: FnMut(Transaction) -> Fut,
+        Fut: Future<Output = anyhow::Result<TxId>>,
+    {
+        let mut submitted = Vec::new();
+
+        for (path, entry) in self.pending()? {
+            let tx_id = submit(entry.transaction)
+                .await
+                .with_context(|| format!("Failed to submit {}", path.display()))?;
+
+            std::fs::remove_file(&path)
+                .with_context(|| format!("Failed to remove {}", path.display()))?;
+
+            submitted.push(tx_id);
+        }
+
+        Ok(submitted)
+    }
+
+    fn entry_paths(&self) -> anyhow::Result<Vec<PathBuf>> {
+        if !self.dir.exists() {
+            return Ok(vec![]);
+        }
+
+        let mut paths = std::fs::read_dir(&self.dir)
+            .with_context(|| format!("Failed to read outbox {}", self.dir.display()))?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        paths.retain(|path| sequence_number(path).is_some());
+        paths.sort_by_key(|path| sequence_number(path));
+
+        Ok(paths)
+    }
+}
+
+fn sequence_number(path: &Path) -> Option<u64> {
+    if path.extension()? != "json" {
+        return None;
+    }
+
+    path.file_name()?
+        .to_str()?
+        .split_once('-')
+        .and_then(|(sequence, _)| sequence.parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use ergo_lib::{
+        chain::transaction::{Input, Transaction, UnsignedInput},
+        ergo_chain_types::{ec_point::generator, Digest32},
+        ergotree_interpreter::sigma_protocol::prover::{ContextExtension, ProofBytes},
+        ergotree_ir::{
+            chain::{
+                address::Address,
+                ergo_box::{ErgoBoxCandidate, NonMandatoryRegisters},
+            },
+            sigma_protocol::sigma_boolean::ProveDlog,
+        },
+    };
+    use uuid::Uuid;
+
+    use super::{Outbox, OutboxEntry};
+
+    fn test_transaction(input: u8) -> Transaction {
+        let address = Address::P2Pk(ProveDlog::new(generator()));
+
+        let input = Input::from_unsigned_input(
+            UnsignedInput::new(
+                Digest32::from([input; 32]).into(),
+                ContextExtension::empty(),
+            ),
+            ProofBytes::Empty,
+        );
+
+        let output = ErgoBoxCandidate {
+            value: 1_000_000u64.try_into().unwrap(),
+            ergo_tree: address.script().unwrap(),
+            tokens: None,
+            additional_registers: NonMandatoryRegisters::empty(),
+            creation_height: 0,
+        };
+
+        Transaction::new_from_vec(vec![input], vec![], vec![output]).unwrap()
+    }
+
+    #[tokio::test]
+    async fn queued_transactions_submitted_in_order() {
+        let dir = std::env::temp_dir().join(format!("outbox-{}", Uuid::new_v4()));
+        let outbox = Outbox::new(&dir);
+
+        let first = test_transaction(1);
+        let second = test_transaction(2);
+
+        outbox
+            .push(&OutboxEntry::new("grid create", first.clone()))
+            .unwrap();
+        outbox
+            .push(&OutboxEntry::new("grid redeem", second.clone()))
+            .unwrap();
+
+        let pending = outbox.pending().unwrap();
+        assert_eq!(pending.len(), 2);
+        assert_eq!(pending[0].1.command, "grid create");
+        assert_eq!(pending[1].1.command, "grid redeem");
+
+        let mut submitted = Vec::new();
+
+        let tx_ids = outbox
+            .submit_all(|tx| {
+                submitted.push(tx.id());
+                async move { Ok(tx.id()) }
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(tx_ids, vec![first.id(), second.id()]);
+        assert_eq!(submitted, tx_ids);
+        assert!(outbox.pending().unwrap().is_empty());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,108 @@
+use std::path::{Path, PathBuf};
+
+use thiserror::Error;
+
+/// Directory holding one subdirectory of configuration files per named profile
+pub const PROFILES_DIR: &str = "profiles";
+
+#[derive(Error, Debug)]
+pub enum ProfileError {
+    #[error("Profile `{0}` not found, expected a directory at {1}")]
+    NotFound(String, String),
+}
+
+/// Location of the node, scan and matcher configs and the token store. Without a named
+/// profile the files are read from the working directory.
+#[derive(Clone, Debug, Default)]
+pub struct Profile {
+    dir: Option<PathBuf>,
+}
+
+impl Profile {
+    /// Resolve the profile `name` in the profiles directory under `root`
+    pub fn resolve(name: Option<&str>, root: &Path) -> Result<Self, ProfileError> {
+        let dir = match name {
+            Some(name) => {
+                let dir = root.join(PROFILES_DIR).join(name);
+
+                if !dir.is_dir() {
+                    return Err(ProfileError::NotFound(
+                        name.to_string(),
+                        dir.display().to_string(),
+                    ));
+                }
+
+                Some(dir)
+            }
+            None => None,
+        };
+
+        Ok(Self { dir })
+    }
+
+    /// Path of a file in the profile
+    pub fn path(&self, file_name: &str) -> String {
+        match &self.dir {
+            Some(dir) => dir.join(file_name).display().to_string(),
+            None => file_name.to_string(),
+        }
+    }
+
+    pub fn token_store_path(&self) -> String {
+        self.path("tokens.json")
+    }
+
+    /// Directory of signed transactions queued with `grid --outbox`
+    pub fn outbox_path(&self) -> String {
+        self.path("outbox")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use crate::{node_config::NodeConfig, scan_config::ScanConfig};
+
+    use super::{Profile, ProfileError, PROFILES_DIR};
+
+    #[test]
+    fn profile_configs_loaded() {
+        let root = std::env::temp_dir().join(format!("profiles-{}", Uuid::new_v4()));
+        let testnet = root.join(PROFILES_DIR).join("testnet");
+        std::fs::create_dir_all(&testnet).unwrap();
+
+        std::fs::write(
+            testnet.join("scan_config.json"),
+            r#"{"n2t_scan_id": 11, "wallet_multigrid_scan_id": 12, "multigrid_scan_id": 13}"#,
+        )
+        .unwrap();
+        std::fs::write(
+            testnet.join("node_config.json"),
+            r#"{"api_url": "http://127.0.0.1:9052", "api_key": "testnet"}"#,
+        )
+        .unwrap();
+
+        let profile = Profile::resolve(Some("testnet"), &root).unwrap();
+
+        let scan_config = ScanConfig::try_create(&profile, None, None).unwrap();
+        assert_eq!(scan_config.n2t_scan_id, 11);
+        assert_eq!(scan_config.wallet_multigrid_scan_id, 12);
+        assert_eq!(scan_config.multigrid_scan_id, 13);
+
+        let node_config = NodeConfig::try_create(&profile, None, None, None, None).unwrap();
+        assert_eq!(node_config.api_url, "http://127.0.0.1:9052");
+        assert_eq!(node_config.api_key, "testnet");
+
+        assert!(profile
+            .token_store_path()
+            .starts_with(&*testnet.to_string_lossy()));
+
+        assert!(matches!(
+            Profile::resolve(Some("mainnet"), &root),
+            Err(ProfileError::NotFound(name, _)) if name == "mainnet"
+        ));
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
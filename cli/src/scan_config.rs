@@ -1,15 +1,76 @@
+use std::collections::BTreeMap;
+
+use anyhow::Context;
 use config::Config;
+use ergo_lib::{
+    ergo_chain_types::blake2b256_hash,
+    ergotree_ir::{ergo_tree::ErgoTree, serialization::SigmaSerializable},
+};
+use off_the_grid::{
+    grid::multigrid_order::MULTIGRID_ORDER_SCRIPT,
+    spectrum::pool::{N2T_POOL_SCRIPT, T2T_POOL_SCRIPT},
+};
 use serde::{Deserialize, Serialize};
 
+use crate::profile::Profile;
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct ScanConfig {
     pub n2t_scan_id: i32,
+    /// `None` if the scan config was created before T2T pools were tracked
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub t2t_scan_id: Option<i32>,
     pub wallet_multigrid_scan_id: i32,
     pub multigrid_scan_id: i32,
+    /// Fingerprints of the scripts the scans were created for, by script name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub script_fingerprints: BTreeMap<String, String>,
+}
+
+/// Fingerprint of an embedded script compared against the one recorded in the scan config
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScriptFingerprint {
+    pub name: &'static str,
+    pub embedded: String,
+    /// `None` if the scan config was created before fingerprints were recorded
+    pub recorded: Option<String>,
+}
+
+impl ScriptFingerprint {
+    pub fn is_mismatch(&self) -> bool {
+        self.recorded
+            .as_ref()
+            .is_some_and(|recorded| *recorded != self.embedded)
+    }
+}
+
+/// Scripts compiled into the binary whose boxes are tracked by the scans
+pub fn embedded_scripts() -> [(&'static str, &'static ErgoTree); 3] {
+    [
+        ("multigrid", &*MULTIGRID_ORDER_SCRIPT),
+        ("n2t_pool", &*N2T_POOL_SCRIPT),
+        ("t2t_pool", &*T2T_POOL_SCRIPT),
+    ]
+}
+
+/// Hex encoded blake2b256 hash of the serialized `script`
+pub fn script_fingerprint(script: &ErgoTree) -> String {
+    // We assume the embedded scripts are always valid
+    let bytes = script.sigma_serialize_bytes().unwrap();
+    String::from(blake2b256_hash(&bytes))
+}
+
+/// Fingerprints of every embedded script
+pub fn embedded_fingerprints() -> BTreeMap<String, String> {
+    embedded_scripts()
+        .into_iter()
+        .map(|(name, script)| (name.to_string(), script_fingerprint(script)))
+        .collect()
 }
 
 impl ScanConfig {
     pub fn try_create(
+        profile: &Profile,
         config_path: Option<String>,
         pool_scan_id: Option<i32>,
     ) -> Result<Self, config::ConfigError> {
@@ -18,12 +79,141 @@ impl ScanConfig {
         let scan_config_reader = Config::builder()
             .add_source(config::Environment::with_prefix("SCAN"))
             .add_source(
-                config::File::with_name(&config_path.unwrap_or_else(|| "scan_config".to_string()))
-                    .required(config_required),
+                config::File::with_name(
+                    &config_path.unwrap_or_else(|| profile.path("scan_config")),
+                )
+                .required(config_required),
             )
             .set_override_option("pool_scan_id", pool_scan_id)?
             .build()?;
 
         scan_config_reader.try_deserialize()
     }
+
+    /// Write the config as JSON to `path`, in the format read by [`ScanConfig::try_create`]
+    pub fn save(&self, path: &str) -> anyhow::Result<()> {
+        std::fs::write(path, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Failed to write scan config to {}", path))
+    }
+
+    /// Fingerprint of every embedded script along with the one recorded when the scans
+    /// were created
+    pub fn script_fingerprints(&self) -> Vec<ScriptFingerprint> {
+        embedded_scripts()
+            .into_iter()
+            .map(|(name, script)| ScriptFingerprint {
+                name,
+                embedded: script_fingerprint(script),
+                recorded: self.script_fingerprints.get(name).cloned(),
+            })
+            .collect()
+    }
+
+    /// Scan tracking the boxes of the embedded script `name`
+    fn script_scan_id(&self, name: &str) -> Option<i32> {
+        match name {
+            "multigrid" => Some(self.multigrid_scan_id),
+            "n2t_pool" => Some(self.n2t_scan_id),
+            "t2t_pool" => self.t2t_scan_id,
+            _ => None,
+        }
+    }
+
+    /// Scans created for a different version of an embedded script, along with the name
+    /// of the script
+    pub fn stale_scans(&self) -> Vec<(&'static str, i32)> {
+        self.script_fingerprints()
+            .into_iter()
+            .filter(|fingerprint| fingerprint.is_mismatch())
+            .filter_map(|fingerprint| {
+                self.script_scan_id(fingerprint.name)
+                    .map(|scan_id| (fingerprint.name, scan_id))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use crate::profile::{Profile, PROFILES_DIR};
+
+    use super::{embedded_fingerprints, ScanConfig};
+
+    #[test]
+    fn created_config_read_back() {
+        let root = std::env::temp_dir().join(format!("scan-config-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(root.join(PROFILES_DIR).join("test")).unwrap();
+        let profile = Profile::resolve(Some("test"), &root).unwrap();
+
+        let created = ScanConfig {
+            n2t_scan_id: 11,
+            t2t_scan_id: Some(14),
+            wallet_multigrid_scan_id: 12,
+            multigrid_scan_id: 13,
+            script_fingerprints: embedded_fingerprints(),
+        };
+
+        // `scans create-config` writes scan_config.json, consumers read scan_config.*
+        created.save(&profile.path("scan_config.json")).unwrap();
+        let read = ScanConfig::try_create(&profile, None, None).unwrap();
+
+        assert_eq!(read.n2t_scan_id, 11);
+        assert_eq!(read.t2t_scan_id, Some(14));
+        assert_eq!(read.wallet_multigrid_scan_id, 12);
+        assert_eq!(read.multigrid_scan_id, 13);
+        assert_eq!(read.script_fingerprints, created.script_fingerprints);
+        assert!(read.stale_scans().is_empty());
+
+        // Configs written before fingerprints were recorded still load
+        std::fs::write(
+            profile.path("scan_config.json"),
+            r#"{"n2t_scan_id": 1, "wallet_multigrid_scan_id": 2, "multigrid_scan_id": 3}"#,
+        )
+        .unwrap();
+        let old = ScanConfig::try_create(&profile, None, None).unwrap();
+
+        assert_eq!(old.multigrid_scan_id, 3);
+        assert_eq!(old.t2t_scan_id, None);
+        assert!(old.script_fingerprints.is_empty());
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn fingerprint_mismatch_detected() {
+        let mut scan_config = ScanConfig {
+            n2t_scan_id: 1,
+            t2t_scan_id: None,
+            wallet_multigrid_scan_id: 2,
+            multigrid_scan_id: 3,
+            script_fingerprints: embedded_fingerprints(),
+        };
+
+        assert!(scan_config
+            .script_fingerprints()
+            .iter()
+            .all(|f| !f.is_mismatch()));
+
+        scan_config
+            .script_fingerprints
+            .insert("multigrid".to_string(), "00".repeat(32));
+
+        let mismatches: Vec<_> = scan_config
+            .script_fingerprints()
+            .into_iter()
+            .filter(|f| f.is_mismatch())
+            .collect();
+
+        assert_eq!(mismatches.len(), 1);
+        assert_eq!(mismatches[0].name, "multigrid");
+        assert_eq!(mismatches[0].recorded, Some("00".repeat(32)));
+
+        scan_config.script_fingerprints.clear();
+        assert!(scan_config
+            .script_fingerprints()
+            .iter()
+            .all(|f| f.recorded.is_none() && !f.is_mismatch()));
+    }
 }
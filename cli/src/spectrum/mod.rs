@@ -1 +1,3 @@
+pub mod oracle;
 pub mod pool;
+pub mod router;
@@ -0,0 +1,132 @@
+use ergo_lib::{ergo_chain_types::Digest32, ergotree_ir::chain::token::TokenId};
+use lazy_static::lazy_static;
+
+use crate::{
+    boxes::tracked_box::TrackedBox,
+    units::{Fraction, Price, TokenInfo, Unit, ERG_UNIT},
+};
+
+use super::pool::{select_pool, SpectrumPool};
+
+const SIGUSD_TOKEN_ID_BASE16: &str =
+    "03faf2cb329f2e90d6d23b58d91bbb6c046aa143261cc21f52fbe2824bfcbf04";
+
+lazy_static! {
+    /// SigmaUSD stablecoin, known by ID so that tokens reusing its name are never used as
+    /// the price source
+    pub static ref SIGUSD_TOKEN_INFO: TokenInfo = TokenInfo {
+        token_id: TokenId::from(
+            Digest32::try_from(SIGUSD_TOKEN_ID_BASE16.to_string())
+                .expect("String is a valid token ID")
+        ),
+        name: "SigUSD".to_string(),
+        decimals: 2,
+    };
+}
+
+/// Source of the price of ERG in another unit, such as a fiat currency
+pub trait PriceSource {
+    /// Price of ERG in the unit of the source
+    fn erg_price(&self) -> Price<'_>;
+}
+
+/// Price of ERG in SigUSD, from the spot price of the ERG/SigUSD pool with the most
+/// liquidity
+pub struct SpectrumPriceSource {
+    pool: SpectrumPool,
+}
+
+impl SpectrumPriceSource {
+    /// Price source using the ERG/SigUSD pool among `pools` holding the most ERG, if there
+    /// is any
+    pub fn sigusd<'a, I>(pools: I) -> Option<Self>
+    where
+        I: IntoIterator<Item = &'a TrackedBox<SpectrumPool>>,
+    {
+        select_pool(pools, SIGUSD_TOKEN_INFO.token_id, None)
+            .ok()
+            .map(|pool| Self {
+                pool: pool.value.clone(),
+            })
+    }
+}
+
+impl PriceSource for SpectrumPriceSource {
+    fn erg_price(&self) -> Price<'_> {
+        Price::new(
+            *ERG_UNIT,
+            Unit::Known(&SIGUSD_TOKEN_INFO),
+            Fraction::new(
+                *self.pool.asset_y.amount.as_u64(),
+                *self.pool.asset_x.amount.as_u64(),
+            ),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ergo_lib::{
+        chain::transaction::TxId,
+        ergo_chain_types::Digest32,
+        ergotree_ir::chain::{ergo_box::ErgoBox, token::TokenId},
+    };
+
+    use crate::{
+        boxes::tracked_box::TrackedBox,
+        spectrum::pool::{SpectrumPool, SpectrumPoolBuilder, DEFAULT_FEE_DENOM},
+        units::{UnitAmount, ERG_UNIT},
+    };
+
+    use super::{PriceSource, SpectrumPriceSource, SIGUSD_TOKEN_INFO};
+
+    fn tracked_pool(
+        nft: u8,
+        token_id: TokenId,
+        erg_amount: u64,
+        token_amount: u64,
+    ) -> TrackedBox<SpectrumPool> {
+        let pool = SpectrumPoolBuilder::new(
+            Digest32::from([nft; 32]).into(),
+            (Digest32::from([9u8; 32]).into(), 1000.try_into().unwrap()).into(),
+            token_id,
+        )
+        .reserves(erg_amount, token_amount)
+        .fee(997, DEFAULT_FEE_DENOM)
+        .build()
+        .unwrap();
+
+        let candidate = pool.into_box_candidate(0).unwrap();
+        ErgoBox::from_box_candidate(&candidate, TxId::zero(), nft as u16)
+            .unwrap()
+            .try_into()
+            .unwrap()
+    }
+
+    #[test]
+    fn sigusd_price_from_largest_pool() {
+        let other_token: TokenId = Digest32::from([3u8; 32]).into();
+
+        assert!(
+            SpectrumPriceSource::sigusd(&[tracked_pool(1, other_token, 1_000_000_000, 1_000)])
+                .is_none()
+        );
+
+        // 1000 ERG for 1500 SigUSD and a shallower pool at a different price
+        let pools = vec![
+            tracked_pool(1, SIGUSD_TOKEN_INFO.token_id, 10_000_000_000, 2_000),
+            tracked_pool(2, SIGUSD_TOKEN_INFO.token_id, 1_000_000_000_000, 150_000),
+            tracked_pool(3, other_token, 1_000_000_000_000_000, 1_000_000),
+        ];
+
+        let source = SpectrumPriceSource::sigusd(&pools).unwrap();
+        let price = source.erg_price();
+
+        assert_eq!(price.to_string(), "1.5 ERG/SigUSD");
+
+        let value = price
+            .convert_price(&UnitAmount::new(*ERG_UNIT, 2_000_000_000))
+            .unwrap();
+        assert_eq!(value.to_string(), "3 SigUSD");
+    }
+}
@@ -6,8 +6,8 @@ use ergo_lib::{
         chain::{
             address::Address,
             ergo_box::{
-                box_value::BoxValueError, BoxId, ErgoBox, ErgoBoxCandidate, NonMandatoryRegisterId,
-                NonMandatoryRegisters,
+                box_value::{BoxValue, BoxValueError},
+                BoxId, ErgoBox, ErgoBoxCandidate, NonMandatoryRegisterId, NonMandatoryRegisters,
             },
             token::{Token, TokenAmount, TokenAmountError, TokenId},
         },
@@ -24,12 +24,21 @@ use crate::{
     boxes::{
         describe_box::{BoxAssetDisplay, ErgoBoxDescriptors},
         liquidity_box::LiquidityProvider,
+        tracked_box::TrackedBox,
     },
-    units::{TokenStore, UnitAmount, ERG_UNIT},
+    units::{token_id_hex, Fraction, Price, TokenStore, Unit, UnitAmount, ERG_UNIT},
 };
 
 const N2T_POOL_ERGO_TREE_BASE16: &str = "1999030f0400040204020404040405feffffffffffffffff0105feffffffffffffffff01050004d00f040004000406050005000580dac409d819d601b2a5730000d602e4c6a70404d603db63087201d604db6308a7d605b27203730100d606b27204730200d607b27203730300d608b27204730400d6099973058c720602d60a999973068c7205027209d60bc17201d60cc1a7d60d99720b720cd60e91720d7307d60f8c720802d6107e720f06d6117e720d06d612998c720702720fd6137e720c06d6147308d6157e721206d6167e720a06d6177e720906d6189c72117217d6199c72157217d1ededededededed93c27201c2a793e4c672010404720293b27203730900b27204730a00938c7205018c720601938c7207018c72080193b17203730b9593720a730c95720e929c9c721072117e7202069c7ef07212069a9c72137e7214067e9c720d7e72020506929c9c721372157e7202069c7ef0720d069a9c72107e7214067e9c72127e7202050695ed720e917212730d907216a19d721872139d72197210ed9272189c721672139272199c7216721091720b730e";
 
+const T2T_POOL_ERGO_TREE_BASE16: &str = "19a3030f0400040204020404040404060406058080a0f6f4acdbe01b058080a0f6f4acdbe01b050004d00f0400040005000500d81ad601b2a5730000d602e4c6a70405d603db63087201d604db6308a7d605b27203730100d606b27204730200d607b27203730300d608b27204730400d609b27203730500d60ab27204730600d60b9973078c720602d60c999973088c720502720bd60d8c720802d60e998c720702720dd60f91720e7309d6108c720a02d6117e721006d6127e720e06d613998c7209027210d6147e720d06d615730ad6167e721306d6177e720c06d6187e720b06d6199c72127218d61a9c72167218d1edededededed93c27201c2a793e4c672010405720292c17201c1a793b27203730b00b27204730c00938c7205018c720601ed938c7207018c720801938c7209018c720a019593720c730d95720f929c9c721172127e7202069c7ef07213069a9c72147e7215067e9c720e720206929c9c721472167e7202069c7ef0720e069a9c72117e7215067e9c721372020695ed720f917213730e907217a19d721972149d721a7211ed9272199c7217721492721a9c72177211";
+
+/// Index of the fee denominator among the constants of the N2T pool script
+const N2T_FEE_DENOM_CONSTANT: usize = 8;
+
+/// Index of the fee denominator among the constants of the T2T pool script
+const T2T_FEE_DENOM_CONSTANT: usize = 10;
+
 lazy_static! {
     /// Spectrum ERG token id
     pub static ref ERG_TOKEN_ID: TokenId =
@@ -40,17 +49,43 @@ lazy_static! {
 
     pub static ref N2T_POOL_SCRIPT: ErgoTree =
         N2T_POOL_ADDRESS.script().expect("Pool address is a valid script");
+
+    pub static ref T2T_POOL_ADDRESS: Address =
+        Address::P2S(base16::decode(T2T_POOL_ERGO_TREE_BASE16).expect("String is a valid base16"));
+
+    pub static ref T2T_POOL_SCRIPT: ErgoTree =
+        T2T_POOL_ADDRESS.script().expect("Pool address is a valid script");
+}
+
+/// Fee denominator read from the constant at `index` if `ergo_tree` is the pool script
+/// `script`. Only the exact pool scripts are tracked by the pool scans, other scripts with
+/// the same template are not pools.
+fn fee_denom(ergo_tree: &ErgoTree, script: &ErgoTree, index: usize) -> Option<i32> {
+    if ergo_tree != script {
+        return None;
+    }
+
+    ergo_tree
+        .get_constant(index)
+        .ok()??
+        .try_extract_into::<i32>()
+        .ok()
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum PoolType {
+    /// ERG to token pool, with the ERG reserve held as the box value
     N2T,
+    /// Token to token pool. The box value is not part of the reserves and is kept as is
+    /// when swapping.
+    T2T(BoxValue),
 }
 
 impl PoolType {
     pub fn as_str(&self) -> &str {
         match self {
             PoolType::N2T => "N2T",
+            PoolType::T2T(_) => "T2T",
         }
     }
 }
@@ -63,13 +98,15 @@ pub enum SpectrumSwapError {
     BigIntTruncated(BigInt),
     #[error(transparent)]
     TokenAmountError(#[from] TokenAmountError),
-    #[error("Cannot swap token {0:?}")]
+    #[error("Cannot swap token {}", token_id_hex(.0))]
     InvalidToken(TokenId),
+    #[error("Swapping {} of token {} yields nothing", .0.amount.as_u64(), token_id_hex(&.0.token_id))]
+    ZeroOutput(Token),
 }
 
 #[derive(Error, Debug)]
 pub enum SpectrumPoolError {
-    #[error("Box parsing failed {0:?}")]
+    #[error("Box parsing failed {0}")]
     BoxParseFailure(BoxId),
     #[error(transparent)]
     BoxValueError(#[from] BoxValueError),
@@ -77,6 +114,18 @@ pub enum SpectrumPoolError {
     TokenAmountError(#[from] TokenAmountError),
 }
 
+#[derive(Error, Debug)]
+pub enum PoolSelectionError {
+    #[error("No liquidity pool found for token {}", token_id_hex(.0))]
+    NoPool(TokenId),
+    #[error("No liquidity pool found with pool NFT {}", token_id_hex(.0))]
+    PinnedPoolNotFound(TokenId),
+    #[error("Pool {} does not trade token {}", token_id_hex(.0), token_id_hex(.1))]
+    TokenMismatch(TokenId, TokenId),
+    #[error("Pool {} does not trade against ERG", token_id_hex(.0))]
+    NotErgPool(TokenId),
+}
+
 #[derive(Clone, Debug)]
 pub struct SpectrumPool {
     pub pool_nft: Token,
@@ -96,43 +145,246 @@ impl SpectrumPool {
         x_amount / y_amount
     }
 
+    /// Spot price of asset y in asset x, ignoring the pool fee
+    pub fn price<'a>(&self, tokens: &'a TokenStore) -> Price<'a> {
+        Price::new(
+            tokens.get_unit(&self.asset_y.token_id),
+            tokens.get_unit(&self.asset_x.token_id),
+            Fraction::new(*self.asset_x.amount.as_u64(), *self.asset_y.amount.as_u64()),
+        )
+    }
+
     pub fn amm_factor(&self) -> BigInt {
         let x_amount: BigInt = (*self.asset_x.amount.as_u64()).into();
         let y_amount: BigInt = (*self.asset_y.amount.as_u64()).into();
 
         x_amount * y_amount
     }
+
+    /// Input required to move the price of the pool, in asset x per asset y, to `price`.
+    /// Selling asset y lowers the price and selling asset x raises it, so the returned
+    /// token is the asset that has to be sold. Returns `None` if the pool is already at
+    /// the price.
+    pub fn input_to_price(&self, price: Fraction) -> Result<Option<Token>, SpectrumSwapError> {
+        let (Some(numer), Some(denom)) = (price.numer(), price.denom()) else {
+            return Ok(None);
+        };
+
+        if *numer == 0 || *denom == 0 {
+            return Ok(None);
+        }
+
+        let x_amount = *self.asset_x.amount.as_u64();
+        let y_amount = *self.asset_y.amount.as_u64();
+
+        let amm_factor = self.amm_factor();
+
+        // Reserves at the target price, ignoring fees: x * y = k and x / y = price
+        let target_x = (&amm_factor * BigInt::from(*numer) / BigInt::from(*denom)).sqrt();
+        let target_y = (&amm_factor * BigInt::from(*denom) / BigInt::from(*numer)).sqrt();
+
+        let to_u64 = |v: BigInt| {
+            v.to_u64()
+                .ok_or(SpectrumSwapError::BigIntTruncated(v.clone()))
+        };
+
+        let output: Option<Token> = if target_x < BigInt::from(x_amount) {
+            let output_amount = x_amount - to_u64(target_x)?;
+            Some((self.asset_x.token_id, output_amount.try_into()?).into())
+        } else if target_y < BigInt::from(y_amount) {
+            let output_amount = y_amount - to_u64(target_y)?;
+            Some((self.asset_y.token_id, output_amount.try_into()?).into())
+        } else {
+            None
+        };
+
+        output.map(|o| self.input_amount(&o)).transpose()
+    }
+
+    /// Pool box with the current reserves. The box value of an N2T pool is its ERG reserve,
+    /// T2T pools keep their box value.
+    pub fn into_box_candidate(
+        self,
+        creation_height: u32,
+    ) -> Result<ErgoBoxCandidate, SpectrumSwapError> {
+        let (value, fee, tokens, ergo_tree) = match self.pool_type {
+            PoolType::N2T => (
+                (*self.asset_x.amount.as_u64()).try_into()?,
+                Constant::from(self.fee_num),
+                vec![self.pool_nft, self.asset_lp, self.asset_y],
+                N2T_POOL_SCRIPT
+                    .clone()
+                    .with_constant(N2T_FEE_DENOM_CONSTANT, self.fee_denom.into())
+                    .expect("Fee denominator is an Int constant of the pool script"),
+            ),
+            PoolType::T2T(value) => (
+                value,
+                Constant::from(self.fee_num as i64),
+                vec![self.pool_nft, self.asset_lp, self.asset_x, self.asset_y],
+                T2T_POOL_SCRIPT
+                    .clone()
+                    .with_constant(T2T_FEE_DENOM_CONSTANT, self.fee_denom.into())
+                    .expect("Fee denominator is an Int constant of the pool script"),
+            ),
+        };
+
+        let registers: HashMap<NonMandatoryRegisterId, Constant> =
+            HashMap::from([(NonMandatoryRegisterId::R4, fee)]);
+
+        let tokens = Some(
+            tokens
+                .try_into()
+                .expect("Token BoundedVec requires >1 tokens"),
+        );
+
+        Ok(ErgoBoxCandidate {
+            value,
+            ergo_tree,
+            tokens,
+            additional_registers: NonMandatoryRegisters::new(registers)
+                .expect("Only R4 is used which is always tightly packed"),
+            creation_height,
+        })
+    }
+}
+
+/// Fee numerator of most N2T pools, a 0.3% fee with [`DEFAULT_FEE_DENOM`]
+pub const DEFAULT_FEE_NUM: i32 = 997;
+
+/// Fee denominator of the N2T pool script
+pub const DEFAULT_FEE_DENOM: i32 = 1000;
+
+/// Builder for a [`SpectrumPool`] that is not parsed from a pool box, e.g. to simulate
+/// swaps. The pool charges the usual 0.3% fee unless set otherwise.
+#[derive(Clone, Debug)]
+pub struct SpectrumPoolBuilder {
+    pool_nft: TokenId,
+    asset_lp: Token,
+    token_x: TokenId,
+    token_y: TokenId,
+    x_amount: u64,
+    y_amount: u64,
+    fee_num: i32,
+    fee_denom: i32,
+    pool_type: PoolType,
+}
+
+impl SpectrumPoolBuilder {
+    /// N2T pool trading ERG against `token_id`
+    pub fn new(pool_nft: TokenId, asset_lp: Token, token_id: TokenId) -> Self {
+        Self {
+            pool_nft,
+            asset_lp,
+            token_x: *ERG_TOKEN_ID,
+            token_y: token_id,
+            x_amount: 0,
+            y_amount: 0,
+            fee_num: DEFAULT_FEE_NUM,
+            fee_denom: DEFAULT_FEE_DENOM,
+            pool_type: PoolType::N2T,
+        }
+    }
+
+    /// T2T pool trading `token_x` against `token_y`, its box holding `box_value`
+    pub fn t2t(
+        pool_nft: TokenId,
+        asset_lp: Token,
+        token_x: TokenId,
+        token_y: TokenId,
+        box_value: BoxValue,
+    ) -> Self {
+        Self {
+            token_x,
+            token_y,
+            pool_type: PoolType::T2T(box_value),
+            ..Self::new(pool_nft, asset_lp, token_y)
+        }
+    }
+
+    /// Reserves of the pool in base units of its assets, nanoERG for X of an N2T pool
+    pub fn reserves(mut self, x_amount: u64, y_amount: u64) -> Self {
+        self.x_amount = x_amount;
+        self.y_amount = y_amount;
+        self
+    }
+
+    pub fn fee(mut self, fee_num: i32, fee_denom: i32) -> Self {
+        self.fee_num = fee_num;
+        self.fee_denom = fee_denom;
+        self
+    }
+
+    /// Fails if a reserve is empty, as the pool box could not hold it
+    pub fn build(self) -> Result<SpectrumPool, SpectrumPoolError> {
+        Ok(SpectrumPool {
+            pool_nft: (self.pool_nft, 1.try_into()?).into(),
+            asset_lp: self.asset_lp,
+            asset_x: (self.token_x, self.x_amount.try_into()?).into(),
+            asset_y: (self.token_y, self.y_amount.try_into()?).into(),
+            fee_num: self.fee_num,
+            fee_denom: self.fee_denom,
+            pool_type: self.pool_type,
+        })
+    }
 }
 
+/// Only the pool script, tokens and the fee in R4 are required. Other registers are
+/// ignored, so pool boxes carrying additional registers still parse. The box must have one
+/// of the known pool scripts, which holds the fee denominator. N2T pools hold the fee as an Int and T2T pools as a Long.
 impl TryFrom<&ErgoBox> for SpectrumPool {
     type Error = SpectrumPoolError;
 
     fn try_from(pool_box: &ErgoBox) -> Result<Self, Self::Error> {
-        let fee_value = pool_box
+        let fee_register = pool_box
             .additional_registers
-            .get_constant(NonMandatoryRegisterId::R4)
-            .and_then(|x| x.clone().try_extract_into::<i32>().ok());
+            .get_constant(NonMandatoryRegisterId::R4);
 
         let tokens = pool_box.tokens.as_ref().map(|v| v.as_slice());
 
-        match (tokens, fee_value) {
-            (Some([pool_nft, pool_lp, pool_y]), Some(fee))
-                if pool_box.ergo_tree == *N2T_POOL_SCRIPT =>
-            {
+        let n2t_fee_denom = fee_denom(
+            &pool_box.ergo_tree,
+            &N2T_POOL_SCRIPT,
+            N2T_FEE_DENOM_CONSTANT,
+        );
+        let t2t_fee_denom = fee_denom(
+            &pool_box.ergo_tree,
+            &T2T_POOL_SCRIPT,
+            T2T_FEE_DENOM_CONSTANT,
+        );
+
+        match (tokens, n2t_fee_denom, t2t_fee_denom) {
+            (Some([pool_nft, pool_lp, pool_y]), Some(fee_denom), _) => {
+                let fee = fee_register.and_then(|x| x.clone().try_extract_into::<i32>().ok());
                 let x_amount = TokenAmount::try_from(*pool_box.value.as_u64())?;
-                let pool = Self {
+
+                fee.map(|fee| Self {
                     pool_nft: pool_nft.clone(),
                     asset_lp: pool_lp.clone(),
                     asset_x: (*ERG_TOKEN_ID, x_amount).into(),
                     asset_y: pool_y.clone(),
                     fee_num: fee,
-                    fee_denom: 1000,
+                    fee_denom,
                     pool_type: PoolType::N2T,
-                };
-                Ok(pool)
+                })
+            }
+            (Some([pool_nft, pool_lp, pool_x, pool_y]), _, Some(fee_denom)) => {
+                let fee = fee_register
+                    .and_then(|x| x.clone().try_extract_into::<i64>().ok())
+                    .and_then(|fee| i32::try_from(fee).ok());
+
+                fee.map(|fee| Self {
+                    pool_nft: pool_nft.clone(),
+                    asset_lp: pool_lp.clone(),
+                    asset_x: pool_x.clone(),
+                    asset_y: pool_y.clone(),
+                    fee_num: fee,
+                    fee_denom,
+                    pool_type: PoolType::T2T(pool_box.value),
+                })
             }
-            _ => Err(SpectrumPoolError::BoxParseFailure(pool_box.box_id())),
+            _ => None,
         }
+        .ok_or_else(|| SpectrumPoolError::BoxParseFailure(pool_box.box_id()))
     }
 }
 
@@ -184,6 +436,10 @@ impl LiquidityProvider for SpectrumPool {
         let output_amount = (to_amount * input_amount * self.fee_num)
             / (from_amount * self.fee_denom + input_amount * self.fee_num);
 
+        if output_amount == BigInt::from(0) {
+            return Err(SpectrumSwapError::ZeroOutput(input.clone()));
+        }
+
         let token_amount: TokenAmount = output_amount
             .to_u64()
             .ok_or(SpectrumSwapError::BigIntTruncated(output_amount))?
@@ -216,32 +472,6 @@ impl LiquidityProvider for SpectrumPool {
         Ok((from.token_id, token_amount).into())
     }
 
-    fn into_box_candidate(self, creation_height: u32) -> Result<ErgoBoxCandidate, Self::Error> {
-        let registers: HashMap<NonMandatoryRegisterId, Constant> =
-            HashMap::from([(NonMandatoryRegisterId::R4, self.fee_num.into())]);
-
-        let tokens = Some(
-            vec![self.pool_nft, self.asset_lp, self.asset_y]
-                .try_into()
-                .expect("Token BoundedVec requires >1 tokens"),
-        );
-
-        let value = (*self.asset_x.amount.as_u64()).try_into()?;
-
-        let ergo_tree = match self.pool_type {
-            PoolType::N2T => N2T_POOL_SCRIPT.clone(),
-        };
-
-        Ok(ErgoBoxCandidate {
-            value,
-            ergo_tree,
-            tokens,
-            additional_registers: NonMandatoryRegisters::new(registers)
-                .expect("Only R4 is used which is always tightly packed"),
-            creation_height,
-        })
-    }
-
     fn asset_x(&self) -> &Token {
         &self.asset_x
     }
@@ -267,13 +497,107 @@ impl ErgoBoxDescriptors for SpectrumPool {
 
                 BoxAssetDisplay::Double(x_amount, y_amount)
             }
+            PoolType::T2T(_) => {
+                let x_unit = tokens.get_unit(&self.asset_x().token_id);
+                let y_unit = tokens.get_unit(&self.asset_y().token_id);
+
+                let x_amount = UnitAmount::new(x_unit, *self.asset_x().amount.as_u64());
+                let y_amount = UnitAmount::new(y_unit, *self.asset_y().amount.as_u64());
+
+                BoxAssetDisplay::Double(x_amount, y_amount)
+            }
+        }
+    }
+}
+
+/// Select the N2T pool to trade `token_id` against ERG. If `pool_nft` is set the pool
+/// holding that NFT is used, otherwise the pool for the token with the most liquidity,
+/// i.e. the most ERG in its reserves. T2T pools are never selected.
+pub fn select_pool<'a, I>(
+    pools: I,
+    token_id: TokenId,
+    pool_nft: Option<TokenId>,
+) -> Result<&'a TrackedBox<SpectrumPool>, PoolSelectionError>
+where
+    I: IntoIterator<Item = &'a TrackedBox<SpectrumPool>>,
+{
+    match pool_nft {
+        Some(pool_nft) => {
+            let pool = pools
+                .into_iter()
+                .find(|p| p.value.pool_nft.token_id == pool_nft)
+                .ok_or(PoolSelectionError::PinnedPoolNotFound(pool_nft))?;
+
+            if pool.value.pool_type != PoolType::N2T {
+                Err(PoolSelectionError::NotErgPool(pool_nft))
+            } else if pool.value.asset_y.token_id == token_id {
+                Ok(pool)
+            } else {
+                Err(PoolSelectionError::TokenMismatch(pool_nft, token_id))
+            }
+        }
+        None => pools
+            .into_iter()
+            .filter(|p| p.value.pool_type == PoolType::N2T && p.value.asset_y.token_id == token_id)
+            .max_by_key(|p| *p.value.asset_x.amount.as_u64())
+            .ok_or(PoolSelectionError::NoPool(token_id)),
+    }
+}
+
+/// Remove pool boxes claiming the same pool NFT, as can happen while a pool update is
+/// pending, keeping the box with the highest creation height. Returns the remaining pools
+/// in their original order and the NFTs that were claimed more than once.
+pub fn dedup_pools(
+    pools: Vec<TrackedBox<SpectrumPool>>,
+) -> (Vec<TrackedBox<SpectrumPool>>, Vec<TokenId>) {
+    let mut positions: HashMap<TokenId, usize> = HashMap::new();
+    let mut unique: Vec<TrackedBox<SpectrumPool>> = Vec::new();
+    let mut duplicates: Vec<TokenId> = Vec::new();
+
+    for pool in pools {
+        let nft = pool.value.pool_nft.token_id;
+
+        match positions.get(&nft) {
+            Some(&position) => {
+                if !duplicates.contains(&nft) {
+                    duplicates.push(nft);
+                }
+
+                if pool.ergo_box.creation_height > unique[position].ergo_box.creation_height {
+                    unique[position] = pool;
+                }
+            }
+            None => {
+                positions.insert(nft, unique.len());
+                unique.push(pool);
+            }
         }
     }
+
+    (unique, duplicates)
+}
+
+/// Price of `unit` in ERG, taken from the pool of the token with the most liquidity
+pub fn erg_price<'a, 'b, I>(
+    unit: Unit<'a>,
+    pools: I,
+    tokens: &'a TokenStore,
+) -> Result<Price<'a>, PoolSelectionError>
+where
+    I: IntoIterator<Item = &'b TrackedBox<SpectrumPool>>,
+{
+    if unit == *ERG_UNIT {
+        return Ok(Price::new(unit, unit, Fraction::from(1u64)));
+    }
+
+    Ok(select_pool(pools, unit.token_id(), None)?
+        .value
+        .price(tokens))
 }
 
 #[cfg(test)]
 pub mod arbitrary {
-    use super::{PoolType, SpectrumPool};
+    use super::{SpectrumPool, SpectrumPoolBuilder, DEFAULT_FEE_DENOM};
     use ergo_lib::ergo_chain_types::Digest32;
     use proptest::{
         prelude::Arbitrary,
@@ -285,8 +609,8 @@ pub mod arbitrary {
         type Strategy = BoxedStrategy<Self>;
 
         fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
-            let x_amount = 1..std::i64::MAX as u64;
-            let y_amount = 1..std::i64::MAX as u64;
+            let x_amount = 1..i64::MAX as u64;
+            let y_amount = 1..i64::MAX as u64;
             let fee_num = 1..=1000;
             (x_amount, y_amount, fee_num)
                 .prop_map(|(x_amount, y_amount, fee_num)| test_pool(x_amount, y_amount, fee_num))
@@ -304,25 +628,57 @@ pub mod arbitrary {
         let mut asset_y_id = [0u8; 32];
         asset_y_id[0] = 3;
 
-        SpectrumPool {
-            pool_nft: (Digest32::from(pool_nft_id).into(), 1.try_into().unwrap()).into(),
-            asset_lp: (Digest32::from(asset_lp_id).into(), 1000.try_into().unwrap()).into(),
-            asset_x: (Digest32::zero().into(), x_amount.try_into().unwrap()).into(),
-            asset_y: (
-                Digest32::from(asset_y_id).into(),
-                y_amount.try_into().unwrap(),
-            )
-                .into(),
-            fee_num,
-            fee_denom: 1000,
-            pool_type: PoolType::N2T,
-        }
+        SpectrumPoolBuilder::new(
+            Digest32::from(pool_nft_id).into(),
+            (Digest32::from(asset_lp_id).into(), 1000.try_into().unwrap()).into(),
+            Digest32::from(asset_y_id).into(),
+        )
+        .reserves(x_amount, y_amount)
+        .fee(fee_num, DEFAULT_FEE_DENOM)
+        .build()
+        .unwrap()
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{boxes::liquidity_box::LiquidityProvider, spectrum::pool::arbitrary::test_pool};
+    use std::collections::HashMap;
+
+    use ergo_lib::{
+        chain::transaction::TxId,
+        ergo_chain_types::{ec_point::generator, Digest32},
+        ergotree_ir::{
+            chain::{
+                address::Address,
+                ergo_box::{
+                    box_value::BoxValue, ErgoBox, NonMandatoryRegisterId, NonMandatoryRegisters,
+                },
+                token::TokenId,
+            },
+            mir::constant::Constant,
+            sigma_protocol::sigma_boolean::ProveDlog,
+        },
+    };
+
+    use crate::{
+        boxes::{
+            liquidity_box::{LiquidityProvider, LiquidityProviderError},
+            tracked_box::TrackedBox,
+        },
+        spectrum::pool::{
+            arbitrary::test_pool, dedup_pools, select_pool, PoolSelectionError, PoolType,
+            SpectrumPool, SpectrumPoolBuilder, SpectrumSwapError, DEFAULT_FEE_DENOM, ERG_TOKEN_ID,
+            N2T_POOL_SCRIPT, T2T_POOL_SCRIPT,
+        },
+        units::Fraction,
+    };
+
+    fn tracked_pool(pool: SpectrumPool, index: u16) -> TrackedBox<SpectrumPool> {
+        let candidate = pool.into_box_candidate(0).unwrap();
+        let ergo_box = ErgoBox::from_box_candidate(&candidate, TxId::zero(), index).unwrap();
+
+        ergo_box.try_into().unwrap()
+    }
 
     #[test]
     fn swap_output() {
@@ -341,4 +697,310 @@ mod tests {
         assert_eq!(swapped.asset_y.amount, 668.try_into().unwrap());
         assert_eq!(swapped.asset_x.amount, 1500000000.try_into().unwrap());
     }
+
+    #[test]
+    fn pinned_pool_selected() {
+        let shallow = test_pool(1_000_000_000, 1_000, 997);
+        let mut deep = test_pool(100_000_000_000, 100_000, 997);
+        deep.pool_nft.token_id = Digest32::from([9u8; 32]).into();
+
+        let token_id = shallow.asset_y.token_id;
+        let shallow_nft = shallow.pool_nft.token_id;
+        let deep_nft = deep.pool_nft.token_id;
+
+        let pools = vec![tracked_pool(shallow, 0), tracked_pool(deep.clone(), 1)];
+
+        let selected = select_pool(&pools, token_id, None).unwrap();
+        assert_eq!(selected.value.pool_nft.token_id, deep_nft);
+
+        let selected = select_pool(&pools, token_id, Some(shallow_nft)).unwrap();
+        assert_eq!(selected.value.pool_nft.token_id, shallow_nft);
+
+        let other_token = Digest32::from([7u8; 32]).into();
+        assert!(matches!(
+            select_pool(&pools, other_token, Some(shallow_nft)),
+            Err(PoolSelectionError::TokenMismatch(..))
+        ));
+
+        // A pool holding more tokens but less ERG is not the deepest
+        let mut token_heavy = test_pool(50_000_000_000, 100_000_000, 997);
+        token_heavy.pool_nft.token_id = Digest32::from([8u8; 32]).into();
+        let pools = vec![tracked_pool(deep, 0), tracked_pool(token_heavy, 1)];
+        let selected = select_pool(&pools, token_id, None).unwrap();
+        assert_eq!(selected.value.pool_nft.token_id, deep_nft);
+    }
+
+    #[test]
+    fn t2t_pool_not_selected() {
+        let n2t = test_pool(1_000_000_000, 1_000, 997);
+        let token_id = n2t.asset_y.token_id;
+
+        // A deeper pool trading the token against another token
+        let t2t = SpectrumPoolBuilder::t2t(
+            Digest32::from([9u8; 32]).into(),
+            n2t.asset_lp.clone(),
+            Digest32::from([8u8; 32]).into(),
+            token_id,
+            BoxValue::SAFE_USER_MIN,
+        )
+        .reserves(1_000_000_000_000_000, 1_000_000)
+        .fee(997, DEFAULT_FEE_DENOM)
+        .build()
+        .unwrap();
+        let t2t_nft = t2t.pool_nft.token_id;
+
+        let n2t_nft = n2t.pool_nft.token_id;
+        let pools = vec![tracked_pool(t2t, 0), tracked_pool(n2t, 1)];
+
+        let selected = select_pool(&pools, token_id, None).unwrap();
+        assert_eq!(selected.value.pool_nft.token_id, n2t_nft);
+
+        assert!(matches!(
+            select_pool(&pools, token_id, Some(t2t_nft)),
+            Err(PoolSelectionError::NotErgPool(_))
+        ));
+        assert!(matches!(
+            select_pool(&pools[..1], token_id, None),
+            Err(PoolSelectionError::NoPool(_))
+        ));
+    }
+
+    #[test]
+    fn input_to_price_increases_with_distance() {
+        // 1000 nanoERG per token
+        let pool = test_pool(1_000_000_000_000, 1_000_000_000, 997);
+
+        let erg_id = pool.asset_x.token_id;
+        let token_id = pool.asset_y.token_id;
+
+        let input_at = |price: u64| pool.input_to_price(Fraction::from(price)).unwrap();
+
+        assert!(input_at(1000).is_none());
+
+        let below: Vec<_> = [900, 800, 500, 100]
+            .into_iter()
+            .map(|price| input_at(price).unwrap())
+            .collect();
+
+        assert!(below.iter().all(|t| t.token_id == token_id));
+        assert!(below.windows(2).all(|w| w[0].amount < w[1].amount));
+
+        let above: Vec<_> = [1100, 1200, 1500, 10000]
+            .into_iter()
+            .map(|price| input_at(price).unwrap())
+            .collect();
+
+        assert!(above.iter().all(|t| t.token_id == erg_id));
+        assert!(above.windows(2).all(|w| w[0].amount < w[1].amount));
+
+        // Swapping the estimated input moves the price past the target, as fees are paid
+        let swapped = pool.clone().with_swap(&input_at(500).unwrap()).unwrap();
+        assert!(swapped.pure_price() <= 500);
+    }
+
+    #[test]
+    fn price_impact_of_swaps() {
+        // 1000 nanoERG per token
+        let pool = test_pool(1_000_000_000_000, 1_000_000_000, 997);
+
+        let erg_input = |amount: u64| (pool.asset_x.token_id, amount.try_into().unwrap()).into();
+        let token_input = |amount: u64| (pool.asset_y.token_id, amount.try_into().unwrap()).into();
+
+        // Small swaps in either direction pay little more than the 0.3% fee
+        let small_buy = pool.price_impact(&erg_input(100_000_000)).unwrap();
+        assert!(small_buy > Fraction::new(3u64, 1000u64));
+        assert!(small_buy < Fraction::new(32u64, 10000u64));
+
+        let small_sell = pool.price_impact(&token_input(100_000)).unwrap();
+        assert!(small_sell > Fraction::new(3u64, 1000u64));
+        assert!(small_sell < Fraction::new(32u64, 10000u64));
+
+        let large_buy = pool.price_impact(&erg_input(100_000_000_000)).unwrap();
+        assert!(large_buy > Fraction::new(1u64, 10u64));
+
+        assert!(matches!(
+            pool.price_impact(&erg_input(1)),
+            Err(LiquidityProviderError::Swap(SpectrumSwapError::ZeroOutput(
+                _
+            )))
+        ));
+    }
+
+    #[test]
+    fn pool_with_extra_register() {
+        let pool = test_pool(1_000_000_000, 1_000, 997);
+        let mut candidate = pool.clone().into_box_candidate(0).unwrap();
+
+        candidate.additional_registers = NonMandatoryRegisters::new(HashMap::from([
+            (NonMandatoryRegisterId::R4, Constant::from(997)),
+            (NonMandatoryRegisterId::R5, Constant::from(42i64)),
+        ]))
+        .unwrap();
+
+        let pool_box = ErgoBox::from_box_candidate(&candidate, TxId::zero(), 0).unwrap();
+        let parsed = SpectrumPool::try_from(&pool_box).expect("Extra register is ignored");
+
+        assert_eq!(parsed.pool_nft, pool.pool_nft);
+        assert_eq!(parsed.asset_x, pool.asset_x);
+        assert_eq!(parsed.asset_y, pool.asset_y);
+        assert_eq!(parsed.fee_num, 997);
+
+        // The script is still validated
+        candidate.ergo_tree = Address::P2Pk(ProveDlog::new(generator())).script().unwrap();
+        let other_box = ErgoBox::from_box_candidate(&candidate, TxId::zero(), 0).unwrap();
+        assert!(SpectrumPool::try_from(&other_box).is_err());
+    }
+
+    #[test]
+    fn builder_pool_swap() {
+        let token_id = Digest32::from([3u8; 32]).into();
+
+        let pool = SpectrumPoolBuilder::new(
+            Digest32::from([1u8; 32]).into(),
+            (Digest32::from([2u8; 32]).into(), 1000.try_into().unwrap()).into(),
+            token_id,
+        )
+        .reserves(1_000_000_000, 1_000)
+        .build()
+        .unwrap();
+
+        assert_eq!(pool.fee_num, 997);
+        assert_eq!(pool.fee_denom, 1000);
+
+        // 0.1 ERG buys 90 of the 1000 tokens after the fee
+        let input = (*ERG_TOKEN_ID, 100_000_000.try_into().unwrap()).into();
+        let output = pool.output_amount(&input).unwrap();
+        assert_eq!(output.token_id, token_id);
+        assert_eq!(*output.amount.as_u64(), 90);
+
+        let swapped = pool.with_swap(&input).unwrap();
+        assert_eq!(*swapped.asset_x.amount.as_u64(), 1_100_000_000);
+        assert_eq!(*swapped.asset_y.amount.as_u64(), 910);
+
+        let empty = SpectrumPoolBuilder::new(
+            Digest32::from([1u8; 32]).into(),
+            (Digest32::from([2u8; 32]).into(), 1000.try_into().unwrap()).into(),
+            token_id,
+        )
+        .build();
+        assert!(empty.is_err());
+    }
+
+    #[test]
+    fn fee_denom_from_pool_script() {
+        let pool = test_pool(1_000_000_000, 1_000, 997);
+        let candidate = pool.clone().into_box_candidate(0).unwrap();
+        assert_eq!(candidate.ergo_tree, *N2T_POOL_SCRIPT);
+
+        let pool_box = ErgoBox::from_box_candidate(&candidate, TxId::zero(), 0).unwrap();
+        assert_eq!(SpectrumPool::try_from(&pool_box).unwrap().fee_denom, 1000);
+
+        let mut fine_fee_pool = test_pool(1_000_000_000, 1_000, 9970);
+        fine_fee_pool.fee_denom = 10000;
+
+        // A script variant with another denominator is not a known pool script
+        let candidate = fine_fee_pool.clone().into_box_candidate(0).unwrap();
+        assert_ne!(candidate.ergo_tree, *N2T_POOL_SCRIPT);
+
+        let pool_box = ErgoBox::from_box_candidate(&candidate, TxId::zero(), 0).unwrap();
+        assert!(SpectrumPool::try_from(&pool_box).is_err());
+
+        // 9970 / 10000 is the same fee as 997 / 1000
+        let input = (*ERG_TOKEN_ID, 10_000_000.try_into().unwrap()).into();
+        let output = fine_fee_pool.output_amount(&input).unwrap();
+
+        assert_eq!(output, pool.output_amount(&input).unwrap());
+        assert_eq!(
+            *output.amount.as_u64(),
+            1_000 * 10_000_000 * 9970 / (1_000_000_000 * 10000 + 10_000_000 * 9970)
+        );
+    }
+
+    #[test]
+    fn t2t_pool_round_trip() {
+        let token_x: TokenId = Digest32::from([4u8; 32]).into();
+        let token_y: TokenId = Digest32::from([5u8; 32]).into();
+        let box_value = BoxValue::try_from(2_000_000u64).unwrap();
+
+        let pool = SpectrumPoolBuilder::t2t(
+            Digest32::from([1u8; 32]).into(),
+            (Digest32::from([2u8; 32]).into(), 1000.try_into().unwrap()).into(),
+            token_x,
+            token_y,
+            box_value,
+        )
+        .reserves(1_000_000, 2_000_000)
+        .fee(997, DEFAULT_FEE_DENOM)
+        .build()
+        .unwrap();
+
+        let candidate = pool.clone().into_box_candidate(0).unwrap();
+        assert_eq!(candidate.ergo_tree, *T2T_POOL_SCRIPT);
+        assert_eq!(candidate.value, box_value);
+        assert_eq!(candidate.tokens.as_ref().unwrap().len(), 4);
+        assert_eq!(
+            candidate
+                .additional_registers
+                .get_constant(NonMandatoryRegisterId::R4),
+            Some(&Constant::from(997i64))
+        );
+
+        let pool_box = ErgoBox::from_box_candidate(&candidate, TxId::zero(), 0).unwrap();
+        let parsed = SpectrumPool::try_from(&pool_box).expect("T2T pool parses");
+
+        assert_eq!(parsed.pool_type, PoolType::T2T(box_value));
+        assert_eq!(parsed.pool_nft, pool.pool_nft);
+        assert_eq!(parsed.asset_lp, pool.asset_lp);
+        assert_eq!(parsed.asset_x, pool.asset_x);
+        assert_eq!(parsed.asset_y, pool.asset_y);
+        assert_eq!(parsed.fee_num, 997);
+        assert_eq!(parsed.fee_denom, 1000);
+
+        let input = (token_x, 10_000.try_into().unwrap()).into();
+        let output = parsed.output_amount(&input).unwrap();
+        assert_eq!(output.token_id, token_y);
+        assert_eq!(
+            *output.amount.as_u64(),
+            2_000_000 * 10_000 * 997 / (1_000_000 * 1000 + 10_000 * 997)
+        );
+
+        // The box value is not a reserve, so it is unchanged by swaps
+        let swapped = parsed.with_swap(&input).unwrap();
+        let candidate = swapped.into_box_candidate(0).unwrap();
+        assert_eq!(candidate.value, box_value);
+    }
+
+    #[test]
+    fn duplicate_pool_nft_deduplicated() {
+        let pool_at = |pool: SpectrumPool, creation_height: u32, index: u16| {
+            let candidate = pool.into_box_candidate(creation_height).unwrap();
+            let ergo_box = ErgoBox::from_box_candidate(&candidate, TxId::zero(), index).unwrap();
+            TrackedBox::<SpectrumPool>::try_from(ergo_box).unwrap()
+        };
+
+        let stale = test_pool(1_000_000_000, 1_000, 997);
+        let updated = test_pool(2_000_000_000, 500, 997);
+        let mut other = test_pool(1_000_000_000, 1_000, 997);
+        other.pool_nft.token_id = Digest32::from([9u8; 32]).into();
+
+        let nft = stale.pool_nft.token_id;
+
+        let pools = vec![
+            pool_at(stale, 100, 0),
+            pool_at(other, 100, 1),
+            pool_at(updated, 101, 2),
+        ];
+
+        let (pools, duplicates) = dedup_pools(pools);
+
+        assert_eq!(duplicates, vec![nft]);
+        assert_eq!(pools.len(), 2);
+        assert_eq!(pools[0].value.pool_nft.token_id, nft);
+        assert_eq!(pools[0].ergo_box.creation_height, 101);
+        assert_eq!(*pools[0].value.asset_y.amount.as_u64(), 500);
+
+        let (pools, duplicates) = dedup_pools(pools);
+        assert!(duplicates.is_empty());
+        assert_eq!(pools.len(), 2);
+    }
 }
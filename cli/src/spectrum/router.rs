@@ -0,0 +1,397 @@
+use ergo_lib::ergotree_ir::chain::token::{Token, TokenAmount, TokenId};
+use thiserror::Error;
+
+use crate::{boxes::liquidity_box::LiquidityProvider, units::token_id_hex};
+
+/// Maximum number of pools a route may pass through
+pub const MAX_HOPS: usize = 3;
+
+#[derive(Error, Debug)]
+pub enum RouterError<E>
+where
+    E: std::error::Error,
+{
+    #[error("No route from token {} to token {}", token_id_hex(.0), token_id_hex(.1))]
+    NoRoute(TokenId, TokenId),
+    #[error("Cannot swap token {}", token_id_hex(.0))]
+    InvalidToken(TokenId),
+    #[error(transparent)]
+    Swap(E),
+}
+
+/// Every path from `from` to `to` as indices into the pools, in the order they are
+/// swapped through
+fn paths<P: LiquidityProvider>(pools: &[P], from: TokenId, to: TokenId) -> Vec<Vec<usize>> {
+    let mut paths = Vec::new();
+    extend_paths(pools, &mut vec![from], &mut vec![], to, &mut paths);
+    paths
+}
+
+fn extend_paths<P: LiquidityProvider>(
+    pools: &[P],
+    tokens: &mut Vec<TokenId>,
+    path: &mut Vec<usize>,
+    to: TokenId,
+    paths: &mut Vec<Vec<usize>>,
+) {
+    let from = *tokens.last().expect("Path starts with a token");
+
+    if from == to && !path.is_empty() {
+        paths.push(path.clone());
+        return;
+    }
+
+    if path.len() == MAX_HOPS {
+        return;
+    }
+
+    for (index, pool) in pools.iter().enumerate() {
+        let next = if pool.asset_x().token_id == from {
+            pool.asset_y().token_id
+        } else if pool.asset_y().token_id == from {
+            pool.asset_x().token_id
+        } else {
+            continue;
+        };
+
+        if tokens.contains(&next) && next != to {
+            continue;
+        }
+
+        tokens.push(next);
+        path.push(index);
+        extend_paths(pools, tokens, path, to, paths);
+        path.pop();
+        tokens.pop();
+    }
+}
+
+/// Reserves of a single pool equivalent to `path` starting at token `from`, ignoring
+/// fees. Two pools with reserves `x, m` and `m', y` chain to `x * m' / (m + m')` and
+/// `y * m / (m + m')`, which keeps the spot price of the path. `None` if a reserve
+/// rounds to zero.
+fn path_reserves<P: LiquidityProvider>(
+    pools: &[P],
+    path: &[usize],
+    from: TokenId,
+) -> Option<(TokenAmount, TokenAmount)> {
+    let mut token_id = from;
+    let mut reserves: Option<(u128, u128)> = None;
+
+    for &index in path {
+        let pool = &pools[index];
+
+        let (from_asset, to_asset) = if pool.asset_x().token_id == token_id {
+            (pool.asset_x(), pool.asset_y())
+        } else {
+            (pool.asset_y(), pool.asset_x())
+        };
+
+        let from_amount = *from_asset.amount.as_u64() as u128;
+        let to_amount = *to_asset.amount.as_u64() as u128;
+
+        reserves = Some(match reserves {
+            None => (from_amount, to_amount),
+            Some((x_amount, m_amount)) => (
+                x_amount * from_amount / (m_amount + from_amount),
+                to_amount * m_amount / (m_amount + from_amount),
+            ),
+        });
+        token_id = to_asset.token_id;
+    }
+
+    let (x_amount, y_amount) = reserves?;
+
+    Some((
+        u64::try_from(x_amount).ok()?.try_into().ok()?,
+        u64::try_from(y_amount).ok()?.try_into().ok()?,
+    ))
+}
+
+/// Swaps between `asset_x` and `asset_y` routed through one or more pools. Every swap
+/// takes the path of at most [`MAX_HOPS`] pools giving the best price, where each pool
+/// charges its own fee on the output of the previous one. A path never passes the same
+/// token twice.
+#[derive(Clone, Debug)]
+pub struct Router<P> {
+    pools: Vec<P>,
+    asset_x: Token,
+    asset_y: Token,
+}
+
+impl<P> Router<P>
+where
+    P: LiquidityProvider,
+{
+    /// Router between `token_x` and `token_y`. The reserves reported for the tokens are
+    /// those of the path with the most of `token_x`, as chained by [`path_reserves`].
+    pub fn new(
+        pools: Vec<P>,
+        token_x: TokenId,
+        token_y: TokenId,
+    ) -> Result<Self, RouterError<P::Error>> {
+        let (x_amount, y_amount) = paths(&pools, token_x, token_y)
+            .iter()
+            .filter_map(|path| path_reserves(&pools, path, token_x))
+            .max_by_key(|(x_amount, _)| *x_amount)
+            .ok_or(RouterError::NoRoute(token_x, token_y))?;
+
+        Ok(Self {
+            pools,
+            asset_x: (token_x, x_amount).into(),
+            asset_y: (token_y, y_amount).into(),
+        })
+    }
+
+    pub fn pools(&self) -> &[P] {
+        &self.pools
+    }
+
+    pub fn into_pools(self) -> Vec<P> {
+        self.pools
+    }
+
+    /// Token swapped for `token_id` by the router
+    fn counter_token(&self, token_id: TokenId) -> Result<TokenId, RouterError<P::Error>> {
+        if token_id == self.asset_x.token_id {
+            Ok(self.asset_y.token_id)
+        } else if token_id == self.asset_y.token_id {
+            Ok(self.asset_x.token_id)
+        } else {
+            Err(RouterError::InvalidToken(token_id))
+        }
+    }
+
+    /// Output of swapping `input` along `path`
+    fn path_output(&self, path: &[usize], input: &Token) -> Result<Token, P::Error> {
+        path.iter().try_fold(input.clone(), |input, &index| {
+            self.pools[index].output_amount(&input)
+        })
+    }
+
+    /// Input required along `path` for `output`
+    fn path_input(&self, path: &[usize], output: &Token) -> Result<Token, P::Error> {
+        path.iter()
+            .rev()
+            .try_fold(output.clone(), |output, &index| {
+                self.pools[index].input_amount(&output)
+            })
+    }
+
+    /// Path giving the most output for `input`, along with the output
+    fn best_output_path(
+        &self,
+        input: &Token,
+    ) -> Result<(Vec<usize>, Token), RouterError<P::Error>> {
+        let to = self.counter_token(input.token_id)?;
+
+        paths(&self.pools, input.token_id, to)
+            .into_iter()
+            .filter_map(|path| {
+                self.path_output(&path, input)
+                    .ok()
+                    .map(|output| (path, output))
+            })
+            .max_by_key(|(_, output)| output.amount)
+            .ok_or(RouterError::NoRoute(input.token_id, to))
+    }
+}
+
+impl<P> LiquidityProvider for Router<P>
+where
+    P: LiquidityProvider,
+{
+    type Error = RouterError<P::Error>;
+
+    fn can_swap(&self, token_id: &TokenId) -> bool {
+        token_id == &self.asset_x.token_id || token_id == &self.asset_y.token_id
+    }
+
+    /// Swap along the best path, updating the pools of the path and the reserves reported
+    /// by the router
+    fn with_swap(mut self, input: &Token) -> Result<Self, Self::Error> {
+        let (path, _) = self.best_output_path(input)?;
+
+        let mut input = input.clone();
+        for index in path {
+            let pool = self.pools[index].clone();
+            let output = pool.output_amount(&input).map_err(RouterError::Swap)?;
+
+            self.pools[index] = pool.with_swap(&input).map_err(RouterError::Swap)?;
+            input = output;
+        }
+
+        Self::new(self.pools, self.asset_x.token_id, self.asset_y.token_id)
+    }
+
+    fn output_amount(&self, input: &Token) -> Result<Token, Self::Error> {
+        self.best_output_path(input).map(|(_, output)| output)
+    }
+
+    fn input_amount(&self, output: &Token) -> Result<Token, Self::Error> {
+        let from = self.counter_token(output.token_id)?;
+
+        paths(&self.pools, from, output.token_id)
+            .into_iter()
+            .filter_map(|path| self.path_input(&path, output).ok())
+            .min_by_key(|input| input.amount)
+            .ok_or(RouterError::NoRoute(from, output.token_id))
+    }
+
+    fn asset_x(&self) -> &Token {
+        &self.asset_x
+    }
+
+    fn asset_y(&self) -> &Token {
+        &self.asset_y
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ergo_lib::{
+        ergo_chain_types::{ec_point::generator, Digest32},
+        ergotree_ir::chain::{ergo_box::box_value::BoxValue, token::TokenId},
+    };
+
+    use crate::{
+        boxes::liquidity_box::LiquidityProvider,
+        grid::multigrid_order::{
+            FillMultiGridOrders, GridOrderEntries, GridOrderEntry, MultiGridOrder, OrderState,
+            DEFAULT_MIN_BOX_VALUE,
+        },
+        spectrum::pool::{SpectrumPool, SpectrumPoolBuilder, DEFAULT_FEE_DENOM, ERG_TOKEN_ID},
+    };
+
+    use super::{paths, Router, RouterError};
+
+    fn pool(nft: u8, x: (TokenId, u64), y: (TokenId, u64)) -> SpectrumPool {
+        let pool_nft = Digest32::from([nft; 32]).into();
+        let asset_lp = (
+            Digest32::from([nft + 100; 32]).into(),
+            1000.try_into().unwrap(),
+        )
+            .into();
+
+        let builder = if x.0 == *ERG_TOKEN_ID {
+            SpectrumPoolBuilder::new(pool_nft, asset_lp, y.0)
+        } else {
+            SpectrumPoolBuilder::t2t(pool_nft, asset_lp, x.0, y.0, BoxValue::SAFE_USER_MIN)
+        };
+
+        builder
+            .reserves(x.1, y.1)
+            .fee(997, DEFAULT_FEE_DENOM)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn two_hop_route() {
+        let erg = *ERG_TOKEN_ID;
+        let middle: TokenId = Digest32::from([1u8; 32]).into();
+        let target: TokenId = Digest32::from([2u8; 32]).into();
+        let other: TokenId = Digest32::from([3u8; 32]).into();
+
+        let erg_middle = pool(10, (erg, 1_000_000_000_000), (middle, 1_000_000_000));
+        let middle_target = pool(11, (middle, 1_000_000_000), (target, 2_000_000_000));
+        // Cycle between the middle token and another token, which must not be looped
+        let middle_other = pool(12, (middle, 1_000_000_000), (other, 1_000_000_000));
+        let other_middle = pool(13, (other, 1_000_000_000), (middle, 1_000_000_000));
+
+        let router = Router::new(
+            vec![
+                erg_middle.clone(),
+                middle_other,
+                other_middle,
+                middle_target.clone(),
+            ],
+            erg,
+            target,
+        )
+        .unwrap();
+
+        assert_eq!(paths(router.pools(), erg, target), vec![vec![0, 3]]);
+
+        // Fees of both pools are charged in turn
+        let input = (erg, 1_000_000_000.try_into().unwrap()).into();
+        let middle_output = erg_middle.output_amount(&input).unwrap();
+        let expected = middle_target.output_amount(&middle_output).unwrap();
+
+        let output = router.output_amount(&input).unwrap();
+        assert_eq!(output, expected);
+
+        let required = router.input_amount(&output).unwrap();
+        assert_eq!(required.token_id, erg);
+        assert!(*required.amount.as_u64() <= 1_000_000_000);
+
+        let swapped = router.clone().with_swap(&input).unwrap();
+        assert_eq!(
+            *swapped.pools()[3].asset_y.amount.as_u64(),
+            2_000_000_000 - *expected.amount.as_u64()
+        );
+        assert_eq!(
+            swapped.pools()[1].asset_x.amount,
+            router.pools()[1].asset_x.amount
+        );
+
+        // The reported reserves follow the swap along the path
+        assert!(swapped.asset_x().amount > router.asset_x().amount);
+        assert!(swapped.asset_y().amount < router.asset_y().amount);
+
+        // A direct pool with a better price is preferred
+        let direct = pool(14, (erg, 1_000_000_000_000), (target, 4_000_000_000));
+        let router = Router::new(vec![erg_middle, middle_target, direct], erg, target).unwrap();
+
+        let (path, _) = router.best_output_path(&input).unwrap();
+        assert_eq!(path, vec![2]);
+
+        let unknown = (other, 1.try_into().unwrap()).into();
+        assert!(matches!(
+            router.output_amount(&unknown),
+            Err(RouterError::InvalidToken(_))
+        ));
+    }
+
+    #[test]
+    fn orders_filled_through_route() {
+        let erg = *ERG_TOKEN_ID;
+        let middle: TokenId = Digest32::from([1u8; 32]).into();
+        let target: TokenId = Digest32::from([2u8; 32]).into();
+
+        // 1000 nanoERG per middle token and 2 target tokens per middle token, so 500
+        // nanoERG per target token
+        let router = Router::new(
+            vec![
+                pool(10, (erg, 1_000_000_000_000), (middle, 1_000_000_000)),
+                pool(11, (middle, 1_000_000_000), (target, 2_000_000_000)),
+            ],
+            erg,
+            target,
+        )
+        .unwrap();
+
+        // Reserves of the path at the same spot price
+        assert_eq!(*router.asset_x().amount.as_u64(), 500_000_000_000);
+        assert_eq!(*router.asset_y().amount.as_u64(), 1_000_000_000);
+
+        // Buying 1000 target tokens at 600 nanoERG each is above the routed price
+        let entries = GridOrderEntries::new(vec![GridOrderEntry::new(
+            OrderState::Buy,
+            1000.try_into().unwrap(),
+            600_000,
+            700_000,
+        )]);
+        let order =
+            MultiGridOrder::new(generator(), target, entries, None, DEFAULT_MIN_BOX_VALUE).unwrap();
+
+        let (swapped, filled) = router.clone().fill_orders(vec![&order]).unwrap();
+
+        assert_eq!(filled.len(), 1);
+        assert_eq!(
+            filled[0].1.entries.iter().next().unwrap().state,
+            OrderState::Sell
+        );
+        assert!(swapped.pools()[1].asset_y.amount < router.pools()[1].asset_y.amount);
+    }
+}
@@ -8,6 +8,12 @@ use thiserror::Error;
 
 pub type Fraction = GenericFraction<u128>;
 
+/// Hex encoding of a token ID for user-facing output. The `Debug` output of `TokenId` is
+/// reserved for internal logs.
+pub fn token_id_hex(token_id: &TokenId) -> String {
+    String::from(*token_id)
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct TokenInfo {
     #[serde(rename = "id")]
@@ -48,7 +54,7 @@ impl Unit<'_> {
     pub fn name(&self) -> String {
         match self {
             Unit::Known(info) => info.name.clone(),
-            Unit::Unknown(token_id) => (*token_id).into(),
+            Unit::Unknown(token_id) => token_id_hex(token_id),
         }
     }
 
@@ -68,7 +74,7 @@ impl Unit<'_> {
         }
     }
 
-    pub fn str_amount(&self, amount: &str) -> Option<UnitAmount> {
+    pub fn str_amount(&self, amount: &str) -> Option<UnitAmount<'_>> {
         Fraction::from_str(amount)
             .ok()
             .and_then(|amount| (amount * self.base_amount()).floor().to_u64())
@@ -76,6 +82,75 @@ impl Unit<'_> {
     }
 }
 
+/// Why an amount given by the user could not be parsed
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum AmountParseReason {
+    #[error("not a number")]
+    NotANumber,
+    #[error("amounts cannot be negative")]
+    Negative,
+    #[error("{unit} has at most {decimals} decimals")]
+    TooPrecise { unit: String, decimals: u32 },
+    #[error("amount is too large")]
+    TooLarge,
+}
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("Invalid {name} `{input}`: {reason}")]
+pub struct AmountParseError {
+    /// What the amount is for, e.g. `fee`
+    pub name: String,
+    pub input: String,
+    pub reason: AmountParseReason,
+}
+
+/// Parser of amounts given by the user in `unit`, e.g. `1.5` for 1.5 ERG. Unlike
+/// [`Unit::str_amount`] amounts with more decimals than the unit are rejected instead of
+/// rounded down.
+#[derive(Clone, Copy, Debug)]
+pub struct AmountParser<'a> {
+    unit: Unit<'a>,
+    name: &'a str,
+}
+
+impl<'a> AmountParser<'a> {
+    /// Parser for the amount `name`, used to describe the amount in errors
+    pub fn new(unit: Unit<'a>, name: &'a str) -> Self {
+        Self { unit, name }
+    }
+
+    pub fn parse(&self, input: &str) -> Result<UnitAmount<'a>, AmountParseError> {
+        let error = |reason| AmountParseError {
+            name: self.name.to_string(),
+            input: input.to_string(),
+            reason,
+        };
+
+        let amount = Fraction::from_str(input.trim())
+            .ok()
+            .filter(|amount| !amount.is_nan() && !amount.is_infinite())
+            .ok_or_else(|| error(AmountParseReason::NotANumber))?;
+
+        if amount.is_sign_negative() && amount != Fraction::from(0u64) {
+            return Err(error(AmountParseReason::Negative));
+        }
+
+        let base_amount = amount * self.unit.base_amount();
+
+        if base_amount.fract() != Fraction::from(0u64) {
+            return Err(error(AmountParseReason::TooPrecise {
+                unit: self.unit.name(),
+                decimals: self.unit.decimals(),
+            }));
+        }
+
+        base_amount
+            .to_u64()
+            .map(|amount| UnitAmount::new(self.unit, amount))
+            .ok_or_else(|| error(AmountParseReason::TooLarge))
+    }
+}
+
 lazy_static! {
     pub static ref ERG_TOKEN_INFO: TokenInfo = TokenInfo {
         token_id: Digest32::zero().into(),
@@ -96,7 +171,7 @@ impl<'a> UnitAmount<'a> {
         Self { unit, amount }
     }
 
-    pub fn unit(&self) -> &Unit {
+    pub fn unit(&self) -> &Unit<'_> {
         &self.unit
     }
 
@@ -131,12 +206,19 @@ impl<'a> Display for UnitAmount<'a> {
                 write!(f, " {}", info.name)
             }
             Unit::Unknown(token_id) => {
-                write!(f, " {:?}", token_id)
+                write!(f, " {}", token_id_hex(token_id))
             }
         }
     }
 }
 
+/// Prices at or above this limit are shown in scientific notation when one of the units
+/// has no decimals
+const SCIENTIFIC_PRICE_LIMIT: f64 = 1e12;
+
+/// Prices needing more decimals than this are shown in scientific notation
+const MAX_PRICE_PRECISION: usize = 18;
+
 #[derive(Clone, Debug)]
 pub struct Price<'a> {
     base: Unit<'a>,
@@ -162,16 +244,58 @@ impl<'a> Price<'a> {
     }
 
     pub fn format(&self) -> String {
+        let price = self.price();
+        let precision = self.quote.decimals() as usize;
+
+        // Prices against a token without decimals, such as an NFT, can easily end up far
+        // outside of what the quote precision can show.
+        let has_zero_decimals = self.base.decimals() == 0 || self.quote.decimals() == 0;
+
+        if has_zero_decimals && !price.is_nan() && !price.is_infinite() {
+            let price_f64 = price.to_f64().unwrap_or_default();
+
+            if price_f64 >= SCIENTIFIC_PRICE_LIMIT {
+                return format!(
+                    "{:.3e} {}/{}",
+                    price_f64,
+                    self.base.name(),
+                    self.quote.name()
+                );
+            }
+
+            if price_f64 > 0.0 && price_f64 < 10f64.powi(-(precision as i32)) {
+                // Show enough decimals for four significant digits
+                let precision = (-price_f64.log10()).ceil() as usize + 3;
+
+                return if precision > MAX_PRICE_PRECISION {
+                    format!(
+                        "{:.3e} {}/{}",
+                        price_f64,
+                        self.base.name(),
+                        self.quote.name()
+                    )
+                } else {
+                    format!(
+                        "{0:.1$} {2}/{3}",
+                        price,
+                        precision,
+                        self.base.name(),
+                        self.quote.name()
+                    )
+                };
+            }
+        }
+
         format!(
             "{0:.1$} {2}/{3}",
-            self.price * Fraction::new(self.base.base_amount(), self.quote.base_amount()),
-            self.quote.decimals() as usize,
+            price,
+            precision,
             self.base.name(),
             self.quote.name()
         )
     }
 
-    pub fn convert_price(&self, other: &UnitAmount) -> Option<UnitAmount> {
+    pub fn convert_price(&self, other: &UnitAmount) -> Option<UnitAmount<'_>> {
         if self.base == *other.unit() {
             let amount = self.price * other.amount;
             Some(UnitAmount::new(
@@ -193,6 +317,14 @@ impl<'a> Price<'a> {
     pub fn price(&self) -> Fraction {
         self.price * Fraction::new(self.base.base_amount(), self.quote.base_amount())
     }
+
+    /// Combine with `other`, a price of this price's quote unit, into a price of the base
+    /// unit in the quote unit of `other`. Two tokens priced in ERG can be converted into
+    /// each other this way.
+    pub fn chain(&self, other: &Price<'a>) -> Option<Price<'a>> {
+        (self.quote == other.base)
+            .then(|| Price::new(self.base, other.quote, self.price * other.price))
+    }
 }
 
 impl Display for Price<'_> {
@@ -209,45 +341,77 @@ pub enum TokenStoreError {
     ParseError(#[from] serde_json::Error),
 }
 
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum TokenLookupError {
+    #[error("`{0}` is not a known token or a valid token ID")]
+    Unknown(String),
+    #[error("Ambiguous token name `{0}`, specify the token by ID")]
+    Ambiguous(String, Vec<TokenId>),
+}
+
 pub struct TokenStore {
     tokens: HashMap<TokenId, TokenInfo>,
+    /// IDs of the tokens with each name, more than one if the name is ambiguous
+    names: HashMap<String, Vec<TokenId>>,
 }
 
 impl Default for TokenStore {
     fn default() -> Self {
-        Self {
-            tokens: HashMap::from([(ERG_TOKEN_INFO.token_id, ERG_TOKEN_INFO.clone())]),
-        }
+        Self::with_tokens(vec![])
     }
 }
 
 impl TokenStore {
     pub fn with_tokens(tokens: Vec<TokenInfo>) -> Self {
-        let mut ret: Self = Default::default();
+        let tokens: HashMap<TokenId, TokenInfo> = std::iter::once(ERG_TOKEN_INFO.clone())
+            .chain(tokens)
+            .map(|token| (token.token_id, token))
+            .collect();
+
+        let mut names: HashMap<String, Vec<TokenId>> = HashMap::new();
+
+        for token in tokens.values() {
+            names
+                .entry(token.name.clone())
+                .or_default()
+                .push(token.token_id);
+        }
 
-        ret.tokens
-            .extend(tokens.into_iter().map(|token| (token.token_id, token)));
+        for token_ids in names.values_mut() {
+            token_ids.sort_by_key(token_id_hex);
+        }
 
-        ret
+        Self { tokens, names }
     }
 
-    pub fn get_unit(&self, token_id: &TokenId) -> Unit {
+    pub fn get_unit(&self, token_id: &TokenId) -> Unit<'_> {
         self.tokens
             .get(token_id)
             .map(Unit::Known)
             .unwrap_or(Unit::Unknown(*token_id))
     }
 
-    pub fn get_unit_by_id(&self, token_name: &str) -> Option<Unit> {
-        self.tokens
-            .values()
-            .find(|token| token.name == token_name)
-            .map(Unit::Known)
-            .or_else(|| {
-                Digest32::try_from(token_name.to_string())
-                    .ok()
-                    .map(|token_id| Unit::Unknown(token_id.into()))
-            })
+    /// Unit of the token named `token_name`, or with `token_name` as its hex encoded ID.
+    /// A name shared by several tokens is an error rather than resolving to any of them.
+    pub fn get_unit_by_id(&self, token_name: &str) -> Result<Unit<'_>, TokenLookupError> {
+        match self.names.get(token_name).map(Vec::as_slice) {
+            Some([token_id]) => Ok(self.get_unit(token_id)),
+            Some(token_ids) => Err(TokenLookupError::Ambiguous(
+                token_name.to_string(),
+                token_ids.to_vec(),
+            )),
+            None => Digest32::try_from(token_name.to_string())
+                .map(|token_id| self.get_unit(&token_id.into()))
+                .map_err(|_| TokenLookupError::Unknown(token_name.to_string())),
+        }
+    }
+
+    /// Names shared by more than one token, with the IDs of those tokens
+    pub fn ambiguous_names(&self) -> impl Iterator<Item = (&str, &[TokenId])> {
+        self.names
+            .iter()
+            .filter(|(_, token_ids)| token_ids.len() > 1)
+            .map(|(name, token_ids)| (name.as_str(), token_ids.as_slice()))
     }
 
     pub fn save(&self, path: Option<String>) -> Result<(), TokenStoreError> {
@@ -277,9 +441,56 @@ mod tests {
     use ergo_lib::ergo_chain_types::{Digest, Digest32};
     use proptest::prelude::*;
 
-    use crate::units::{Price, UnitAmount};
+    use crate::{
+        spectrum::pool::PoolSelectionError,
+        units::{Price, UnitAmount, ERG_UNIT},
+    };
 
-    use super::{Fraction, TokenInfo, Unit};
+    use super::{
+        token_id_hex, AmountParser, Fraction, TokenInfo, TokenLookupError, TokenStore, Unit,
+    };
+
+    #[test]
+    fn amount_parse_errors() {
+        let token_info = TokenInfo {
+            token_id: Digest32::from([1u8; 32]).into(),
+            name: "SigUSD".to_string(),
+            decimals: 2,
+        };
+        let token = Unit::Known(&token_info);
+
+        let fee = AmountParser::new(*ERG_UNIT, "fee");
+        let token_amount = AmountParser::new(token, "token amount");
+        let total_value = AmountParser::new(*ERG_UNIT, "total value");
+
+        assert_eq!(fee.parse("0.0015").unwrap().amount(), 1_500_000);
+        assert_eq!(token_amount.parse(" 12.34 ").unwrap().amount(), 1234);
+        assert_eq!(total_value.parse("0").unwrap().amount(), 0);
+
+        let message = |parser: AmountParser, input| parser.parse(input).unwrap_err().to_string();
+
+        assert_eq!(message(fee, "fast"), "Invalid fee `fast`: not a number");
+        assert_eq!(
+            message(fee, "0.0000000001"),
+            "Invalid fee `0.0000000001`: ERG has at most 9 decimals"
+        );
+        assert_eq!(
+            message(token_amount, "-5"),
+            "Invalid token amount `-5`: amounts cannot be negative"
+        );
+        assert_eq!(
+            message(token_amount, "1.005"),
+            "Invalid token amount `1.005`: SigUSD has at most 2 decimals"
+        );
+        assert_eq!(
+            message(total_value, "100000000000"),
+            "Invalid total value `100000000000`: amount is too large"
+        );
+        assert_eq!(
+            message(total_value, ""),
+            "Invalid total value ``: not a number"
+        );
+    }
 
     proptest! {
         #[test]
@@ -360,6 +571,94 @@ mod tests {
         assert_eq!(unit_amount2.amount(), 2000 / 13);
     }
 
+    #[test]
+    fn unknown_token_display() {
+        let token_id = Digest::<32>([0xab; 32]).into();
+        let hex = "ab".repeat(32);
+
+        assert_eq!(token_id_hex(&token_id), hex);
+
+        let unit = Unit::Unknown(token_id);
+        assert_eq!(unit.name(), hex);
+        assert_eq!(UnitAmount::new(unit, 5).to_string(), format!("5 {}", hex));
+
+        let error = PoolSelectionError::NoPool(token_id).to_string();
+        assert_eq!(error, format!("No liquidity pool found for token {}", hex));
+    }
+
+    #[test]
+    fn zero_decimal_price_display() {
+        let nft_info = TokenInfo {
+            token_id: Digest::<32>([1u8; 32]).into(),
+            name: "NFT".to_string(),
+            decimals: 0,
+        };
+        let nft = Unit::Known(&nft_info);
+
+        // 5 ERG per NFT
+        let price = Price::new(nft, *ERG_UNIT, Fraction::from(5_000_000_000u64));
+
+        assert_eq!(price.to_string(), "5 NFT/ERG");
+        assert_eq!(price.indirect().to_string(), "0.2 ERG/NFT");
+
+        // 1 nanoERG per 10^15 NFT
+        let price = Price::new(
+            nft,
+            *ERG_UNIT,
+            Fraction::new(1u64, 1_000_000_000_000_000u64),
+        );
+
+        assert_eq!(price.to_string(), "1.000e-24 NFT/ERG");
+        assert_eq!(price.indirect().to_string(), "1.000e24 ERG/NFT");
+    }
+
+    #[test]
+    fn duplicate_token_names_are_ambiguous() {
+        let token = |byte: u8, name: &str| TokenInfo {
+            token_id: Digest32::from([byte; 32]).into(),
+            name: name.to_string(),
+            decimals: 0,
+        };
+
+        let tokens = TokenStore::with_tokens(vec![
+            token(1, "COMET"),
+            token(2, "COMET"),
+            token(3, "SigUSD"),
+        ]);
+
+        let comet_ids = vec![
+            Digest32::from([1u8; 32]).into(),
+            Digest32::from([2u8; 32]).into(),
+        ];
+
+        assert_eq!(
+            tokens.get_unit_by_id("COMET"),
+            Err(TokenLookupError::Ambiguous(
+                "COMET".to_string(),
+                comet_ids.clone()
+            ))
+        );
+        assert_eq!(
+            tokens.ambiguous_names().collect::<Vec<_>>(),
+            vec![("COMET", comet_ids.as_slice())]
+        );
+
+        // Tokens sharing a name can still be selected by ID
+        let comet_id = token_id_hex(&comet_ids[1]);
+        assert!(matches!(
+            tokens.get_unit_by_id(&comet_id),
+            Ok(Unit::Known(info)) if info.token_id == comet_ids[1]
+        ));
+        assert!(matches!(
+            tokens.get_unit_by_id("SigUSD"),
+            Ok(Unit::Known(info)) if info.name == "SigUSD"
+        ));
+        assert_eq!(
+            tokens.get_unit_by_id("sigusd"),
+            Err(TokenLookupError::Unknown("sigusd".to_string()))
+        );
+    }
+
     fn convert_price(decimals1: u32, decimals2: u32, price1: u64, price2: u64, amount: u64) {
         let mut token_bytes = [0u8; 32];
         token_bytes[0] = 1;
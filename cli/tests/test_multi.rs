@@ -27,7 +27,7 @@ use ergo_lib::{
 use lazy_static::lazy_static;
 
 use off_the_grid::grid::multigrid_order::{
-    GridOrderEntries, GridOrderEntry, MultiGridOrder, OrderState, MAX_FEE,
+    GridOrderEntries, GridOrderEntry, MultiGridOrder, OrderState, DEFAULT_MIN_BOX_VALUE, MAX_FEE,
 };
 
 const HEADERS_JSON: &[u8] = include_bytes!("./headers.json");
@@ -102,7 +102,14 @@ fn generate_test_grid() -> (MultiGridOrder, TestProver) {
 
     let entries = GridOrderEntries::new(entries);
 
-    let grid = MultiGridOrder::new(group_element, token_id, entries, None).unwrap();
+    let grid = MultiGridOrder::new(
+        group_element,
+        token_id,
+        entries,
+        None,
+        DEFAULT_MIN_BOX_VALUE,
+    )
+    .unwrap();
 
     (grid, prover)
 }
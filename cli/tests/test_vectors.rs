@@ -0,0 +1,188 @@
+//! Fixed grid orders with the exact registers and box IDs they are expected to produce.
+//! Grids that are already deployed can only be spent and parsed as long as the register
+//! encoding stays the same, so any change to it must fail these tests.
+
+use std::collections::HashMap;
+
+use ergo_lib::{
+    chain::transaction::TxId,
+    ergo_chain_types::{Digest32, EcPoint},
+    ergotree_ir::{
+        chain::ergo_box::{ErgoBox, NonMandatoryRegisterId},
+        serialization::SigmaSerializable,
+    },
+};
+use off_the_grid::grid::multigrid_order::{
+    GridOrderEntries, GridOrderEntry, MultiGridOrder, OrderState,
+};
+use serde::Deserialize;
+
+const VECTORS_JSON: &[u8] = include_bytes!("./grid_vectors.json");
+
+#[derive(Deserialize)]
+struct GridVector {
+    name: String,
+    /// Compressed owner public key
+    owner: String,
+    token_id: String,
+    /// (state, token amount, bid value, ask value), state is either "buy" or "sell"
+    entries: Vec<(String, u64, u64, u64)>,
+    /// Contents of R7, if any
+    metadata: Option<String>,
+    min_box_value: u64,
+    creation_height: u32,
+    expected: ExpectedBox,
+}
+
+#[derive(Deserialize)]
+struct ExpectedBox {
+    value: u64,
+    token_amount: Option<u64>,
+    /// Serialized constants keyed by register
+    registers: HashMap<String, String>,
+    /// ID of the box created from the candidate by output 0 of the zero transaction ID
+    box_id: String,
+}
+
+impl GridVector {
+    fn order(&self) -> MultiGridOrder {
+        let owner = EcPoint::sigma_parse_bytes(&base16::decode(&self.owner).unwrap()).unwrap();
+        let token_id = Digest32::try_from(self.token_id.clone()).unwrap().into();
+
+        let entries: GridOrderEntries = self
+            .entries
+            .iter()
+            .map(|(state, amount, bid_value, ask_value)| {
+                let state = match state.as_str() {
+                    "buy" => OrderState::Buy,
+                    "sell" => OrderState::Sell,
+                    _ => panic!("{}: invalid state {}", self.name, state),
+                };
+
+                GridOrderEntry::new(state, (*amount).try_into().unwrap(), *bid_value, *ask_value)
+            })
+            .collect();
+
+        let metadata = self.metadata.as_ref().map(|m| base16::decode(m).unwrap());
+
+        MultiGridOrder::new(owner, token_id, entries, metadata, self.min_box_value).unwrap()
+    }
+}
+
+fn vectors() -> Vec<GridVector> {
+    serde_json::from_slice(VECTORS_JSON).unwrap()
+}
+
+fn register_bytes(ergo_box: &ErgoBox) -> HashMap<String, String> {
+    [
+        ("R4", NonMandatoryRegisterId::R4),
+        ("R5", NonMandatoryRegisterId::R5),
+        ("R6", NonMandatoryRegisterId::R6),
+        ("R7", NonMandatoryRegisterId::R7),
+    ]
+    .into_iter()
+    .filter_map(|(name, id)| {
+        ergo_box.additional_registers.get_constant(id).map(|c| {
+            (
+                name.to_string(),
+                base16::encode_lower(&c.sigma_serialize_bytes().unwrap()),
+            )
+        })
+    })
+    .collect()
+}
+
+#[test]
+fn vectors_encode_to_expected_boxes() {
+    let vectors = vectors();
+    assert!(vectors.len() >= 3);
+
+    for vector in vectors {
+        let candidate = vector
+            .order()
+            .into_box_candidate(vector.creation_height)
+            .unwrap();
+        let ergo_box = ErgoBox::from_box_candidate(&candidate, TxId::zero(), 0).unwrap();
+
+        let token_amount = ergo_box.tokens.as_ref().map(|t| *t.first().amount.as_u64());
+
+        assert_eq!(
+            *ergo_box.value.as_u64(),
+            vector.expected.value,
+            "{}",
+            vector.name
+        );
+        assert_eq!(
+            token_amount, vector.expected.token_amount,
+            "{}",
+            vector.name
+        );
+        assert_eq!(
+            register_bytes(&ergo_box),
+            vector.expected.registers,
+            "{}",
+            vector.name
+        );
+        assert_eq!(
+            String::from(ergo_box.box_id()),
+            vector.expected.box_id,
+            "{}",
+            vector.name
+        );
+    }
+}
+
+#[test]
+fn vectors_parse_from_expected_boxes() {
+    for vector in vectors() {
+        let candidate = vector
+            .order()
+            .into_box_candidate(vector.creation_height)
+            .unwrap();
+        let ergo_box = ErgoBox::from_box_candidate(&candidate, TxId::zero(), 0).unwrap();
+
+        let parsed = MultiGridOrder::try_from(&ergo_box).unwrap();
+
+        let entries: Vec<_> = parsed
+            .entries
+            .iter()
+            .map(|e| {
+                let state = match e.state {
+                    OrderState::Buy => "buy",
+                    OrderState::Sell => "sell",
+                };
+                (
+                    state.to_string(),
+                    e.order_amount(),
+                    e.bid_value,
+                    e.ask_value,
+                )
+            })
+            .collect();
+
+        assert_eq!(entries, vector.entries, "{}", vector.name);
+        assert_eq!(
+            parsed.metadata.as_ref().map(base16::encode_lower),
+            vector.metadata,
+            "{}",
+            vector.name
+        );
+        assert_eq!(
+            String::from(parsed.token_id),
+            vector.token_id,
+            "{}",
+            vector.name
+        );
+
+        // Encoding the parsed order again gives back the same box
+        let reencoded = parsed.into_box_candidate(vector.creation_height).unwrap();
+        let reencoded = ErgoBox::from_box_candidate(&reencoded, TxId::zero(), 0).unwrap();
+
+        assert_eq!(
+            String::from(reencoded.box_id()),
+            vector.expected.box_id,
+            "{}",
+            vector.name
+        );
+    }
+}